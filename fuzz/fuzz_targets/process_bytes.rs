@@ -0,0 +1,34 @@
+//! Feeds arbitrary program output through the terminal, with resizes in
+//! between, and checks the grid stays consistent.
+//!
+//! Run with `cargo +nightly fuzz run process_bytes` from the repository
+//! root.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rt::terminal::Terminal;
+
+#[derive(Debug, Arbitrary)]
+enum Step {
+    Output(Vec<u8>),
+    Resize { cols: u8, rows: u8 },
+}
+
+fuzz_target!(|steps: Vec<Step>| {
+    let mut terminal = Terminal::new(80, 24);
+    for step in steps {
+        match step {
+            Step::Output(bytes) => terminal.process_pty_output(&bytes),
+            Step::Resize { cols, rows } => terminal.resize(cols as usize, rows as usize),
+        }
+        assert!(terminal.cursor_x <= terminal.width);
+        assert!(terminal.cursor_y < terminal.height);
+        assert!(terminal
+            .iter_rows()
+            .all(|row| row.cells().len() == terminal.width));
+        assert_eq!(terminal.iter_rows().count(), terminal.height);
+        assert!(terminal.history_len() <= terminal.scrollback_limit);
+    }
+});
@@ -0,0 +1,255 @@
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+
+use crate::geometry::{Geometry, WindowConfig};
+
+/// Options that take a value in the next argument, so a following `-e`
+/// is that value rather than the start of a command.
+const VALUE_OPTIONS: &[&str] = &[
+    "--working-directory",
+    "--title",
+    "--config",
+    "--geometry",
+    "--log-file",
+    "--record",
+    "--play",
+    "--view",
+    "--attach",
+    "--daemon",
+    "--profile",
+];
+
+/// Command line options.
+#[derive(Debug, Clone, Default, PartialEq, Parser)]
+#[command(name = "rt", version, about = "A GPU-accelerated terminal emulator")]
+pub struct Cli {
+    /// Run COMMAND with its arguments instead of the shell and close when
+    /// it exits. Everything after -e is passed on verbatim, as in xterm.
+    #[arg(
+        short = 'e',
+        long = "command",
+        value_name = "COMMAND",
+        num_args = 1..,
+        allow_hyphen_values = true
+    )]
+    pub command: Vec<String>,
+    /// Directory to start the shell or command in.
+    #[arg(long, value_name = "PATH")]
+    pub working_directory: Option<PathBuf>,
+    /// Window title shown while the program sets none.
+    #[arg(long)]
+    pub title: Option<String>,
+    /// Config file to use instead of the default one.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Initial grid size.
+    #[arg(long, value_name = "COLSxROWS")]
+    pub geometry: Option<Geometry>,
+    #[arg(long)]
+    pub maximized: bool,
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Append diagnostics to FILE instead of stderr.
+    #[arg(long, value_name = "FILE")]
+    pub log_file: Option<PathBuf>,
+    /// Save everything the shell writes to FILE.
+    #[arg(long, value_name = "FILE", conflicts_with = "play")]
+    pub record: Option<PathBuf>,
+    /// Show a file made with --record instead of starting a shell.
+    #[arg(long, value_name = "FILE")]
+    pub play: Option<PathBuf>,
+    /// Show FILE, or standard input for -, read-only with scrollback,
+    /// search and copy mode instead of starting a shell.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["play", "record", "command"])]
+    pub view: Option<PathBuf>,
+    /// Start with profile NAME from the config, running its command in
+    /// its directory and environment.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["play", "view", "command"])]
+    pub profile: Option<String>,
+    /// Run the shell in a background daemon for session NAME, started if
+    /// none is running, so closing the window detaches from the shell
+    /// instead of ending it. Attaching again replays the recent output.
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["play", "view"])]
+    pub attach: Option<String>,
+    /// Serve session NAME to --attach; started by it.
+    #[arg(long, value_name = "NAME", hide = true, conflicts_with_all = ["play", "view", "attach"])]
+    pub daemon: Option<String>,
+    /// Print the available GPU adapters and exit.
+    #[arg(long)]
+    pub list_gpus: bool,
+}
+
+impl Cli {
+    /// Parses `args`, starting with the program name. Arguments after `-e`
+    /// never reach the option parser, so `rt -e ssh host -- ls -la` runs
+    /// `ssh` with `host -- ls -la`.
+    pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, clap::Error> {
+        let args: Vec<String> = args.into_iter().collect();
+        let (mut options, command) = split_command(&args);
+        // Let the parser see the program, so options that conflict with
+        // -e are still reported
+        if let Some(program) = command.as_ref().and_then(|command| command.first()) {
+            options.push(format!("--command={}", program));
+        }
+        let mut cli = Self::try_parse_from(options)?;
+        if let Some(command) = command {
+            cli.command = command;
+        }
+        Ok(cli)
+    }
+
+    /// Options passed on to the daemon `--attach` starts: those choosing
+    /// what it runs and where.
+    pub fn daemon_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(path) = &self.config {
+            args.push("--config".into());
+            args.push(path.clone().into_os_string());
+        }
+        if let Some(dir) = &self.working_directory {
+            args.push("--working-directory".into());
+            args.push(dir.clone().into_os_string());
+        }
+        if let Some(profile) = &self.profile {
+            args.push("--profile".into());
+            args.push(profile.into());
+        }
+        if !self.command.is_empty() {
+            args.push("-e".into());
+            args.extend(self.command.iter().map(OsString::from));
+        }
+        args
+    }
+
+    /// Applies the window options over the config.
+    pub fn apply_window(&self, window: &mut WindowConfig) {
+        if let Some(geometry) = self.geometry {
+            window.geometry = geometry;
+        }
+        window.maximized |= self.maximized;
+        window.fullscreen |= self.fullscreen;
+    }
+}
+
+/// Splits the arguments at `-e` or `--command` into the options before it
+/// and the command after it. A trailing `-e` with nothing after it is left
+/// to the option parser to report.
+pub fn split_command(args: &[String]) -> (Vec<String>, Option<Vec<String>>) {
+    let mut index = 1;
+    while index < args.len() {
+        let arg = args[index].as_str();
+        if arg == "--" {
+            break;
+        }
+        if (arg == "-e" || arg == "--command") && index + 1 < args.len() {
+            return (args[..index].to_vec(), Some(args[index + 1..].to_vec()));
+        }
+        if let Some(program) = arg.strip_prefix("--command=") {
+            let mut command = vec![program.to_string()];
+            command.extend_from_slice(&args[index + 1..]);
+            return (args[..index].to_vec(), Some(command));
+        }
+        if VALUE_OPTIONS.contains(&arg) {
+            index += 1;
+        }
+        index += 1;
+    }
+    (args.to_vec(), None)
+}
+
+/// Sends everything written to stderr to the end of `path`.
+#[cfg(unix)]
+pub fn redirect_stderr(path: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    if unsafe { libc::dup2(file.as_raw_fd(), libc::STDERR_FILENO) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn redirect_stderr(path: &Path) -> io::Result<()> {
+    let _ = OpenOptions::new().create(true).append(true).open(path)?;
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--log-file is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, clap::Error> {
+        Cli::parse_args(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn arguments_after_e_are_passed_on_verbatim() {
+        let cli = parse("rt -e ssh host -- ls -la").unwrap();
+        assert_eq!(cli.command, ["ssh", "host", "--", "ls", "-la"]);
+
+        let cli = parse("rt --title x --command vim --title y").unwrap();
+        assert_eq!(cli.title.as_deref(), Some("x"));
+        assert_eq!(cli.command, ["vim", "--title", "y"]);
+
+        let cli = parse("rt --command=htop -d 5").unwrap();
+        assert_eq!(cli.command, ["htop", "-d", "5"]);
+    }
+
+    #[test]
+    fn e_as_an_option_value_is_not_a_command() {
+        let args = ["rt", "--title", "-e", "--geometry", "100x30"].map(String::from);
+        assert_eq!(split_command(&args), (args.to_vec(), None));
+        let args = ["rt", "--", "-e", "ls"].map(String::from);
+        assert_eq!(split_command(&args), (args.to_vec(), None));
+    }
+
+    #[test]
+    fn malformed_arguments_are_errors() {
+        assert!(parse("rt -e").is_err());
+        assert!(parse("rt --geometry 100").is_err());
+        assert!(parse("rt --unknown").is_err());
+        assert!(parse("rt --record a --play b").is_err());
+        assert!(parse("rt --view f -e ls").is_err());
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let cli = parse("rt --working-directory /tmp --maximized --list-gpus").unwrap();
+        assert_eq!(cli.working_directory, Some(PathBuf::from("/tmp")));
+        assert!(cli.maximized && cli.list_gpus && !cli.fullscreen);
+        assert_eq!(parse("rt").unwrap(), Cli::default());
+    }
+
+    #[test]
+    fn window_options_override_the_config() {
+        let mut window = WindowConfig {
+            padding: 2.0,
+            ..WindowConfig::default()
+        };
+        parse("rt --geometry 90x20 --fullscreen")
+            .unwrap()
+            .apply_window(&mut window);
+        assert_eq!(window.geometry, Geometry { cols: 90, rows: 20 });
+        assert!(window.fullscreen && !window.maximized);
+        assert_eq!(window.padding, 2.0);
+    }
+
+    #[test]
+    fn the_daemon_gets_what_to_run_and_where() {
+        let cli = parse("rt --attach work --working-directory /tmp --title t -e top -d 1").unwrap();
+        assert_eq!(
+            cli.daemon_args(),
+            ["--working-directory", "/tmp", "-e", "top", "-d", "1"]
+                .map(OsString::from)
+                .to_vec()
+        );
+    }
+}
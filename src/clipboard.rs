@@ -0,0 +1,179 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+/// A pair of commands that set and print the system clipboard. The
+/// clipboard is reached through the platform's own tools, as pipe actions
+/// reach other programs, rather than through the windowing system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClipboardTool {
+    /// Reads the new contents from stdin.
+    pub copy: Vec<String>,
+    /// Prints the contents to stdout.
+    pub paste: Vec<String>,
+}
+
+impl ClipboardTool {
+    pub fn new(copy: &[&str], paste: &[&str]) -> Self {
+        let argv = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+        Self {
+            copy: argv(copy),
+            paste: argv(paste),
+        }
+    }
+
+    /// Replaces the clipboard with `text`. Blocks until the tool exits.
+    pub fn copy(&self, text: &str) -> io::Result<()> {
+        // The tool's output isn't read: X11 tools fork to keep serving the
+        // selection, and would hold a piped stdout open
+        let mut child = Command::new(&self.copy[0])
+            .args(&self.copy[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let written = stdin.write_all(text.as_bytes());
+        drop(stdin);
+        let status = child.wait()?;
+        written?;
+        if !status.success() {
+            return Err(io::Error::other(format!("exited with {}", status)));
+        }
+        Ok(())
+    }
+
+    /// The clipboard's text. Blocks until the tool exits.
+    pub fn paste(&self) -> io::Result<String> {
+        let output = Command::new(&self.paste[0])
+            .args(&self.paste[1..])
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!("exited with {}", output.status)));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// The tools to try on this platform, in order; `wayland` is whether the
+/// session is a Wayland one.
+pub fn tools(wayland: bool) -> Vec<ClipboardTool> {
+    if cfg!(target_os = "macos") {
+        vec![ClipboardTool::new(&["pbcopy"], &["pbpaste"])]
+    } else if cfg!(windows) {
+        vec![ClipboardTool::new(
+            &[
+                "powershell",
+                "-NoProfile",
+                "-Command",
+                "$input | Set-Clipboard",
+            ],
+            &["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"],
+        )]
+    } else {
+        let x11 = [
+            ClipboardTool::new(
+                &["xclip", "-selection", "clipboard"],
+                &["xclip", "-selection", "clipboard", "-o"],
+            ),
+            ClipboardTool::new(
+                &["xsel", "--clipboard", "--input"],
+                &["xsel", "--clipboard", "--output"],
+            ),
+        ];
+        let wayland =
+            wayland.then(|| ClipboardTool::new(&["wl-copy"], &["wl-paste", "--no-newline"]));
+        wayland.into_iter().chain(x11).collect()
+    }
+}
+
+/// Runs `action` with the first of `tools` that is installed.
+fn with_first_installed<T>(
+    tools: &[ClipboardTool],
+    action: impl Fn(&ClipboardTool) -> io::Result<T>,
+) -> io::Result<T> {
+    for tool in tools {
+        match action(tool) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            result => return result,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no clipboard tool found",
+    ))
+}
+
+fn session_tools() -> Vec<ClipboardTool> {
+    tools(std::env::var_os("WAYLAND_DISPLAY").is_some())
+}
+
+/// Replaces the system clipboard with `text`. Blocks until the tool exits,
+/// so call it off the UI thread.
+pub fn copy(text: &str) -> io::Result<()> {
+    with_first_installed(&session_tools(), |tool| tool.copy(text))
+}
+
+/// The system clipboard's text. Blocks until the tool exits, so call it
+/// off the UI thread.
+pub fn paste() -> io::Result<String> {
+    with_first_installed(&session_tools(), ClipboardTool::paste)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn wayland_sessions_try_wl_copy_before_the_x11_tools() {
+        let programs = |wayland| -> Vec<String> {
+            tools(wayland)
+                .into_iter()
+                .map(|tool| tool.copy[0].clone())
+                .collect()
+        };
+        assert_eq!(programs(false), ["xclip", "xsel"]);
+        assert_eq!(programs(true), ["wl-copy", "xclip", "xsel"]);
+    }
+
+    /// A clipboard kept in a file by `sh`.
+    #[cfg(unix)]
+    fn file_clipboard(name: &str) -> (ClipboardTool, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!("rt-{}-{}", std::process::id(), name));
+        let copy = format!("cat > '{}'", path.display());
+        let paste = format!("cat '{}'", path.display());
+        (
+            ClipboardTool::new(&["sh", "-c", &copy], &["sh", "-c", &paste]),
+            path,
+        )
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copied_text_is_pasted_back() {
+        let (tool, path) = file_clipboard("round-trip");
+        tool.copy("first line\nsecond ✓").unwrap();
+        assert_eq!(tool.paste().unwrap(), "first line\nsecond ✓");
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missing_tools_are_skipped_and_failures_reported() {
+        let (tool, path) = file_clipboard("fallback");
+        let missing = ClipboardTool::new(&["rt-no-such-copy"], &["rt-no-such-paste"]);
+        let tools = [missing.clone(), tool];
+        with_first_installed(&tools, |tool| tool.copy("text")).unwrap();
+        assert_eq!(
+            with_first_installed(&tools, ClipboardTool::paste).unwrap(),
+            "text"
+        );
+        let _ = std::fs::remove_file(path);
+
+        let error = with_first_installed(&[missing], ClipboardTool::paste).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        let failing = ClipboardTool::new(&["false"], &["false"]);
+        assert!(failing.copy("text").is_err());
+        assert!(failing.paste().is_err());
+    }
+}
@@ -0,0 +1,555 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::geometry::WindowConfig;
+use crate::gpu::{self, GpuConfig};
+use crate::hints::HintPattern;
+use crate::input::{KeyBinding, KeyBindings};
+use crate::notifications::DEFAULT_MIN_COMMAND_DURATION;
+use crate::profile::Profile;
+use crate::pty::DEFAULT_COALESCE_BYTES;
+use crate::renderer::{PresentModePreference, MAX_LINE_SPACING, MIN_LINE_SPACING};
+use crate::terminal::selection::DEFAULT_WORD_SEPARATORS;
+use crate::terminal::triggers::Trigger;
+use crate::terminal::{Color, CursorBlink, ScrollConfig};
+
+/// Lines of scrollback saved for the next start by default.
+pub const DEFAULT_SAVED_LINES: usize = 10_000;
+
+/// An error in the config file, with the 1-based line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// User configuration, read from an INI-style file:
+///
+/// ```text
+/// [keybindings]
+/// ctrl+shift+t = new_tab
+/// copy-mode: y = copy
+/// ctrl+shift+o = pipe_paste selection sort
+///
+/// [hints]
+/// issue = open https://example\.com/issues/\d+
+///
+/// [triggers]
+/// notify Build failed = ERROR
+/// highlight #803030 = WARN(ING)?
+///
+/// [notifications]
+/// min_command_duration = 10
+///
+/// [scrolling]
+/// lines_per_notch = 5
+/// smooth = false
+/// keep_overflow = true
+///
+/// [selection]
+/// word_separators = ,;:'"()[]{}<>
+///
+/// [mouse]
+/// context_menu_with_reporting = true
+///
+/// [window]
+/// title_template = {cwd}: {title}
+/// geometry = 100x30
+/// padding = 4
+/// maximized = false
+/// fullscreen = false
+///
+/// [display]
+/// present_mode = mailbox
+/// background_opacity = 0.9
+/// bold_is_bright = true
+/// color1 = #e06c75
+/// cursor_color = #ffcc00
+/// cursor_color_unfocused = #806600
+/// cursor_blink = true
+/// cursor_blink_interval = 600
+/// cursor_blink_force_off = false
+/// min_contrast = 4.5
+///
+/// [shell]
+/// login = true
+/// coalesce_reads = true
+/// coalesce_max_bytes = 65536
+///
+/// [session]
+/// restore_scrollback = true
+/// saved_lines = 10000
+///
+/// [gpu]
+/// power_preference = low-power
+/// backend = vulkan
+///
+/// [profile prod-db]
+/// command = ssh -t db.example.com
+/// env = LC_ALL=C
+/// title = Prod DB
+/// ```
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub key_bindings: KeyBindings,
+    /// Patterns for hint mode; a pattern replaces a default of the same name.
+    pub hints: Vec<HintPattern>,
+    pub triggers: Vec<Trigger>,
+    /// Commands running at least this long notify when they finish
+    /// while the window is unfocused.
+    pub min_command_duration: Duration,
+    pub scrolling: ScrollConfig,
+    /// Keep output printed past the right margin with autowrap off, to be
+    /// scrolled to sideways, instead of overwriting the last column.
+    pub keep_overflow: bool,
+    /// Characters besides whitespace that end a double-clicked word.
+    pub word_separators: String,
+    /// Right-click opens the context menu even when the program tracks the
+    /// mouse, instead of being reported to it.
+    pub context_menu_with_reporting: bool,
+    pub window: WindowConfig,
+    /// Window title with `{title}`, `{cwd}`, `{user}` and `{host}`
+    /// placeholders; empty shows the title as the program sets it.
+    pub title_template: String,
+    pub present_mode: PresentModePreference,
+    /// Alpha of the default background, from 0 (transparent) to 1.
+    pub background_opacity: f32,
+    /// Keep explicitly colored cell backgrounds opaque when the default
+    /// background is translucent.
+    pub opaque_cell_backgrounds: bool,
+    /// Draw bold text in the 8 base colors with their bright variants.
+    pub bold_is_bright: bool,
+    /// The 16 ANSI colors, set one at a time as `color0` to `color15`.
+    pub palette: [Color; 16],
+    /// Draw box-drawing and block characters geometrically instead of with
+    /// the font.
+    pub builtin_box_drawing: bool,
+    /// Shape text so the font can draw ligatures.
+    pub ligatures: bool,
+    /// Fill of the block cursor; `None` inverts the cell under it.
+    pub cursor_color: Option<Color>,
+    /// Fill of the cursor while the window is unfocused; `None` draws an
+    /// underline.
+    pub cursor_color_unfocused: Option<Color>,
+    /// Whether the cursor starts blinking and how fast; `force_off`
+    /// overrides programs that turn blinking on.
+    pub cursor_blink: CursorBlink,
+    /// Least WCAG contrast ratio text is drawn with; 1 keeps program colors.
+    pub min_contrast: f32,
+    /// Show a scrollbar while scrolling through the scrollback.
+    pub scrollbar: bool,
+    /// Factor applied to the font's line height.
+    pub line_spacing: f32,
+    /// Pixels added to the width of every cell.
+    pub letter_spacing: f32,
+    /// Start the shell from the passwd database as a login shell.
+    pub login_shell: bool,
+    /// Merge PTY reads that arrive together before parsing them.
+    pub coalesce_reads: bool,
+    /// Largest chunk of merged PTY output.
+    pub coalesce_max_bytes: usize,
+    /// Save the scrollback and screen on exit and show them above the new
+    /// shell on the next start.
+    pub restore_scrollback: bool,
+    /// Most lines saved for the next start.
+    pub saved_lines: usize,
+    pub gpu: GpuConfig,
+    /// Named commands to start with `--profile`.
+    pub profiles: Vec<Profile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            key_bindings: KeyBindings::default(),
+            hints: HintPattern::defaults(),
+            triggers: Vec::new(),
+            min_command_duration: DEFAULT_MIN_COMMAND_DURATION,
+            scrolling: ScrollConfig::default(),
+            keep_overflow: false,
+            word_separators: DEFAULT_WORD_SEPARATORS.to_string(),
+            context_menu_with_reporting: true,
+            window: WindowConfig::default(),
+            title_template: String::new(),
+            present_mode: PresentModePreference::default(),
+            background_opacity: 1.0,
+            opaque_cell_backgrounds: true,
+            bold_is_bright: true,
+            palette: Color::ansi_palette(),
+            builtin_box_drawing: false,
+            ligatures: true,
+            cursor_color: None,
+            cursor_color_unfocused: None,
+            cursor_blink: CursorBlink::default(),
+            min_contrast: 1.0,
+            scrollbar: false,
+            line_spacing: 1.0,
+            letter_spacing: 0.0,
+            login_shell: false,
+            coalesce_reads: true,
+            coalesce_max_bytes: DEFAULT_COALESCE_BYTES,
+            restore_scrollback: false,
+            saved_lines: DEFAULT_SAVED_LINES,
+            gpu: GpuConfig::default(),
+            profiles: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/rt/rt.conf`, falling back to `~/.config/rt/rt.conf`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(base.join("rt").join("rt.conf"))
+    }
+
+    /// Loads the config at `path`. A missing file yields the defaults.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        match fs::read_to_string(path) {
+            Ok(source) => Ok(Self::parse(&source)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses config text on top of the defaults.
+    pub fn parse(source: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        let mut section = None;
+        // Line of each profile's section header, for validation errors
+        let mut profile_lines = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| ConfigError {
+                line: index + 1,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if let Some(profile) = name.strip_prefix("profile ") {
+                    let profile = profile.trim();
+                    if config.profile(profile).is_some() {
+                        return Err(error(format!("profile '{}' is defined twice", profile)));
+                    }
+                    config.profiles.push(Profile::new(profile));
+                    profile_lines.push(index + 1);
+                }
+                section = Some(name.to_string());
+                continue;
+            }
+            match section.as_deref() {
+                Some("keybindings") => {
+                    let binding: KeyBinding = line.parse().map_err(error)?;
+                    config.key_bindings.insert(binding);
+                }
+                Some("hints") => {
+                    let pattern: HintPattern = line.parse().map_err(error)?;
+                    match config.hints.iter_mut().find(|p| p.name == pattern.name) {
+                        Some(existing) => *existing = pattern,
+                        None => config.hints.push(pattern),
+                    }
+                }
+                Some("triggers") => config.triggers.push(line.parse().map_err(error)?),
+                Some("notifications") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "min_command_duration" => {
+                            let seconds = parse_value(value).map_err(error)?;
+                            config.min_command_duration = Duration::from_secs(seconds);
+                        }
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("scrolling") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    let scrolling = &mut config.scrolling;
+                    match key {
+                        "speed" => scrolling.speed = parse_value(value).map_err(error)?,
+                        "damping" => scrolling.damping = parse_value(value).map_err(error)?,
+                        "lines_per_notch" => {
+                            scrolling.lines_per_notch = parse_value(value).map_err(error)?
+                        }
+                        "pixel_factor" => {
+                            scrolling.pixel_factor = parse_value(value).map_err(error)?
+                        }
+                        "smooth" => scrolling.smooth = parse_value(value).map_err(error)?,
+                        "keep_overflow" => {
+                            config.keep_overflow = parse_value(value).map_err(error)?
+                        }
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("selection") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "word_separators" => config.word_separators = value.to_string(),
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("mouse") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "context_menu_with_reporting" => {
+                            config.context_menu_with_reporting =
+                                parse_value(value).map_err(error)?
+                        }
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("window") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    let window = &mut config.window;
+                    match key {
+                        "title_template" => config.title_template = value.to_string(),
+                        "geometry" => window.geometry = value.parse().map_err(error)?,
+                        "padding" => window.padding = parse_value(value).map_err(error)?,
+                        "maximized" => window.maximized = parse_value(value).map_err(error)?,
+                        "fullscreen" => window.fullscreen = parse_value(value).map_err(error)?,
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("display") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "present_mode" => config.present_mode = value.parse().map_err(error)?,
+                        "background_opacity" => {
+                            let opacity: f32 = parse_value(value).map_err(error)?;
+                            if !(0.0..=1.0).contains(&opacity) {
+                                return Err(error(format!(
+                                    "background_opacity must be between 0 and 1, got {}",
+                                    opacity
+                                )));
+                            }
+                            config.background_opacity = opacity;
+                        }
+                        "opaque_cell_backgrounds" => {
+                            config.opaque_cell_backgrounds = parse_value(value).map_err(error)?
+                        }
+                        "bold_is_bright" => {
+                            config.bold_is_bright = parse_value(value).map_err(error)?
+                        }
+                        "builtin_box_drawing" => {
+                            config.builtin_box_drawing = parse_value(value).map_err(error)?
+                        }
+                        "ligatures" => config.ligatures = parse_value(value).map_err(error)?,
+                        "scrollbar" => config.scrollbar = parse_value(value).map_err(error)?,
+                        "cursor_color" => {
+                            config.cursor_color = Some(parse_color(value).map_err(error)?)
+                        }
+                        "cursor_color_unfocused" => {
+                            config.cursor_color_unfocused = Some(parse_color(value).map_err(error)?)
+                        }
+                        "cursor_blink" => {
+                            config.cursor_blink.enabled = parse_value(value).map_err(error)?
+                        }
+                        "cursor_blink_interval" => {
+                            config.cursor_blink.interval_ms = parse_value(value).map_err(error)?
+                        }
+                        "min_contrast" => {
+                            let ratio: f32 = parse_value(value).map_err(error)?;
+                            if !(1.0..=21.0).contains(&ratio) {
+                                return Err(error(format!(
+                                    "min_contrast must be between 1 and 21, got {}",
+                                    ratio
+                                )));
+                            }
+                            config.min_contrast = ratio;
+                        }
+                        "cursor_blink_force_off" => {
+                            config.cursor_blink.force_off = parse_value(value).map_err(error)?
+                        }
+                        "line_spacing" => {
+                            let spacing: f32 = parse_value(value).map_err(error)?;
+                            if !(MIN_LINE_SPACING..=MAX_LINE_SPACING).contains(&spacing) {
+                                return Err(error(format!(
+                                    "line_spacing must be between {} and {}, got {}",
+                                    MIN_LINE_SPACING, MAX_LINE_SPACING, spacing
+                                )));
+                            }
+                            config.line_spacing = spacing;
+                        }
+                        "letter_spacing" => {
+                            config.letter_spacing = parse_value(value).map_err(error)?
+                        }
+                        _ => match palette_index(key) {
+                            Some(index) => {
+                                config.palette[index] = parse_color(value).map_err(error)?
+                            }
+                            None => return Err(error(format!("unknown setting '{}'", key))),
+                        },
+                    }
+                }
+                Some("shell") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "login" => config.login_shell = parse_value(value).map_err(error)?,
+                        "coalesce_reads" => {
+                            config.coalesce_reads = parse_value(value).map_err(error)?
+                        }
+                        "coalesce_max_bytes" => {
+                            config.coalesce_max_bytes = parse_value(value).map_err(error)?
+                        }
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("session") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "restore_scrollback" => {
+                            config.restore_scrollback = parse_value(value).map_err(error)?
+                        }
+                        "saved_lines" => config.saved_lines = parse_value(value).map_err(error)?,
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some("gpu") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    match key {
+                        "power_preference" => {
+                            config.gpu.power_preference = value.parse().map_err(error)?
+                        }
+                        "backend" => {
+                            config.gpu.backend = gpu::parse_backend(value).map_err(error)?
+                        }
+                        "force_fallback_adapter" => {
+                            config.gpu.force_fallback_adapter = parse_value(value).map_err(error)?
+                        }
+                        _ => return Err(error(format!("unknown setting '{}'", key))),
+                    }
+                }
+                Some(name) if name.starts_with("profile ") => {
+                    let (key, value) = setting(line).map_err(error)?;
+                    let profile = config.profiles.last_mut().expect("added with its section");
+                    profile.set(key, value).map_err(error)?;
+                }
+                Some(name) => return Err(error(format!("unknown section '{}'", name))),
+                None => return Err(error("setting outside of a section".to_string())),
+            }
+        }
+        for (profile, &line) in config.profiles.iter().zip(&profile_lines) {
+            profile
+                .validate()
+                .map_err(|message| ConfigError { line, message })?;
+        }
+        Ok(config)
+    }
+
+    /// The profile called `name`.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+}
+
+/// Splits a `key = value` line.
+fn setting(line: &str) -> Result<(&str, &str), String> {
+    line.split_once('=')
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .ok_or_else(|| format!("expected 'key = value' in '{}'", line))
+}
+
+/// The palette entry a `colorN` setting names.
+fn palette_index(key: &str) -> Option<usize> {
+    let index: usize = key.strip_prefix("color")?.parse().ok()?;
+    (index < 16).then_some(index)
+}
+
+fn parse_color(value: &str) -> Result<Color, String> {
+    Color::from_hex(value).ok_or_else(|| {
+        format!(
+            "expected a #rgb, #rrggbb or #rrggbbaa color, got '{}'",
+            value
+        )
+    })
+}
+
+fn parse_value<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}'", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+    use super::*;
+    use crate::input::{Action, InputMode};
+
+    #[test]
+    fn bindings_are_parsed_with_their_mode() {
+        let config = Config::parse(
+            "[keybindings]\n\
+             ctrl+shift+t = new_tab\n\
+             # a comment\n\
+             copy-mode: y = copy\n\
+             search: Alt+Escape = exit_mode\n",
+        )
+        .unwrap();
+        let bindings = &config.key_bindings;
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        let t = Key::Character("t".into());
+        assert_eq!(
+            bindings.get(&t, ctrl_shift, InputMode::Normal),
+            Some(&Action::NewTab)
+        );
+        let y = Key::Character("y".into());
+        let none = ModifiersState::empty();
+        assert_eq!(bindings.get(&y, none, InputMode::Copy), Some(&Action::Copy));
+        assert_eq!(bindings.get(&y, none, InputMode::Normal), None);
+        let escape = Key::Named(NamedKey::Escape);
+        assert_eq!(
+            bindings.get(&escape, ModifiersState::ALT, InputMode::Search),
+            Some(&Action::ExitMode)
+        );
+    }
+
+    #[test]
+    fn bad_bindings_are_errors_on_their_line() {
+        for (binding, message) in [
+            ("ctrl+t = open_window", "unknown action 'open_window'"),
+            ("ctrl+nokey = copy", "unknown key 'nokey'"),
+            ("hyper+t = copy", "unknown modifier 'hyper'"),
+            ("visual: y = copy", "unknown mode 'visual'"),
+            ("ctrl+t copy", "expected 'keys = action'"),
+        ] {
+            let source = format!("[keybindings]\nctrl+shift+t = new_tab\n\n{}\n", binding);
+            let error = Config::parse(&source).unwrap_err();
+            assert_eq!(error.line, 4, "{}", binding);
+            assert!(
+                error.message.starts_with(message),
+                "{}: {}",
+                binding,
+                error.message
+            );
+        }
+    }
+
+    #[test]
+    fn palette_colors_are_set_by_number() {
+        let config = Config::parse("[display]\ncolor1 = #e06c75\ncolor15 = #fff\n").unwrap();
+        let mut palette = Color::ansi_palette();
+        palette[1] = Color(0xe0, 0x6c, 0x75, 255);
+        palette[15] = Color::WHITE;
+        assert_eq!(config.palette, palette);
+        let error = Config::parse("[display]\ncolor16 = #000000\n").unwrap_err();
+        assert_eq!(error.message, "unknown setting 'color16'");
+    }
+}
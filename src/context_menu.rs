@@ -0,0 +1,201 @@
+use crate::input::Action;
+
+/// An entry of the context menu.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MenuItem {
+    pub label: &'static str,
+    pub action: Action,
+    pub enabled: bool,
+}
+
+/// Result of clicking while the menu is open.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MenuClick {
+    /// An enabled entry was clicked; the menu closes and runs its action.
+    Activate(Action),
+    /// A disabled entry was clicked; the menu stays open.
+    Ignored,
+    /// The click landed outside the menu, which closes it.
+    Outside,
+}
+
+/// Right-click menu drawn over the grid. Positions are viewport cells; the
+/// menu is a column of `items` rows starting at `(col, row)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextMenu {
+    items: Vec<MenuItem>,
+    col: usize,
+    row: usize,
+    selected: Option<usize>,
+}
+
+impl ContextMenu {
+    /// Builds the standard menu at cell `at`, moved so it fits a grid of
+    /// `cols` by `rows`. Copy is only enabled when there is a selection.
+    pub fn new(at: (usize, usize), cols: usize, rows: usize, has_selection: bool) -> Self {
+        let item = |label, action, enabled| MenuItem {
+            label,
+            action,
+            enabled,
+        };
+        let items = vec![
+            item("Copy", Action::Copy, has_selection),
+            item("Paste", Action::Paste, true),
+            item("Select All", Action::SelectAll, true),
+            item("Clear Scrollback", Action::ClearScrollback, true),
+            item("Toggle Fullscreen", Action::ToggleFullscreen, true),
+        ];
+        let mut menu = Self {
+            items,
+            col: 0,
+            row: 0,
+            selected: None,
+        };
+        menu.col = at.0.min(cols.saturating_sub(menu.width()));
+        menu.row = at.1.min(rows.saturating_sub(menu.items.len()));
+        menu
+    }
+
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+
+    /// Top-left cell of the menu.
+    pub fn origin(&self) -> (usize, usize) {
+        (self.col, self.row)
+    }
+
+    /// Columns taken by the menu: the longest label with a space either side.
+    pub fn width(&self) -> usize {
+        self.items
+            .iter()
+            .map(|item| item.label.chars().count() + 2)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Highlighted entry, if any.
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Index of the entry at viewport cell `(col, row)`.
+    pub fn item_at(&self, col: usize, row: usize) -> Option<usize> {
+        let inside = (self.col..self.col + self.width()).contains(&col)
+            && (self.row..self.row + self.items.len()).contains(&row);
+        inside.then(|| row - self.row)
+    }
+
+    /// Highlights the enabled entry under the pointer, if any.
+    pub fn hover(&mut self, col: usize, row: usize) {
+        self.selected = self
+            .item_at(col, row)
+            .filter(|&index| self.items[index].enabled);
+    }
+
+    /// Moves the highlight `delta` enabled entries down (or up when
+    /// negative), wrapping around. Starts from the top or bottom when
+    /// nothing is highlighted.
+    pub fn move_selection(&mut self, delta: isize) {
+        let len = self.items.len() as isize;
+        if len == 0 || !self.items.iter().any(|item| item.enabled) {
+            return;
+        }
+        let mut index = match self.selected {
+            Some(index) => index as isize,
+            None if delta > 0 => -1,
+            None => len,
+        };
+        for _ in 0..delta.unsigned_abs() {
+            loop {
+                index = (index + delta.signum()).rem_euclid(len);
+                if self.items[index as usize].enabled {
+                    break;
+                }
+            }
+        }
+        self.selected = Some(index as usize);
+    }
+
+    /// Action of the highlighted entry.
+    pub fn activate(&self) -> Option<Action> {
+        let item = &self.items[self.selected?];
+        item.enabled.then(|| item.action.clone())
+    }
+
+    pub fn click(&self, col: usize, row: usize) -> MenuClick {
+        match self.item_at(col, row) {
+            Some(index) if self.items[index].enabled => {
+                MenuClick::Activate(self.items[index].action.clone())
+            }
+            Some(_) => MenuClick::Ignored,
+            None => MenuClick::Outside,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_is_enabled_only_with_a_selection() {
+        let menu = ContextMenu::new((0, 0), 80, 24, false);
+        assert!(!menu.items()[0].enabled);
+        assert!(menu.items()[1..].iter().all(|item| item.enabled));
+        let menu = ContextMenu::new((0, 0), 80, 24, true);
+        assert!(menu.items()[0].enabled);
+    }
+
+    #[test]
+    fn the_menu_is_moved_to_fit_the_grid() {
+        let menu = ContextMenu::new((5, 3), 80, 24, false);
+        assert_eq!(menu.origin(), (5, 3));
+        assert_eq!(menu.width(), "Toggle Fullscreen".len() + 2);
+        let menu = ContextMenu::new((79, 23), 80, 24, false);
+        assert_eq!(menu.origin(), (80 - menu.width(), 24 - menu.items().len()));
+        let menu = ContextMenu::new((7, 7), 4, 2, false);
+        assert_eq!(menu.origin(), (0, 0));
+    }
+
+    #[test]
+    fn arrow_keys_skip_disabled_entries_and_wrap() {
+        let mut menu = ContextMenu::new((0, 0), 80, 24, false);
+        assert_eq!(menu.activate(), None);
+        menu.move_selection(1);
+        assert_eq!(menu.selected(), Some(1));
+        menu.move_selection(-1);
+        assert_eq!(menu.selected(), Some(4));
+        menu.move_selection(1);
+        assert_eq!(menu.selected(), Some(1));
+        menu.move_selection(2);
+        assert_eq!(menu.activate(), Some(Action::ClearScrollback));
+
+        let mut menu = ContextMenu::new((0, 0), 80, 24, true);
+        menu.move_selection(-1);
+        assert_eq!(menu.activate(), Some(Action::ToggleFullscreen));
+    }
+
+    #[test]
+    fn hovering_highlights_only_enabled_entries() {
+        let mut menu = ContextMenu::new((10, 5), 80, 24, false);
+        menu.hover(12, 6);
+        assert_eq!(menu.activate(), Some(Action::Paste));
+        menu.hover(12, 5);
+        assert_eq!(menu.selected(), None);
+        menu.hover(12, 8);
+        menu.hover(0, 0);
+        assert_eq!(menu.selected(), None);
+    }
+
+    #[test]
+    fn clicks_run_enabled_entries_and_close_outside() {
+        let menu = ContextMenu::new((10, 5), 80, 24, false);
+        let right = 10 + menu.width() - 1;
+        assert_eq!(menu.click(right, 7), MenuClick::Activate(Action::SelectAll));
+        assert_eq!(menu.click(10, 5), MenuClick::Ignored);
+        assert_eq!(menu.click(right + 1, 7), MenuClick::Outside);
+        assert_eq!(menu.click(10, 11), MenuClick::Outside);
+        assert_eq!(menu.click(9, 5), MenuClick::Outside);
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+use crate::pty::{PtyConfig, PtySession, PtySize};
+
+/// Version of the messages below; a client and a daemon of different
+/// versions refuse each other.
+pub const PROTOCOL_VERSION: u8 = 1;
+/// Most recent output a daemon keeps to replay when a client attaches.
+pub const REPLAY_BYTES: usize = 256 * 1024;
+/// Largest message accepted, bounding what a bad peer can make us allocate.
+const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+/// How long a client gets to say hello, and to take output before it is
+/// dropped, so a stuck window can't stall the program.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `attach` waits for a daemon it started to listen.
+const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of output chunks the window may fall behind by.
+const OUTPUT_CHANNEL_CAPACITY: usize = 1024;
+
+const HELLO: u8 = 0;
+const OUTPUT: u8 = 1;
+const INPUT: u8 = 2;
+const RESIZE: u8 = 3;
+const EXIT: u8 = 4;
+
+/// A message between a window and a daemon. Each is framed as a
+/// big-endian `u32` length of the rest, a kind byte and the payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// The first message in each direction.
+    Hello { version: u8 },
+    /// Program output, from the daemon.
+    Output(Vec<u8>),
+    /// Keyboard input and terminal responses, from the window.
+    Input(Vec<u8>),
+    /// The window's grid size, from the window.
+    Resize { cols: u16, rows: u16 },
+    /// The program closed the terminal and the daemon is going away.
+    Exit,
+}
+
+impl Message {
+    pub fn write_to(&self, writer: &mut impl Write) -> io::Result<()> {
+        let mut size = [0u8; 4];
+        let (kind, payload) = match self {
+            Message::Hello { version } => (HELLO, std::slice::from_ref(version)),
+            Message::Output(bytes) => (OUTPUT, bytes.as_slice()),
+            Message::Input(bytes) => (INPUT, bytes.as_slice()),
+            Message::Resize { cols, rows } => {
+                size[..2].copy_from_slice(&cols.to_be_bytes());
+                size[2..].copy_from_slice(&rows.to_be_bytes());
+                (RESIZE, &size[..])
+            }
+            Message::Exit => (EXIT, &[][..]),
+        };
+        if payload.len() >= MAX_MESSAGE_LEN {
+            return Err(invalid(format!(
+                "message of {} bytes is too long",
+                payload.len()
+            )));
+        }
+        let mut frame = Vec::with_capacity(5 + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32 + 1).to_be_bytes());
+        frame.push(kind);
+        frame.extend_from_slice(payload);
+        writer.write_all(&frame)
+    }
+
+    pub fn read_from(reader: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if len == 0 || len > MAX_MESSAGE_LEN {
+            return Err(invalid(format!("bad message length {}", len)));
+        }
+        let mut payload = vec![0; len - 1];
+        reader.read_exact(&mut payload)?;
+        match header[4] {
+            OUTPUT => return Ok(Message::Output(payload)),
+            INPUT => return Ok(Message::Input(payload)),
+            _ => {}
+        }
+        match (header[4], payload.as_slice()) {
+            (HELLO, &[version]) => Ok(Message::Hello { version }),
+            (RESIZE, &[c0, c1, r0, r1]) => Ok(Message::Resize {
+                cols: u16::from_be_bytes([c0, c1]),
+                rows: u16::from_be_bytes([r0, r1]),
+            }),
+            (EXIT, []) => Ok(Message::Exit),
+            (kind, _) => Err(invalid(format!("bad message of kind {}", kind))),
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Checks the peer's hello against our protocol version.
+fn expect_hello(stream: &mut UnixStream, peer: &str) -> io::Result<()> {
+    match Message::read_from(stream)? {
+        Message::Hello { version } if version == PROTOCOL_VERSION => Ok(()),
+        Message::Hello { version } => Err(io::Error::other(format!(
+            "{} speaks protocol {}, expected {}",
+            peer, version, PROTOCOL_VERSION
+        ))),
+        message => Err(invalid(format!("expected hello, got {:?}", message))),
+    }
+}
+
+/// Where the daemon for session `name` listens: `$XDG_RUNTIME_DIR/rt`, or
+/// a directory of the user's under the temporary directory, created
+/// private to the user.
+pub fn socket_path(name: &str) -> io::Result<PathBuf> {
+    if name.is_empty() || name.contains('/') || name.starts_with('.') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid session name", name),
+        ));
+    }
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("rt"),
+        None => std::env::temp_dir().join(format!("rt-{}", unsafe { libc::getuid() })),
+    };
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)?;
+    Ok(dir.join(format!("{}.sock", name)))
+}
+
+/// Output kept for replay and the client it is sent to.
+struct Shared {
+    replay: VecDeque<u8>,
+    /// `None` while detached.
+    client: Option<UnixStream>,
+}
+
+impl Shared {
+    fn record(&mut self, chunk: &[u8]) {
+        self.replay.extend(chunk);
+        let excess = self.replay.len().saturating_sub(REPLAY_BYTES);
+        self.replay.drain(..excess);
+    }
+}
+
+/// A program on a PTY that outlives the windows showing it, served to one
+/// attached window at a time over a unix socket. A window that attaches
+/// takes over from the one before it.
+pub struct Daemon {
+    listener: UnixListener,
+    path: PathBuf,
+    session: Arc<Mutex<PtySession>>,
+    shared: Arc<Mutex<Shared>>,
+    exited: Arc<AtomicBool>,
+}
+
+impl Daemon {
+    /// Listens on `path` and starts the program. A socket left behind by a
+    /// daemon that died is replaced; one still served is an error.
+    pub fn bind(path: &Path, config: &PtyConfig) -> io::Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("a daemon is already listening on {}", path.display()),
+            ));
+        }
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        let session = PtySession::spawn(config)?;
+        let mut output = session.subscribe();
+        let shared = Arc::new(Mutex::new(Shared {
+            replay: VecDeque::new(),
+            client: None,
+        }));
+        let exited = Arc::new(AtomicBool::new(false));
+
+        let (forward_shared, forward_exited) = (shared.clone(), exited.clone());
+        let wake_path = path.to_path_buf();
+        thread::spawn(move || loop {
+            let chunk = match output.blocking_recv() {
+                Ok(chunk) => chunk,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => Vec::new(),
+            };
+            let mut shared = forward_shared.lock().unwrap();
+            if chunk.is_empty() {
+                if let Some(mut client) = shared.client.take() {
+                    let _ = Message::Exit.write_to(&mut client);
+                }
+                forward_exited.store(true, Ordering::SeqCst);
+                // Wakes the accept loop so it sees the exit
+                let _ = UnixStream::connect(&wake_path);
+                break;
+            }
+            shared.record(&chunk);
+            if let Some(client) = shared.client.as_mut() {
+                if Message::Output(chunk).write_to(client).is_err() {
+                    shared.client = None;
+                }
+            }
+        });
+
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+            session: Arc::new(Mutex::new(session)),
+            shared,
+            exited,
+        })
+    }
+
+    /// Serves windows until the program exits, then removes the socket.
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            if self.exited.load(Ordering::SeqCst) {
+                break;
+            }
+            let result = stream.and_then(|stream| self.accept(stream));
+            if let Err(e) = result {
+                eprintln!("rt: daemon: dropped a client: {}", e);
+            }
+        }
+        fs::remove_file(&self.path)
+    }
+
+    /// Replays the recent output to a new client and makes it the one
+    /// output goes to.
+    fn accept(&self, mut stream: UnixStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        expect_hello(&mut stream, "client")?;
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        Message::Hello {
+            version: PROTOCOL_VERSION,
+        }
+        .write_to(&mut stream)?;
+        let reader = stream.try_clone()?;
+        {
+            // Held across the replay so no output slips in before it
+            let mut shared = self.shared.lock().unwrap();
+            if !shared.replay.is_empty() {
+                let replay = shared.replay.iter().copied().collect();
+                Message::Output(replay).write_to(&mut stream)?;
+            }
+            if let Some(previous) = shared.client.replace(stream) {
+                let _ = previous.shutdown(Shutdown::Both);
+            }
+        }
+        let session = self.session.clone();
+        thread::spawn(move || forward_input(reader, session));
+        Ok(())
+    }
+}
+
+/// Passes a client's input and size to the program until it detaches.
+fn forward_input(mut reader: UnixStream, session: Arc<Mutex<PtySession>>) {
+    loop {
+        match Message::read_from(&mut reader) {
+            Ok(Message::Input(bytes)) => {
+                if let Err(e) = session.lock().unwrap().write(&bytes) {
+                    eprintln!("rt: daemon: failed to write to PTY: {}", e);
+                }
+            }
+            Ok(Message::Resize { cols, rows }) => {
+                let session = session.lock().unwrap();
+                let size = PtySize {
+                    rows,
+                    cols,
+                    pixel_width: 0,
+                    pixel_height: 0,
+                };
+                if let Err(e) = session.resize(size).and_then(|_| session.notify_resize()) {
+                    eprintln!("rt: daemon: failed to resize the PTY: {}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+}
+
+/// A window's connection to a daemon, standing in for a local PTY session.
+pub struct Client {
+    stream: UnixStream,
+    output: broadcast::Sender<Vec<u8>>,
+}
+
+impl Client {
+    /// Connects to the daemon listening on `path`. The receiver gets the
+    /// replayed output followed by live output; like a PTY session's, an
+    /// empty chunk marks the end, when the program exits or the daemon
+    /// goes away.
+    pub fn connect(path: &Path) -> io::Result<(Self, broadcast::Receiver<Vec<u8>>)> {
+        let mut stream = UnixStream::connect(path)?;
+        Message::Hello {
+            version: PROTOCOL_VERSION,
+        }
+        .write_to(&mut stream)?;
+        expect_hello(&mut stream, "daemon")?;
+
+        let (output, receiver) = broadcast::channel(OUTPUT_CHANNEL_CAPACITY);
+        let sender = output.clone();
+        let mut reader = stream.try_clone()?;
+        thread::spawn(move || loop {
+            match Message::read_from(&mut reader) {
+                Ok(Message::Output(bytes)) if !bytes.is_empty() => {
+                    let _ = sender.send(bytes);
+                }
+                Ok(Message::Exit) | Err(_) => {
+                    let _ = sender.send(Vec::new());
+                    break;
+                }
+                Ok(_) => {}
+            }
+        });
+        Ok((Self { stream, output }, receiver))
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.output.subscribe()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        Message::Input(bytes.to_vec()).write_to(&mut self.stream)
+    }
+
+    /// Gives the program's PTY the window's grid size.
+    pub fn resize(&mut self, cols: u16, rows: u16) -> io::Result<()> {
+        Message::Resize { cols, rows }.write_to(&mut self.stream)
+    }
+}
+
+/// Connects to the daemon for session `name`, first starting one as
+/// `rt --daemon NAME` with `daemon_args` when none is running.
+pub fn attach(
+    name: &str,
+    daemon_args: &[OsString],
+) -> io::Result<(Client, broadcast::Receiver<Vec<u8>>)> {
+    let path = socket_path(name)?;
+    if let Ok(attached) = Client::connect(&path) {
+        return Ok(attached);
+    }
+    start_daemon(name, daemon_args)?;
+    let deadline = Instant::now() + DAEMON_START_TIMEOUT;
+    loop {
+        match Client::connect(&path) {
+            Ok(attached) => return Ok(attached),
+            Err(e) if Instant::now() >= deadline => return Err(e),
+            Err(_) => thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+fn start_daemon(name: &str, daemon_args: &[OsString]) -> io::Result<()> {
+    let mut child = Command::new(std::env::current_exe()?)
+        .arg("--daemon")
+        .arg(name)
+        .args(daemon_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        // Out of our process group, so a hangup sent to the window's
+        // group doesn't reach the daemon
+        .process_group(0)
+        .spawn()?;
+    // Reaped if it exits while the window is still open
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects output from `receiver` until it contains `needle`.
+    fn wait_for(receiver: &mut broadcast::Receiver<Vec<u8>>, needle: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut text = String::new();
+        while !text.contains(needle) && Instant::now() < deadline {
+            match receiver.try_recv() {
+                Ok(chunk) => text.push_str(&String::from_utf8_lossy(&chunk)),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let messages = [
+            Message::Hello {
+                version: PROTOCOL_VERSION,
+            },
+            Message::Output(b"output".to_vec()),
+            Message::Input(Vec::new()),
+            Message::Resize { cols: 80, rows: 24 },
+            Message::Exit,
+        ];
+        let mut wire = Vec::new();
+        for message in &messages {
+            message.write_to(&mut wire).unwrap();
+        }
+        assert_eq!(&wire[..5], [0, 0, 0, 2, HELLO]);
+        let mut reader = wire.as_slice();
+        for message in messages {
+            assert_eq!(Message::read_from(&mut reader).unwrap(), message);
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let frames: [&[u8]; 4] = [
+            &[0, 0, 0, 0],
+            &[0, 0, 0, 2, RESIZE, 1],
+            &[0, 0, 0, 1, 9],
+            &[0xff, 0, 0, 0, OUTPUT],
+        ];
+        for frame in frames {
+            assert!(Message::read_from(&mut &frame[..]).is_err(), "{:?}", frame);
+        }
+    }
+
+    #[test]
+    fn session_names_stay_in_the_socket_directory() {
+        for name in ["", "../other", ".hidden", "a/b"] {
+            assert!(socket_path(name).is_err(), "{}", name);
+        }
+        assert!(socket_path("work").unwrap().ends_with("work.sock"));
+    }
+
+    #[test]
+    fn a_reattached_window_sees_earlier_output() {
+        let path = std::env::temp_dir().join(format!("rt-detach-test-{}.sock", std::process::id()));
+        let config = PtyConfig {
+            shell: "sh".to_string(),
+            ..PtyConfig::default()
+        };
+        let daemon = Daemon::bind(&path, &config).unwrap();
+        let server = thread::spawn(move || daemon.run());
+
+        let (mut client, mut output) = Client::connect(&path).unwrap();
+        client.resize(100, 30).unwrap();
+        client.write(b"echo marker-$((6 * 7))\n").unwrap();
+        assert!(wait_for(&mut output, "marker-42").contains("marker-42"));
+        // Detach, as closing the window does
+        drop(client);
+        drop(output);
+
+        let (mut client, mut output) = Client::connect(&path).unwrap();
+        assert!(wait_for(&mut output, "marker-42").contains("marker-42"));
+        client.write(b"stty size; exit\n").unwrap();
+        assert!(wait_for(&mut output, "30 100").contains("30 100"));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !server.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(server.is_finished());
+        server.join().unwrap().unwrap();
+        assert!(!path.exists());
+    }
+}
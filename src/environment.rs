@@ -0,0 +1,64 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::pty::PtyConfig;
+
+/// Environment that new sessions start in.
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentManager {
+    working_directory: Option<PathBuf>,
+    /// Variables set on top of the inherited environment.
+    vars: Vec<(String, String)>,
+}
+
+impl EnvironmentManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory new sessions start in. Relative paths resolve
+    /// against the current directory; the path must be an existing
+    /// directory.
+    pub fn set_working_directory(&mut self, path: &Path) -> io::Result<()> {
+        let path = fs::canonicalize(path)?;
+        if !path.is_dir() {
+            return Err(io::Error::other(format!(
+                "{} is not a directory",
+                path.display()
+            )));
+        }
+        self.working_directory = Some(path);
+        Ok(())
+    }
+
+    pub fn working_directory(&self) -> Option<&Path> {
+        self.working_directory.as_deref()
+    }
+
+    /// Sets `name` to `value` in new sessions, replacing an earlier value.
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.vars.retain(|(existing, _)| existing != name);
+        self.vars.push((name.to_string(), value.to_string()));
+    }
+
+    /// Fills in the parts of `config` this manager controls.
+    pub fn apply(&self, config: &mut PtyConfig) {
+        if let Some(dir) = &self.working_directory {
+            config.cwd = Some(dir.clone());
+        }
+        for (name, value) in &self.vars {
+            config.env.retain(|(existing, _)| existing != name);
+            config.env.push((name.clone(), value.clone()));
+        }
+    }
+
+    /// Starts `command` where new sessions start.
+    pub fn apply_to_command(&self, command: &mut Command) {
+        if let Some(dir) = &self.working_directory {
+            command.current_dir(dir);
+        }
+        command.envs(self.vars.iter().map(|(name, value)| (name, value)));
+    }
+}
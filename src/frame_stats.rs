@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span over which the frame rate and PTY throughput are measured.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+/// Number of frame times kept for percentiles.
+const FRAME_TIME_SAMPLES: usize = 240;
+/// Redraw interval while an animation such as smooth scrolling runs.
+pub const ANIMATION_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+/// Value below which `p` percent of `samples` fall, by nearest rank.
+pub fn percentile(samples: &[Duration], p: f64) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = (p.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.saturating_sub(1)])
+}
+
+/// When the event loop should next wake up to check for a redraw: the next
+/// animation frame while something animates, otherwise the earliest of
+/// `deadlines`, such as blinks and timeouts. `None` sleeps until an event,
+/// such as PTY output, wakes the loop.
+pub fn next_wakeup(
+    now: Instant,
+    animating: bool,
+    deadlines: impl IntoIterator<Item = Instant>,
+) -> Option<Instant> {
+    if animating {
+        return Some(now + ANIMATION_FRAME_INTERVAL);
+    }
+    deadlines.into_iter().min()
+}
+
+/// Counts rendered frames and shell output to show how often the window
+/// actually redraws and what each frame costs.
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    /// Times of the frames rendered within the last `RATE_WINDOW`.
+    recent: VecDeque<Instant>,
+    total: u64,
+    /// CPU time spent building and submitting the most recent frames.
+    frame_times: VecDeque<Duration>,
+    /// Bytes read from the PTY within the last `RATE_WINDOW`.
+    pty_reads: VecDeque<(Instant, usize)>,
+    gpu_time: Option<Duration>,
+}
+
+/// Drops entries older than `RATE_WINDOW` from the front of `queue`.
+fn expire<T>(queue: &mut VecDeque<T>, now: Instant, time: impl Fn(&T) -> Instant) {
+    while let Some(oldest) = queue.front() {
+        if now.duration_since(time(oldest)) < RATE_WINDOW {
+            break;
+        }
+        queue.pop_front();
+    }
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a frame presented at `now` that took `cpu_time` to render.
+    pub fn record_frame(&mut self, now: Instant, cpu_time: Duration) {
+        self.recent.push_back(now);
+        self.total += 1;
+        expire(&mut self.recent, now, |&frame| frame);
+
+        if self.frame_times.len() == FRAME_TIME_SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(cpu_time);
+    }
+
+    pub fn record_pty_read(&mut self, now: Instant, bytes: usize) {
+        self.pty_reads.push_back((now, bytes));
+        expire(&mut self.pty_reads, now, |&(time, _)| time);
+    }
+
+    /// Sets the duration of the last render pass as measured on the GPU.
+    pub fn record_gpu_time(&mut self, time: Duration) {
+        self.gpu_time = Some(time);
+    }
+
+    /// Frames rendered during the second before `now`.
+    pub fn fps(&self, now: Instant) -> usize {
+        self.recent
+            .iter()
+            .filter(|&&frame| now.duration_since(frame) < RATE_WINDOW)
+            .count()
+    }
+
+    pub fn total_frames(&self) -> u64 {
+        self.total
+    }
+
+    /// Frame time below which `p` percent of the recent frames fall.
+    pub fn frame_time_percentile(&self, p: f64) -> Option<Duration> {
+        let samples: Vec<Duration> = self.frame_times.iter().copied().collect();
+        percentile(&samples, p)
+    }
+
+    /// Bytes per second read from the PTY during the second before `now`.
+    pub fn pty_throughput(&self, now: Instant) -> usize {
+        self.pty_reads
+            .iter()
+            .filter(|(time, _)| now.duration_since(*time) < RATE_WINDOW)
+            .map(|(_, bytes)| bytes)
+            .sum()
+    }
+
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.gpu_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    /// Runs the event loop's redraw decisions over a simulated second, with
+    /// program output arriving every `output_every`, and counts the frames.
+    fn frames_in_a_second(terminal: &mut Terminal, output_every: Option<Duration>) -> u64 {
+        let start = Instant::now();
+        let end = start + RATE_WINDOW;
+        let mut stats = FrameStats::new();
+        let mut next_output = output_every.map(|every| start + every);
+        let mut now = start;
+        while now < end {
+            if let (Some(at), Some(every)) = (next_output, output_every) {
+                if at <= now {
+                    terminal.process_pty_output(b"x");
+                    next_output = Some(at + every);
+                }
+            }
+            let damaged = !terminal.take_damage().is_empty();
+            if damaged || terminal.is_animating() {
+                stats.record_frame(now, Duration::ZERO);
+            }
+            let wakeup = next_wakeup(now, terminal.is_animating(), terminal.next_blink(now));
+            match wakeup.into_iter().chain(next_output).min() {
+                Some(wakeup) => now = wakeup,
+                None => break,
+            }
+        }
+        stats.total_frames()
+    }
+
+    #[test]
+    fn an_idle_terminal_sleeps_and_a_busy_one_redraws() {
+        let mut terminal = Terminal::new(20, 5);
+        terminal.process_pty_output("\x1b[?12l$ \r\n".repeat(10).as_bytes());
+        // The first frame draws the output so far
+        assert_eq!(frames_in_a_second(&mut terminal, None), 1);
+        assert_eq!(frames_in_a_second(&mut terminal, None), 0);
+        assert!(terminal.take_damage().is_empty());
+
+        let refresh_rate = (RATE_WINDOW.as_millis() / ANIMATION_FRAME_INTERVAL.as_millis()) as u64;
+        let output_every = ANIMATION_FRAME_INTERVAL / 2;
+        assert!(frames_in_a_second(&mut terminal, Some(output_every)) >= refresh_rate);
+
+        terminal.scroll_by(3.0);
+        assert!(terminal.is_animating());
+        assert_eq!(frames_in_a_second(&mut terminal, None), refresh_rate + 1);
+    }
+
+    fn ms(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let samples: Vec<Duration> = (1..=10).rev().map(ms).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(ms(5)));
+        assert_eq!(percentile(&samples, 90.0), Some(ms(9)));
+        assert_eq!(percentile(&samples, 99.0), Some(ms(10)));
+        assert_eq!(percentile(&samples, 0.0), Some(ms(1)));
+        assert_eq!(percentile(&samples, 150.0), Some(ms(10)));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn rates_cover_only_the_last_second() {
+        let mut stats = FrameStats::new();
+        let start = Instant::now();
+        for i in 0..30 {
+            stats.record_frame(start + ms(i * 50), ms(2));
+            stats.record_pty_read(start + ms(i * 50), 100);
+        }
+        let now = start + ms(29 * 50);
+        assert_eq!(stats.fps(now), 20);
+        assert_eq!(stats.pty_throughput(now), 2000);
+        assert_eq!(stats.total_frames(), 30);
+        assert_eq!(stats.fps(now + ms(2000)), 0);
+    }
+
+    #[test]
+    fn frame_times_keep_a_rolling_window() {
+        let mut stats = FrameStats::new();
+        let now = Instant::now();
+        for _ in 0..FRAME_TIME_SAMPLES {
+            stats.record_frame(now, ms(100));
+        }
+        assert_eq!(stats.frame_time_percentile(50.0), Some(ms(100)));
+        for _ in 0..FRAME_TIME_SAMPLES {
+            stats.record_frame(now, ms(1));
+        }
+        assert_eq!(stats.frame_time_percentile(100.0), Some(ms(1)));
+    }
+}
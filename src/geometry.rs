@@ -0,0 +1,229 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalSize;
+
+/// Smallest grid the window can be shrunk to.
+pub const MIN_GEOMETRY: Geometry = Geometry { cols: 20, rows: 5 };
+/// How long the window size has to hold still before it is applied.
+pub const RESIZE_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// A terminal grid size, written `COLSxROWS` as in `--geometry 100x30`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub cols: usize,
+    pub rows: usize,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self { cols: 80, rows: 24 }
+    }
+}
+
+impl FromStr for Geometry {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (cols, rows) = value
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("expected COLSxROWS, got '{}'", value))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("invalid geometry '{}'", value))
+        };
+        Ok(Self {
+            cols: parse(cols)?,
+            rows: parse(rows)?,
+        })
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.cols, self.rows)
+    }
+}
+
+impl Geometry {
+    /// Grows the geometry to at least `MIN_GEOMETRY`.
+    pub fn clamped(self) -> Self {
+        Self {
+            cols: self.cols.max(MIN_GEOMETRY.cols),
+            rows: self.rows.max(MIN_GEOMETRY.rows),
+        }
+    }
+}
+
+/// Inner window size that fits `geometry` cells of `cell` physical pixels,
+/// as reported by `Renderer::cell_size`, plus `padding` logical pixels on
+/// every side at `scale_factor`.
+pub fn window_size(
+    geometry: Geometry,
+    cell: (f32, f32),
+    padding: f32,
+    scale_factor: f64,
+) -> PhysicalSize<u32> {
+    let padding = 2.0 * padding as f64 * scale_factor;
+    let size = |cells: usize, cell: f32| (cells as f64 * cell as f64 + padding).round() as u32;
+    PhysicalSize::new(size(geometry.cols, cell.0), size(geometry.rows, cell.1))
+}
+
+/// Cells of `cell` physical pixels that fit in an inner window of `size`
+/// with `padding` logical pixels on every side: the inverse of
+/// `window_size`.
+pub fn grid_size(
+    size: PhysicalSize<u32>,
+    cell: (f32, f32),
+    padding: f32,
+    scale_factor: f64,
+) -> Geometry {
+    let padding = 2.0 * padding as f64 * scale_factor;
+    // Allow for `window_size` having rounded the padding down
+    let cells = |length: u32, cell: f32| {
+        ((length as f64 - padding + 0.5).max(0.0) / cell as f64).floor() as usize
+    };
+    Geometry {
+        cols: cells(size.width, cell.0),
+        rows: cells(size.height, cell.1),
+    }
+}
+
+/// Initial window settings from the `[window]` config section, which the
+/// command line can override.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct WindowConfig {
+    pub geometry: Geometry,
+    /// Logical pixels between the grid and the window edge.
+    pub padding: f32,
+    pub maximized: bool,
+    pub fullscreen: bool,
+}
+
+/// Coalesces the burst of resizes a window drag produces into one, applied
+/// once the size has held still for a delay. The last size requested wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResizeDebounce {
+    delay: Duration,
+    /// Size waiting to be applied and when it was requested.
+    pending: Option<(PhysicalSize<u32>, Instant)>,
+}
+
+impl ResizeDebounce {
+    pub fn new(delay: Duration) -> Self {
+        Self {
+            delay,
+            pending: None,
+        }
+    }
+
+    /// Records the window's new size, restarting the wait.
+    pub fn request(&mut self, size: PhysicalSize<u32>, now: Instant) {
+        self.pending = Some((size, now));
+    }
+
+    /// When the pending size will be due, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, requested)| requested + self.delay)
+    }
+
+    /// The pending size once it has held still for the delay.
+    pub fn take_due(&mut self, now: Instant) -> Option<PhysicalSize<u32>> {
+        if self.deadline()? > now {
+            return None;
+        }
+        self.pending.take().map(|(size, _)| size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_parses_cols_by_rows() {
+        let geometry = Geometry {
+            cols: 100,
+            rows: 30,
+        };
+        assert_eq!("100x30".parse(), Ok(geometry));
+        assert_eq!("100X30".parse(), Ok(geometry));
+        assert_eq!("100 x 30".parse(), Ok(geometry));
+        assert_eq!(geometry.to_string(), "100x30");
+        for invalid in ["100", "0x30", "100x", "ax30", "-1x30", ""] {
+            assert!(invalid.parse::<Geometry>().is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn small_geometries_grow_to_the_minimum() {
+        let tiny = Geometry { cols: 4, rows: 30 };
+        assert_eq!(tiny.clamped(), Geometry { cols: 20, rows: 30 });
+        assert_eq!(Geometry::default().clamped(), Geometry::default());
+    }
+
+    #[test]
+    fn window_size_fits_the_cells_and_padding() {
+        let size = window_size(Geometry::default(), (9.0, 18.0), 4.0, 1.0);
+        assert_eq!(size, PhysicalSize::new(80 * 9 + 8, 24 * 18 + 8));
+    }
+
+    #[test]
+    fn padding_scales_with_the_scale_factor() {
+        // Cells are already in physical pixels; only the padding is logical
+        let size = window_size(Geometry { cols: 10, rows: 2 }, (18.0, 36.0), 4.0, 2.0);
+        assert_eq!(size, PhysicalSize::new(180 + 16, 72 + 16));
+        let size = window_size(Geometry { cols: 10, rows: 2 }, (13.5, 27.0), 3.0, 1.5);
+        assert_eq!(size, PhysicalSize::new(135 + 9, 54 + 9));
+    }
+
+    #[test]
+    fn grid_size_inverts_window_size() {
+        for scale_factor in [1.0, 1.25, 1.5, 2.0] {
+            let cell = (9.0 * scale_factor as f32, 18.0 * scale_factor as f32);
+            for geometry in [Geometry::default(), MIN_GEOMETRY, "131x7".parse().unwrap()] {
+                let size = window_size(geometry, cell, 5.0, scale_factor);
+                assert_eq!(grid_size(size, cell, 5.0, scale_factor), geometry);
+            }
+        }
+        let partial = PhysicalSize::new(80 * 9 + 8 + 8, 24 * 18 + 8 + 17);
+        assert_eq!(
+            grid_size(partial, (9.0, 18.0), 4.0, 1.0),
+            Geometry::default()
+        );
+        assert_eq!(
+            grid_size(PhysicalSize::new(4, 4), (9.0, 18.0), 4.0, 1.0),
+            Geometry { cols: 0, rows: 0 }
+        );
+    }
+
+    #[test]
+    fn a_burst_of_resizes_is_applied_once() {
+        let delay = Duration::from_millis(50);
+        let mut debounce = ResizeDebounce::new(delay);
+        let start = Instant::now();
+        assert_eq!(debounce.take_due(start), None);
+
+        let mut applied = Vec::new();
+        // A drag reporting a new size every 10ms
+        for step in 0..20u32 {
+            let now = start + Duration::from_millis(10 * step as u64);
+            debounce.request(PhysicalSize::new(800 + step, 600), now);
+            applied.extend(debounce.take_due(now));
+        }
+        let last = start + Duration::from_millis(190);
+        assert_eq!(debounce.deadline(), Some(last + delay));
+        assert_eq!(
+            debounce.take_due(last + delay - Duration::from_millis(1)),
+            None
+        );
+        applied.extend(debounce.take_due(last + delay));
+        applied.extend(debounce.take_due(last + delay * 2));
+        assert_eq!(applied, [PhysicalSize::new(819, 600)]);
+        assert_eq!(debounce.deadline(), None);
+    }
+}
@@ -0,0 +1,244 @@
+use std::str::FromStr;
+
+use wgpu::{
+    AdapterInfo, Backend, Backends, DeviceType, Instance, InstanceDescriptor, PowerPreference,
+};
+
+/// Power preference from the config; `Auto` saves power on battery.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PowerSetting {
+    #[default]
+    Auto,
+    LowPower,
+    HighPerformance,
+}
+
+impl FromStr for PowerSetting {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "auto" => Ok(PowerSetting::Auto),
+            "low-power" => Ok(PowerSetting::LowPower),
+            "high-performance" => Ok(PowerSetting::HighPerformance),
+            _ => Err(format!("unknown power preference '{}'", name)),
+        }
+    }
+}
+
+impl PowerSetting {
+    pub fn resolve(self, on_battery: bool) -> PowerPreference {
+        match self {
+            PowerSetting::Auto if on_battery => PowerPreference::LowPower,
+            PowerSetting::Auto | PowerSetting::HighPerformance => PowerPreference::HighPerformance,
+            PowerSetting::LowPower => PowerPreference::LowPower,
+        }
+    }
+}
+
+/// Adapter selection settings from the `[gpu]` config section.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GpuConfig {
+    pub power_preference: PowerSetting,
+    /// Restricts adapters to one backend; `None` tries all of them.
+    pub backend: Option<Backend>,
+    /// Only use a software adapter, e.g. for CI.
+    pub force_fallback_adapter: bool,
+}
+
+impl GpuConfig {
+    pub fn backends(&self) -> Backends {
+        match self.backend {
+            Some(backend) => Backends::from(backend),
+            None => Backends::all(),
+        }
+    }
+}
+
+/// Parses a backend name; `auto` means no override.
+pub fn parse_backend(name: &str) -> Result<Option<Backend>, String> {
+    match name {
+        "auto" => Ok(None),
+        "vulkan" => Ok(Some(Backend::Vulkan)),
+        "metal" => Ok(Some(Backend::Metal)),
+        "dx12" => Ok(Some(Backend::Dx12)),
+        "gl" => Ok(Some(Backend::Gl)),
+        _ => Err(format!("unknown backend '{}'", name)),
+    }
+}
+
+/// Lower is better.
+fn device_rank(device_type: DeviceType, power: PowerPreference) -> u8 {
+    match (device_type, power) {
+        (DeviceType::IntegratedGpu, PowerPreference::LowPower) => 0,
+        (DeviceType::DiscreteGpu, PowerPreference::LowPower) => 1,
+        (DeviceType::DiscreteGpu, _) => 0,
+        (DeviceType::IntegratedGpu, _) => 1,
+        (DeviceType::VirtualGpu, _) => 2,
+        (DeviceType::Other, _) => 3,
+        (DeviceType::Cpu, _) => 4,
+    }
+}
+
+/// Index of the adapter in `adapters` that best matches `config`, or `None`
+/// if none is allowed by the backend and fallback settings.
+pub fn select_adapter(
+    adapters: &[AdapterInfo],
+    config: &GpuConfig,
+    power: PowerPreference,
+) -> Option<usize> {
+    adapters
+        .iter()
+        .enumerate()
+        .filter(|(_, info)| config.backend.is_none_or(|backend| info.backend == backend))
+        .filter(|(_, info)| !config.force_fallback_adapter || info.device_type == DeviceType::Cpu)
+        .min_by_key(|(_, info)| device_rank(info.device_type, power))
+        .map(|(index, _)| index)
+}
+
+/// One-line description of an adapter for logs and `--list-gpus`.
+pub fn describe(info: &AdapterInfo) -> String {
+    format!(
+        "{} ({:?}, {:?}, driver {} {})",
+        info.name, info.device_type, info.backend, info.driver, info.driver_info
+    )
+}
+
+/// Prints every adapter on every backend.
+pub fn list_adapters() {
+    let instance = Instance::new(InstanceDescriptor::default());
+    let adapters = instance.enumerate_adapters(Backends::all());
+    if adapters.is_empty() {
+        println!("No GPU adapters found");
+    }
+    for (index, adapter) in adapters.iter().enumerate() {
+        println!("{}: {}", index, describe(&adapter.get_info()));
+    }
+}
+
+/// Whether the machine is running on battery power.
+#[cfg(target_os = "linux")]
+pub fn on_battery() -> bool {
+    let Ok(supplies) = std::fs::read_dir("/sys/class/power_supply") else {
+        return false;
+    };
+    supplies.flatten().any(|supply| {
+        let read =
+            |name: &str| std::fs::read_to_string(supply.path().join(name)).unwrap_or_default();
+        read("type").trim() == "Battery" && read("status").trim() == "Discharging"
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn on_battery() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(name: &str, device_type: DeviceType, backend: Backend) -> AdapterInfo {
+        AdapterInfo {
+            name: name.to_string(),
+            vendor: 0,
+            device: 0,
+            device_type,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend,
+        }
+    }
+
+    fn laptop() -> Vec<AdapterInfo> {
+        vec![
+            adapter("llvmpipe", DeviceType::Cpu, Backend::Gl),
+            adapter("Intel", DeviceType::IntegratedGpu, Backend::Vulkan),
+            adapter("NVIDIA", DeviceType::DiscreteGpu, Backend::Vulkan),
+            adapter("NVIDIA GL", DeviceType::DiscreteGpu, Backend::Gl),
+        ]
+    }
+
+    #[test]
+    fn power_preference_picks_the_gpu_type() {
+        let config = GpuConfig::default();
+        let high = select_adapter(&laptop(), &config, PowerPreference::HighPerformance);
+        assert_eq!(high, Some(2));
+        let low = select_adapter(&laptop(), &config, PowerPreference::LowPower);
+        assert_eq!(low, Some(1));
+    }
+
+    #[test]
+    fn auto_saves_power_on_battery() {
+        assert_eq!(PowerSetting::Auto.resolve(true), PowerPreference::LowPower);
+        assert_eq!(
+            PowerSetting::Auto.resolve(false),
+            PowerPreference::HighPerformance
+        );
+        assert_eq!(
+            PowerSetting::HighPerformance.resolve(true),
+            PowerPreference::HighPerformance
+        );
+        assert_eq!(
+            PowerSetting::LowPower.resolve(false),
+            PowerPreference::LowPower
+        );
+    }
+
+    #[test]
+    fn backend_override_filters_adapters() {
+        let config = GpuConfig {
+            backend: Some(Backend::Gl),
+            ..GpuConfig::default()
+        };
+        let chosen = select_adapter(&laptop(), &config, PowerPreference::HighPerformance);
+        assert_eq!(chosen, Some(3));
+        assert_eq!(config.backends(), Backends::GL);
+
+        let config = GpuConfig {
+            backend: Some(Backend::Metal),
+            ..GpuConfig::default()
+        };
+        let chosen = select_adapter(&laptop(), &config, PowerPreference::HighPerformance);
+        assert_eq!(chosen, None);
+    }
+
+    #[test]
+    fn fallback_adapter_only_uses_software_rendering() {
+        let config = GpuConfig {
+            force_fallback_adapter: true,
+            ..GpuConfig::default()
+        };
+        let chosen = select_adapter(&laptop(), &config, PowerPreference::HighPerformance);
+        assert_eq!(chosen, Some(0));
+        assert_eq!(
+            select_adapter(&laptop()[1..], &config, PowerPreference::LowPower),
+            None
+        );
+    }
+
+    #[test]
+    fn software_rendering_is_the_last_resort() {
+        let adapters = vec![
+            adapter("llvmpipe", DeviceType::Cpu, Backend::Gl),
+            adapter("virgl", DeviceType::VirtualGpu, Backend::Gl),
+        ];
+        let config = GpuConfig::default();
+        let chosen = select_adapter(&adapters, &config, PowerPreference::LowPower);
+        assert_eq!(chosen, Some(1));
+        assert_eq!(
+            select_adapter(&[], &config, PowerPreference::LowPower),
+            None
+        );
+    }
+
+    #[test]
+    fn names_are_parsed() {
+        assert_eq!(parse_backend("auto"), Ok(None));
+        assert_eq!(parse_backend("vulkan"), Ok(Some(Backend::Vulkan)));
+        assert_eq!(parse_backend("dx12"), Ok(Some(Backend::Dx12)));
+        assert!(parse_backend("opengl").is_err());
+        assert_eq!("low-power".parse(), Ok(PowerSetting::LowPower));
+        assert!("fast".parse::<PowerSetting>().is_err());
+    }
+}
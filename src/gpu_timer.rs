@@ -0,0 +1,130 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use wgpu::{
+    Buffer, BufferDescriptor, BufferUsages, CommandEncoder, Device, Features, MapMode, QuerySet,
+    QuerySetDescriptor, QueryType, Queue, RenderPassTimestampWrites,
+};
+
+/// Two timestamps: start and end of the render pass.
+const QUERY_COUNT: u32 = 2;
+const QUERY_BYTES: u64 = QUERY_COUNT as u64 * std::mem::size_of::<u64>() as u64;
+
+/// Measures the render pass on the GPU with timestamp queries. Results are
+/// read back asynchronously; while one is in flight, frames go unmeasured
+/// rather than stalling on the map.
+pub struct GpuTimer {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+    readback_buffer: Buffer,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    /// Timestamps were written in the frame being recorded.
+    written: bool,
+    /// The readback buffer is being mapped or waits to be read.
+    in_flight: bool,
+    /// Outcome of the last `map_async`, set by its callback.
+    mapped: Arc<Mutex<Option<bool>>>,
+}
+
+impl GpuTimer {
+    /// Returns `None` when the device lacks `Features::TIMESTAMP_QUERY`.
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("Frame Timestamps"),
+            ty: QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size: QUERY_BYTES,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size: QUERY_BYTES,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            written: false,
+            in_flight: false,
+            mapped: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Timestamp writes for the next render pass, unless a previous result
+    /// is still being read back.
+    pub fn timestamp_writes(&mut self) -> Option<RenderPassTimestampWrites<'_>> {
+        if self.in_flight {
+            return None;
+        }
+        self.written = true;
+        Some(RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    /// Copies this frame's timestamps to the readback buffer; call after
+    /// the render pass ends.
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        if !self.written {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            QUERY_BYTES,
+        );
+    }
+
+    /// Starts reading back the timestamps; call after submitting the frame.
+    pub fn after_submit(&mut self) {
+        if !std::mem::take(&mut self.written) {
+            return;
+        }
+        self.in_flight = true;
+        let mapped = Arc::clone(&self.mapped);
+        self.readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result.is_ok());
+            });
+    }
+
+    /// Returns the pass duration once the readback has completed.
+    pub fn poll(&mut self, device: &Device) -> Option<Duration> {
+        if !self.in_flight {
+            return None;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = self.mapped.lock().unwrap().take()?;
+        if !mapped {
+            self.in_flight = false;
+            return None;
+        }
+        let ticks = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+            timestamps[1].saturating_sub(timestamps[0])
+        };
+        self.readback_buffer.unmap();
+        self.in_flight = false;
+        Some(Duration::from_nanos(
+            (ticks as f64 * self.period as f64) as u64,
+        ))
+    }
+}
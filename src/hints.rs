@@ -0,0 +1,276 @@
+use std::str::FromStr;
+
+use regex::Regex;
+
+use crate::terminal::Terminal;
+
+/// Label characters, home row first.
+const LABEL_ALPHABET: &str = "asdfghjklqwertyuiopzxcvbnm";
+
+/// What happens to a match when its label is typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HintAction {
+    /// Open with the system handler (URLs).
+    Open,
+    Copy,
+    /// Write the text to the PTY.
+    Paste,
+}
+
+impl FromStr for HintAction {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "open" => Ok(HintAction::Open),
+            "copy" => Ok(HintAction::Copy),
+            "paste" => Ok(HintAction::Paste),
+            _ => Err(format!("unknown hint action '{}'", name)),
+        }
+    }
+}
+
+/// A named regex whose matches become hints, written in the config as
+/// `name = action regex`.
+#[derive(Debug, Clone)]
+pub struct HintPattern {
+    pub name: String,
+    pub regex: Regex,
+    pub action: HintAction,
+}
+
+impl HintPattern {
+    pub fn new(name: &str, pattern: &str, action: HintAction) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Self {
+            name: name.to_string(),
+            regex,
+            action,
+        })
+    }
+
+    /// URLs, file paths and git SHAs.
+    pub fn defaults() -> Vec<HintPattern> {
+        [
+            (
+                "url",
+                r#"(?:https?|ftp|file)://[^\s<>"']+"#,
+                HintAction::Open,
+            ),
+            (
+                "path",
+                r"(?:~|\.{1,2})?/[\w.\-]+(?:/[\w.\-]+)*",
+                HintAction::Paste,
+            ),
+            ("sha", r"\b[0-9a-f]{7,40}\b", HintAction::Copy),
+        ]
+        .into_iter()
+        .map(|(name, pattern, action)| {
+            HintPattern::new(name, pattern, action).expect("default hint pattern is valid")
+        })
+        .collect()
+    }
+}
+
+impl FromStr for HintPattern {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'name = action regex' in '{}'", line))?;
+        let (action, pattern) = rest
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("missing regex in '{}'", line))?;
+        HintPattern::new(name.trim(), pattern.trim(), action.parse()?)
+    }
+}
+
+/// A match on the visible screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HintMatch {
+    pub text: String,
+    pub action: HintAction,
+    /// Visible row and column of the first character.
+    pub row: usize,
+    pub col: usize,
+}
+
+/// Generates `count` labels, none of which is a prefix of another: single
+/// letters while they suffice, otherwise two letters for every label.
+pub fn generate_labels(count: usize) -> Vec<String> {
+    let alphabet: Vec<char> = LABEL_ALPHABET.chars().collect();
+    if count <= alphabet.len() {
+        return alphabet[..count].iter().map(|c| c.to_string()).collect();
+    }
+    alphabet
+        .iter()
+        .flat_map(|&first| {
+            alphabet
+                .iter()
+                .map(move |&second| format!("{}{}", first, second))
+        })
+        .take(count)
+        .collect()
+}
+
+/// Finds pattern matches on the visible screen. Soft-wrapped rows are
+/// joined so matches may span them; matches overlapping an earlier one
+/// (by pattern order, then position) are dropped.
+pub fn find_matches(terminal: &Terminal, patterns: &[HintPattern]) -> Vec<HintMatch> {
+    let mut matches: Vec<HintMatch> = Vec::new();
+    let mut claimed: Vec<Vec<bool>> = vec![vec![false; terminal.width]; terminal.height];
+
+    let mut row = 0;
+    while row < terminal.height {
+        // Join this row with the rows it wraps into
+        let mut text = String::new();
+        let mut positions = Vec::new();
+        while let Some(cells) = terminal.visible_row(row) {
+            for (col, cell) in cells.iter().take(terminal.width).enumerate() {
+                text.push(cell.ch);
+                positions.push((row, col));
+            }
+            row += 1;
+            if !Terminal::is_wrapped(cells) || row >= terminal.height {
+                break;
+            }
+        }
+        if positions.is_empty() {
+            row += 1;
+            continue;
+        }
+
+        for pattern in patterns {
+            for found in pattern.regex.find_iter(&text) {
+                let start = text[..found.start()].chars().count();
+                let len = found.as_str().chars().count();
+                let span = &positions[start..start + len];
+                if span.iter().any(|&(r, c)| claimed[r][c]) {
+                    continue;
+                }
+                for &(r, c) in span {
+                    claimed[r][c] = true;
+                }
+                let (row, col) = span[0];
+                matches.push(HintMatch {
+                    text: found.as_str().to_string(),
+                    action: pattern.action,
+                    row,
+                    col,
+                });
+            }
+        }
+    }
+
+    matches.sort_by_key(|m| (m.row, m.col));
+    matches
+}
+
+/// Result of typing a character in hint mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HintInput {
+    /// The typed text is a prefix of some label.
+    Pending,
+    Selected(HintMatch),
+    /// No label starts with the typed text.
+    NoMatch,
+}
+
+/// Labels shown over the screen while hint mode is active.
+#[derive(Debug, Clone, Default)]
+pub struct HintState {
+    hints: Vec<(String, HintMatch)>,
+    typed: String,
+}
+
+impl HintState {
+    pub fn new(matches: Vec<HintMatch>) -> Self {
+        let labels = generate_labels(matches.len());
+        Self {
+            hints: labels.into_iter().zip(matches).collect(),
+            typed: String::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hints.is_empty()
+    }
+
+    /// Labels still reachable from the typed prefix, with their matches.
+    pub fn visible(&self) -> impl Iterator<Item = (&str, &HintMatch)> {
+        self.hints
+            .iter()
+            .filter(|(label, _)| label.starts_with(&self.typed))
+            .map(|(label, hint)| (label.as_str(), hint))
+    }
+
+    pub fn typed(&self) -> &str {
+        &self.typed
+    }
+
+    pub fn input(&mut self, ch: char) -> HintInput {
+        self.typed.push(ch.to_ascii_lowercase());
+        if let Some((_, hint)) = self.hints.iter().find(|(label, _)| *label == self.typed) {
+            return HintInput::Selected(hint.clone());
+        }
+        if self.visible().next().is_some() {
+            HintInput::Pending
+        } else {
+            self.typed.pop();
+            HintInput::NoMatch
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_label_is_a_prefix_of_another() {
+        for count in [1, 5, 26, 27, 100] {
+            let labels = generate_labels(count);
+            assert_eq!(labels.len(), count);
+            for (i, a) in labels.iter().enumerate() {
+                for (j, b) in labels.iter().enumerate() {
+                    assert!(i == j || !b.starts_with(a.as_str()), "{} and {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_are_joined_across_wrapped_rows() {
+        let mut terminal = Terminal::new(12, 4);
+        terminal.process_pty_output(b"go https://example.com/a/b now\r\nsha 1a2b3c4d");
+        let matches = find_matches(&terminal, &HintPattern::defaults());
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.text.as_str(), m.action, m.row, m.col))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("https://example.com/a/b", HintAction::Open, 0, 3),
+                ("1a2b3c4d", HintAction::Copy, 3, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn typing_a_label_selects_its_match() {
+        let hint = |text: &str| HintMatch {
+            text: text.to_string(),
+            action: HintAction::Copy,
+            row: 0,
+            col: 0,
+        };
+        let mut state = HintState::new((0..30).map(|i| hint(&i.to_string())).collect());
+        assert_eq!(state.input('a'), HintInput::Pending);
+        assert_eq!(state.visible().count(), 26);
+        assert_eq!(state.input('1'), HintInput::NoMatch);
+        assert_eq!(state.input('S'), HintInput::Selected(hint("1")));
+    }
+}
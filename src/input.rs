@@ -0,0 +1,830 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use winit::event::{ElementState, Ime, KeyEvent};
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+
+use crate::pipe::PipeCommand;
+
+/// Commands a key binding can trigger instead of sending the key to the PTY.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Copy,
+    Paste,
+    NewTab,
+    Search,
+    CopyMode,
+    /// Labels URLs, paths and hashes on screen for keyboard selection.
+    Hints,
+    /// Leaves search, copy or hint mode.
+    ExitMode,
+    ZoomIn,
+    ZoomOut,
+    ZoomReset,
+    ScrollUp,
+    ScrollDown,
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollToTop,
+    ScrollToBottom,
+    SelectAll,
+    ClearScrollback,
+    /// Resets modes and attributes, keeping the screen (DECSTR).
+    SoftReset,
+    /// Clears the screen and resets the terminal, keeping the scrollback (RIS).
+    Reset,
+    ToggleFullscreen,
+    /// Opens the context menu at the cursor.
+    ContextMenu,
+    /// Highlights the previous or next context menu entry.
+    MenuUp,
+    MenuDown,
+    /// Runs the highlighted context menu entry.
+    MenuSelect,
+    SendBytes(Vec<u8>),
+    /// Runs a command with terminal text on its stdin.
+    Pipe(PipeCommand),
+    /// Switches between vsync and the lowest-latency present mode available.
+    ToggleVsync,
+    /// Shows or hides the frame statistics overlay.
+    ToggleHud,
+    /// Consumes the chord without doing anything, so it never reaches the shell.
+    Reserved,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if let Some((name, arguments)) = name.split_once(char::is_whitespace) {
+            return match name {
+                "pipe" => PipeCommand::parse(arguments, false).map(Action::Pipe),
+                "pipe_paste" => PipeCommand::parse(arguments, true).map(Action::Pipe),
+                _ => Err(format!("unknown action '{}'", name)),
+            };
+        }
+        Ok(match name {
+            "copy" => Action::Copy,
+            "paste" => Action::Paste,
+            "new_tab" => Action::NewTab,
+            "search" => Action::Search,
+            "copy_mode" => Action::CopyMode,
+            "hints" => Action::Hints,
+            "exit_mode" => Action::ExitMode,
+            "zoom_in" => Action::ZoomIn,
+            "zoom_out" => Action::ZoomOut,
+            "zoom_reset" => Action::ZoomReset,
+            "scroll_up" => Action::ScrollUp,
+            "scroll_down" => Action::ScrollDown,
+            "scroll_page_up" => Action::ScrollPageUp,
+            "scroll_page_down" => Action::ScrollPageDown,
+            "scroll_to_top" => Action::ScrollToTop,
+            "scroll_to_bottom" => Action::ScrollToBottom,
+            "select_all" => Action::SelectAll,
+            "clear_scrollback" => Action::ClearScrollback,
+            "soft_reset" => Action::SoftReset,
+            "reset" => Action::Reset,
+            "toggle_fullscreen" => Action::ToggleFullscreen,
+            "context_menu" => Action::ContextMenu,
+            "menu_up" => Action::MenuUp,
+            "menu_down" => Action::MenuDown,
+            "menu_select" => Action::MenuSelect,
+            "toggle_vsync" => Action::ToggleVsync,
+            "toggle_hud" => Action::ToggleHud,
+            "reserved" => Action::Reserved,
+            _ => return Err(format!("unknown action '{}'", name)),
+        })
+    }
+}
+
+/// Input modes that change how keys are dispatched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum InputMode {
+    #[default]
+    Normal,
+    Search,
+    Copy,
+    /// Typed characters select hint labels.
+    Hints,
+    /// The context menu is open.
+    Menu,
+}
+
+impl FromStr for InputMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "normal" => Ok(InputMode::Normal),
+            "search" => Ok(InputMode::Search),
+            "copy-mode" => Ok(InputMode::Copy),
+            "hints" => Ok(InputMode::Hints),
+            "menu" => Ok(InputMode::Menu),
+            _ => Err(format!("unknown mode '{}'", name)),
+        }
+    }
+}
+
+fn parse_modifier(name: &str) -> Option<ModifiersState> {
+    match name {
+        "ctrl" | "control" => Some(ModifiersState::CONTROL),
+        "shift" => Some(ModifiersState::SHIFT),
+        "alt" | "option" => Some(ModifiersState::ALT),
+        "super" | "logo" | "cmd" => Some(ModifiersState::SUPER),
+        _ => None,
+    }
+}
+
+/// Parses a key name. Letters, digits and symbols are matched as the
+/// character they type, lower-cased; other keys by name.
+fn parse_key(name: &str) -> Option<Key> {
+    const FUNCTION_KEYS: [NamedKey; 12] = [
+        NamedKey::F1,
+        NamedKey::F2,
+        NamedKey::F3,
+        NamedKey::F4,
+        NamedKey::F5,
+        NamedKey::F6,
+        NamedKey::F7,
+        NamedKey::F8,
+        NamedKey::F9,
+        NamedKey::F10,
+        NamedKey::F11,
+        NamedKey::F12,
+    ];
+
+    let mut chars = name.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        if ch.is_ascii_lowercase() || ch.is_ascii_digit() {
+            return Some(Key::Character(name.into()));
+        }
+    }
+    if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse::<usize>().ok()) {
+        return FUNCTION_KEYS
+            .get(number.checked_sub(1)?)
+            .map(|&key| Key::Named(key));
+    }
+    let named = match name {
+        "plus" => return Some(Key::Character("+".into())),
+        "minus" => return Some(Key::Character("-".into())),
+        "equals" => return Some(Key::Character("=".into())),
+        "up" => NamedKey::ArrowUp,
+        "down" => NamedKey::ArrowDown,
+        "left" => NamedKey::ArrowLeft,
+        "right" => NamedKey::ArrowRight,
+        "home" => NamedKey::Home,
+        "end" => NamedKey::End,
+        "pageup" => NamedKey::PageUp,
+        "pagedown" => NamedKey::PageDown,
+        "insert" => NamedKey::Insert,
+        "delete" => NamedKey::Delete,
+        "tab" => NamedKey::Tab,
+        "enter" | "return" => NamedKey::Enter,
+        "escape" | "esc" => NamedKey::Escape,
+        "backspace" => NamedKey::Backspace,
+        "space" => NamedKey::Space,
+        _ => return None,
+    };
+    Some(Key::Named(named))
+}
+
+/// The key bindings match `key` as: characters lower-cased, so a chord
+/// with Shift still finds the binding for its letter.
+fn binding_key(key: &Key) -> Key {
+    match key {
+        Key::Character(text) => Key::Character(text.to_lowercase().into()),
+        key => key.clone(),
+    }
+}
+
+/// Result of translating a key press.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyInput {
+    Action(Action),
+    Bytes(Vec<u8>),
+    /// Text the key typed, for the current mode to handle.
+    Text(String),
+}
+
+/// A key binding parsed from the config syntax, e.g. `ctrl+shift+t = new_tab`
+/// or `copy-mode: y = copy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub key: Key,
+    pub modifiers: ModifiersState,
+    /// Mode the binding is limited to; `None` applies in every mode.
+    pub mode: Option<InputMode>,
+    pub action: Action,
+}
+
+impl FromStr for KeyBinding {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (chord, action) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'keys = action' in '{}'", line))?;
+        let action = action.trim().parse()?;
+
+        let chord = chord.trim().to_ascii_lowercase();
+        let (mode, chord) = match chord.split_once(':') {
+            Some((mode, chord)) => (Some(mode.trim().parse()?), chord.trim()),
+            None => (None, chord.as_str()),
+        };
+
+        let mut parts: Vec<&str> = chord.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+        let key = parse_key(key_name).ok_or_else(|| format!("unknown key '{}'", key_name))?;
+        let mut modifiers = ModifiersState::empty();
+        for name in parts {
+            modifiers |=
+                parse_modifier(name).ok_or_else(|| format!("unknown modifier '{}'", name))?;
+        }
+
+        Ok(KeyBinding {
+            key,
+            modifiers,
+            mode,
+            action,
+        })
+    }
+}
+
+/// Maps key chords to actions. Bound chords are consumed and never reach
+/// the PTY. Bindings for a specific mode take precedence over ones that
+/// apply in every mode.
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    bindings: HashMap<(Key, ModifiersState, Option<InputMode>), Action>,
+}
+
+impl KeyBindings {
+    /// Creates a table without any bindings.
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    pub fn bind(&mut self, key: Key, modifiers: ModifiersState, action: Action) {
+        self.bindings.insert((key, modifiers, None), action);
+    }
+
+    pub fn bind_in_mode(
+        &mut self,
+        key: Key,
+        modifiers: ModifiersState,
+        mode: InputMode,
+        action: Action,
+    ) {
+        self.bindings.insert((key, modifiers, Some(mode)), action);
+    }
+
+    pub fn unbind(&mut self, key: Key, modifiers: ModifiersState) {
+        self.bindings.remove(&(key, modifiers, None));
+    }
+
+    /// Adds a parsed binding, replacing any existing one for the same chord.
+    pub fn insert(&mut self, binding: KeyBinding) {
+        self.bindings.insert(
+            (binding.key, binding.modifiers, binding.mode),
+            binding.action,
+        );
+    }
+
+    pub fn get(&self, key: &Key, modifiers: ModifiersState, mode: InputMode) -> Option<&Action> {
+        let key = binding_key(key);
+        self.bindings
+            .get(&(key.clone(), modifiers, Some(mode)))
+            .or_else(|| self.bindings.get(&(key, modifiers, None)))
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let ctrl = ModifiersState::CONTROL;
+        let shift = ModifiersState::SHIFT;
+        let ctrl_shift = ctrl | shift;
+        let char = |text: &str| Key::Character(text.into());
+
+        let mut bindings = Self::empty();
+        bindings.bind(char("c"), ctrl_shift, Action::Copy);
+        bindings.bind(char("v"), ctrl_shift, Action::Paste);
+        bindings.bind(char("t"), ctrl_shift, Action::NewTab);
+        bindings.bind(char("f"), ctrl_shift, Action::Search);
+        bindings.bind(Key::Named(NamedKey::Space), ctrl_shift, Action::CopyMode);
+        bindings.bind(char("u"), ctrl_shift, Action::Hints);
+        bindings.bind(Key::Named(NamedKey::F12), ctrl_shift, Action::ToggleHud);
+        bindings.bind(char("k"), ctrl_shift, Action::ClearScrollback);
+        bindings.bind(char("="), ctrl, Action::ZoomIn);
+        bindings.bind(char("+"), ctrl, Action::ZoomIn);
+        bindings.bind(char("-"), ctrl, Action::ZoomOut);
+        bindings.bind(char("0"), ctrl, Action::ZoomReset);
+        bindings.bind(Key::Named(NamedKey::ArrowUp), ctrl_shift, Action::ScrollUp);
+        bindings.bind(
+            Key::Named(NamedKey::ArrowDown),
+            ctrl_shift,
+            Action::ScrollDown,
+        );
+        bindings.bind(Key::Named(NamedKey::PageUp), shift, Action::ScrollPageUp);
+        bindings.bind(
+            Key::Named(NamedKey::PageDown),
+            shift,
+            Action::ScrollPageDown,
+        );
+        bindings.bind(Key::Named(NamedKey::Home), shift, Action::ScrollToTop);
+        bindings.bind(Key::Named(NamedKey::End), shift, Action::ScrollToBottom);
+
+        let none = ModifiersState::empty();
+        for mode in [
+            InputMode::Search,
+            InputMode::Copy,
+            InputMode::Hints,
+            InputMode::Menu,
+        ] {
+            bindings.bind_in_mode(Key::Named(NamedKey::Escape), none, mode, Action::ExitMode);
+        }
+        bindings.bind_in_mode(char("y"), none, InputMode::Copy, Action::Copy);
+        bindings.bind_in_mode(char("k"), none, InputMode::Copy, Action::ScrollUp);
+        bindings.bind_in_mode(char("j"), none, InputMode::Copy, Action::ScrollDown);
+        for (key, action) in [
+            (NamedKey::ArrowUp, Action::ScrollUp),
+            (NamedKey::ArrowDown, Action::ScrollDown),
+            (NamedKey::PageUp, Action::ScrollPageUp),
+            (NamedKey::PageDown, Action::ScrollPageDown),
+            (NamedKey::Home, Action::ScrollToTop),
+            (NamedKey::End, Action::ScrollToBottom),
+        ] {
+            bindings.bind_in_mode(Key::Named(key), none, InputMode::Copy, action);
+        }
+        for (key, action) in [
+            (NamedKey::ArrowUp, Action::MenuUp),
+            (NamedKey::ArrowDown, Action::MenuDown),
+            (NamedKey::Enter, Action::MenuSelect),
+        ] {
+            bindings.bind_in_mode(Key::Named(key), none, InputMode::Menu, action);
+        }
+        bindings
+    }
+}
+
+/// Input method composition state. The preedit string is only an overlay
+/// drawn at the cursor; nothing reaches the grid or the PTY until commit.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImeState {
+    preedit: String,
+    /// Byte range of the preedit the IME shows its cursor at, if any.
+    cursor: Option<(usize, usize)>,
+}
+
+impl ImeState {
+    /// Applies an IME event, returning committed text that should be sent to the PTY.
+    pub fn handle_event(&mut self, event: &Ime) -> Option<String> {
+        match event {
+            Ime::Enabled => None,
+            Ime::Preedit(text, cursor) => {
+                self.preedit = text.clone();
+                self.cursor = *cursor;
+                None
+            }
+            Ime::Commit(text) => {
+                self.clear();
+                (!text.is_empty()).then(|| text.clone())
+            }
+            Ime::Disabled => {
+                self.clear();
+                None
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.preedit.clear();
+        self.cursor = None;
+    }
+
+    pub fn is_composing(&self) -> bool {
+        !self.preedit.is_empty()
+    }
+
+    pub fn preedit(&self) -> Option<&str> {
+        self.is_composing().then_some(self.preedit.as_str())
+    }
+
+    /// Byte range of the preedit the cursor is shown at while composing.
+    pub fn preedit_cursor(&self) -> Option<(usize, usize)> {
+        self.cursor.filter(|_| self.is_composing())
+    }
+}
+
+/// Longest gap between clicks that still counts as a multi-click.
+pub const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Counts consecutive clicks on the same cell: 1 for a single click, 2 for
+/// a double click, 3 for a triple click, then back to 1.
+#[derive(Debug, Default, Clone)]
+pub struct ClickCounter {
+    last: Option<(Instant, (usize, usize))>,
+    count: u8,
+}
+
+impl ClickCounter {
+    pub fn click(&mut self, now: Instant, cell: (usize, usize)) -> u8 {
+        let repeated = self.last.is_some_and(|(time, last_cell)| {
+            last_cell == cell && now.duration_since(time) <= MULTI_CLICK_INTERVAL
+        });
+        self.count = if repeated && self.count < 3 {
+            self.count + 1
+        } else {
+            1
+        };
+        self.last = Some((now, cell));
+        self.count
+    }
+}
+
+/// Quotes `text` for a POSIX shell: wraps it in single quotes and writes
+/// each embedded quote as `'\''`.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// The text typed for files dropped on the window: each path quoted, joined
+/// by spaces.
+pub fn dropped_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| shell_quote(&path.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Translates winit keyboard events into actions or bytes for the PTY.
+pub struct InputHandler {
+    modifiers: ModifiersState,
+    ime: ImeState,
+    bindings: KeyBindings,
+    mode: InputMode,
+}
+
+impl InputHandler {
+    pub fn new() -> Self {
+        Self::with_bindings(KeyBindings::default())
+    }
+
+    pub fn with_bindings(bindings: KeyBindings) -> Self {
+        Self {
+            modifiers: ModifiersState::empty(),
+            ime: ImeState::default(),
+            bindings,
+            mode: InputMode::Normal,
+        }
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: InputMode) {
+        self.mode = mode;
+    }
+
+    pub fn ime(&self) -> &ImeState {
+        &self.ime
+    }
+
+    pub fn handle_ime(&mut self, event: &Ime) -> Option<String> {
+        self.ime.handle_event(event)
+    }
+
+    /// Drops any in-progress composition, e.g. when the window loses focus.
+    pub fn clear_preedit(&mut self) {
+        self.ime.clear();
+    }
+
+    pub fn modifiers(&self) -> ModifiersState {
+        self.modifiers
+    }
+
+    pub fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = modifiers;
+    }
+
+    pub fn handle_keyboard_input(&mut self, event: &KeyEvent) -> Option<KeyInput> {
+        if event.state != ElementState::Pressed {
+            return None;
+        }
+        // Keys belong to the input method while it is composing
+        if self.ime.is_composing() {
+            return None;
+        }
+        let key = &event.logical_key;
+        if let Some(action) = self.bindings.get(key, self.modifiers, self.mode) {
+            return Some(KeyInput::Action(action.clone()));
+        }
+        if self.mode == InputMode::Normal {
+            if let Some(bytes) = encode_key(key, self.modifiers) {
+                return Some(KeyInput::Bytes(bytes));
+            }
+        }
+        // Outside normal mode unbound keys only type text for the mode
+        let text = event.text.as_ref()?;
+        (!text.is_empty()).then(|| KeyInput::Text(text.to_string()))
+    }
+}
+
+/// Encodes keys that don't type text, and Ctrl with a letter, into the
+/// byte sequences expected by the PTY.
+fn encode_key(key: &Key, modifiers: ModifiersState) -> Option<Vec<u8>> {
+    let named = match key {
+        Key::Named(named) => named,
+        Key::Character(text) if modifiers.control_key() => {
+            let mut chars = text.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(ch @ ('a'..='z' | 'A'..='Z' | '@' | '[' | '\\' | ']' | '^' | '_')), None) => {
+                    Some(vec![ch.to_ascii_uppercase() as u8 & 0x1f])
+                }
+                _ => None,
+            };
+        }
+        _ => return None,
+    };
+    let bytes: &[u8] = match named {
+        NamedKey::Enter => b"\r",
+        NamedKey::Backspace => b"\x7f",
+        NamedKey::Tab if modifiers.shift_key() => b"\x1b[Z",
+        NamedKey::Tab => b"\t",
+        NamedKey::Escape => b"\x1b",
+        NamedKey::ArrowUp => b"\x1b[A",
+        NamedKey::ArrowDown => b"\x1b[B",
+        NamedKey::ArrowRight => b"\x1b[C",
+        NamedKey::ArrowLeft => b"\x1b[D",
+        NamedKey::Home => b"\x1b[H",
+        NamedKey::End => b"\x1b[F",
+        NamedKey::PageUp => b"\x1b[5~",
+        NamedKey::PageDown => b"\x1b[6~",
+        NamedKey::Insert => b"\x1b[2~",
+        NamedKey::Delete => b"\x1b[3~",
+        _ => return None,
+    };
+    Some(bytes.to_vec())
+}
+
+impl Default for InputHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    #[test]
+    fn scrollback_keys_are_bound_by_default() {
+        let bindings = KeyBindings::default();
+        let shift = ModifiersState::SHIFT;
+        let ctrl_shift = ModifiersState::CONTROL | shift;
+        for (key, modifiers, action) in [
+            (NamedKey::PageUp, shift, Action::ScrollPageUp),
+            (NamedKey::PageDown, shift, Action::ScrollPageDown),
+            (NamedKey::Home, shift, Action::ScrollToTop),
+            (NamedKey::End, shift, Action::ScrollToBottom),
+            (NamedKey::ArrowUp, ctrl_shift, Action::ScrollUp),
+            (NamedKey::ArrowDown, ctrl_shift, Action::ScrollDown),
+        ] {
+            assert_eq!(
+                bindings.get(&Key::Named(key), modifiers, InputMode::Normal),
+                Some(&action)
+            );
+        }
+        let page_up = Key::Named(NamedKey::PageUp);
+        let unmodified = ModifiersState::empty();
+        assert_eq!(bindings.get(&page_up, unmodified, InputMode::Normal), None);
+    }
+
+    #[test]
+    fn clicks_on_one_cell_count_up_to_a_triple_click() {
+        let mut clicks = ClickCounter::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(clicks.click(at(0), (3, 1)), 1);
+        assert_eq!(clicks.click(at(100), (3, 1)), 2);
+        assert_eq!(clicks.click(at(200), (3, 1)), 3);
+        assert_eq!(clicks.click(at(300), (3, 1)), 1);
+        // Too late, or on another cell, starts over
+        assert_eq!(clicks.click(at(1000), (3, 1)), 1);
+        assert_eq!(clicks.click(at(1100), (4, 1)), 1);
+    }
+
+    #[test]
+    fn rebound_chords_map_to_their_action() {
+        let mut bindings = KeyBindings::empty();
+        bindings.insert("ctrl+shift+v = paste".parse().unwrap());
+        bindings.insert("ctrl+shift+c = paste".parse().unwrap());
+
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        for key in ["v", "V", "c"] {
+            let key = Key::Character(key.into());
+            let action = bindings.get(&key, ctrl_shift, InputMode::Normal);
+            assert_eq!(action, Some(&Action::Paste));
+        }
+    }
+
+    #[test]
+    fn mode_bindings_take_precedence_over_global_ones() {
+        let mut bindings = KeyBindings::empty();
+        bindings.insert("ctrl+shift+c = copy".parse().unwrap());
+        bindings.insert("copy-mode: ctrl+shift+c = exit_mode".parse().unwrap());
+
+        let key = Key::Character("c".into());
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        assert_eq!(
+            bindings.get(&key, ctrl_shift, InputMode::Copy),
+            Some(&Action::ExitMode)
+        );
+        for mode in [InputMode::Normal, InputMode::Search] {
+            assert_eq!(bindings.get(&key, ctrl_shift, mode), Some(&Action::Copy));
+        }
+        // Binding the global chord again leaves the mode's binding alone
+        bindings.insert("ctrl+shift+c = paste".parse().unwrap());
+        assert_eq!(
+            bindings.get(&key, ctrl_shift, InputMode::Copy),
+            Some(&Action::ExitMode)
+        );
+    }
+
+    #[test]
+    fn unbound_keys_fall_through_to_byte_encoding() {
+        let bindings = KeyBindings::default();
+        let ctrl = ModifiersState::CONTROL;
+        let a = Key::Character("a".into());
+        assert_eq!(bindings.get(&a, ctrl, InputMode::Normal), None);
+        assert_eq!(encode_key(&a, ctrl), Some(vec![0x01]));
+        let enter = Key::Named(NamedKey::Enter);
+        let none = ModifiersState::empty();
+        assert_eq!(bindings.get(&enter, none, InputMode::Normal), None);
+        assert_eq!(encode_key(&enter, none), Some(b"\r".to_vec()));
+        assert_eq!(encode_key(&a, none), None);
+    }
+
+    #[test]
+    fn quoting_survives_nasty_paths() {
+        assert_eq!(
+            shell_quote("it's a file (1).txt"),
+            "'it'\\''s a file (1).txt'"
+        );
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quoted_paths_reach_the_shell_unchanged() {
+        let nasty = "it's a file (1).txt $HOME `x` \\ \"q\" \n*";
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", shell_quote(nasty)))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), nasty);
+    }
+
+    #[test]
+    fn dropped_files_are_joined_by_spaces() {
+        let paths = [
+            PathBuf::from("/tmp/a b"),
+            PathBuf::from("/tmp/it's"),
+            PathBuf::from("c"),
+        ];
+        assert_eq!(dropped_paths(&paths), "'/tmp/a b' '/tmp/it'\\''s' 'c'");
+        assert_eq!(dropped_paths(&[]), "");
+    }
+
+    #[test]
+    fn the_context_menu_is_driven_by_keys() {
+        let bindings = KeyBindings::default();
+        let none = ModifiersState::empty();
+        for (key, action) in [
+            (NamedKey::ArrowUp, Action::MenuUp),
+            (NamedKey::ArrowDown, Action::MenuDown),
+            (NamedKey::Enter, Action::MenuSelect),
+            (NamedKey::Escape, Action::ExitMode),
+        ] {
+            let key = Key::Named(key);
+            assert_eq!(bindings.get(&key, none, InputMode::Menu), Some(&action));
+        }
+    }
+
+    #[test]
+    fn ctrl_shift_k_clears_the_scrollback() {
+        let bindings = KeyBindings::default();
+        let ctrl_shift = ModifiersState::CONTROL | ModifiersState::SHIFT;
+        let k = Key::Character("K".into());
+        let action = bindings.get(&k, ctrl_shift, InputMode::Normal);
+        assert_eq!(action, Some(&Action::ClearScrollback));
+    }
+
+    #[test]
+    fn reset_actions_can_be_bound() {
+        assert_eq!("soft_reset".parse(), Ok(Action::SoftReset));
+        assert_eq!("reset".parse(), Ok(Action::Reset));
+    }
+
+    /// Replays `events` as the window loop does, returning the bytes that
+    /// reached the PTY.
+    fn replay(handler: &mut InputHandler, terminal: &mut Terminal, events: &[Ime]) -> Vec<u8> {
+        for event in events {
+            if let Some(text) = handler.handle_ime(event) {
+                for ch in text.chars() {
+                    terminal.handle_char_input(ch);
+                }
+            }
+        }
+        terminal.take_pending_input()
+    }
+
+    fn preedit(text: &str, cursor: usize) -> Ime {
+        Ime::Preedit(text.to_string(), Some((cursor, cursor)))
+    }
+
+    #[test]
+    fn only_the_committed_text_reaches_the_pty() {
+        let mut handler = InputHandler::new();
+        let mut terminal = Terminal::new(10, 2);
+        let sent = replay(
+            &mut handler,
+            &mut terminal,
+            &[Ime::Enabled, preedit("k", 1), preedit("か", 3)],
+        );
+        assert!(sent.is_empty());
+        assert_eq!(handler.ime().preedit(), Some("か"));
+        assert_eq!(handler.ime().preedit_cursor(), Some((3, 3)));
+
+        let sent = replay(
+            &mut handler,
+            &mut terminal,
+            &[preedit("かな", 6), Ime::Commit("仮名".to_string())],
+        );
+        assert_eq!(sent, "仮名".as_bytes());
+        assert_eq!(handler.ime().preedit(), None);
+        assert_eq!(handler.ime().preedit_cursor(), None);
+        // Nothing was drawn into the grid by the composition
+        assert_eq!(terminal.line_text(0).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn disabling_the_ime_drops_the_composition() {
+        let mut handler = InputHandler::new();
+        let mut terminal = Terminal::new(10, 2);
+        let sent = replay(
+            &mut handler,
+            &mut terminal,
+            &[
+                Ime::Enabled,
+                preedit("に", 3),
+                preedit("にほ", 3),
+                Ime::Disabled,
+            ],
+        );
+        assert!(sent.is_empty());
+        assert!(!handler.ime().is_composing());
+        assert_eq!(handler.ime().preedit_cursor(), None);
+    }
+
+    #[test]
+    fn losing_focus_drops_the_composition() {
+        let mut handler = InputHandler::new();
+        let mut terminal = Terminal::new(10, 2);
+        replay(
+            &mut handler,
+            &mut terminal,
+            &[Ime::Enabled, preedit("ㅎ", 3)],
+        );
+        assert_eq!(handler.ime().preedit(), Some("ㅎ"));
+
+        // What the window loop does on `Focused(false)`; the IME then
+        // clears its preedit and commits nothing
+        handler.clear_preedit();
+        assert_eq!(handler.ime().preedit(), None);
+        let sent = replay(
+            &mut handler,
+            &mut terminal,
+            &[
+                Ime::Preedit(String::new(), None),
+                Ime::Commit(String::new()),
+            ],
+        );
+        assert!(sent.is_empty());
+
+        // A new composition starts afresh after focus returns
+        let sent = replay(
+            &mut handler,
+            &mut terminal,
+            &[preedit("한", 3), Ime::Commit("한".to_string())],
+        );
+        assert_eq!(sent, "한".as_bytes());
+    }
+}
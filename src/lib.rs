@@ -0,0 +1,23 @@
+pub mod cli;
+pub mod clipboard;
+pub mod config;
+pub mod context_menu;
+#[cfg(unix)]
+pub mod detach;
+pub mod environment;
+pub mod frame_stats;
+pub mod geometry;
+pub mod gpu;
+pub mod gpu_timer;
+pub mod hints;
+pub mod input;
+pub mod notifications;
+pub mod pipe;
+pub mod profile;
+pub mod pty;
+pub mod renderer;
+pub mod session;
+pub mod signals;
+pub mod terminal;
+pub mod viewer;
+pub mod window_state;
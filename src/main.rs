@@ -1,55 +1,216 @@
-use std::time::Instant;
-use wgpu::{Instance, InstanceDescriptor, RequestAdapterOptions, AdapterOptions, DeviceDescriptor, Features, Limits, SurfaceConfiguration, PresentMode, TextureFormat, TextureUsages, TextureViewDescriptor, CommandEncoderDescriptor, RenderPassColorAttachment, RenderPassDescriptor, Operations, Color};
-use winit::{event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode}, event_loop::{EventLoop, ControlFlow}, window::{WindowBuilder, Window}};
-use fontdue::{Font, FontSettings};
-use image::{ImageBuffer, Rgba};
-use tokio::runtime::Runtime;
-
-mod terminal;
-mod renderer;
-mod input;
-
-use terminal::{Terminal, TerminalBuffer};
-use renderer::{Renderer, TextRenderer};
-use input::InputHandler;
-
-const WINDOW_WIDTH: u32 = 1200;
-const WINDOW_HEIGHT: u32 = 800;
-const FONT_SIZE: f32 = 16.0;
-const TERMINAL_COLS: usize = 80;
-const TERMINAL_ROWS: usize = 24;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use wgpu::{
+    CommandEncoderDescriptor, DeviceDescriptor, Features, Instance, InstanceDescriptor, Limits,
+    Operations, PresentMode, RenderPassColorAttachment, RenderPassDescriptor, SurfaceConfiguration,
+    TextureUsages, TextureViewDescriptor,
+};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent},
+    event_loop::{ControlFlow, EventLoopBuilder, EventLoopProxy},
+    window::{Fullscreen, UserAttentionType, Window, WindowBuilder},
+};
+
+#[cfg(unix)]
+use rt::detach;
+use rt::{
+    cli, clipboard, config, context_menu, environment, frame_stats, geometry, gpu, gpu_timer,
+    hints, input, notifications, pipe, profile, pty, renderer, session, signals, terminal, viewer,
+    window_state,
+};
+
+use cli::Cli;
+use config::Config;
+use context_menu::{ContextMenu, MenuClick};
+use environment::EnvironmentManager;
+use frame_stats::FrameStats;
+use geometry::{Geometry, ResizeDebounce, RESIZE_DEBOUNCE};
+use gpu::GpuConfig;
+use gpu_timer::GpuTimer;
+use hints::{HintAction, HintInput, HintPattern, HintState};
+use input::{dropped_paths, Action, ClickCounter, InputHandler, InputMode, KeyInput};
+use notifications::{DesktopNotifier, Notifications};
+use pipe::PipeCommand;
+use profile::Profile;
+use pty::{PtyConfig, PtyManager, PtySize, ReadCoalescing};
+use renderer::{
+    CellRange, CellRangeHighlight, PresentModePreference, RenderLayers, Renderer, TextRenderer,
+    SCROLLBAR_COLOR, SCROLLBAR_WIDTH,
+};
+use session::Session;
+use signals::SignalEvent;
+use terminal::events::TerminalEvent;
+use terminal::mouse::{self, MouseEvent, MouseEventKind, MouseTracking};
+use terminal::output::TerminalOutput;
+use terminal::triggers::TriggerAction;
+use terminal::{ScrollDelta, Terminal, TerminalHost};
+use viewer::FileViewer;
+use window_state::{MonitorArea, WindowState};
+
+const WINDOW_TITLE: &str = "RT Terminal";
+/// How often the debug HUD refreshes while nothing else redraws.
+const HUD_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// Time spent per frame writing a `--view` file into the terminal, so the
+/// window stays responsive while a large file loads.
+const VIEW_LOAD_BUDGET: Duration = Duration::from_millis(8);
+
+/// Events delivered to the winit loop from outside the windowing system.
+#[derive(Debug)]
+enum UserEvent {
+    Signal(SignalEvent),
+    /// The shell produced output; wakes the loop to process it.
+    PtyOutput,
+    /// A pipe action's command exited, with what it printed.
+    PipeFinished {
+        command: PipeCommand,
+        output: std::io::Result<Vec<u8>>,
+    },
+    /// The clipboard was read for a paste.
+    ClipboardRead(std::io::Result<String>),
+}
+
+/// Snapshot of window metrics handed to the terminal for size reports.
+struct WindowMetrics {
+    text_area: (u32, u32),
+    cell: (u32, u32),
+}
+
+impl TerminalHost for WindowMetrics {
+    fn text_area_size(&self) -> (u32, u32) {
+        self.text_area
+    }
+
+    fn cell_size(&self) -> (u32, u32) {
+        self.cell
+    }
+}
+
+/// Hands terminal responses to the event loop, which writes them to the PTY.
+struct ChannelOutput(Sender<Vec<u8>>);
+
+impl TerminalOutput for ChannelOutput {
+    fn write(&mut self, bytes: &[u8]) {
+        // The receiver only goes away with the app
+        let _ = self.0.send(bytes.to_vec());
+    }
+}
 
 struct AppState {
-    window: Window,
-    surface: wgpu::Surface,
+    window: Arc<Window>,
+    instance: Instance,
+    surface: wgpu::Surface<'static>,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// Set by the device lost callback; the device is rebuilt on the next frame.
+    device_lost: Arc<AtomicBool>,
     config: SurfaceConfiguration,
     renderer: Renderer,
     terminal: Terminal,
+    terminal_events: Receiver<TerminalEvent>,
+    /// Replies the terminal generated, written to the PTY after user input.
+    terminal_responses: Receiver<Vec<u8>>,
     input_handler: InputHandler,
-    last_frame_time: Instant,
+    frame_stats: FrameStats,
+    gpu_timer: Option<GpuTimer>,
+    show_hud: bool,
+    last_frame: Instant,
+    /// Present modes supported by the surface, for toggling vsync.
+    present_modes: Vec<PresentMode>,
+    /// Adapter preferences, kept for rebuilding after a device loss.
+    gpu_config: GpuConfig,
+    /// Something outside the terminal grid changed and needs a redraw.
+    redraw_pending: bool,
+    /// Window size waiting to settle before the surface and grid follow.
+    resize_debounce: ResizeDebounce,
+    ime_position: Option<(u32, u32)>,
+    /// Logical pixels between the grid and the window edge.
+    padding: f32,
+    /// Last pointer position in window pixels.
+    mouse_position: PhysicalPosition<f64>,
+    clicks: ClickCounter,
+    /// Button held for mouse reporting, and the cell last reported.
+    mouse_button: Option<mouse::MouseButton>,
+    reported_cell: Option<(usize, usize)>,
+    /// Files dropped on the window since the last update, typed together.
+    dropped_files: Vec<std::path::PathBuf>,
+    pty_manager: PtyManager,
+    /// `None` while replaying a recording or viewing a file instead of
+    /// running a shell.
+    session_id: Option<usize>,
+    /// The daemon running the shell with `--attach`.
+    #[cfg(unix)]
+    attached: Option<detach::Client>,
+    pty_output: broadcast::Receiver<Vec<u8>>,
+    close_requested: bool,
+    window_title: String,
+    /// Title shown while the program sets none and has no foreground process.
+    default_title: String,
+    /// The `--profile` the window started with, named in notifications.
+    profile_name: Option<String>,
+    /// File the shell's output is saved to with `--record`.
+    recording: Option<std::fs::File>,
+    /// The shell or command closed the terminal, or q closed the viewer;
+    /// the window closes too.
+    output_ended: bool,
+    /// The file shown with `--view`.
+    viewer: Option<FileViewer>,
+    /// Where commands started from the window run.
+    environment: EnvironmentManager,
+    /// Sends completions of background work to the event loop.
+    event_proxy: Option<EventLoopProxy<UserEvent>>,
+    /// Where the window state is saved on shutdown, if anywhere.
+    state_path: Option<std::path::PathBuf>,
+    /// State restored at startup, updated on shutdown.
+    window_state: WindowState,
+    /// Where the scrollback is saved on shutdown, if restoring it is on.
+    session_path: Option<std::path::PathBuf>,
+    saved_lines: usize,
+    hint_patterns: Vec<HintPattern>,
+    hints: Option<HintState>,
+    menu: Option<ContextMenu>,
+    /// Highlights over the grid, rebuilt every frame.
+    render_layers: RenderLayers,
+    /// Right-click opens the menu even while the program tracks the mouse.
+    context_menu_with_reporting: bool,
+    notifications: Notifications<DesktopNotifier>,
+    focused: bool,
 }
 
 impl AppState {
-    async fn new(window: Window) -> Self {
+    async fn new(
+        window: Arc<Window>,
+        app_config: Config,
+        cli: &Cli,
+        environment: &EnvironmentManager,
+    ) -> Self {
         let size = window.inner_size();
         
         // Initialize WGPU
-        let instance = Instance::new(InstanceDescriptor::default());
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-        
-        let adapter = instance.request_adapter(&RequestAdapterOptions {
-            power_preference: wgpu::PowerPreference::HighPerformance,
-            compatible_surface: Some(&surface),
-            force_fallback_adapter: false,
-        }).await.unwrap();
+        let instance = Instance::new(InstanceDescriptor {
+            backends: app_config.gpu.backends(),
+            ..Default::default()
+        });
+        let surface = match instance.create_surface(Arc::clone(&window)) {
+            Ok(surface) => surface,
+            Err(e) => {
+                eprintln!("rt: failed to create a surface for the window: {}", e);
+                std::process::exit(1);
+            }
+        };
         
-        let (device, queue) = adapter.request_device(&DeviceDescriptor {
-            label: None,
-            features: Features::empty(),
-            limits: Limits::default(),
-        }, None).await.unwrap();
+        let Some((adapter, device, queue)) =
+            request_device(&instance, &surface, &app_config.gpu).await
+        else {
+            eprintln!("No GPU adapter matches the [gpu] settings; run with --list-gpus to see the available adapters");
+            std::process::exit(1);
+        };
+        let device_lost = watch_device_lost(&device);
+        let gpu_timer = GpuTimer::new(&device, &queue);
         
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps.formats.iter()
@@ -62,33 +223,235 @@ impl AppState {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: PresentMode::Fifo,
-            alpha_mode: surface_caps.alpha_modes[0],
+            present_mode: renderer::choose_present_mode(
+                app_config.present_mode,
+                &surface_caps.present_modes,
+            ),
+            alpha_mode: renderer::choose_alpha_mode(
+                app_config.background_opacity,
+                &surface_caps.alpha_modes,
+            ),
             view_formats: vec![],
+            desired_maximum_frame_latency: 2,
         };
         
         surface.configure(&device, &config);
-        
-        // Initialize renderer
-        let renderer = Renderer::new(&device, &config);
+        eprintln!("Present mode: {:?}", config.present_mode);
         
         // Initialize terminal
-        let terminal = Terminal::new(TERMINAL_COLS, TERMINAL_ROWS);
+        let geometry = app_config.window.geometry.clamped();
+        let mut terminal = Terminal::new(geometry.cols, geometry.rows);
+        terminal.set_triggers(app_config.triggers.clone());
+        terminal.set_scroll_config(app_config.scrolling);
+        terminal.keep_overflow = app_config.keep_overflow;
+        terminal.set_word_separators(&app_config.word_separators);
+        terminal.bold_is_bright = app_config.bold_is_bright;
+        terminal.set_palette(app_config.palette);
+        terminal.set_min_contrast(app_config.min_contrast);
+        terminal.set_title_template(&app_config.title_template);
+        terminal.set_cursor_color(app_config.cursor_color);
+        terminal.set_cursor_color_unfocused(app_config.cursor_color_unfocused);
+        terminal.set_cursor_blink(app_config.cursor_blink);
+        let terminal_events = terminal.subscribe_events();
+        let (response_sender, terminal_responses) = mpsc::channel();
+        terminal.set_output(Box::new(ChannelOutput(response_sender)));
         
-        // Initialize input handler
-        let input_handler = InputHandler::new();
+        let mut notifications = Notifications::new(DesktopNotifier);
+        notifications.min_command_duration = app_config.min_command_duration;
+
+        // Initialize renderer
+        let mut renderer = Renderer::new(&device, &config).unwrap();
+        renderer.set_scale_factor(window.scale_factor() as f32);
+        renderer.set_padding(app_config.window.padding * window.scale_factor() as f32);
+        renderer.set_clear_color(terminal.default_colors().1);
+        renderer.set_background_opacity(
+            app_config.background_opacity,
+            config.alpha_mode,
+            app_config.opaque_cell_backgrounds,
+        );
+        renderer.set_builtin_box_drawing(app_config.builtin_box_drawing);
+        renderer.set_ligatures(app_config.ligatures);
+        renderer.set_scrollbar(
+            app_config.scrollbar,
+            SCROLLBAR_WIDTH * window.scale_factor() as f32,
+            SCROLLBAR_COLOR,
+        );
+        renderer.set_line_spacing(app_config.line_spacing);
+        renderer.set_letter_spacing(app_config.letter_spacing);
+        if app_config.background_opacity < 1.0
+            && !matches!(
+                config.alpha_mode,
+                wgpu::CompositeAlphaMode::PreMultiplied | wgpu::CompositeAlphaMode::PostMultiplied
+            )
+        {
+            eprintln!("Transparent windows are not supported here; rendering opaque");
+        }
+
+        // Output of the last session goes above the new shell's
+        let session_path = WindowState::default_path()
+            .filter(|_| app_config.restore_scrollback && cli.play.is_none() && cli.view.is_none())
+            .map(|path| path.with_file_name("scrollback"));
+        if let Some(path) = &session_path {
+            match terminal.restore_session(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    eprintln!(
+                        "Failed to restore the session from {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        let viewer = cli.view.as_ref().map(|path| {
+            FileViewer::open(path).unwrap_or_else(|e| {
+                eprintln!("rt: --view {}: {}", path.display(), e);
+                std::process::exit(1);
+            })
+        });
+
+        // Start the shell, or the command given with -e
+        let mut pty_manager = PtyManager::new();
+        #[cfg(unix)]
+        let mut attached = None;
+        let (session_id, pty_output) = match &cli.play {
+            Some(path) => {
+                match std::fs::read(path) {
+                    Ok(recording) => terminal.write_bytes(&recording),
+                    Err(e) => {
+                        eprintln!("rt: --play {}: {}", path.display(), e);
+                        std::process::exit(1);
+                    }
+                }
+                // Nothing is ever sent on this channel
+                (None, broadcast::channel(1).1)
+            }
+            // The viewer feeds the terminal itself
+            None if viewer.is_some() => (None, broadcast::channel(1).1),
+            // The daemon runs the shell; output and input go through it
+            #[cfg(unix)]
+            None if cli.attach.is_some() => {
+                let name = cli.attach.as_deref().unwrap();
+                let (mut client, output) =
+                    detach::attach(name, &cli.daemon_args()).unwrap_or_else(|e| {
+                        eprintln!("rt: --attach {}: {}", name, e);
+                        std::process::exit(1);
+                    });
+                if let Err(e) = client.resize(geometry.cols as u16, geometry.rows as u16) {
+                    eprintln!("Failed to resize the PTY: {}", e);
+                }
+                attached = Some(client);
+                (None, output)
+            }
+            None => {
+                let pty_config = PtyConfig {
+                    size: PtySize {
+                        rows: geometry.rows as u16,
+                        cols: geometry.cols as u16,
+                        pixel_width: 0,
+                        pixel_height: 0,
+                    },
+                    ..shell_config(&app_config, cli, environment)
+                };
+                let session_id = pty_manager.spawn(&pty_config).unwrap_or_else(|e| {
+                    eprintln!("rt: failed to start {}: {}", pty_config.shell, e);
+                    std::process::exit(1);
+                });
+                (
+                    Some(session_id),
+                    pty_manager.get(session_id).unwrap().subscribe(),
+                )
+            }
+        };
+        let recording = cli.record.as_ref().map(|path| {
+            std::fs::File::create(path).unwrap_or_else(|e| {
+                eprintln!("rt: --record {}: {}", path.display(), e);
+                std::process::exit(1);
+            })
+        });
+        let profile = cli
+            .profile
+            .as_deref()
+            .and_then(|name| app_config.profile(name));
+        let profile_name = profile.map(|profile| profile.name.clone());
+        let default_title = default_title(cli, profile);
+
+        // Initialize input handler; a viewed file is navigated in copy mode
+        let mut input_handler = InputHandler::with_bindings(app_config.key_bindings);
+        if viewer.is_some() {
+            input_handler.set_mode(InputMode::Copy);
+        }
+        window.set_ime_allowed(true);
         
-        Self {
+        let mut state = Self {
             window,
+            instance,
             surface,
             device,
             queue,
+            device_lost,
             config,
             renderer,
             terminal,
+            terminal_events,
+            terminal_responses,
             input_handler,
-            last_frame_time: Instant::now(),
-        }
+            frame_stats: FrameStats::new(),
+            gpu_timer,
+            show_hud: false,
+            last_frame: Instant::now(),
+            present_modes: surface_caps.present_modes,
+            gpu_config: app_config.gpu,
+            redraw_pending: true,
+            resize_debounce: ResizeDebounce::new(RESIZE_DEBOUNCE),
+            ime_position: None,
+            padding: app_config.window.padding,
+            mouse_position: PhysicalPosition::new(0.0, 0.0),
+            clicks: ClickCounter::default(),
+            mouse_button: None,
+            reported_cell: None,
+            dropped_files: Vec::new(),
+            pty_manager,
+            session_id,
+            #[cfg(unix)]
+            attached,
+            pty_output,
+            close_requested: false,
+            window_title: default_title.clone(),
+            default_title,
+            profile_name,
+            recording,
+            output_ended: false,
+            viewer,
+            environment: environment.clone(),
+            event_proxy: None,
+            state_path: None,
+            window_state: WindowState::default(),
+            session_path,
+            saved_lines: app_config.saved_lines,
+            hint_patterns: app_config.hints,
+            hints: None,
+            menu: None,
+            render_layers: RenderLayers::new(),
+            context_menu_with_reporting: app_config.context_menu_with_reporting,
+            notifications,
+            focused: true,
+        };
+        state.sync_host_metrics();
+        state
+    }
+
+    fn sync_host_metrics(&mut self) {
+        let (cell_width, cell_height) = self.renderer.cell_size();
+        let padding = 2 * self.renderer.padding() as u32;
+        self.terminal.set_host(Box::new(WindowMetrics {
+            text_area: (
+                self.config.width.saturating_sub(padding),
+                self.config.height.saturating_sub(padding),
+            ),
+            cell: (cell_width as u32, cell_height as u32),
+        }));
     }
     
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -96,42 +459,900 @@ impl AppState {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.renderer.resize(new_size.width, new_size.height);
+            self.sync_host_metrics();
         }
     }
     
+    /// Resizes the grid and tells the shell.
+    fn resize_grid(&mut self, cols: usize, rows: usize) {
+        let pty = self.session_id.and_then(|id| self.pty_manager.get(id));
+        if let Err(e) = Session::new(&mut self.terminal, pty).set_size(cols, rows) {
+            eprintln!("Failed to resize the PTY: {}", e);
+        }
+        #[cfg(unix)]
+        if let Some(client) = self.attached.as_mut() {
+            let (cols, rows) = (self.terminal.width as u16, self.terminal.height as u16);
+            if let Err(e) = client.resize(cols, rows) {
+                eprintln!("Failed to resize the PTY: {}", e);
+            }
+        }
+    }
+
+    /// Switches the grid and the shell to `cols` columns for DECCOLM and
+    /// widens or narrows the window to match.
+    fn set_columns(&mut self, cols: usize) {
+        let rows = self.terminal.height;
+        self.resize_grid(cols, rows);
+        let geometry = Geometry { cols, rows }.clamped();
+        let _ = self.window.request_inner_size(geometry::window_size(
+            geometry,
+            self.renderer.cell_size(),
+            self.padding,
+            self.window.scale_factor(),
+        ));
+    }
+
+    /// Follows the window to a display with a different scale factor.
+    /// Glyphs re-rasterize at the new size and the padding is rescaled;
+    /// returns the window size that keeps the grid's columns and rows.
+    fn set_scale_factor(&mut self, scale_factor: f64) -> PhysicalSize<u32> {
+        self.renderer.set_scale_factor(scale_factor as f32);
+        self.renderer
+            .set_padding(self.padding * scale_factor as f32);
+        let geometry = Geometry {
+            cols: self.terminal.width,
+            rows: self.terminal.height,
+        };
+        geometry::window_size(
+            geometry,
+            self.renderer.cell_size(),
+            self.padding,
+            scale_factor,
+        )
+    }
+
+    /// Fits the grid to the window after a scale change or a settled
+    /// resize, in case the window didn't take the size asked for.
+    fn fit_grid(&mut self, size: PhysicalSize<u32>) {
+        let scale_factor = self.renderer.scale_factor() as f64;
+        let geometry =
+            geometry::grid_size(size, self.renderer.cell_size(), self.padding, scale_factor)
+                .clamped();
+        if (geometry.cols, geometry.rows) != (self.terminal.width, self.terminal.height) {
+            self.resize_grid(geometry.cols, geometry.rows);
+        }
+    }
+
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
-            WindowEvent::KeyboardInput { input, .. } => {
-                if let Some(key) = self.input_handler.handle_keyboard_input(input) {
-                    self.terminal.handle_key_input(key);
-                    true
+            WindowEvent::KeyboardInput { event, .. } => {
+                match self.input_handler.handle_keyboard_input(event) {
+                    Some(KeyInput::Action(action)) => {
+                        self.perform_action(action);
+                        true
+                    }
+                    Some(KeyInput::Bytes(bytes)) => {
+                        self.terminal.write_input(&bytes);
+                        true
+                    }
+                    Some(KeyInput::Text(text)) => self.type_text(&text),
+                    None => false,
+                }
+            }
+            WindowEvent::Ime(ime) => {
+                if let Some(text) = self.input_handler.handle_ime(ime) {
+                    for ch in text.chars() {
+                        self.terminal.handle_char_input(ch);
+                    }
+                }
+                true
+            }
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                self.renderer.set_focused(*focused);
+                if !focused {
+                    self.input_handler.clear_preedit();
+                    self.close_menu();
+                }
+                // The cursor is drawn differently without focus
+                self.redraw_pending = true;
+                false
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.input_handler.set_modifiers(modifiers.state());
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                // Shift+wheel pans sideways over lines wider than the window
+                if self.input_handler.modifiers().shift_key()
+                    && self.terminal.max_h_scroll_offset() > 0
+                {
+                    let columns = match delta {
+                        MouseScrollDelta::LineDelta(_, y) => {
+                            y * self.terminal.scroll_config().lines_per_notch
+                        }
+                        MouseScrollDelta::PixelDelta(pos) => {
+                            pos.y as f32 / self.renderer.cell_size().0
+                        }
+                    };
+                    self.terminal.scroll_horizontal(-columns.round() as isize);
+                    return true;
+                }
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y.round() as i32,
+                    MouseScrollDelta::PixelDelta(pos) => pos.y.signum() as i32,
+                };
+                let button = if lines > 0 {
+                    mouse::MouseButton::WheelUp
                 } else {
-                    false
+                    mouse::MouseButton::WheelDown
+                };
+                if lines != 0
+                    && (0..lines.abs()).all(|_| self.report_mouse(MouseEventKind::Press, button))
+                {
+                    return true;
+                }
+                let delta = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => ScrollDelta::Lines(*y),
+                    MouseScrollDelta::PixelDelta(pos) => ScrollDelta::Pixels(pos.y as f32),
+                };
+                self.terminal.handle_scroll(delta);
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse_position = *position;
+                let (col, row) = self.mouse_cell();
+                if let Some(menu) = self.menu.as_mut() {
+                    let before = menu.selected();
+                    menu.hover(col, row);
+                    return menu.selected() != before;
+                }
+                if self.reported_cell != Some(self.mouse_cell()) {
+                    let button = self.mouse_button.unwrap_or(mouse::MouseButton::None);
+                    return self.report_mouse(MouseEventKind::Motion, button);
                 }
+                false
             }
-            WindowEvent::ReceivedCharacter(char) => {
-                self.terminal.handle_char_input(*char);
+            WindowEvent::MouseInput { state, button, .. } => {
+                let button = match button {
+                    MouseButton::Left => mouse::MouseButton::Left,
+                    MouseButton::Middle => mouse::MouseButton::Middle,
+                    MouseButton::Right => mouse::MouseButton::Right,
+                    MouseButton::Back | MouseButton::Forward | MouseButton::Other(_) => {
+                        return false
+                    }
+                };
+                let kind = match state {
+                    ElementState::Pressed => MouseEventKind::Press,
+                    ElementState::Released => MouseEventKind::Release,
+                };
+                if kind == MouseEventKind::Release {
+                    // Only releases of presses the program saw are reported
+                    return self.mouse_button.take() == Some(button)
+                        && self.report_mouse(kind, button);
+                }
+                if self.menu.is_some() {
+                    self.click_menu();
+                    return true;
+                }
+                let tracking = self.terminal.mouse_tracking() != MouseTracking::Off
+                    && !self.input_handler.modifiers().shift_key();
+                if button == mouse::MouseButton::Right
+                    && (!tracking || self.context_menu_with_reporting)
+                {
+                    self.open_menu(self.mouse_cell());
+                    return true;
+                }
+                if self.report_mouse(kind, button) {
+                    self.mouse_button = Some(button);
+                    return true;
+                }
+                if button != mouse::MouseButton::Left {
+                    return false;
+                }
+                let (x, y) = self.mouse_cell();
+                match self.clicks.click(Instant::now(), (x, y)) {
+                    2 => self.terminal.select_word(x, y),
+                    3 => self.terminal.select_line(y),
+                    _ => self.terminal.clear_selection(),
+                }
+                self.redraw_pending = true;
+                true
+            }
+            WindowEvent::HoveredFile(_) => {
+                self.renderer.set_drop_target(true);
+                self.redraw_pending = true;
+                true
+            }
+            WindowEvent::HoveredFileCancelled => {
+                self.renderer.set_drop_target(false);
+                self.redraw_pending = true;
+                true
+            }
+            WindowEvent::DroppedFile(path) => {
+                // Several files arrive as one event each; they're typed together in `update`
+                self.dropped_files.push(path.clone());
+                self.renderer.set_drop_target(false);
+                self.redraw_pending = true;
                 true
             }
             _ => false,
         }
     }
     
+    /// Handles text typed by a key press in the current input mode.
+    /// Returns whether the mode used it.
+    fn type_text(&mut self, text: &str) -> bool {
+        match self.input_handler.mode() {
+            InputMode::Normal => {
+                for ch in text.chars() {
+                    self.terminal.handle_char_input(ch);
+                }
+            }
+            InputMode::Hints => {
+                for ch in text.chars() {
+                    self.select_hint(ch);
+                }
+            }
+            InputMode::Copy if text == "q" && self.viewer.is_some() => self.output_ended = true,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Reports a mouse event to the program if it enabled mouse tracking.
+    /// Holding Shift keeps the mouse for selection instead. Returns whether
+    /// the event was reported.
+    fn report_mouse(&mut self, kind: MouseEventKind, button: mouse::MouseButton) -> bool {
+        let modifiers = self.input_handler.modifiers();
+        if self.terminal.mouse_tracking() == MouseTracking::Off || modifiers.shift_key() {
+            return false;
+        }
+        let (col, row) = self.mouse_cell();
+        self.reported_cell = Some((col, row));
+        self.terminal.report_mouse(&MouseEvent {
+            kind,
+            button,
+            col,
+            row,
+            shift: false,
+            alt: modifiers.alt_key(),
+            ctrl: modifiers.control_key(),
+        })
+    }
+
+    /// Viewport cell under the mouse pointer, clamped to the grid.
+    fn mouse_cell(&self) -> (usize, usize) {
+        let (cell_width, cell_height) = self.renderer.cell_size();
+        let padding = self.renderer.padding() as f64;
+        let x = ((self.mouse_position.x - padding).max(0.0) / cell_width as f64) as usize;
+        let y = ((self.mouse_position.y - padding).max(0.0) / cell_height as f64) as usize;
+        (
+            x.min(self.terminal.width.saturating_sub(1)),
+            y.min(self.terminal.height.saturating_sub(1)),
+        )
+    }
+
+    fn perform_action(&mut self, action: Action) {
+        match action {
+            Action::Copy => {
+                if let Some(text) = self.terminal.selection_text() {
+                    copy_to_clipboard(text);
+                }
+            }
+            Action::Paste => self.start_paste(),
+            Action::NewTab => {
+                eprintln!("{:?}: tabs are not available yet", action);
+            }
+            Action::Search => self.input_handler.set_mode(InputMode::Search),
+            Action::CopyMode => self.input_handler.set_mode(InputMode::Copy),
+            Action::Hints => self.start_hints(),
+            Action::ExitMode => {
+                self.hints = None;
+                self.menu = None;
+                self.input_handler.set_mode(self.base_mode());
+            }
+            Action::SelectAll => self.terminal.select_all(),
+            Action::ClearScrollback => self.terminal.clear_scrollback(),
+            Action::SoftReset => self.terminal.soft_reset(),
+            Action::Reset => self.terminal.reset(),
+            Action::ToggleFullscreen => {
+                let fullscreen = match self.window.fullscreen() {
+                    Some(_) => None,
+                    None => Some(Fullscreen::Borderless(None)),
+                };
+                self.window.set_fullscreen(fullscreen);
+            }
+            Action::ContextMenu => {
+                let cursor = (self.terminal.cursor_x, self.terminal.cursor_y);
+                self.open_menu(cursor);
+            }
+            Action::MenuUp => {
+                if let Some(menu) = self.menu.as_mut() {
+                    menu.move_selection(-1);
+                }
+            }
+            Action::MenuDown => {
+                if let Some(menu) = self.menu.as_mut() {
+                    menu.move_selection(1);
+                }
+            }
+            Action::MenuSelect => {
+                if let Some(action) = self.menu.as_ref().and_then(ContextMenu::activate) {
+                    self.close_menu();
+                    self.perform_action(action);
+                }
+            }
+            Action::ZoomIn => {
+                self.renderer.set_font_size(self.renderer.font_size() + 1.0);
+                self.sync_host_metrics();
+            }
+            Action::ZoomOut => {
+                self.renderer.set_font_size(self.renderer.font_size() - 1.0);
+                self.sync_host_metrics();
+            }
+            Action::ZoomReset => {
+                self.renderer.set_font_size(renderer::FONT_SIZE);
+                self.sync_host_metrics();
+            }
+            Action::ScrollUp => self.terminal.scroll_by(1.0),
+            Action::ScrollDown => self.terminal.scroll_by(-1.0),
+            Action::ScrollPageUp => self.terminal.scroll_page_up(),
+            Action::ScrollPageDown => self.terminal.scroll_page_down(),
+            Action::ScrollToTop => self.terminal.scroll_to_top(),
+            Action::ScrollToBottom => self.terminal.scroll_to_bottom(),
+            Action::SendBytes(bytes) => self.terminal.write_input(&bytes),
+            Action::Pipe(command) => self.start_pipe(command),
+            Action::ToggleVsync => {
+                let preference = if self.config.present_mode == PresentMode::Fifo {
+                    PresentModePreference::Immediate
+                } else {
+                    PresentModePreference::Fifo
+                };
+                self.set_present_mode(renderer::choose_present_mode(
+                    preference,
+                    &self.present_modes,
+                ));
+            }
+            Action::ToggleHud => self.show_hud = !self.show_hud,
+            Action::Reserved => {}
+        }
+    }
+
+    /// Lines of the debug HUD.
+    fn hud_lines(&self) -> Vec<String> {
+        let now = Instant::now();
+        let stats = &self.frame_stats;
+        let renderer = self.renderer.stats();
+        let millis = |time: Option<Duration>| {
+            time.map_or_else(
+                || "-".to_string(),
+                |t| format!("{:.2}", t.as_secs_f64() * 1000.0),
+            )
+        };
+        vec![
+            format!(" {} fps ", stats.fps(now)),
+            format!(
+                " frame p50 {} p95 {} p99 {} ms ",
+                millis(stats.frame_time_percentile(50.0)),
+                millis(stats.frame_time_percentile(95.0)),
+                millis(stats.frame_time_percentile(99.0)),
+            ),
+            format!(" gpu {} ms ", millis(stats.gpu_time())),
+            format!(
+                " {} quads, {} draws, {} grid builds ",
+                renderer.instances, renderer.draw_calls, renderer.grid_builds
+            ),
+            format!(
+                " {} glyphs, atlas {:.0}% ",
+                renderer.glyphs,
+                renderer.atlas_occupancy * 100.0
+            ),
+            format!(" grid {} KiB ", self.terminal.grid_memory() / 1024),
+            format!(" pty {} KiB/s ", stats.pty_throughput(now) / 1024),
+        ]
+    }
+
+    /// Replaces a surface that was lost, keeping the device.
+    fn recreate_surface(&mut self) {
+        match self.instance.create_surface(Arc::clone(&self.window)) {
+            Ok(surface) => {
+                self.surface = surface;
+                self.surface.configure(&self.device, &self.config);
+            }
+            Err(e) => eprintln!("Failed to recreate surface: {}", e),
+        }
+    }
+
+    /// Opens a new device after the old one was lost and rebuilds the surface
+    /// and renderer on it. Glyphs are re-uploaded from the CPU-side atlas.
+    fn recover_device(&mut self) {
+        eprintln!("GPU device lost, recreating renderer");
+        let surface = match self.instance.create_surface(Arc::clone(&self.window)) {
+            Ok(surface) => surface,
+            Err(e) => {
+                eprintln!("Failed to recreate surface: {}", e);
+                return;
+            }
+        };
+        let Some((adapter, device, queue)) = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(request_device(
+                &self.instance,
+                &surface,
+                &self.gpu_config,
+            ))
+        }) else {
+            eprintln!("No GPU adapter available, retrying on the next frame");
+            return;
+        };
+
+        let surface_caps = surface.get_capabilities(&adapter);
+        if !surface_caps
+            .present_modes
+            .contains(&self.config.present_mode)
+        {
+            self.config.present_mode = PresentMode::Fifo;
+        }
+        surface.configure(&device, &self.config);
+        self.renderer.recreate(&device, &self.config);
+        self.gpu_timer = GpuTimer::new(&device, &queue);
+        self.device_lost = watch_device_lost(&device);
+        self.present_modes = surface_caps.present_modes;
+        self.surface = surface;
+        self.device = device;
+        self.queue = queue;
+    }
+
+    /// Reconfigures the surface in place; the device and renderer are kept.
+    fn set_present_mode(&mut self, mode: PresentMode) {
+        self.config.present_mode = mode;
+        self.surface.configure(&self.device, &self.config);
+        eprintln!("Present mode: {:?}", mode);
+    }
+
+    /// Opens the context menu at viewport cell `at`.
+    fn open_menu(&mut self, at: (usize, usize)) {
+        let has_selection = self.terminal.selection().is_some();
+        self.menu = Some(ContextMenu::new(
+            at,
+            self.terminal.width,
+            self.terminal.height,
+            has_selection,
+        ));
+        self.input_handler.set_mode(InputMode::Menu);
+    }
+
+    /// Mode input returns to when leaving search, hints or the menu: copy
+    /// mode while viewing a file, as there is no program to type into.
+    fn base_mode(&self) -> InputMode {
+        if self.viewer.is_some() {
+            InputMode::Copy
+        } else {
+            InputMode::Normal
+        }
+    }
+
+    fn close_menu(&mut self) {
+        if self.menu.take().is_some() {
+            self.input_handler.set_mode(self.base_mode());
+        }
+    }
+
+    /// Handles a click while the menu is open: runs the entry under the
+    /// pointer, or closes the menu when the click is outside it.
+    fn click_menu(&mut self) {
+        let Some(menu) = &self.menu else {
+            return;
+        };
+        let (col, row) = self.mouse_cell();
+        match menu.click(col, row) {
+            MenuClick::Activate(action) => {
+                self.close_menu();
+                self.perform_action(action);
+            }
+            MenuClick::Ignored => {}
+            MenuClick::Outside => self.close_menu(),
+        }
+    }
+
+    fn start_hints(&mut self) {
+        let matches = hints::find_matches(&self.terminal, &self.hint_patterns);
+        if matches.is_empty() {
+            return;
+        }
+        self.hints = Some(HintState::new(matches));
+        self.input_handler.set_mode(InputMode::Hints);
+    }
+
+    fn select_hint(&mut self, ch: char) {
+        let Some(state) = self.hints.as_mut() else {
+            return;
+        };
+        if let HintInput::Selected(hint) = state.input(ch) {
+            self.hints = None;
+            self.input_handler.set_mode(self.base_mode());
+            match hint.action {
+                HintAction::Open => open_external(&hint.text),
+                HintAction::Copy => copy_to_clipboard(hint.text),
+                HintAction::Paste => self.terminal.write_input(hint.text.as_bytes()),
+            }
+        }
+    }
+
+    /// Runs a pipe action's command on a blocking task, so a slow command
+    /// doesn't stall rendering; its completion arrives as an event.
+    fn start_pipe(&mut self, command: PipeCommand) {
+        let Some(input) = command.input(&self.terminal) else {
+            return;
+        };
+        let Some(proxy) = self.event_proxy.clone() else {
+            return;
+        };
+        let process = command.command(&self.environment);
+        tokio::task::spawn_blocking(move || {
+            let output = pipe::run(process, input.into_bytes());
+            let _ = proxy.send_event(UserEvent::PipeFinished { command, output });
+        });
+    }
+
+    /// Reads the clipboard on a blocking task; the text arrives as an event
+    /// and is pasted by `finish_paste`.
+    fn start_paste(&mut self) {
+        let Some(proxy) = self.event_proxy.clone() else {
+            return;
+        };
+        tokio::task::spawn_blocking(move || {
+            let _ = proxy.send_event(UserEvent::ClipboardRead(clipboard::paste()));
+        });
+    }
+
+    fn finish_paste(&mut self, text: std::io::Result<String>) {
+        match text {
+            Ok(text) if !text.is_empty() => self.terminal.paste(&text),
+            Ok(_) => {}
+            Err(e) => eprintln!("Reading the clipboard failed: {}", e),
+        }
+    }
+
+    /// Pastes what a pipe command printed, if its binding asks for that.
+    fn finish_pipe(&mut self, command: PipeCommand, output: std::io::Result<Vec<u8>>) {
+        match output {
+            Ok(output) if command.paste_output && !output.is_empty() => {
+                self.terminal.paste(&String::from_utf8_lossy(&output));
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("Pipe to {:?} failed: {}", command.argv, e),
+        }
+    }
+
+    /// Carries out actions fired by output triggers.
+    fn run_triggers(&mut self) {
+        for event in self.terminal.take_trigger_events() {
+            match event.action {
+                TriggerAction::Notify(title) => self.notifications.notify(&title, &event.line),
+                TriggerAction::PlayBell => self
+                    .window
+                    .request_user_attention(Some(UserAttentionType::Informational)),
+                TriggerAction::RunCommand(argv) => {
+                    let result = std::process::Command::new(&argv[0])
+                        .args(&argv[1..])
+                        .env("RT_TRIGGER_LINE", &event.line)
+                        .env("RT_TRIGGER_MATCH", &event.matched)
+                        .spawn();
+                    if let Err(e) = result {
+                        eprintln!("Trigger command {:?} failed: {}", argv, e);
+                    }
+                }
+                // Kept by the terminal and drawn as a render layer
+                TriggerAction::Highlight(_) => {}
+            }
+        }
+    }
+
+    /// Returns whether the window may close now. The first request while a
+    /// program is running in the foreground only asks for confirmation.
+    fn confirm_close(&mut self) -> bool {
+        if self.close_requested || !self.pty_manager.has_foreground_jobs() {
+            return true;
+        }
+        self.close_requested = true;
+        self.set_window_title(format!(
+            "{} - a program is still running, close again to quit",
+            self.default_title
+        ));
+        false
+    }
+
+    fn set_window_title(&mut self, title: String) {
+        if self.window_title != title {
+            self.window.set_title(&title);
+            self.window_title = title;
+        }
+    }
+
+    /// Title set by the application via OSC, falling back to the name of
+    /// the foreground process, through the title template.
+    fn update_title(&mut self) {
+        if self.close_requested {
+            return;
+        }
+        let title = if let Some(viewer) = &self.viewer {
+            viewer.title()
+        } else if !self.terminal.title.is_empty() {
+            self.terminal.formatted_title()
+        } else {
+            let fallback = self
+                .session_id
+                .and_then(|id| self.pty_manager.get_mut(id))
+                .and_then(|session| session.foreground_process())
+                .map(|process| process.name)
+                .unwrap_or_else(|| self.default_title.clone());
+            self.terminal.format_title(&fallback)
+        };
+        self.set_window_title(title);
+    }
+
+    /// Terminates all shells and their children before the loop exits.
+    fn shutdown(&mut self) {
+        self.save_window_state();
+        if let Some(path) = &self.session_path {
+            if let Err(e) = self.terminal.save_session(path, self.saved_lines) {
+                eprintln!("Failed to save the session to {}: {}", path.display(), e);
+            }
+        }
+        self.pty_manager.shutdown_all();
+    }
+
+    /// Saves the window placement and the shell's directory for the next
+    /// start. A maximized window keeps its last normal size and position.
+    fn save_window_state(&mut self) {
+        let Some(path) = self.state_path.clone() else {
+            return;
+        };
+        let state = &mut self.window_state;
+        state.maximized = self.window.is_maximized();
+        if !state.maximized && self.window.fullscreen().is_none() {
+            let size = self.window.inner_size();
+            state.size = Some((size.width, size.height));
+            state.position = self
+                .window
+                .outer_position()
+                .ok()
+                .map(|position| (position.x, position.y));
+        }
+        let cwd = self
+            .session_id
+            .and_then(|id| self.pty_manager.get_mut(id))
+            .and_then(|session| session.foreground_process())
+            .and_then(|process| process.cwd);
+        if cwd.is_some() {
+            self.window_state.working_directory = cwd;
+        }
+        if let Err(e) = self.window_state.save(&path) {
+            eprintln!("Failed to save window state to {}: {}", path.display(), e);
+        }
+    }
+
+    fn pump_pty(&mut self) {
+        if let Some(viewer) = self.viewer.as_mut() {
+            viewer.feed(&mut self.terminal, VIEW_LOAD_BUDGET);
+        }
+        // Leave output queued while paused by XOFF
+        while !self.terminal.output_paused() {
+            match self.pty_output.try_recv() {
+                Ok(bytes) if bytes.is_empty() => self.output_ended = true,
+                Ok(bytes) => {
+                    self.frame_stats
+                        .record_pty_read(Instant::now(), bytes.len());
+                    self.terminal.write_bytes(&bytes);
+                    if let Some(file) = self.recording.as_mut() {
+                        if let Err(e) = file.write_all(&bytes) {
+                            eprintln!("Stopped recording: {}", e);
+                            self.recording = None;
+                        }
+                    }
+                }
+                Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        if let Some(session) = self.session_id.and_then(|id| self.pty_manager.get(id)) {
+            session.set_output_paused(self.terminal.output_paused());
+        }
+
+        let mut input = self.terminal.take_pending_input();
+        input.extend(self.terminal_responses.try_iter().flatten());
+        if !input.is_empty() {
+            if let Some(session) = self.session_id.and_then(|id| self.pty_manager.get_mut(id)) {
+                if let Err(e) = session.write(&input) {
+                    eprintln!("Failed to write to PTY: {}", e);
+                }
+            }
+            #[cfg(unix)]
+            if let Some(client) = self.attached.as_mut() {
+                if let Err(e) = client.write(&input) {
+                    eprintln!("Failed to write to PTY: {}", e);
+                }
+            }
+        }
+    }
+
     fn update(&mut self) {
+        if let Some(size) = self.resize_debounce.take_due(Instant::now()) {
+            self.resize(size);
+            self.fit_grid(size);
+            self.redraw_pending = true;
+        }
+
+        if !self.dropped_files.is_empty() {
+            let paths = std::mem::take(&mut self.dropped_files);
+            self.terminal.paste(&dropped_paths(&paths));
+        }
+
+        // Exchange data with the shell
+        self.pump_pty();
+
+        self.run_triggers();
+
+        while let Ok(event) = self.terminal_events.try_recv() {
+            match event {
+                TerminalEvent::WorkingDirectoryChanged(path) => {
+                    self.window_state.working_directory = Some(path)
+                }
+                TerminalEvent::ClipboardSet(text) => copy_to_clipboard(text),
+                TerminalEvent::ColumnModeChange(cols) => self.set_columns(cols),
+                // Titles are applied below, bells through the attention events
+                TerminalEvent::TitleChanged(_) | TerminalEvent::Bell => {}
+            }
+        }
+
+        // Tell the user about bells and finished commands they can't see
+        for event in self.terminal.take_attention_events() {
+            let title = match &self.profile_name {
+                Some(profile) => format!("{}: {}", profile, self.window_title),
+                None => self.window_title.clone(),
+            };
+            if self.notifications.handle(&event, self.focused, &title) {
+                self.window
+                    .request_user_attention(Some(UserAttentionType::Informational));
+            }
+        }
+
         // Update terminal state
         self.terminal.update();
+        self.update_title();
         
-        // Calculate frame time for performance monitoring
-        let frame_time = self.last_frame_time.elapsed();
-        self.last_frame_time = Instant::now();
-        
-        // Log performance metrics (in a real app, this would be more sophisticated)
-        if frame_time.as_millis() > 16 {
-            println!("Frame time: {}ms (target: 16ms)", frame_time.as_millis());
+        let (cell_width, cell_height) = self.renderer.cell_size();
+
+        // Apply window resizes requested through XTWINOPS
+        if let Some((cols, rows)) = self.terminal.take_window_resize_request() {
+            let geometry = Geometry { cols, rows }.clamped();
+            let _ = self.window.request_inner_size(geometry::window_size(
+                geometry,
+                (cell_width, cell_height),
+                self.padding,
+                self.window.scale_factor(),
+            ));
+        }
+
+        // Keep the IME candidate window next to the cursor cell
+        let cursor_x = self
+            .terminal
+            .cursor_x
+            .min(self.terminal.width.saturating_sub(1));
+        let padding = self.renderer.padding();
+        let ime_position = (
+            (padding + cursor_x as f32 * cell_width) as u32,
+            (padding + self.terminal.cursor_y as f32 * cell_height) as u32,
+        );
+        if self.ime_position != Some(ime_position) {
+            self.window.set_ime_cursor_area(
+                PhysicalPosition::new(ime_position.0, ime_position.1),
+                PhysicalSize::new(cell_width as u32, cell_height as u32),
+            );
+            self.ime_position = Some(ime_position);
+        }
+    }
+
+    /// Whether the next frame would differ from the last one rendered.
+    /// Nothing is painted mid-way through a synchronized update.
+    fn needs_redraw(&mut self) -> bool {
+        if self
+            .terminal
+            .synchronized_until()
+            .is_some_and(|until| until > Instant::now())
+        {
+            return false;
+        }
+        let terminal_changed = self.terminal.needs_redraw(Instant::now());
+        let hud_stale = self.show_hud && self.last_frame.elapsed() >= HUD_REFRESH_INTERVAL;
+        let device_lost = self.device_lost.load(Ordering::Acquire);
+        let scrollbar_hidden = self
+            .renderer
+            .scrollbar_hides_at()
+            .is_some_and(|hides_at| hides_at <= Instant::now());
+        std::mem::take(&mut self.redraw_pending)
+            || terminal_changed
+            || hud_stale
+            || device_lost
+            || scrollbar_hidden
+    }
+
+    /// Waits for the next event, or the next animation frame, blink, HUD
+    /// refresh, scrollbar hide, settled resize or synchronized update
+    /// timeout if one is due.
+    fn control_flow(&self) -> ControlFlow {
+        let now = Instant::now();
+        if self.viewer.as_ref().is_some_and(FileViewer::has_backlog) {
+            return ControlFlow::Poll;
+        }
+        // Nothing wakes the loop when more of a viewed file arrives
+        let animating = self.terminal.is_animating()
+            || self.viewer.as_ref().is_some_and(FileViewer::is_loading);
+        let hud_refresh = self
+            .show_hud
+            .then(|| self.last_frame + HUD_REFRESH_INTERVAL);
+        let deadlines = hud_refresh
+            .into_iter()
+            .chain(self.terminal.next_blink(now))
+            .chain(self.renderer.scrollbar_hides_at())
+            .chain(self.resize_debounce.deadline())
+            .chain(self.terminal.synchronized_until());
+        match frame_stats::next_wakeup(now, animating, deadlines) {
+            Some(deadline) => ControlFlow::WaitUntil(deadline),
+            None => ControlFlow::Wait,
         }
     }
     
+    /// Highlights the selection and the cursor.
+    fn update_render_layers(&mut self) {
+        self.render_layers.clear();
+        for (y, columns, color) in self.terminal.visible_trigger_highlights() {
+            let range = CellRange::new((columns.start, y), (columns.end - 1, y));
+            self.render_layers
+                .push(CellRangeHighlight::colors(range, None, Some(color)));
+        }
+        if let Some(selection) = self.terminal.selection() {
+            self.render_layers
+                .push(CellRangeHighlight::inverse(selection.into()));
+        }
+        if let Some(cursor) = self.renderer.cursor_layer(&self.terminal) {
+            self.render_layers.push(cursor);
+        }
+    }
+
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        if self.device_lost.load(Ordering::Acquire) {
+            self.recover_device();
+        }
+        let frame_start = Instant::now();
+        if let Some(gpu_time) = self
+            .gpu_timer
+            .as_mut()
+            .and_then(|timer| timer.poll(&self.device))
+        {
+            self.frame_stats.record_gpu_time(gpu_time);
+        }
+        let hud = if self.show_hud {
+            self.hud_lines()
+        } else {
+            Vec::new()
+        };
+        self.renderer
+            .set_clear_color(self.terminal.screen_background());
+        self.update_render_layers();
+        self.renderer.prepare(
+            &self.device,
+            &self.queue,
+            &self.terminal,
+            &self.render_layers,
+            self.input_handler.ime().preedit(),
+            self.hints.as_ref(),
+            self.menu.as_ref(),
+            &hud,
+        );
+
         let output = self.surface.get_current_texture()?;
         let view = output.texture.create_view(&TextureViewDescriptor::default());
         
@@ -146,92 +1367,442 @@ impl AppState {
                     view: &view,
                     resolve_target: None,
                     ops: Operations {
-                        load: wgpu::LoadOp::Clear(Color {
-                            r: 0.1,
-                            g: 0.1,
-                            b: 0.1,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(self.renderer.clear_color()),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
                 depth_stencil_attachment: None,
                 occlusion_query_set: None,
-                timestamp_writes: None,
+                timestamp_writes: self.gpu_timer.as_mut().and_then(GpuTimer::timestamp_writes),
             });
             
             // Render terminal content
-            self.renderer.render_terminal(&mut render_pass, &self.terminal);
+            self.renderer.render_terminal(&mut render_pass);
+        }
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.resolve(&mut encoder);
         }
         
         self.queue.submit(std::iter::once(encoder.finish()));
+        if let Some(timer) = self.gpu_timer.as_mut() {
+            timer.after_submit();
+        }
+        let cpu_time = frame_start.elapsed();
         output.present();
+        self.last_frame = Instant::now();
+        self.frame_stats.record_frame(self.last_frame, cpu_time);
         
         Ok(())
     }
 }
 
+/// Picks the adapter that best matches `gpu` among those that can present
+/// to `surface` and opens a device on it, with timestamp queries when the
+/// adapter has them.
+async fn request_device(
+    instance: &Instance,
+    surface: &wgpu::Surface<'_>,
+    gpu: &GpuConfig,
+) -> Option<(wgpu::Adapter, wgpu::Device, wgpu::Queue)> {
+    let adapters: Vec<wgpu::Adapter> = instance
+        .enumerate_adapters(gpu.backends())
+        .into_iter()
+        .filter(|adapter| adapter.is_surface_supported(surface))
+        .collect();
+    let infos: Vec<wgpu::AdapterInfo> = adapters.iter().map(wgpu::Adapter::get_info).collect();
+    let power = gpu.power_preference.resolve(gpu::on_battery());
+    let index = gpu::select_adapter(&infos, gpu, power)?;
+    eprintln!("Using GPU adapter {}", gpu::describe(&infos[index]));
+    let adapter = adapters.into_iter().nth(index)?;
+
+    // Timestamp queries feed GPU timings to the debug HUD when available
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: None,
+                required_features: adapter.features() & Features::TIMESTAMP_QUERY,
+                required_limits: Limits::default(),
+            },
+            None,
+        )
+        .await
+        .ok()?;
+    Some((adapter, device, queue))
+}
+
+/// Returns a flag raised when `device` is lost to a driver reset or GPU
+/// removal. Dropping the device ourselves doesn't count.
+fn watch_device_lost(device: &wgpu::Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&lost);
+    device.set_device_lost_callback(move |reason, message| {
+        if matches!(
+            reason,
+            wgpu::DeviceLostReason::Unknown | wgpu::DeviceLostReason::Destroyed
+        ) {
+            eprintln!("GPU device lost: {}", message);
+            flag.store(true, Ordering::Release);
+        }
+    });
+    lost
+}
+
+/// Opens a URL or path with the platform's default handler.
+/// What runs in a new PTY: the shell, the command given with -e, or the
+/// profile's command.
+fn shell_config(app_config: &Config, cli: &Cli, environment: &EnvironmentManager) -> PtyConfig {
+    let mut pty_config = if app_config.login_shell {
+        PtyConfig::login_shell()
+    } else {
+        PtyConfig::default()
+    };
+    if let Some((program, args)) = cli.command.split_first() {
+        pty_config.shell = program.clone();
+        pty_config.args = args.to_vec();
+        pty_config.login = false;
+    } else if let Some(profile) = cli
+        .profile
+        .as_deref()
+        .and_then(|name| app_config.profile(name))
+    {
+        profile.apply(&mut pty_config);
+    }
+    pty_config.coalescing = app_config.coalesce_reads.then(|| ReadCoalescing {
+        max_bytes: app_config.coalesce_max_bytes,
+        ..ReadCoalescing::default()
+    });
+    environment.apply(&mut pty_config);
+    pty_config
+}
+
+/// Title shown while the program sets none and has no foreground process.
+fn default_title(cli: &Cli, profile: Option<&Profile>) -> String {
+    cli.title
+        .clone()
+        .or_else(|| profile.and_then(|profile| profile.title.clone()))
+        .unwrap_or_else(|| WINDOW_TITLE.to_string())
+}
+
+fn open_external(target: &str) {
+    let result = if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg(target).spawn()
+    } else if cfg!(windows) {
+        std::process::Command::new("cmd")
+            .args(["/C", "start", "", target])
+            .spawn()
+    } else {
+        std::process::Command::new("xdg-open").arg(target).spawn()
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to open {}: {}", target, e);
+    }
+}
+
+/// Puts `text` on the system clipboard from a blocking task, as the tool
+/// may take a moment to start.
+fn copy_to_clipboard(text: String) {
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = clipboard::copy(&text) {
+            eprintln!("Copying to the clipboard failed: {}", e);
+        }
+    });
+}
+
 #[tokio::main]
-async fn main() {
-    // Initialize Tokio runtime for async operations
-    let runtime = Runtime::new().unwrap();
-    
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse_args(std::env::args()).unwrap_or_else(|e| e.exit());
+    if let Some(path) = &cli.log_file {
+        if let Err(e) = cli::redirect_stderr(path) {
+            eprintln!("rt: --log-file {}: {}", path.display(), e);
+            std::process::exit(2);
+        }
+    }
+    if cli.list_gpus {
+        gpu::list_adapters();
+        return Ok(());
+    }
+
+    let mut environment = EnvironmentManager::new();
+    if let Some(dir) = &cli.working_directory {
+        if let Err(e) = environment.set_working_directory(dir) {
+            eprintln!("rt: --working-directory {}: {}", dir.display(), e);
+            std::process::exit(2);
+        }
+    }
+
+    // Load user configuration
+    let mut app_config = match cli.config.clone().or_else(Config::default_path) {
+        Some(path) if cli.config.is_some() && !path.exists() => {
+            eprintln!("rt: --config {}: file not found", path.display());
+            std::process::exit(2);
+        }
+        Some(path) => Config::load(&path).unwrap_or_else(|e| {
+            eprintln!("Ignoring config {}: {}", path.display(), e);
+            Config::default()
+        }),
+        None => Config::default(),
+    };
+    cli.apply_window(&mut app_config.window);
+
+    // Restore the last session's window unless the command line places it
+    let state_path = WindowState::default_path();
+    let saved = state_path
+        .as_deref()
+        .map(WindowState::load)
+        .unwrap_or_default();
+    let restore = cli.geometry.is_none() && !cli.maximized && !cli.fullscreen;
+    if cli.working_directory.is_none() {
+        if let Some(dir) = &saved.working_directory {
+            // The directory may have been removed since; start in the default one then
+            let _ = environment.set_working_directory(dir);
+        }
+    }
+
+    // Start in the profile's directory and environment
+    let mut profile = match &cli.profile {
+        Some(name) => match app_config.profile(name) {
+            Some(profile) => Some(profile.clone()),
+            None => {
+                eprintln!("rt: --profile {}: no such profile in the config", name);
+                std::process::exit(2);
+            }
+        },
+        None => None,
+    };
+    if let Some(profile) = &mut profile {
+        // A directory given on the command line wins
+        if cli.working_directory.is_some() {
+            profile.working_directory = None;
+        }
+        if let Err(e) = profile.configure(&mut environment) {
+            eprintln!("rt: profile {}: {}", profile.name, e);
+            std::process::exit(2);
+        }
+    }
+
+    // Serve a shell for --attach instead of opening a window
+    #[cfg(unix)]
+    if let Some(name) = &cli.daemon {
+        let pty_config = shell_config(&app_config, &cli, &environment);
+        let result = detach::socket_path(name)
+            .and_then(|path| detach::Daemon::bind(&path, &pty_config))
+            .and_then(detach::Daemon::run);
+        if let Err(e) = result {
+            eprintln!("rt: --daemon {}: {}", name, e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    #[cfg(not(unix))]
+    if cli.attach.is_some() || cli.daemon.is_some() {
+        eprintln!("rt: --attach is not supported on this platform");
+        std::process::exit(2);
+    }
+
     // Initialize window
-    let event_loop = EventLoop::new().unwrap();
+    let event_loop = EventLoopBuilder::<UserEvent>::with_user_event().build()?;
     let window = WindowBuilder::new()
-        .with_title("RT Terminal")
-        .with_inner_size(winit::dpi::PhysicalSize::new(WINDOW_WIDTH, WINDOW_HEIGHT))
-        .build(&event_loop)
-        .unwrap();
-    
+        .with_title(default_title(&cli, profile.as_ref()))
+        .with_transparent(app_config.background_opacity < 1.0)
+        .with_maximized(app_config.window.maximized)
+        .with_fullscreen(
+            app_config
+                .window
+                .fullscreen
+                .then_some(Fullscreen::Borderless(None)),
+        )
+        .build(&event_loop)?;
+
+    // Size the window to fit the grid
+    let scale_factor = window.scale_factor();
+    let cell = TextRenderer::from_system_font(renderer::FONT_SIZE)
+        .map(|mut text| {
+            text.set_scale_factor(scale_factor as f32);
+            text.cell_size()
+        })
+        .unwrap_or_else(|_| renderer::cell_size_for(renderer::FONT_SIZE * scale_factor as f32));
+    let cell = renderer::spaced_cell_size(
+        cell,
+        app_config.line_spacing,
+        app_config.letter_spacing * scale_factor as f32,
+    );
+    let padding = app_config.window.padding;
+    window.set_min_inner_size(Some(geometry::window_size(
+        geometry::MIN_GEOMETRY,
+        cell,
+        padding,
+        scale_factor,
+    )));
+    if !app_config.window.maximized && !app_config.window.fullscreen {
+        let size = match saved.size {
+            Some((width, height)) if restore => PhysicalSize::new(width, height),
+            _ => geometry::window_size(
+                app_config.window.geometry.clamped(),
+                cell,
+                padding,
+                scale_factor,
+            ),
+        };
+        let _ = window.request_inner_size(size);
+        if let Some(position) = saved.position.filter(|_| restore) {
+            let monitors: Vec<MonitorArea> = window
+                .primary_monitor()
+                .into_iter()
+                .chain(window.available_monitors())
+                .map(|monitor| MonitorArea {
+                    position: (monitor.position().x, monitor.position().y),
+                    size: (monitor.size().width, monitor.size().height),
+                })
+                .collect();
+            let (x, y) =
+                window_state::clamp_to_monitors(position, (size.width, size.height), &monitors);
+            window.set_outer_position(PhysicalPosition::new(x, y));
+        }
+        if restore && saved.maximized {
+            window.set_maximized(true);
+        }
+    }
+
     // Initialize application state
-    let mut state = AppState::new(window).await;
+    let mut state = AppState::new(Arc::new(window), app_config, &cli, &environment).await;
+    state.state_path = state_path;
+    state.window_state = saved;
+    state.event_proxy = Some(event_loop.create_proxy());
     
+    // Forward process signals into the event loop
+    let mut background_tasks = Vec::new();
+    match signals::setup_signal_handlers() {
+        Ok((mut signal_rx, signal_tasks)) => {
+            let proxy = event_loop.create_proxy();
+            background_tasks = signal_tasks;
+            background_tasks.push(tokio::spawn(async move {
+                while let Some(signal) = signal_rx.recv().await {
+                    if proxy.send_event(UserEvent::Signal(signal)).is_err() {
+                        break;
+                    }
+                }
+            }));
+        }
+        Err(e) => eprintln!(
+            "Failed to install signal handlers, continuing without them: {}",
+            e
+        ),
+    }
+
+    // Wake the event loop when the shell writes output
+    let pty_wakeups = state
+        .session_id
+        .and_then(|id| state.pty_manager.get(id))
+        .map(|session| session.subscribe());
+    #[cfg(unix)]
+    let pty_wakeups =
+        pty_wakeups.or_else(|| state.attached.as_ref().map(|client| client.subscribe()));
+    if let Some(mut pty_wakeups) = pty_wakeups {
+        let proxy = event_loop.create_proxy();
+        background_tasks.push(tokio::spawn(async move {
+            while let Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) = pty_wakeups.recv().await
+            {
+                if proxy.send_event(UserEvent::PtyOutput).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+
     // Main event loop
-    event_loop.run(move |event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
-        
+    event_loop.run(move |mut event, elwt| {
         match event {
             Event::WindowEvent {
-                ref event,
+                ref mut event,
                 window_id,
             } if window_id == state.window.id() => {
-                if !state.input(event) {
+                if state.input(event) {
+                    state.redraw_pending = true;
+                } else {
                     match event {
-                        WindowEvent::CloseRequested => {
-                            *control_flow = ControlFlow::Exit;
+                        WindowEvent::CloseRequested if state.confirm_close() => {
+                            state.shutdown();
+                            elwt.exit();
                         }
+                        // Applied in `update` once the size settles; frames
+                        // keep the last size until then
                         WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
+                            state
+                                .resize_debounce
+                                .request(*physical_size, Instant::now());
                         }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                            state.resize(**new_inner_size);
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            inner_size_writer,
+                        } => {
+                            let size = state.set_scale_factor(*scale_factor);
+                            // The platform may not let the size change; the grid follows whatever it becomes
+                            let _ = inner_size_writer.request_inner_size(size);
+                            state.resize(size);
+                            state.fit_grid(size);
+                            state.redraw_pending = true;
                         }
+                        WindowEvent::RedrawRequested => match state.render() {
+                            Ok(_) => {}
+                            Err(wgpu::SurfaceError::Lost) => {
+                                state.recreate_surface();
+                                state.window.request_redraw();
+                            }
+                            // The window changed size under us, e.g. mid-resize on Wayland
+                            Err(wgpu::SurfaceError::Outdated) => {
+                                state.resize(state.window.inner_size());
+                                state.window.request_redraw();
+                            }
+                            Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
+                            Err(e) => {
+                                eprintln!("Render error: {:?}", e);
+                            }
+                        },
                         _ => {}
                     }
                 }
             }
-            Event::MainEventsCleared => {
-                state.update();
+            Event::UserEvent(UserEvent::Signal(signal)) => match signal {
+                SignalEvent::Shutdown => {
+                    state.shutdown();
+                    elwt.exit();
+                }
+                SignalEvent::Resize => {
+                    state.resize(state.window.inner_size());
+                    state.redraw_pending = true;
+                }
+            },
+            // Output is read in `update` once the pending events are handled
+            Event::UserEvent(UserEvent::PtyOutput) => {}
+            Event::UserEvent(UserEvent::PipeFinished { command, output }) => {
+                state.finish_pipe(command, output);
+                state.redraw_pending = true;
             }
-            Event::RedrawRequested(window_id) if window_id == state.window.id() => {
-                match state.render() {
-                    Ok(_) => {}
-                    Err(wgpu::SurfaceError::Lost) => {
-                        state.resize(state.window.inner_size());
-                    }
-                    Err(wgpu::SurfaceError::OutOfMemory) => {
-                        *control_flow = ControlFlow::Exit;
-                    }
-                    Err(e) => {
-                        eprintln!("Render error: {:?}", e);
-                    }
+            Event::UserEvent(UserEvent::ClipboardRead(text)) => {
+                state.finish_paste(text);
+                state.redraw_pending = true;
+            }
+            Event::LoopExiting => {
+                for task in background_tasks.drain(..) {
+                    task.abort();
+                }
+            }
+            Event::AboutToWait => {
+                state.update();
+                if state.output_ended {
+                    state.shutdown();
+                    elwt.exit();
+                    return;
+                }
+                if state.needs_redraw() {
+                    state.window.request_redraw();
+                }
+                if !elwt.exiting() {
+                    elwt.set_control_flow(state.control_flow());
                 }
             }
             _ => {}
         }
-        
-        // Request redraw for next frame
-        state.window.request_redraw();
-    }).unwrap();
+    })?;
+    Ok(())
 }
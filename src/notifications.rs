@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::terminal::AttentionEvent;
+
+pub const DEFAULT_MIN_COMMAND_DURATION: Duration = Duration::from_secs(10);
+
+/// Something that can show a notification to the user.
+pub trait Notifier {
+    fn notify(&mut self, title: &str, body: &str);
+}
+
+/// Desktop notifications through the platform's notification service.
+pub struct DesktopNotifier;
+
+impl Notifier for DesktopNotifier {
+    fn notify(&mut self, title: &str, body: &str) {
+        let result = notify_rust::Notification::new()
+            .appname("rt")
+            .summary(title)
+            .body(body)
+            .show();
+        if let Err(e) = result {
+            eprintln!("Failed to show notification: {}", e);
+        }
+    }
+}
+
+/// Decides which attention events become notifications.
+pub struct Notifications<N: Notifier> {
+    notifier: N,
+    /// Commands finishing sooner than this don't notify.
+    pub min_command_duration: Duration,
+}
+
+impl<N: Notifier> Notifications<N> {
+    pub fn new(notifier: N) -> Self {
+        Self {
+            notifier,
+            min_command_duration: DEFAULT_MIN_COMMAND_DURATION,
+        }
+    }
+
+    /// Shows a notification unconditionally.
+    pub fn notify(&mut self, title: &str, body: &str) {
+        self.notifier.notify(title, body);
+    }
+
+    /// Notifies about `event` unless the window is focused or the command
+    /// was too short. Returns whether the window should request attention.
+    pub fn handle(&mut self, event: &AttentionEvent, focused: bool, title: &str) -> bool {
+        if focused {
+            return false;
+        }
+        let body = match *event {
+            AttentionEvent::Bell => "Bell".to_string(),
+            AttentionEvent::CommandFinished {
+                duration,
+                exit_code,
+            } => {
+                if duration < self.min_command_duration {
+                    return false;
+                }
+                let seconds = duration.as_secs();
+                match exit_code {
+                    Some(0) | None => format!("Command finished after {}s", seconds),
+                    Some(code) => format!(
+                        "Command failed with exit status {} after {}s",
+                        code, seconds
+                    ),
+                }
+            }
+        };
+        self.notifier.notify(title, &body);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    #[derive(Default)]
+    struct Recorder(Vec<(String, String)>);
+
+    impl Notifier for Recorder {
+        fn notify(&mut self, title: &str, body: &str) {
+            self.0.push((title.to_string(), body.to_string()));
+        }
+    }
+
+    fn finished(seconds: u64, exit_code: Option<i32>) -> AttentionEvent {
+        AttentionEvent::CommandFinished {
+            duration: Duration::from_secs(seconds),
+            exit_code,
+        }
+    }
+
+    #[test]
+    fn nothing_is_shown_while_focused() {
+        let mut notifications = Notifications::new(Recorder::default());
+        assert!(!notifications.handle(&AttentionEvent::Bell, true, "vim"));
+        assert!(!notifications.handle(&finished(60, Some(0)), true, "make"));
+        assert!(notifications.notifier.0.is_empty());
+    }
+
+    #[test]
+    fn short_commands_are_not_reported() {
+        let mut notifications = Notifications::new(Recorder::default());
+        notifications.min_command_duration = Duration::from_secs(10);
+        assert!(!notifications.handle(&finished(9, Some(0)), false, "make"));
+        assert!(notifications.handle(&finished(10, Some(0)), false, "make"));
+        assert!(notifications.handle(&finished(75, Some(2)), false, "make"));
+        assert!(notifications.handle(&AttentionEvent::Bell, false, "vim"));
+        let shown: Vec<_> = notifications
+            .notifier
+            .0
+            .iter()
+            .map(|(title, body)| (title.as_str(), body.as_str()))
+            .collect();
+        assert_eq!(
+            shown,
+            [
+                ("make", "Command finished after 10s"),
+                ("make", "Command failed with exit status 2 after 75s"),
+                ("vim", "Bell"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_terminal_reports_bells_and_finished_commands() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x07\x1b]133;D;1\x07\x1b]133;C\x07\x1b]133;D;1\x07");
+        let events = terminal.take_attention_events();
+        assert_eq!(events[0], AttentionEvent::Bell);
+        assert!(matches!(
+            events[1..],
+            [AttentionEvent::CommandFinished {
+                exit_code: Some(1),
+                ..
+            }]
+        ));
+        assert!(terminal.take_attention_events().is_empty());
+    }
+}
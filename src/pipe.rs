@@ -0,0 +1,184 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+
+use crate::environment::EnvironmentManager;
+use crate::terminal::Terminal;
+
+/// Which text a pipe action sends to its command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipeSource {
+    /// The live screen, whatever the scroll position.
+    Screen,
+    /// The scrollback followed by the screen.
+    Scrollback,
+    Selection,
+}
+
+impl FromStr for PipeSource {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "screen" => Ok(PipeSource::Screen),
+            "scrollback" => Ok(PipeSource::Scrollback),
+            "selection" => Ok(PipeSource::Selection),
+            _ => Err(format!(
+                "expected screen, scrollback or selection, got '{}'",
+                name
+            )),
+        }
+    }
+}
+
+/// A command run with terminal text on its stdin, bound as
+/// `pipe SOURCE COMMAND...`, or `pipe_paste SOURCE COMMAND...` to paste
+/// what it prints back into the terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipeCommand {
+    pub source: PipeSource,
+    pub argv: Vec<String>,
+    pub paste_output: bool,
+}
+
+impl PipeCommand {
+    /// Parses the `SOURCE COMMAND...` part of a binding.
+    pub fn parse(arguments: &str, paste_output: bool) -> Result<Self, String> {
+        let mut words = arguments.split_whitespace();
+        let source = words
+            .next()
+            .ok_or("pipe needs a source and a command")?
+            .parse()?;
+        let argv: Vec<String> = words.map(String::from).collect();
+        if argv.is_empty() {
+            return Err("pipe needs a command".to_string());
+        }
+        Ok(Self {
+            source,
+            argv,
+            paste_output,
+        })
+    }
+
+    /// The text to send, or `None` when the source is an empty selection.
+    pub fn input(&self, terminal: &Terminal) -> Option<String> {
+        match self.source {
+            PipeSource::Screen => Some(terminal.screen_text()),
+            PipeSource::Scrollback => {
+                let mut text = String::new();
+                for line in terminal.iter_scrollback() {
+                    text.push_str(&line);
+                    text.push('\n');
+                }
+                text.push_str(&terminal.screen_text());
+                Some(text)
+            }
+            PipeSource::Selection => terminal.selection_text(),
+        }
+    }
+
+    /// The command, started where new sessions start.
+    pub fn command(&self, environment: &EnvironmentManager) -> Command {
+        let mut command = Command::new(&self.argv[0]);
+        command.args(&self.argv[1..]);
+        environment.apply_to_command(&mut command);
+        command
+    }
+}
+
+/// Runs `command` with `input` on its stdin and returns what it printed.
+/// Blocks until the command exits, so call it off the UI thread.
+pub fn run(mut command: Command, input: Vec<u8>) -> io::Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // Written from another thread so a command that prints before reading
+    // everything can't fill its stdout pipe and deadlock with us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || {
+        // A command may exit without reading all of it, as `head` does
+        let _ = stdin.write_all(&input);
+    });
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(io::Error::other(format!("exited with {}", output.status)));
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::selection::Selection;
+
+    fn command(line: &str) -> PipeCommand {
+        PipeCommand::parse(line, false).unwrap()
+    }
+
+    #[test]
+    fn bindings_name_a_source_and_a_command() {
+        assert_eq!(
+            PipeCommand::parse("selection xclip -selection clipboard", true),
+            Ok(PipeCommand {
+                source: PipeSource::Selection,
+                argv: vec![
+                    "xclip".to_string(),
+                    "-selection".to_string(),
+                    "clipboard".to_string()
+                ],
+                paste_output: true,
+            })
+        );
+        assert!(PipeCommand::parse("screen", false).is_err());
+        assert!(PipeCommand::parse("history less", false).is_err());
+    }
+
+    #[test]
+    fn sources_pick_the_text_to_send() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.process_pty_output(b"one\r\ntwo\r\nthree");
+        assert_eq!(
+            command("screen cat").input(&terminal).unwrap(),
+            "two\nthree\n"
+        );
+        assert_eq!(
+            command("scrollback cat").input(&terminal).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+        assert_eq!(command("selection cat").input(&terminal), None);
+        terminal.select(Selection {
+            start: (1, 1),
+            end: (3, 1),
+        });
+        assert_eq!(
+            command("selection cat").input(&terminal).as_deref(),
+            Some("hre")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_scrollback_is_piped_through_wc() {
+        let mut terminal = Terminal::new(10, 3);
+        for line in 1..=7 {
+            terminal.process_pty_output(format!("line {}\r\n", line).as_bytes());
+        }
+        let pipe = command("scrollback wc -l");
+        let input = pipe.input(&terminal).unwrap();
+        let output = run(pipe.command(&EnvironmentManager::new()), input.into_bytes()).unwrap();
+        // Five lines in the scrollback, then the three rows of the screen
+        assert_eq!(String::from_utf8(output).unwrap().trim(), "8");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_commands_are_errors() {
+        let pipe = command("screen false");
+        assert!(run(pipe.command(&EnvironmentManager::new()), b"text".to_vec()).is_err());
+        let missing = command("screen rt-no-such-command");
+        assert!(run(missing.command(&EnvironmentManager::new()), Vec::new()).is_err());
+    }
+}
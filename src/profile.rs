@@ -0,0 +1,222 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::environment::EnvironmentManager;
+use crate::pty::PtyConfig;
+
+/// A named way to start the terminal, typically ssh to a host, chosen
+/// with `rt --profile NAME` and configured in a `[profile NAME]` section:
+///
+/// ```text
+/// [profile prod-db]
+/// command = ssh -t -o "ProxyCommand=ssh -W %h:%p bastion" db.example.com
+/// working_directory = /srv
+/// env = LC_ALL=C
+/// title = Prod DB
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub name: String,
+    /// Program and arguments run instead of the shell, split like a shell
+    /// would: quotes and backslashes keep spaces within an argument.
+    pub command: Vec<String>,
+    pub working_directory: Option<PathBuf>,
+    /// Variables set on top of the inherited environment, one `env` line
+    /// each.
+    pub env: Vec<(String, String)>,
+    /// Window title shown while the program sets none.
+    pub title: Option<String>,
+}
+
+impl Profile {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Applies a `key = value` line of the profile's section.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "command" => self.command = split_command(value)?,
+            "working_directory" => self.working_directory = Some(PathBuf::from(value)),
+            "env" => {
+                let (name, value) = value
+                    .split_once('=')
+                    .filter(|(name, _)| !name.trim().is_empty())
+                    .ok_or_else(|| format!("expected env = NAME=VALUE, got '{}'", value))?;
+                self.env
+                    .push((name.trim().to_string(), value.trim().to_string()));
+            }
+            "title" => self.title = Some(value.to_string()),
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Checks the settings that have no usable default.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.command.is_empty() {
+            return Err(format!("profile '{}' needs a command", self.name));
+        }
+        Ok(())
+    }
+
+    /// Starts new sessions in the profile's directory and environment.
+    pub fn configure(&self, environment: &mut EnvironmentManager) -> io::Result<()> {
+        for (name, value) in &self.env {
+            environment.set_var(name, value);
+        }
+        if let Some(dir) = &self.working_directory {
+            environment.set_working_directory(dir).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("working_directory {}: {}", dir.display(), e),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Runs the profile's command in `config`.
+    pub fn apply(&self, config: &mut PtyConfig) {
+        if let Some((program, args)) = self.command.split_first() {
+            config.shell = program.clone();
+            config.args = args.to_vec();
+            config.login = false;
+        }
+    }
+}
+
+/// Splits `command` into arguments at unquoted whitespace, as a POSIX
+/// shell would: single quotes keep everything up to the next one, and a
+/// backslash keeps the next character, though within double quotes only
+/// before `"` or `\`.
+pub(crate) fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            ch if ch.is_whitespace() => args.extend(arg.take()),
+            '\'' => {
+                let quoted = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(ch) => quoted.push(ch),
+                        None => return Err("unterminated ' in command".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let quoted = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(ch @ ('"' | '\\')) => quoted.push(ch),
+                            Some(ch) => {
+                                quoted.push('\\');
+                                quoted.push(ch);
+                            }
+                            None => return Err("unterminated \" in command".to_string()),
+                        },
+                        Some(ch) => quoted.push(ch),
+                        None => return Err("unterminated \" in command".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or_else(|| "command ends with a lone \\".to_string())?;
+                arg.get_or_insert_with(String::new).push(escaped);
+            }
+            ch => arg.get_or_insert_with(String::new).push(ch),
+        }
+    }
+    args.extend(arg);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/profiles.conf");
+
+    /// The PtyConfig a new session of profile `name` in the fixture starts
+    /// with.
+    fn pty_config(name: &str) -> PtyConfig {
+        let config = Config::parse(FIXTURE).unwrap();
+        let profile = config.profile(name).unwrap();
+        let mut environment = EnvironmentManager::new();
+        profile.configure(&mut environment).unwrap();
+        let mut pty_config = PtyConfig::login_shell();
+        profile.apply(&mut pty_config);
+        environment.apply(&mut pty_config);
+        pty_config
+    }
+
+    #[test]
+    fn quoted_arguments_stay_whole() {
+        let config = pty_config("prod-db");
+        assert_eq!(config.shell, "ssh");
+        assert_eq!(
+            config.args,
+            [
+                "-t",
+                "-o",
+                "ProxyCommand=ssh -W %h:%p bastion",
+                "db.example.com"
+            ]
+        );
+        assert!(!config.login);
+    }
+
+    #[test]
+    fn single_quotes_keep_double_quotes_and_backslashes() {
+        let config = pty_config("logs");
+        assert_eq!(config.shell, "sh");
+        assert_eq!(
+            config.args,
+            [
+                "-c",
+                r#"tail -f /var/log/syslog | grep -v "cron\[[0-9]*\]""#
+            ]
+        );
+    }
+
+    #[test]
+    fn env_and_working_directory_reach_the_pty() {
+        let config = pty_config("prod-db");
+        assert_eq!(config.cwd.as_deref(), Some(std::path::Path::new("/")));
+        let var = |name: &str| {
+            config
+                .env
+                .iter()
+                .find(|(existing, _)| existing == name)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(var("LC_ALL"), Some("C"));
+        assert_eq!(var("PGUSER"), Some("readonly"));
+    }
+
+    #[test]
+    fn split_command_handles_escapes() {
+        assert_eq!(
+            split_command(r#"a\ b "c \"d\" \e" ''"#).unwrap(),
+            ["a b", r#"c "d" \e"#, ""]
+        );
+    }
+
+    #[test]
+    fn unterminated_quotes_are_rejected() {
+        assert!(split_command("ssh 'host").is_err());
+        assert!(split_command("ssh \"host").is_err());
+        assert!(Profile::new("p").set("command", "ssh \\").is_err());
+    }
+}
@@ -0,0 +1,873 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use portable_pty::{native_pty_system, Child, CommandBuilder, MasterPty};
+use tokio::sync::broadcast;
+
+pub use portable_pty::PtySize;
+
+/// Size of a single read from the PTY master.
+const READ_BUFFER_SIZE: usize = 4096;
+/// Number of output chunks a slow subscriber may fall behind by.
+const OUTPUT_CHANNEL_CAPACITY: usize = 1024;
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(2);
+/// Minimum time between foreground process lookups for a session.
+const FOREGROUND_REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+/// Largest chunk merged from consecutive reads by default.
+pub const DEFAULT_COALESCE_BYTES: usize = 64 * 1024;
+/// How long a merge waits for more output after a full read by default.
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(2);
+/// Shell run when neither the passwd database nor `$SHELL` names one.
+const FALLBACK_SHELL: &str = "/bin/bash";
+
+fn pty_error(err: impl std::fmt::Display) -> io::Error {
+    io::Error::other(err.to_string())
+}
+
+/// What to run inside a new PTY.
+#[derive(Debug, Clone)]
+pub struct PtyConfig {
+    pub shell: String,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub env: Vec<(String, String)>,
+    pub size: PtySize,
+    /// Start `shell` as a login shell, with an argv[0] of its file name
+    /// prefixed with `-`, so it reads the user's profile. `args` are not
+    /// passed then.
+    pub login: bool,
+    /// Merge reads that arrive together into larger chunks, so a burst of
+    /// output costs subscribers fewer wakeups and parser calls. `None`
+    /// sends every read as it is.
+    pub coalescing: Option<ReadCoalescing>,
+}
+
+/// How consecutive reads from the PTY are merged before they're sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadCoalescing {
+    /// Merging stops once a chunk holds this many bytes.
+    pub max_bytes: usize,
+    /// How long to wait for more output after a read that filled the read
+    /// buffer, a sign that more is on its way. Other reads are sent once
+    /// the output already read is merged, so echoes aren't held back.
+    pub window: Duration,
+}
+
+impl Default for ReadCoalescing {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_COALESCE_BYTES,
+            window: DEFAULT_COALESCE_WINDOW,
+        }
+    }
+}
+
+impl Default for PtyConfig {
+    fn default() -> Self {
+        Self {
+            shell: std::env::var("SHELL").unwrap_or_else(|_| FALLBACK_SHELL.to_string()),
+            args: Vec::new(),
+            cwd: None,
+            env: vec![("TERM".to_string(), "xterm-256color".to_string())],
+            size: PtySize::default(),
+            login: false,
+            coalescing: Some(ReadCoalescing::default()),
+        }
+    }
+}
+
+impl PtyConfig {
+    /// Runs the user's shell from the passwd database as a login shell.
+    /// Falls back to `$SHELL` where there is no usable entry.
+    pub fn login_shell() -> Self {
+        Self {
+            shell: passwd_shell()
+                .or_else(|| std::env::var("SHELL").ok())
+                .unwrap_or_else(|| FALLBACK_SHELL.to_string()),
+            login: true,
+            ..Self::default()
+        }
+    }
+
+    /// The argv[0] the shell is started with.
+    pub fn argv0(&self) -> String {
+        if !self.login {
+            return self.shell.clone();
+        }
+        let name = self.shell.rsplit('/').next().unwrap_or(&self.shell);
+        format!("-{}", name)
+    }
+}
+
+/// The shell in the user's passwd entry, if it is set and executable.
+#[cfg(unix)]
+fn passwd_shell() -> Option<String> {
+    use std::ffi::{CStr, CString};
+
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut found = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let status = unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            &mut entry,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut found,
+        )
+    };
+    if status != 0 || found.is_null() || entry.pw_shell.is_null() {
+        return None;
+    }
+    let shell = unsafe { CStr::from_ptr(entry.pw_shell) }.to_str().ok()?;
+    let path = CString::new(shell).ok()?;
+    let executable = unsafe { libc::access(path.as_ptr(), libc::X_OK) } == 0;
+    (!shell.is_empty() && executable).then(|| shell.to_string())
+}
+
+#[cfg(not(unix))]
+fn passwd_shell() -> Option<String> {
+    None
+}
+
+/// Holds the reader thread back while output is paused, so the kernel's PTY
+/// buffer fills and the child blocks on its writes.
+#[derive(Debug, Default)]
+struct ReadGate {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl ReadGate {
+    fn set_paused(&self, paused: bool) {
+        let mut state = self.paused.lock().unwrap_or_else(|e| e.into_inner());
+        if *state != paused {
+            *state = paused;
+            self.resumed.notify_all();
+        }
+    }
+
+    /// Blocks until output isn't paused.
+    fn wait(&self) {
+        let state = self.paused.lock().unwrap_or_else(|e| e.into_inner());
+        drop(
+            self.resumed
+                .wait_while(state, |paused| *paused)
+                .unwrap_or_else(|e| e.into_inner()),
+        );
+    }
+}
+
+/// Reads until EOF or an error, handing each read to `send`. No read starts
+/// while `gate` is paused.
+fn read_pty(mut reader: Box<dyn Read + Send>, gate: &ReadGate, mut send: impl FnMut(Vec<u8>)) {
+    let mut buf = [0u8; READ_BUFFER_SIZE];
+    loop {
+        gate.wait();
+        match reader.read(&mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => send(buf[..n].to_vec()),
+        }
+    }
+}
+
+/// Merges the reads queued on `reads` and hands each merged chunk to
+/// `send`, until the sending side is dropped.
+pub fn coalesce(reads: Receiver<Vec<u8>>, config: ReadCoalescing, mut send: impl FnMut(Vec<u8>)) {
+    while let Ok(mut chunk) = reads.recv() {
+        let deadline = Instant::now() + config.window;
+        let mut full = chunk.len() >= READ_BUFFER_SIZE;
+        while chunk.len() < config.max_bytes {
+            let next = if full {
+                reads
+                    .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    .ok()
+            } else {
+                reads.try_recv().ok()
+            };
+            match next {
+                Some(more) => {
+                    full = more.len() >= READ_BUFFER_SIZE;
+                    chunk.extend_from_slice(&more);
+                }
+                None => break,
+            }
+        }
+        send(chunk);
+    }
+}
+
+/// A process running on a PTY.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    pub cwd: Option<PathBuf>,
+}
+
+#[cfg(target_os = "linux")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    let cwd = std::fs::read_link(format!("/proc/{}/cwd", pid)).ok();
+    Some(ProcessInfo {
+        pid,
+        name: name.trim_end().to_string(),
+        cwd,
+    })
+}
+
+#[cfg(target_os = "macos")]
+fn process_info(pid: u32) -> Option<ProcessInfo> {
+    let mut name = [0u8; 256];
+    let len = unsafe {
+        libc::proc_name(
+            pid as libc::c_int,
+            name.as_mut_ptr() as *mut libc::c_void,
+            name.len() as u32,
+        )
+    };
+    if len <= 0 {
+        return None;
+    }
+    Some(ProcessInfo {
+        pid,
+        name: String::from_utf8_lossy(&name[..len as usize]).into_owned(),
+        cwd: None,
+    })
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+fn process_info(_pid: u32) -> Option<ProcessInfo> {
+    None
+}
+
+/// Signals delivered to a child's whole process group.
+pub struct ProcessManager;
+
+#[cfg(unix)]
+impl ProcessManager {
+    pub fn signal_group(pgid: u32, signal: libc::c_int) -> io::Result<()> {
+        if unsafe { libc::killpg(pgid as libc::pid_t, signal) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        // The group is already gone
+        if err.raw_os_error() == Some(libc::ESRCH) {
+            return Ok(());
+        }
+        Err(err)
+    }
+
+    /// Asks the group to exit, as a terminal does when its window closes.
+    pub fn hangup_group(pgid: u32) -> io::Result<()> {
+        Self::signal_group(pgid, libc::SIGHUP)
+    }
+
+    pub fn terminate_group(pgid: u32) -> io::Result<()> {
+        Self::signal_group(pgid, libc::SIGKILL)
+    }
+
+    /// Whether any process, zombies included, is left in the group.
+    pub fn group_exists(pgid: u32) -> bool {
+        unsafe { libc::killpg(pgid as libc::pid_t, 0) == 0 }
+    }
+}
+
+/// A child process attached to a PTY, with its output fanned out to
+/// subscribers from a reader thread.
+pub struct PtySession {
+    master: Box<dyn MasterPty + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn Child + Send + Sync>,
+    output: broadcast::Sender<Vec<u8>>,
+    exited: bool,
+    /// The spawned command, reported as the foreground process where it
+    /// can't be looked up.
+    spawned: ProcessInfo,
+    foreground: Option<(Instant, Option<ProcessInfo>)>,
+    gate: Arc<ReadGate>,
+    /// The process groups sent SIGHUP at shutdown: the child's and the
+    /// job's that owned the terminal then, if another.
+    #[cfg(unix)]
+    hung_up: Vec<u32>,
+}
+
+impl PtySession {
+    pub fn spawn(config: &PtyConfig) -> io::Result<Self> {
+        let pair = native_pty_system()
+            .openpty(config.size)
+            .map_err(pty_error)?;
+
+        let mut command = if config.login && cfg!(unix) {
+            // portable-pty runs $SHELL with a dash-prefixed argv[0] as its
+            // default program
+            CommandBuilder::new_default_prog()
+        } else {
+            let mut command = CommandBuilder::new(&config.shell);
+            command.args(&config.args);
+            command
+        };
+        if let Some(cwd) = &config.cwd {
+            command.cwd(cwd);
+        }
+        for (key, value) in &config.env {
+            command.env(key, value);
+        }
+        if config.login {
+            command.env("SHELL", &config.shell);
+        }
+
+        let child = pair.slave.spawn_command(command).map_err(pty_error)?;
+        let spawned = ProcessInfo {
+            pid: child.process_id().unwrap_or(0),
+            name: std::path::Path::new(&config.shell)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| config.shell.clone()),
+            cwd: config.cwd.clone(),
+        };
+        // The child holds its own copy; keeping ours would hide EOF on exit
+        drop(pair.slave);
+
+        let reader = pair.master.try_clone_reader().map_err(pty_error)?;
+        let writer = pair.master.take_writer().map_err(pty_error)?;
+        let (output, _) = broadcast::channel(OUTPUT_CHANNEL_CAPACITY);
+        let gate = Arc::new(ReadGate::default());
+        let read_gate = Arc::clone(&gate);
+
+        let sender = output.clone();
+        // No subscribers is fine; output is simply dropped
+        let broadcast = move |chunk| {
+            let _ = sender.send(chunk);
+        };
+        match config.coalescing {
+            Some(coalescing) => {
+                let (read_sender, reads) = mpsc::channel();
+                thread::spawn(move || {
+                    read_pty(reader, &read_gate, |chunk| {
+                        let _ = read_sender.send(chunk);
+                    })
+                });
+                thread::spawn(move || {
+                    let mut broadcast = broadcast;
+                    coalesce(reads, coalescing, &mut broadcast);
+                    broadcast(Vec::new());
+                });
+            }
+            None => {
+                thread::spawn(move || {
+                    let mut broadcast = broadcast;
+                    read_pty(reader, &read_gate, &mut broadcast);
+                    broadcast(Vec::new());
+                });
+            }
+        }
+
+        Ok(Self {
+            master: pair.master,
+            writer,
+            child,
+            output,
+            exited: false,
+            spawned,
+            foreground: None,
+            gate,
+            #[cfg(unix)]
+            hung_up: Vec::new(),
+        })
+    }
+
+    /// Stops or resumes reading the PTY. While stopped, output the child
+    /// writes stays in the kernel's buffer until it fills and blocks the
+    /// child.
+    pub fn set_output_paused(&self, paused: bool) {
+        self.gate.set_paused(paused);
+    }
+
+    /// Receives output chunks read from the PTY after this call. An empty
+    /// chunk marks the end of output: the child and everything it started
+    /// have closed the terminal.
+    pub fn subscribe(&self) -> broadcast::Receiver<Vec<u8>> {
+        self.output.subscribe()
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.writer.flush()
+    }
+
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.master.resize(size).map_err(pty_error)
+    }
+
+    /// The size the PTY reports to programs.
+    pub fn size(&self) -> io::Result<PtySize> {
+        self.master.get_size().map_err(pty_error)
+    }
+
+    /// Sends SIGWINCH to the foreground process group, or to the child's
+    /// group when it can't be looked up, so programs re-read the size.
+    #[cfg(unix)]
+    pub fn notify_resize(&self) -> io::Result<()> {
+        let pgid = self
+            .master
+            .process_group_leader()
+            .map(|leader| leader as u32)
+            .or_else(|| self.process_id());
+        match pgid {
+            Some(pgid) => ProcessManager::signal_group(pgid, libc::SIGWINCH),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn notify_resize(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn process_id(&self) -> Option<u32> {
+        self.child.process_id()
+    }
+
+    /// Reaps the child if it has exited.
+    pub fn has_exited(&mut self) -> bool {
+        if !self.exited {
+            self.exited = matches!(self.child.try_wait(), Ok(Some(_)));
+        }
+        self.exited
+    }
+
+    /// The leader of the process group that owns the terminal, e.g. vim
+    /// when the shell is running it. Lookups are cached for
+    /// `FOREGROUND_REFRESH_INTERVAL`.
+    pub fn foreground_process(&mut self) -> Option<ProcessInfo> {
+        if let Some((checked, info)) = &self.foreground {
+            if checked.elapsed() < FOREGROUND_REFRESH_INTERVAL {
+                return info.clone();
+            }
+        }
+        let info = self.lookup_foreground_process();
+        self.foreground = Some((Instant::now(), info.clone()));
+        info
+    }
+
+    #[cfg(unix)]
+    fn lookup_foreground_process(&self) -> Option<ProcessInfo> {
+        let leader = self.master.process_group_leader()?;
+        process_info(leader as u32)
+    }
+
+    #[cfg(not(unix))]
+    fn lookup_foreground_process(&self) -> Option<ProcessInfo> {
+        Some(self.spawned.clone())
+    }
+
+    /// Whether a program other than the spawned child owns the terminal.
+    pub fn has_foreground_job(&mut self) -> bool {
+        self.foreground_process()
+            .is_some_and(|info| info.pid != self.spawned.pid)
+    }
+
+    /// The child's process group and, when a job owns the terminal, the
+    /// job's, which a shell with job control starts in its own group.
+    #[cfg(unix)]
+    fn process_groups(&self) -> Vec<u32> {
+        let mut groups: Vec<u32> = self.process_id().into_iter().collect();
+        if let Some(leader) = self.master.process_group_leader() {
+            let leader = leader as u32;
+            if leader > 0 && !groups.contains(&leader) {
+                groups.push(leader);
+            }
+        }
+        groups
+    }
+
+    #[cfg(unix)]
+    fn hangup(&mut self) {
+        self.hung_up = self.process_groups();
+        if self.hung_up.is_empty() {
+            self.kill();
+        }
+        for &pgid in &self.hung_up {
+            let _ = ProcessManager::hangup_group(pgid);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn hangup(&mut self) {
+        self.kill();
+    }
+
+    /// Whether the child and the groups it was hung up with are gone.
+    #[cfg(unix)]
+    fn has_stopped(&mut self) -> bool {
+        self.has_exited()
+            && self
+                .hung_up
+                .iter()
+                .all(|&pgid| !ProcessManager::group_exists(pgid))
+    }
+
+    #[cfg(not(unix))]
+    fn has_stopped(&mut self) -> bool {
+        self.has_exited()
+    }
+
+    #[cfg(unix)]
+    fn terminate(&mut self) {
+        if self.hung_up.is_empty() {
+            self.hung_up = self.process_groups();
+        }
+        if self.hung_up.is_empty() {
+            self.kill();
+        }
+        for &pgid in &self.hung_up {
+            let _ = ProcessManager::terminate_group(pgid);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn terminate(&mut self) {
+        self.kill();
+    }
+
+    pub fn kill(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+/// Owns every PTY session of the application.
+pub struct PtyManager {
+    sessions: HashMap<usize, PtySession>,
+    next_id: usize,
+    /// How long children get to exit after SIGHUP before being killed.
+    pub grace_period: Duration,
+}
+
+impl PtyManager {
+    pub fn new() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            next_id: 0,
+            grace_period: DEFAULT_GRACE_PERIOD,
+        }
+    }
+
+    pub fn spawn(&mut self, config: &PtyConfig) -> io::Result<usize> {
+        let session = PtySession::spawn(config)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sessions.insert(id, session);
+        Ok(id)
+    }
+
+    pub fn get(&self, id: usize) -> Option<&PtySession> {
+        self.sessions.get(&id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut PtySession> {
+        self.sessions.get_mut(&id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Whether closing now would interrupt a running program.
+    pub fn has_foreground_jobs(&mut self) -> bool {
+        self.sessions
+            .values_mut()
+            .any(PtySession::has_foreground_job)
+    }
+
+    /// Hangs up every session, including the job in its foreground, waits
+    /// up to `grace_period` for them to exit, kills the process groups of
+    /// those that didn't, and drops all sessions.
+    pub fn shutdown_all(&mut self) {
+        for session in self.sessions.values_mut() {
+            session.hangup();
+        }
+
+        let deadline = Instant::now() + self.grace_period;
+        while Instant::now() < deadline {
+            let mut running = false;
+            for session in self.sessions.values_mut() {
+                running |= !session.has_stopped();
+            }
+            if !running {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for (_, mut session) in self.sessions.drain() {
+            if !session.has_stopped() {
+                session.terminate();
+            }
+            if !session.has_exited() {
+                let _ = session.child.wait();
+            }
+        }
+    }
+}
+
+impl Default for PtyManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paused_gate_holds_back_reads() {
+        let gate = Arc::new(ReadGate::default());
+        gate.set_paused(true);
+        let (sender, reads) = mpsc::channel();
+        let reader_gate = Arc::clone(&gate);
+        let reader = thread::spawn(move || {
+            let reader: Box<dyn Read + Send> = Box::new(io::Cursor::new(b"output".to_vec()));
+            read_pty(reader, &reader_gate, |chunk| {
+                let _ = sender.send(chunk);
+            });
+        });
+
+        assert!(reads.recv_timeout(Duration::from_millis(50)).is_err());
+        gate.set_paused(false);
+        assert_eq!(
+            reads.recv_timeout(Duration::from_secs(5)).ok(),
+            Some(b"output".to_vec())
+        );
+        reader.join().unwrap();
+    }
+
+    fn coalesced(reads: Vec<Vec<u8>>, config: ReadCoalescing) -> Vec<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel();
+        for read in reads {
+            sender.send(read).unwrap();
+        }
+        drop(sender);
+        let mut chunks = Vec::new();
+        coalesce(receiver, config, |chunk| chunks.push(chunk));
+        chunks
+    }
+
+    #[test]
+    fn queued_reads_are_merged_up_to_the_cap() {
+        let reads = vec![b"ab".to_vec(); 100];
+        let chunks = coalesced(reads.clone(), ReadCoalescing::default());
+        assert_eq!(chunks, [b"ab".repeat(100)]);
+
+        let config = ReadCoalescing {
+            max_bytes: 64,
+            ..ReadCoalescing::default()
+        };
+        let sizes: Vec<usize> = coalesced(reads, config).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [64, 64, 64, 8]);
+    }
+
+    #[test]
+    fn a_lone_read_is_sent_at_once() {
+        let config = ReadCoalescing {
+            window: Duration::from_secs(5),
+            ..ReadCoalescing::default()
+        };
+        let (reads, receiver) = mpsc::channel();
+        let (sender, chunks) = mpsc::channel();
+        let merger =
+            thread::spawn(move || coalesce(receiver, config, |chunk| sender.send(chunk).unwrap()));
+
+        // An echoed keystroke doesn't wait out the window
+        let start = Instant::now();
+        reads.send(b"x".to_vec()).unwrap();
+        assert_eq!(chunks.recv_timeout(Duration::from_secs(1)).unwrap(), b"x");
+        assert!(start.elapsed() < config.window);
+
+        // A full read is a sign more is coming, so the window is waited out
+        reads.send(vec![b'a'; READ_BUFFER_SIZE]).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        reads.send(b"tail".to_vec()).unwrap();
+        assert_eq!(
+            chunks.recv_timeout(Duration::from_secs(5)).unwrap().len(),
+            READ_BUFFER_SIZE + 4
+        );
+        drop(reads);
+        merger.join().unwrap();
+    }
+
+    #[cfg(unix)]
+    fn is_running(pid: u32) -> bool {
+        if cfg!(target_os = "linux") {
+            // An init that doesn't reap orphans leaves them as zombies
+            return std::fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+                stat.rsplit_once(") ")
+                    .is_some_and(|(_, fields)| !fields.starts_with('Z'))
+            });
+        }
+        unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_hangs_up_children_and_forgets_sessions() {
+        let mut manager = PtyManager::new();
+        let id = manager
+            .spawn(&PtyConfig {
+                shell: "sleep".to_string(),
+                args: vec!["100".to_string()],
+                ..PtyConfig::default()
+            })
+            .unwrap();
+        let pid = manager.get(id).and_then(PtySession::process_id).unwrap();
+        assert!(is_running(pid));
+
+        manager.shutdown_all();
+        assert!(manager.is_empty());
+        assert!(!is_running(pid));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shutdown_kills_children_that_ignore_the_hangup() {
+        let mut manager = PtyManager::new();
+        manager.grace_period = Duration::from_millis(100);
+        let id = manager
+            .spawn(&PtyConfig {
+                shell: "sh".to_string(),
+                args: vec!["-c".to_string(), "trap '' HUP; exec sleep 100".to_string()],
+                ..PtyConfig::default()
+            })
+            .unwrap();
+        let pid = manager.get(id).and_then(PtySession::process_id).unwrap();
+        // Give the shell time to install the trap before it's hung up
+        thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        manager.shutdown_all();
+        assert!(manager.is_empty());
+        assert!(!is_running(pid));
+        assert!(start.elapsed() >= manager.grace_period);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_login_shell_is_an_executable_with_a_dashed_argv0() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config = PtyConfig::login_shell();
+        assert!(config.login);
+        let path = std::path::Path::new(&config.shell);
+        let mode = std::fs::metadata(path).unwrap().permissions().mode();
+        assert!(path.is_absolute() && mode & 0o111 != 0, "{}", config.shell);
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert_eq!(config.argv0(), format!("-{}", name));
+
+        let plain = PtyConfig {
+            shell: "/bin/sh".to_string(),
+            ..PtyConfig::default()
+        };
+        assert_eq!(plain.argv0(), "/bin/sh");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn login_shells_start_with_the_dashed_argv0() {
+        let mut session = PtySession::spawn(&PtyConfig {
+            shell: "/bin/sh".to_string(),
+            login: true,
+            ..PtyConfig::default()
+        })
+        .unwrap();
+        let mut output = session.subscribe();
+        session.write(b"echo \"[$0]\"; exit\n").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut text = String::new();
+        while !text.contains("[-sh]") && Instant::now() < deadline {
+            match output.try_recv() {
+                Ok(chunk) => text.push_str(&String::from_utf8_lossy(&chunk)),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        assert!(text.contains("[-sh]"), "{:?}", text);
+        session.kill();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn foreground_process_is_the_job_the_shell_runs() {
+        let mut session = PtySession::spawn(&PtyConfig {
+            shell: "sh".to_string(),
+            args: vec!["-i".to_string()],
+            ..PtyConfig::default()
+        })
+        .unwrap();
+        session.write(b"sleep 5\n").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut foreground = None;
+        while Instant::now() < deadline {
+            foreground = session.foreground_process();
+            if foreground.as_ref().is_some_and(|info| info.name == "sleep") {
+                break;
+            }
+            thread::sleep(FOREGROUND_REFRESH_INTERVAL);
+        }
+        let foreground = foreground.unwrap();
+        assert_eq!(foreground.name, "sleep");
+        assert!(session.has_foreground_job());
+        let _ = ProcessManager::terminate_group(foreground.pid);
+        session.kill();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn shutdown_ends_the_job_in_the_foreground() {
+        let mut manager = PtyManager::new();
+        manager.grace_period = Duration::from_millis(200);
+        let id = manager
+            .spawn(&PtyConfig {
+                shell: "sh".to_string(),
+                args: vec!["-i".to_string()],
+                ..PtyConfig::default()
+            })
+            .unwrap();
+        let session = manager.get_mut(id).unwrap();
+        // The job ignores the hangup, so only the kill ends it
+        session
+            .write(b"sh -c \"trap '' HUP; exec sleep 100\"\n")
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut job = None;
+        while job.is_none() && Instant::now() < deadline {
+            job = session
+                .foreground_process()
+                .filter(|info| info.name == "sleep")
+                .map(|info| info.pid);
+            thread::sleep(FOREGROUND_REFRESH_INTERVAL);
+        }
+        let job = job.expect("sleep never reached the foreground");
+        assert!(is_running(job));
+
+        manager.shutdown_all();
+        assert!(manager.is_empty());
+        // SIGKILL lands asynchronously, so give the job a moment to die
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while is_running(job) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!is_running(job));
+    }
+}
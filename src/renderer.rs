@@ -0,0 +1,2070 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use fontdue::{Font, FontSettings};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferAddress,
+    BufferBindingType, BufferDescriptor, BufferUsages, ColorTargetState, ColorWrites,
+    CompositeAlphaMode, Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture,
+    ImageDataLayout, MultisampleState, Origin3d, PipelineLayoutDescriptor, PresentMode,
+    PrimitiveState, Queue, RenderPass, RenderPipeline, RenderPipelineDescriptor,
+    SamplerBindingType, SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages,
+    SurfaceConfiguration, Texture, TextureAspect, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureSampleType, TextureUsages, TextureViewDescriptor, TextureViewDimension,
+    VertexAttribute, VertexBufferLayout, VertexState, VertexStepMode,
+};
+
+mod box_drawing;
+mod images;
+mod layers;
+mod shaping;
+
+pub use images::clip_image;
+use images::ImageRenderer;
+pub use layers::{CellRange, CellRangeHighlight, RenderLayers};
+pub use shaping::{ShapedGlyph, Shaper};
+
+use crate::context_menu::ContextMenu;
+use crate::hints::HintState;
+use crate::terminal::grapheme::Combining;
+use crate::terminal::{char_width, Blink, Color, Terminal, TerminalCell, UnderlineStyle};
+
+pub const FONT_SIZE: f32 = 16.0;
+const MIN_FONT_SIZE: f32 = 6.0;
+const MAX_FONT_SIZE: f32 = 72.0;
+const ATLAS_SIZE: u32 = 1024;
+pub const MIN_LINE_SPACING: f32 = 0.5;
+pub const MAX_LINE_SPACING: f32 = 3.0;
+/// Size of the fully-covered block at the atlas origin used for solid quads.
+const SOLID_BLOCK: u32 = 2;
+const HINT_FG: Color = Color(0, 0, 0, 255);
+const HINT_BG: Color = Color(255, 200, 0, 255);
+const OVERLAY_FG: Color = Color(230, 230, 230, 255);
+const OVERLAY_BG: Color = Color(0, 0, 0, 200);
+const DROP_TARGET: Color = Color(90, 140, 255, 160);
+const MENU_FG: Color = Color(230, 230, 230, 255);
+const MENU_BG: Color = Color(45, 45, 45, 255);
+const MENU_SELECTED_BG: Color = Color(60, 95, 170, 255);
+const MENU_DISABLED_FG: Color = Color(120, 120, 120, 255);
+pub const SCROLLBAR_WIDTH: f32 = 6.0;
+pub const SCROLLBAR_COLOR: Color = Color(160, 160, 160, 140);
+/// How long the scrollbar stays after the viewport last moved.
+const SCROLLBAR_IDLE: Duration = Duration::from_millis(1500);
+/// Shortest thumb, so it stays visible over long scrollback.
+const MIN_SCROLLBAR_THUMB: f32 = 16.0;
+
+const FONT_PATHS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/TTF/DejaVuSansMono.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSansMono.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationMono-Regular.ttf",
+    "/System/Library/Fonts/Menlo.ttc",
+    "C:\\Windows\\Fonts\\consola.ttf",
+];
+
+/// One quad: a cell background, glyph, line or overlay. The vertex shader
+/// expands each instance into two triangles. A cell takes as many quads as
+/// it draws, since underlines, combining marks, box drawing and shaped
+/// glyphs don't fit one quad per cell; blank default cells take none.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Instance {
+    /// `[left, top, right, bottom]` in pixels from the grid origin.
+    rect: [f32; 4],
+    /// Atlas coordinates of the same corners.
+    uv: [f32; 4],
+    color: [f32; 4],
+}
+
+impl Instance {
+    const ATTRIBUTES: [VertexAttribute; 3] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4, 2 => Float32x4];
+
+    fn layout() -> VertexBufferLayout<'static> {
+        VertexBufferLayout {
+            array_stride: std::mem::size_of::<Instance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Maps grid pixels to clip space; see `Uniforms` in `text.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct Uniforms {
+    surface_size: [f32; 2],
+    /// Position of the grid origin on the surface: the padding.
+    origin: [f32; 2],
+    /// Pixels the quads are moved down by, mid-way through scrolling a
+    /// line; always 0 for the overlays.
+    scroll: f32,
+    _padding: f32,
+}
+
+/// A uniform buffer and the bind group drawing text with it. The grid and
+/// the overlays each have one, as only the grid scrolls.
+struct TextBinding {
+    buffer: Buffer,
+    bind_group: BindGroup,
+    /// Uniforms last written, so unchanged ones aren't uploaded again.
+    written: Option<Uniforms>,
+}
+
+impl TextBinding {
+    fn write(&mut self, queue: &Queue, uniforms: Uniforms) {
+        if self.written != Some(uniforms) {
+            queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniforms));
+            self.written = Some(uniforms);
+        }
+    }
+}
+
+/// Everything the grid's instances are built from besides the scroll
+/// fraction, so a scroll by part of a line only updates the uniforms.
+#[derive(Debug, Clone, PartialEq)]
+struct GridKey {
+    /// Rows drawn, by viewport line.
+    rows: Vec<(isize, Vec<TerminalCell>)>,
+    layers: RenderLayers,
+    cursor: Option<(usize, usize)>,
+    h_offset: usize,
+    /// Slow and rapid blinking text shown.
+    blink: (bool, bool),
+    reverse_screen: bool,
+    bold_is_bright: bool,
+    min_contrast: Option<f32>,
+    default_colors: (Color, Color),
+    cell_size: (f32, f32),
+    background: Color,
+    background_opacity: f32,
+    opaque_cell_backgrounds: bool,
+    builtin_box_drawing: bool,
+    ligatures: bool,
+    /// Atlas generation, which the glyphs' coordinates belong to.
+    generation: u64,
+}
+
+/// Location of a rasterized glyph inside the atlas.
+#[derive(Debug, Clone, Copy)]
+pub struct GlyphInfo {
+    pub uv: [f32; 4],
+    pub width: f32,
+    pub height: f32,
+    pub xmin: f32,
+    pub ymin: f32,
+}
+
+/// Counters shown in the debug HUD.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RendererStats {
+    /// Quads drawn, one instance each.
+    pub instances: usize,
+    /// Times the grid's instances were built rather than reused.
+    pub grid_builds: usize,
+    pub draw_calls: usize,
+    pub glyphs: usize,
+    /// Fraction of the atlas height used by glyph shelves.
+    pub atlas_occupancy: f32,
+}
+
+/// Rasterizes glyphs with fontdue and packs them into a CPU-side atlas that
+/// the renderer uploads when it changes. Glyphs are cached by glyph id, so
+/// characters and the ligatures the shaper substitutes share the atlas.
+pub struct TextRenderer {
+    font: Font,
+    /// Size in logical pixels; glyphs are rasterized at this times
+    /// `scale_factor`.
+    font_size: f32,
+    scale_factor: f32,
+    /// Bumped whenever the atlas is cleared and glyphs re-rasterize.
+    generation: u64,
+    /// `None` if rustybuzz couldn't read the font; text is then unshaped.
+    shaper: Option<Shaper>,
+    glyphs: HashMap<u16, GlyphInfo>,
+    atlas: Vec<u8>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    atlas_dirty: bool,
+}
+
+impl TextRenderer {
+    pub fn new(font_data: &[u8], font_size: f32) -> Result<Self, String> {
+        let font =
+            Font::from_bytes(font_data, FontSettings::default()).map_err(|e| e.to_string())?;
+        let mut atlas = vec![0u8; (ATLAS_SIZE * ATLAS_SIZE) as usize];
+        for y in 0..SOLID_BLOCK {
+            for x in 0..SOLID_BLOCK {
+                atlas[(y * ATLAS_SIZE + x) as usize] = 255;
+            }
+        }
+        Ok(Self {
+            font,
+            font_size,
+            scale_factor: 1.0,
+            generation: 0,
+            shaper: Shaper::new(font_data),
+            glyphs: HashMap::new(),
+            atlas,
+            shelf_x: SOLID_BLOCK + 1,
+            shelf_y: 0,
+            shelf_height: SOLID_BLOCK,
+            atlas_dirty: true,
+        })
+    }
+
+    /// Loads the first monospace font found in `RT_FONT` or the usual system locations.
+    pub fn from_system_font(font_size: f32) -> Result<Self, String> {
+        let env_path = std::env::var("RT_FONT").ok();
+        let candidates = env_path
+            .iter()
+            .map(String::as_str)
+            .chain(FONT_PATHS.iter().copied());
+        for path in candidates {
+            if let Ok(data) = std::fs::read(path) {
+                return Self::new(&data, font_size);
+            }
+        }
+        Err("no monospace font found; set RT_FONT to a .ttf file".to_string())
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.font_size
+    }
+
+    /// Changes the rasterization size, dropping all cached glyphs.
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.font_size = font_size;
+        self.reset_atlas();
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// Rasterizes for a display with `scale_factor` physical pixels per
+    /// logical one, dropping all cached glyphs if it changed.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        if scale_factor > 0.0 && scale_factor != self.scale_factor {
+            self.scale_factor = scale_factor;
+            self.reset_atlas();
+        }
+    }
+
+    /// Size glyphs are rasterized at, in physical pixels.
+    pub fn pixel_size(&self) -> f32 {
+        self.font_size * self.scale_factor
+    }
+
+    /// Counts atlas resets, so callers can tell cached glyph positions
+    /// went stale.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn ascent(&self) -> f32 {
+        self.font
+            .horizontal_line_metrics(self.pixel_size())
+            .map(|m| m.ascent)
+            .unwrap_or(self.pixel_size())
+    }
+
+    /// Natural cell size in physical pixels at the current font size and
+    /// scale: the advance of `M` by the line height (ascent, descent and
+    /// line gap).
+    pub fn cell_size(&self) -> (f32, f32) {
+        let size = self.pixel_size();
+        let width = self.font.metrics('M', size).advance_width;
+        match self.font.horizontal_line_metrics(size) {
+            Some(line) if width > 0.0 => (width, line.new_line_size),
+            _ => cell_size_for(size),
+        }
+    }
+
+    /// Texture coordinates of the solid block used for backgrounds and lines.
+    pub fn solid_uv(&self) -> [f32; 4] {
+        let texel = 0.5 / ATLAS_SIZE as f32;
+        [texel, texel, texel, texel]
+    }
+
+    pub fn glyph(&mut self, ch: char) -> Option<GlyphInfo> {
+        self.glyph_by_id(self.font.lookup_glyph_index(ch))
+    }
+
+    pub fn glyph_by_id(&mut self, id: u16) -> Option<GlyphInfo> {
+        if let Some(info) = self.glyphs.get(&id) {
+            return Some(*info);
+        }
+        let (metrics, bitmap) = self.font.rasterize_indexed(id, self.pixel_size());
+        if metrics.width == 0 || metrics.height == 0 {
+            return None;
+        }
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+        if width + 1 > ATLAS_SIZE || height + 1 > ATLAS_SIZE {
+            return None;
+        }
+        if self.shelf_x + width + 1 > ATLAS_SIZE {
+            self.shelf_x = 0;
+            self.shelf_y += self.shelf_height + 1;
+            self.shelf_height = 0;
+        }
+        if self.shelf_y + height + 1 > ATLAS_SIZE {
+            // Atlas full: start over and let glyphs re-rasterize on demand
+            self.reset_atlas();
+        }
+
+        let (x0, y0) = (self.shelf_x, self.shelf_y);
+        for row in 0..height {
+            let src = (row * width) as usize;
+            let dst = ((y0 + row) * ATLAS_SIZE + x0) as usize;
+            self.atlas[dst..dst + width as usize]
+                .copy_from_slice(&bitmap[src..src + width as usize]);
+        }
+        self.shelf_x += width + 1;
+        self.shelf_height = self.shelf_height.max(height);
+        self.atlas_dirty = true;
+
+        let size = ATLAS_SIZE as f32;
+        let info = GlyphInfo {
+            uv: [
+                x0 as f32 / size,
+                y0 as f32 / size,
+                (x0 + width) as f32 / size,
+                (y0 + height) as f32 / size,
+            ],
+            width: width as f32,
+            height: height as f32,
+            xmin: metrics.xmin as f32,
+            ymin: metrics.ymin as f32,
+        };
+        self.glyphs.insert(id, info);
+        Some(info)
+    }
+
+    /// Glyphs that replace the characters of `cells`, from
+    /// [`Shaper::shape_row`], and the scale from font units to pixels.
+    /// Empty if the font can't be shaped.
+    pub fn shape_row(
+        &mut self,
+        cells: &[TerminalCell],
+        cursor: Option<usize>,
+    ) -> (Rc<[ShapedGlyph]>, f32) {
+        let pixel_size = self.pixel_size();
+        match &mut self.shaper {
+            Some(shaper) => (
+                shaper.shape_row(cells, cursor),
+                pixel_size / shaper.units_per_em(),
+            ),
+            None => (Rc::new([]), 0.0),
+        }
+    }
+
+    pub fn glyph_count(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    pub fn atlas_occupancy(&self) -> f32 {
+        (self.shelf_y + self.shelf_height) as f32 / ATLAS_SIZE as f32
+    }
+
+    fn reset_atlas(&mut self) {
+        self.glyphs.clear();
+        for (i, texel) in self.atlas.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % ATLAS_SIZE, i as u32 / ATLAS_SIZE);
+            *texel = if x < SOLID_BLOCK && y < SOLID_BLOCK {
+                255
+            } else {
+                0
+            };
+        }
+        self.shelf_x = SOLID_BLOCK + 1;
+        self.shelf_y = 0;
+        self.shelf_height = SOLID_BLOCK;
+        self.atlas_dirty = true;
+        self.generation += 1;
+    }
+}
+
+pub struct Renderer {
+    pipeline: RenderPipeline,
+    atlas_texture: Texture,
+    grid_binding: TextBinding,
+    overlay_binding: TextBinding,
+    instance_buffer: Buffer,
+    instance_capacity: usize,
+    instance_count: u32,
+    /// What the grid's instances, the first `grid_len`, were built from;
+    /// `None` rebuilds them on the next `prepare`.
+    grid_key: Option<GridKey>,
+    grid_len: usize,
+    grid_builds: usize,
+    /// Instances drawn by the image pipeline, between the grid's and the
+    /// overlays'.
+    image_instances: Range<u32>,
+    images: ImageRenderer,
+    text: TextRenderer,
+    width: f32,
+    height: f32,
+    /// Physical pixels between the grid and the surface edge.
+    padding: f32,
+    instances: Vec<Instance>,
+    /// Default background the surface is cleared to.
+    background: Color,
+    /// Alpha of the default background; 1 unless the surface is translucent.
+    background_opacity: f32,
+    /// Keep explicitly colored cell backgrounds opaque when translucent.
+    opaque_cell_backgrounds: bool,
+    /// The surface expects premultiplied colors.
+    premultiplied: bool,
+    /// Files are dragged over the window; the grid is outlined.
+    drop_target: bool,
+    /// Draw box-drawing and block characters from quads instead of the font.
+    builtin_box_drawing: bool,
+    /// Factor applied to the font's line height.
+    line_spacing: f32,
+    /// Logical pixels added to the font's advance.
+    letter_spacing: f32,
+    /// Shape rows so the font can draw ligatures.
+    ligatures: bool,
+    /// Whether the window has focus, which decides how the cursor is drawn.
+    focused: bool,
+    /// Columns of the current row drawn by shaped glyphs.
+    shaped_columns: Vec<bool>,
+    /// Foreground of each column of the current row; `None` while hidden.
+    row_fg: Vec<Option<Color>>,
+    /// Width and color of the scrollbar, if it is enabled.
+    scrollbar: Option<(f32, Color)>,
+    /// History length and scroll offset the scrollbar last showed.
+    scrollbar_position: (usize, f32),
+    /// When the viewport last moved; the scrollbar hides once it has been
+    /// still for `SCROLLBAR_IDLE`.
+    scrollbar_shown_at: Option<Instant>,
+}
+
+impl Renderer {
+    pub fn new(device: &Device, config: &SurfaceConfiguration) -> Result<Self, String> {
+        let text = TextRenderer::from_system_font(FONT_SIZE)?;
+        Ok(Self::with_text(device, config, text))
+    }
+
+    /// Creates a renderer drawing glyphs from `text`.
+    pub fn with_text(device: &Device, config: &SurfaceConfiguration, text: TextRenderer) -> Self {
+        let (pipeline, atlas_texture, grid_binding, overlay_binding) =
+            Self::create_pipeline(device, config.format);
+        let instance_capacity = 4096;
+        let instance_buffer = Self::create_instance_buffer(device, instance_capacity);
+
+        Self {
+            pipeline,
+            atlas_texture,
+            grid_binding,
+            overlay_binding,
+            instance_buffer,
+            instance_capacity,
+            instance_count: 0,
+            grid_key: None,
+            grid_len: 0,
+            grid_builds: 0,
+            image_instances: 0..0,
+            images: ImageRenderer::new(device, config.format),
+            text,
+            width: config.width as f32,
+            height: config.height as f32,
+            padding: 0.0,
+            instances: Vec::new(),
+            background: Color::BLACK,
+            background_opacity: 1.0,
+            opaque_cell_backgrounds: true,
+            premultiplied: false,
+            drop_target: false,
+            builtin_box_drawing: false,
+            line_spacing: 1.0,
+            letter_spacing: 0.0,
+            ligatures: true,
+            focused: true,
+            shaped_columns: Vec::new(),
+            row_fg: Vec::new(),
+            scrollbar: None,
+            scrollbar_position: (0, 0.0),
+            scrollbar_shown_at: None,
+        }
+    }
+
+    /// Rebuilds every GPU resource on `device`, e.g. after the device was
+    /// lost. Cached glyphs keep their atlas coordinates; the CPU-side atlas
+    /// and the images are uploaded again on the next `prepare`.
+    pub fn recreate(&mut self, device: &Device, config: &SurfaceConfiguration) {
+        let (pipeline, atlas_texture, grid_binding, overlay_binding) =
+            Self::create_pipeline(device, config.format);
+        self.pipeline = pipeline;
+        self.atlas_texture = atlas_texture;
+        self.grid_binding = grid_binding;
+        self.overlay_binding = overlay_binding;
+        self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        self.instance_count = 0;
+        self.grid_key = None;
+        self.image_instances = 0..0;
+        self.images = ImageRenderer::new(device, config.format);
+        self.text.atlas_dirty = true;
+        self.resize(config.width, config.height);
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        format: TextureFormat,
+    ) -> (RenderPipeline, Texture, TextBinding, TextBinding) {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Text Shader"),
+            source: ShaderSource::Wgsl(include_str!("shaders/text.wgsl").into()),
+        });
+
+        let atlas_texture = device.create_texture(&TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let atlas_view = atlas_texture.create_view(&TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Glyph Sampler"),
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Text Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let binding = |label| {
+            let buffer = device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size: std::mem::size_of::<Uniforms>() as BufferAddress,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some(label),
+                layout: &bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(&atlas_view),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            });
+            TextBinding {
+                buffer,
+                bind_group,
+                written: None,
+            }
+        };
+        let grid_binding = binding("Text Grid Uniforms");
+        let overlay_binding = binding("Text Overlay Uniforms");
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Text Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Text Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Instance::layout()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        (pipeline, atlas_texture, grid_binding, overlay_binding)
+    }
+
+    fn create_instance_buffer(device: &Device, capacity: usize) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("Text Instance Buffer"),
+            size: (capacity * std::mem::size_of::<Instance>()) as BufferAddress,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width as f32;
+        self.height = height as f32;
+    }
+
+    pub fn font_size(&self) -> f32 {
+        self.text.font_size()
+    }
+
+    /// Sets the color the surface is cleared to, normally the terminal's
+    /// default background.
+    pub fn set_clear_color(&mut self, color: Color) {
+        self.background = color;
+    }
+
+    /// The default background in linear space with the background opacity
+    /// applied, premultiplied if the surface expects it.
+    pub fn clear_color(&self) -> wgpu::Color {
+        let [r, g, b, _] = linear_color(self.background);
+        let a = self.background_opacity;
+        let scale = if self.premultiplied { a } else { 1.0 };
+        wgpu::Color {
+            r: (r * scale) as f64,
+            g: (g * scale) as f64,
+            b: (b * scale) as f64,
+            a: a as f64,
+        }
+    }
+
+    /// Makes the default background translucent. `alpha_mode` is the mode
+    /// the surface was configured with; an opaque one keeps everything
+    /// opaque whatever `opacity` says.
+    pub fn set_background_opacity(
+        &mut self,
+        opacity: f32,
+        alpha_mode: CompositeAlphaMode,
+        opaque_cell_backgrounds: bool,
+    ) {
+        let translucent = matches!(
+            alpha_mode,
+            CompositeAlphaMode::PreMultiplied | CompositeAlphaMode::PostMultiplied
+        );
+        self.background_opacity = if translucent {
+            opacity.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        self.premultiplied = alpha_mode == CompositeAlphaMode::PreMultiplied;
+        self.opaque_cell_backgrounds = opaque_cell_backgrounds;
+    }
+
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.text
+            .set_font_size(font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE));
+    }
+
+    /// Size of one terminal cell in physical pixels: the font's metrics
+    /// at the current scale with the line and letter spacing applied.
+    pub fn cell_size(&self) -> (f32, f32) {
+        spaced_cell_size(
+            self.text.cell_size(),
+            self.line_spacing,
+            self.letter_spacing * self.text.scale_factor(),
+        )
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.text.scale_factor()
+    }
+
+    /// Follows the window to a display with `scale_factor` physical pixels
+    /// per logical one: glyphs re-rasterize at the new size and the
+    /// scrollbar keeps its logical width. Padding is the caller's to update.
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        if scale_factor <= 0.0 {
+            return;
+        }
+        let ratio = scale_factor / self.text.scale_factor();
+        if let Some((width, _)) = &mut self.scrollbar {
+            *width *= ratio;
+        }
+        self.text.set_scale_factor(scale_factor);
+    }
+
+    /// Scales the line height by `factor`, between 0.5 and 3. Glyphs stay
+    /// vertically centered in the taller or shorter cell.
+    pub fn set_line_spacing(&mut self, factor: f32) {
+        self.line_spacing = factor.clamp(MIN_LINE_SPACING, MAX_LINE_SPACING);
+    }
+
+    /// Widens (or, when negative, narrows) every cell by `pixels`. Glyphs
+    /// stay horizontally centered.
+    pub fn set_letter_spacing(&mut self, pixels: f32) {
+        self.letter_spacing = pixels;
+    }
+
+    pub fn padding(&self) -> f32 {
+        self.padding
+    }
+
+    /// Insets the grid by `padding` physical pixels on every side.
+    pub fn set_padding(&mut self, padding: f32) {
+        self.padding = padding.max(0.0);
+    }
+
+    /// Outlines the grid while files are dragged over the window.
+    pub fn set_drop_target(&mut self, drop_target: bool) {
+        self.drop_target = drop_target;
+    }
+
+    /// Draws box-drawing (U+2500–257F) and block element (U+2580–259F)
+    /// characters as quads fitted to the cell, so borders have no seams.
+    /// Characters it doesn't cover, like the diagonals, still use the font.
+    pub fn set_builtin_box_drawing(&mut self, builtin_box_drawing: bool) {
+        self.builtin_box_drawing = builtin_box_drawing;
+    }
+
+    /// Shapes text so fonts with ligatures, like Fira Code, can join
+    /// sequences such as `=>` into one glyph.
+    pub fn set_ligatures(&mut self, ligatures: bool) {
+        self.ligatures = ligatures;
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// The cursor as a layer over the grid: a block in the terminal's
+    /// cursor color, or one inverting the cell without a color. Without
+    /// focus it takes the unfocused color, or is an underline when there is
+    /// none.
+    pub fn cursor_layer(&self, terminal: &Terminal) -> Option<CellRangeHighlight> {
+        let (x, y) = terminal.visible_cursor()?;
+        // An unfocused cursor stays put rather than blinking
+        if self.focused && !terminal.cursor_blink_visible() {
+            return None;
+        }
+        let cell = CellRange::cell(x, y);
+        let color = if self.focused {
+            terminal.cursor_color()
+        } else {
+            terminal.cursor_color_unfocused()
+        };
+        Some(match color {
+            Some(color) => CellRangeHighlight {
+                bg: Some(color),
+                ..CellRangeHighlight::inverse(cell)
+            },
+            None if self.focused => CellRangeHighlight::inverse(cell),
+            None => CellRangeHighlight::underline(cell),
+        })
+    }
+
+    /// Shows a `width_px` wide scrollbar along the right edge while the
+    /// viewport moves through the scrollback.
+    pub fn set_scrollbar(&mut self, enabled: bool, width_px: f32, color: Color) {
+        self.scrollbar = enabled.then_some((width_px.max(1.0), color));
+    }
+
+    /// When the scrollbar will hide, so the frame without it can be drawn.
+    pub fn scrollbar_hides_at(&self) -> Option<Instant> {
+        self.scrollbar_shown_at.map(|shown| shown + SCROLLBAR_IDLE)
+    }
+
+    pub fn stats(&self) -> RendererStats {
+        RendererStats {
+            instances: self.instances.len(),
+            grid_builds: self.grid_builds,
+            draw_calls: self
+                .text_ranges()
+                .iter()
+                .filter(|range| !range.is_empty())
+                .count()
+                + self.images.count(),
+            glyphs: self.text.glyph_count(),
+            atlas_occupancy: self.text.atlas_occupancy(),
+        }
+    }
+
+    /// Builds the vertex data for the visible rows and their inline images.
+    /// `layers` are composited over the cells' own colors, `preedit` is the IME composition
+    /// string, drawn underlined at the cursor, `hints` are
+    /// labels drawn over the start of their matches, `menu` is drawn above
+    /// the grid, and `overlay` lines are drawn right-aligned in the top
+    /// corner.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prepare(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        terminal: &Terminal,
+        layers: &RenderLayers,
+        preedit: Option<&str>,
+        hints: Option<&HintState>,
+        menu: Option<&ContextMenu>,
+        overlay: &[String],
+    ) {
+        let (cell_width, cell_height) = self.cell_size();
+        let cursor = terminal.visible_cursor();
+        // Columns are drawn from the horizontal scroll offset; layers and
+        // the cursor keep row coordinates
+        let h_offset = terminal.h_scroll_offset();
+        let rows = (0..terminal.height)
+            .filter_map(|y| Some((y as isize, terminal.scrolled_row(y)?.into_owned())))
+            .chain(
+                terminal
+                    .scrolled_edge_row()
+                    .map(|(y, row)| (y, row.into_owned())),
+            )
+            .collect();
+        let key = GridKey {
+            rows,
+            layers: layers.clone(),
+            cursor,
+            h_offset,
+            blink: (
+                terminal.blink_visible(Blink::Slow),
+                terminal.blink_visible(Blink::Rapid),
+            ),
+            reverse_screen: terminal.state.reverse_screen,
+            bold_is_bright: terminal.bold_is_bright,
+            min_contrast: terminal.min_contrast(),
+            default_colors: terminal.default_colors(),
+            cell_size: (cell_width, cell_height),
+            background: self.background,
+            background_opacity: self.background_opacity,
+            opaque_cell_backgrounds: self.opaque_cell_backgrounds,
+            builtin_box_drawing: self.builtin_box_drawing,
+            ligatures: self.ligatures,
+            generation: self.text.generation(),
+        };
+        // Only the fraction of a line scrolled changed: the grid is moved
+        // by the uniforms alone
+        let grid_cached = self.grid_key.as_ref() == Some(&key);
+        if grid_cached {
+            self.instances.truncate(self.grid_len);
+        } else {
+            self.instances.clear();
+            for (y, row) in &key.rows {
+                self.push_row(terminal, layers, *y, row, cursor, h_offset);
+            }
+            self.grid_len = self.instances.len();
+            self.grid_builds += 1;
+        }
+
+        // Images cover the text under them; everything after is drawn over
+        self.images.begin();
+        let first_image = self.instances.len() as u32;
+        let bounds = [
+            0.0,
+            0.0,
+            terminal.width as f32 * cell_width,
+            terminal.height as f32 * cell_height,
+        ];
+        for (row, placement) in terminal.visible_images() {
+            let left = (placement.col as f32 - h_offset as f32) * cell_width;
+            let top = row as f32 * cell_height;
+            let rect = [
+                left,
+                top,
+                left + placement.width * cell_width,
+                top + placement.height * cell_height,
+            ];
+            if let Some((rect, uv)) = clip_image(rect, bounds) {
+                self.images
+                    .draw(device, queue, &self.grid_binding.buffer, &placement.image);
+                self.push_quad(rect, uv, Color::WHITE);
+            }
+        }
+        self.images.finish();
+        self.image_instances = first_image..self.instances.len() as u32;
+
+        if let Some(text) = preedit {
+            let origin_y = terminal.cursor_y as f32 * cell_height;
+            let mut x = terminal.cursor_x.min(terminal.width.saturating_sub(1)) as f32 * cell_width;
+            let (fg_color, bg_color) = terminal.default_colors();
+            for ch in text.chars() {
+                let mut cell = TerminalCell {
+                    ch,
+                    fg_color,
+                    bg_color,
+                    width: char_width(ch) as u8,
+                    ..TerminalCell::default()
+                };
+                cell.attrs.underline = UnderlineStyle::Single;
+                let (fg, bg) = terminal.resolve_colors(&cell);
+                self.push_cell(x, origin_y, &cell, fg, bg);
+                x += cell_width * cell.width as f32;
+            }
+        }
+
+        if let Some(hints) = hints {
+            let typed = hints.typed().chars().count();
+            for (label, hint) in hints.visible() {
+                let y = hint.row as f32 * cell_height;
+                for (i, ch) in label.chars().skip(typed).enumerate() {
+                    let col = hint.col + i;
+                    if col >= terminal.width {
+                        break;
+                    }
+                    let cell = TerminalCell {
+                        ch,
+                        ..TerminalCell::default()
+                    };
+                    self.push_cell(col as f32 * cell_width, y, &cell, HINT_FG, HINT_BG);
+                }
+            }
+        }
+
+        if let Some(menu) = menu {
+            let (col, row) = menu.origin();
+            let width = menu.width();
+            for (index, item) in menu.items().iter().enumerate() {
+                let fg = if item.enabled {
+                    MENU_FG
+                } else {
+                    MENU_DISABLED_FG
+                };
+                let bg = if menu.selected() == Some(index) {
+                    MENU_SELECTED_BG
+                } else {
+                    MENU_BG
+                };
+                let y = (row + index) as f32 * cell_height;
+                let text = format!(" {:<1$}", item.label, width - 1);
+                for (i, ch) in text.chars().enumerate() {
+                    let cell = TerminalCell {
+                        ch,
+                        ..TerminalCell::default()
+                    };
+                    self.push_cell((col + i) as f32 * cell_width, y, &cell, fg, bg);
+                }
+            }
+        }
+
+        if self.drop_target {
+            let (right, bottom) = (
+                self.width - 2.0 * self.padding,
+                self.height - 2.0 * self.padding,
+            );
+            let thickness = (cell_height / 8.0).max(2.0);
+            let solid = self.text.solid_uv();
+            for rect in [
+                [0.0, 0.0, right, thickness],
+                [0.0, bottom - thickness, right, bottom],
+                [0.0, thickness, thickness, bottom - thickness],
+                [right - thickness, thickness, right, bottom - thickness],
+            ] {
+                self.push_quad(rect, solid, DROP_TARGET);
+            }
+        }
+
+        if let Some((width, color)) = self.scrollbar {
+            self.push_scrollbar(terminal, width, color);
+        }
+
+        for (row, line) in overlay.iter().enumerate() {
+            let y = row as f32 * cell_height;
+            let mut x = self.width - 2.0 * self.padding - line.chars().count() as f32 * cell_width;
+            for ch in line.chars() {
+                let cell = TerminalCell {
+                    ch,
+                    ..TerminalCell::default()
+                };
+                self.push_cell(x, y, &cell, OVERLAY_FG, OVERLAY_BG);
+                x += cell_width;
+            }
+        }
+
+        // A reused grid is already in the buffer
+        let mut upload_from = if grid_cached { self.grid_len } else { 0 };
+        if self.instances.len() > self.instance_capacity {
+            self.instance_capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+            upload_from = 0;
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            (upload_from * std::mem::size_of::<Instance>()) as BufferAddress,
+            bytemuck::cast_slice(&self.instances[upload_from..]),
+        );
+        self.instance_count = self.instances.len() as u32;
+        self.grid_key = Some(key);
+
+        // Resizes, padding changes and scrolling by part of a line only
+        // touch the projection
+        let uniforms = Uniforms {
+            surface_size: [self.width, self.height],
+            origin: [self.padding, self.padding],
+            scroll: 0.0,
+            _padding: 0.0,
+        };
+        self.overlay_binding.write(queue, uniforms);
+        let scroll = terminal.scroll_fraction() * cell_height;
+        self.grid_binding
+            .write(queue, Uniforms { scroll, ..uniforms });
+
+        if self.text.atlas_dirty {
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.atlas_texture,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                &self.text.atlas,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(ATLAS_SIZE),
+                    rows_per_image: Some(ATLAS_SIZE),
+                },
+                Extent3d {
+                    width: ATLAS_SIZE,
+                    height: ATLAS_SIZE,
+                    depth_or_array_layers: 1,
+                },
+            );
+            self.text.atlas_dirty = false;
+        }
+    }
+
+    /// Draws `row` at viewport line `y`, which is -1 or the screen height
+    /// for the row coming into view mid-way through a scroll.
+    fn push_row(
+        &mut self,
+        terminal: &Terminal,
+        layers: &RenderLayers,
+        y: isize,
+        row: &[TerminalCell],
+        cursor: Option<(usize, usize)>,
+        h_offset: usize,
+    ) {
+        let (cell_width, cell_height) = self.cell_size();
+        let top = y as f32 * cell_height;
+        let (shaped, scale) = if self.ligatures {
+            let cursor = cursor.filter(|&(_, cursor_y)| cursor_y as isize == y);
+            let cursor = cursor.and_then(|(x, _)| x.checked_sub(h_offset));
+            self.text.shape_row(row, cursor.filter(|&x| x < row.len()))
+        } else {
+            (Rc::new([]) as Rc<[ShapedGlyph]>, 0.0)
+        };
+        self.shaped_columns.clear();
+        self.shaped_columns.resize(row.len(), false);
+        for glyph in shaped.iter() {
+            let end = (glyph.col + glyph.cols).min(row.len());
+            self.shaped_columns[glyph.col.min(end)..end].fill(true);
+        }
+        self.row_fg.clear();
+        self.row_fg.resize(row.len(), None);
+        let row_layers: Vec<_> = match usize::try_from(y) {
+            Ok(y) if y < terminal.height => layers.on_row(y).collect(),
+            _ => Vec::new(),
+        };
+
+        for (x, cell) in row.iter().enumerate() {
+            // The wide glyph before a spacer already covers its column
+            if cell.is_spacer() {
+                continue;
+            }
+            let mut cell = *cell;
+            let hidden = !terminal.blink_visible(cell.attrs.blink);
+            // Shaped glyphs are drawn after the row, over its backgrounds
+            if hidden || self.shaped_columns[x] {
+                cell.ch = ' ';
+                cell.combining = Combining::EMPTY;
+            }
+            let (mut fg, mut bg) = terminal.resolve_colors(&cell);
+            if !row_layers.is_empty() {
+                let layered =
+                    layers::compose(row_layers.iter().copied(), x + h_offset, y as usize, fg, bg);
+                (fg, bg) = (layered.fg, layered.bg);
+                if layered.underline && cell.attrs.underline == UnderlineStyle::None {
+                    cell.attrs.underline = UnderlineStyle::Single;
+                }
+            }
+            self.row_fg[x] = (!hidden).then_some(fg);
+            let bg = cell_background(
+                bg,
+                self.background,
+                self.background_opacity,
+                self.opaque_cell_backgrounds,
+            );
+            self.push_cell(x as f32 * cell_width, top, &cell, fg, bg);
+        }
+        for glyph in shaped.iter() {
+            if let Some(fg) = self.row_fg.get(glyph.col).copied().flatten() {
+                let x = glyph.col as f32 * cell_width;
+                self.push_shaped_glyph(x, top, glyph, scale, fg);
+            }
+        }
+    }
+
+    fn push_cell(&mut self, x: f32, y: f32, cell: &TerminalCell, fg: Color, bg: Color) {
+        let (cell_width, cell_height) = self.cell_size();
+        let cell_width = cell_width * cell.width.max(1) as f32;
+        let solid = self.text.solid_uv();
+        if bg.3 > 0 {
+            self.push_quad([x, y, x + cell_width, y + cell_height], solid, bg);
+        }
+
+        let underline_color = cell.attrs.underline_color.unwrap_or(fg);
+        self.push_underline(
+            [x, y + cell_height, x + cell_width],
+            cell.attrs.underline,
+            underline_color,
+        );
+        if cell.attrs.overline {
+            let thickness = (cell_height / 16.0).max(1.0);
+            self.push_quad([x, y, x + cell_width, y + thickness], solid, fg);
+        }
+        if cell.ch == ' ' {
+            return;
+        }
+        if self.builtin_box_drawing {
+            let rect = [x, y, x + cell_width, y + cell_height];
+            if let Some(shapes) = box_drawing::shapes(cell.ch, rect) {
+                for (rect, coverage) in shapes {
+                    let alpha = (fg.3 as f32 * coverage).round() as u8;
+                    self.push_quad(rect, solid, Color(fg.0, fg.1, fg.2, alpha));
+                }
+                return;
+            }
+        }
+        let Some(glyph) = self.text.glyph(cell.ch) else {
+            return;
+        };
+        // Spacing is shared evenly either side of the font's own cell
+        let (natural_width, natural_height) = self.text.cell_size();
+        let (spaced_width, spaced_height) = self.cell_size();
+        let baseline = y + ((spaced_height - natural_height) / 2.0 + self.text.ascent()).round();
+        let origin = x + ((spaced_width - natural_width) / 2.0).round();
+        let left = origin + glyph.xmin;
+        let top = baseline - glyph.ymin - glyph.height;
+        self.push_quad(
+            [left, top, left + glyph.width, top + glyph.height],
+            glyph.uv,
+            fg,
+        );
+        // Without shaping, combining marks are drawn over the first
+        // character. Marks have no advance and sit left of their origin,
+        // which goes where the first character ends.
+        let end = origin + natural_width * cell.width.max(1) as f32;
+        for mark in cell.combining.marks() {
+            if let Some(glyph) = self.text.glyph(mark) {
+                let left = end + glyph.xmin;
+                let top = baseline - glyph.ymin - glyph.height;
+                self.push_quad(
+                    [left, top, left + glyph.width, top + glyph.height],
+                    glyph.uv,
+                    fg,
+                );
+            }
+        }
+    }
+
+    /// Draws the scrollbar thumb if the viewport moved within the last
+    /// `SCROLLBAR_IDLE`.
+    fn push_scrollbar(&mut self, terminal: &Terminal, width: f32, color: Color) {
+        let now = Instant::now();
+        let position = (terminal.history_len(), terminal.scroll_state.offset);
+        if position.1 != self.scrollbar_position.1 {
+            self.scrollbar_shown_at = Some(now);
+        }
+        self.scrollbar_position = position;
+        if self
+            .scrollbar_hides_at()
+            .is_some_and(|hides_at| hides_at <= now)
+        {
+            self.scrollbar_shown_at = None;
+        }
+        if self.scrollbar_shown_at.is_none() {
+            return;
+        }
+
+        let track_height = self.height - 2.0 * self.padding;
+        let Some((top, height)) =
+            scrollbar_thumb(position.0, position.1, terminal.height, track_height)
+        else {
+            return;
+        };
+        let right = self.width - 2.0 * self.padding;
+        let solid = self.text.solid_uv();
+        self.push_quad([right - width, top, right, top + height], solid, color);
+    }
+
+    /// Draws a glyph from shaping with its cluster's first cell at `(x, y)`.
+    /// `scale` converts the glyph's offsets from font units to pixels.
+    fn push_shaped_glyph(&mut self, x: f32, y: f32, glyph: &ShapedGlyph, scale: f32, fg: Color) {
+        let Some(info) = self.text.glyph_by_id(glyph.id) else {
+            return;
+        };
+        let (natural_width, natural_height) = self.text.cell_size();
+        let (spaced_width, spaced_height) = self.cell_size();
+        let baseline = y + ((spaced_height - natural_height) / 2.0 + self.text.ascent()).round();
+        let origin = x + ((spaced_width - natural_width) / 2.0).round();
+        let left = origin + (glyph.x_offset * scale).round() + info.xmin;
+        let top = baseline - (glyph.y_offset * scale).round() - info.ymin - info.height;
+        self.push_quad(
+            [left, top, left + info.width, top + info.height],
+            info.uv,
+            fg,
+        );
+    }
+
+    /// Draws an underline of `style` along `[left, bottom, right]`. Patterns
+    /// are aligned to the surface rather than the cell so they continue
+    /// across neighboring cells.
+    fn push_underline(&mut self, span: [f32; 3], style: UnderlineStyle, color: Color) {
+        let [left, bottom, right] = span;
+        let (_, cell_height) = self.cell_size();
+        let thickness = (cell_height / 16.0).max(1.0);
+        let solid = self.text.solid_uv();
+        let line = |renderer: &mut Self, start: f32, end: f32, bottom: f32| {
+            let (start, end) = (start.max(left), end.min(right));
+            if start < end {
+                renderer.push_quad([start, bottom - thickness, end, bottom], solid, color);
+            }
+        };
+        let pattern = |on: f32, period: f32| {
+            let first = (left / period).floor() * period;
+            (0..)
+                .map(move |i| first + i as f32 * period)
+                .take_while(move |&start| start < right)
+                .map(move |start| (start, start + on))
+        };
+        match style {
+            UnderlineStyle::None => {}
+            UnderlineStyle::Single => line(self, left, right, bottom),
+            UnderlineStyle::Double => {
+                line(self, left, right, bottom);
+                line(self, left, right, bottom - 2.0 * thickness);
+            }
+            UnderlineStyle::Dotted => {
+                for (start, end) in pattern(thickness, 2.0 * thickness) {
+                    line(self, start, end, bottom);
+                }
+            }
+            UnderlineStyle::Dashed => {
+                for (start, end) in pattern(3.0 * thickness, 5.0 * thickness) {
+                    line(self, start, end, bottom);
+                }
+            }
+            UnderlineStyle::Curly => {
+                // One-pixel slices of a sine wave, one period every 8 strokes
+                let amplitude = thickness;
+                let wavelength = 8.0 * thickness;
+                for (start, end) in pattern(1.0, 1.0) {
+                    let phase = start / wavelength * std::f32::consts::TAU;
+                    let offset = amplitude * (1.0 + phase.sin());
+                    line(self, start, end, bottom - offset);
+                }
+            }
+        }
+    }
+
+    /// Appends a quad given in pixel coordinates `[left, top, right, bottom]`
+    /// from the grid origin.
+    fn push_quad(&mut self, rect: [f32; 4], uv: [f32; 4], color: Color) {
+        self.instances.push(Instance {
+            rect,
+            uv,
+            color: linear_color(color),
+        });
+    }
+
+    /// Instances drawn by the text pipeline: the grid's, before the
+    /// images, and the overlays', after them.
+    fn text_ranges(&self) -> [Range<u32>; 2] {
+        [
+            0..self.image_instances.start,
+            self.image_instances.end..self.instance_count,
+        ]
+    }
+
+    pub fn render_terminal<'a>(&'a self, render_pass: &mut RenderPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+        let [grid, overlays] = self.text_ranges();
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        // Rows moved part of a line are cut off at the padding
+        let scrolling = self
+            .grid_binding
+            .written
+            .is_some_and(|uniforms| uniforms.scroll != 0.0);
+        let (width, height) = (self.width as u32, self.height as u32);
+        if scrolling {
+            let padding = (self.padding.round() as u32).min(width / 2).min(height / 2);
+            render_pass.set_scissor_rect(
+                padding,
+                padding,
+                width - 2 * padding,
+                height - 2 * padding,
+            );
+        }
+        self.render_text(render_pass, &self.grid_binding, grid);
+        if !self.image_instances.is_empty() {
+            self.images.render(render_pass, self.image_instances.start);
+        }
+        if scrolling {
+            render_pass.set_scissor_rect(0, 0, width, height);
+        }
+        self.render_text(render_pass, &self.overlay_binding, overlays);
+    }
+
+    fn render_text<'a>(
+        &'a self,
+        render_pass: &mut RenderPass<'a>,
+        binding: &'a TextBinding,
+        instances: Range<u32>,
+    ) {
+        if instances.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &binding.bind_group, &[]);
+        render_pass.draw(0..6, instances);
+    }
+}
+
+/// Approximate cell size for a font rasterized at `font_size` pixels, for
+/// when its metrics aren't available.
+pub fn cell_size_for(font_size: f32) -> (f32, f32) {
+    (font_size * 0.6, font_size * 1.2)
+}
+
+/// Top and height of the scrollbar thumb in a track `track_height` pixels
+/// tall, for `rows` visible rows over `history` lines of scrollback viewed
+/// `offset` lines above the live screen. `None` without scrollback.
+pub fn scrollbar_thumb(
+    history: usize,
+    offset: f32,
+    rows: usize,
+    track_height: f32,
+) -> Option<(f32, f32)> {
+    if history == 0 || track_height <= 0.0 {
+        return None;
+    }
+    let total = (history + rows) as f32;
+    let height = (track_height * rows as f32 / total)
+        .max(MIN_SCROLLBAR_THUMB)
+        .min(track_height);
+    // At offset 0 the thumb rests at the bottom of the track
+    let scrolled = 1.0 - offset.clamp(0.0, history as f32) / history as f32;
+    Some(((track_height - height) * scrolled, height))
+}
+
+/// `cell` with its height scaled by `line_spacing` and `letter_spacing`
+/// pixels added to its width, rounded to whole pixels so columns and rows
+/// land on pixel boundaries at fractional scale factors, and never less
+/// than a pixel either way.
+pub fn spaced_cell_size(cell: (f32, f32), line_spacing: f32, letter_spacing: f32) -> (f32, f32) {
+    (
+        (cell.0 + letter_spacing).round().max(1.0),
+        (cell.1 * line_spacing).round().max(1.0),
+    )
+}
+
+/// Converts an sRGB color to the linear values expected by the sRGB surface.
+fn linear_color(color: Color) -> [f32; 4] {
+    let channel = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [
+        channel(color.0),
+        channel(color.1),
+        channel(color.2),
+        color.3 as f32 / 255.0,
+    ]
+}
+
+/// Background quad color for a grid cell. With a translucent window the
+/// default background is left to the clear color, which already carries
+/// the opacity, so it isn't blended in twice.
+pub fn cell_background(bg: Color, default_bg: Color, opacity: f32, opaque_cells: bool) -> Color {
+    if opacity >= 1.0 {
+        bg
+    } else if bg == default_bg {
+        Color(bg.0, bg.1, bg.2, 0)
+    } else if opaque_cells {
+        bg
+    } else {
+        Color(bg.0, bg.1, bg.2, (opacity * bg.3 as f32).round() as u8)
+    }
+}
+
+/// Picks a composite alpha mode for a window with the given background
+/// opacity, preferring premultiplied alpha. Falls back to an opaque mode
+/// when the window is opaque or the platform can't blend the surface.
+pub fn choose_alpha_mode(opacity: f32, supported: &[CompositeAlphaMode]) -> CompositeAlphaMode {
+    let preferred: &[CompositeAlphaMode] = if opacity < 1.0 {
+        &[
+            CompositeAlphaMode::PreMultiplied,
+            CompositeAlphaMode::PostMultiplied,
+            CompositeAlphaMode::Opaque,
+        ]
+    } else {
+        &[CompositeAlphaMode::Opaque]
+    };
+    preferred
+        .iter()
+        .copied()
+        .find(|mode| supported.contains(mode))
+        .or_else(|| supported.first().copied())
+        .unwrap_or(CompositeAlphaMode::Auto)
+}
+
+/// Present mode requested in the config. Anything the surface doesn't
+/// support falls back to the closest supported mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PresentModePreference {
+    /// Vsync, supported everywhere.
+    #[default]
+    Auto,
+    Fifo,
+    /// Vsync without blocking; lower latency than FIFO and no tearing.
+    Mailbox,
+    /// No vsync; lowest latency, may tear.
+    Immediate,
+}
+
+impl FromStr for PresentModePreference {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "auto" => Ok(PresentModePreference::Auto),
+            "fifo" => Ok(PresentModePreference::Fifo),
+            "mailbox" => Ok(PresentModePreference::Mailbox),
+            "immediate" => Ok(PresentModePreference::Immediate),
+            _ => Err(format!("unknown present mode '{}'", name)),
+        }
+    }
+}
+
+/// Picks the first mode supported by the surface, trying `preference` and
+/// then modes with progressively more latency. FIFO is guaranteed by every
+/// backend; the first available mode is the last resort.
+pub fn choose_present_mode(
+    preference: PresentModePreference,
+    available: &[PresentMode],
+) -> PresentMode {
+    let candidates: &[PresentMode] = match preference {
+        PresentModePreference::Auto | PresentModePreference::Fifo => &[PresentMode::Fifo],
+        PresentModePreference::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+        PresentModePreference::Immediate => &[
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+            PresentMode::Fifo,
+        ],
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|mode| available.contains(mode))
+        .or_else(|| available.first().copied())
+        .unwrap_or(PresentMode::Fifo)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard, OnceLock};
+
+    use super::*;
+    use crate::geometry::{grid_size, window_size, Geometry};
+
+    #[test]
+    fn present_modes_fall_back_towards_fifo() {
+        use PresentMode::{AutoVsync, Fifo, Immediate, Mailbox};
+        use PresentModePreference as Pref;
+
+        let all = [Fifo, Mailbox, Immediate];
+        for (preference, available, chosen) in [
+            (Pref::Auto, &all[..], Fifo),
+            (Pref::Fifo, &all[..], Fifo),
+            (Pref::Mailbox, &all[..], Mailbox),
+            (Pref::Immediate, &all[..], Immediate),
+            (Pref::Immediate, &[Fifo, Mailbox][..], Mailbox),
+            (Pref::Immediate, &[Fifo][..], Fifo),
+            (Pref::Mailbox, &[Immediate, Fifo][..], Fifo),
+            (Pref::Mailbox, &[AutoVsync][..], AutoVsync),
+            (Pref::Fifo, &[][..], Fifo),
+        ] {
+            assert_eq!(
+                choose_present_mode(preference, available),
+                chosen,
+                "{:?} from {:?}",
+                preference,
+                available
+            );
+        }
+    }
+
+    #[test]
+    fn present_mode_names_parse() {
+        assert_eq!("mailbox".parse(), Ok(PresentModePreference::Mailbox));
+        assert_eq!("auto".parse(), Ok(PresentModePreference::Auto));
+        assert!("vsync".parse::<PresentModePreference>().is_err());
+    }
+
+    /// The instance, device and queue shared by the GPU tests.
+    struct Gpu {
+        _instance: wgpu::Instance,
+        device: Device,
+        queue: Queue,
+    }
+
+    /// A device on the first adapter available, usually a software one in
+    /// CI, or `None` where there is no adapter at all. It is created once
+    /// and handed to one test at a time, since some backends can't have
+    /// two devices alive in one process.
+    fn gpu() -> Option<MutexGuard<'static, Gpu>> {
+        static GPU: OnceLock<Option<Mutex<Gpu>>> = OnceLock::new();
+        let gpu = GPU.get_or_init(|| {
+            let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+            let adapter = instance
+                .enumerate_adapters(wgpu::Backends::all())
+                .into_iter()
+                .next()?;
+            let runtime = tokio::runtime::Builder::new_current_thread().build().ok()?;
+            let (device, queue) = runtime
+                .block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None))
+                .ok()?;
+            Some(Mutex::new(Gpu {
+                _instance: instance,
+                device,
+                queue,
+            }))
+        });
+        let gpu = gpu.as_ref()?;
+        Some(gpu.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+
+    const TARGET_WIDTH: u32 = 64;
+    const TARGET_HEIGHT: u32 = 32;
+
+    fn target_config() -> SurfaceConfiguration {
+        SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: TextureFormat::Rgba8Unorm,
+            width: TARGET_WIDTH,
+            height: TARGET_HEIGHT,
+            present_mode: PresentMode::Fifo,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: CompositeAlphaMode::Opaque,
+            view_formats: Vec::new(),
+        }
+    }
+
+    /// Draws `terminal` into an offscreen texture and reads back its pixels.
+    fn draw(
+        renderer: &mut Renderer,
+        device: &Device,
+        queue: &Queue,
+        terminal: &Terminal,
+    ) -> Vec<u8> {
+        renderer.prepare(
+            device,
+            queue,
+            terminal,
+            &RenderLayers::new(),
+            None,
+            None,
+            None,
+            &[],
+        );
+        let texture = device.create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: TARGET_WIDTH,
+                height: TARGET_HEIGHT,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: None,
+            size: (TARGET_WIDTH * TARGET_HEIGHT * 4) as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(renderer.clear_color()),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            renderer.render_terminal(&mut pass);
+        }
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(TARGET_WIDTH * 4),
+                    rows_per_image: None,
+                },
+            },
+            texture.size(),
+        );
+        queue.submit([encoder.finish()]);
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let pixels = slice.get_mapped_range().to_vec();
+        pixels
+    }
+
+    #[test]
+    fn rendering_resumes_after_the_gpu_resources_are_rebuilt() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        let config = target_config();
+        let mut renderer = Renderer::with_text(device, &config, text);
+        let mut terminal = Terminal::new(4, 1);
+        terminal.process_pty_output(b"\x1b[?25lHi");
+
+        let before = draw(&mut renderer, device, queue, &terminal);
+        let glyph = renderer.text.glyph('H').unwrap().uv;
+        assert!(before.chunks(4).any(|pixel| pixel != [0, 0, 0, 255]));
+
+        // As after a lost surface or device: every GPU resource is rebuilt
+        renderer.recreate(device, &config);
+        let after = draw(&mut renderer, device, queue, &terminal);
+        assert_eq!(renderer.text.glyph('H').unwrap().uv, glyph);
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn only_the_default_background_is_translucent() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        let config = target_config();
+        let mut renderer = Renderer::with_text(device, &config, text);
+        renderer.set_background_opacity(0.5, CompositeAlphaMode::PreMultiplied, true);
+        let mut terminal = Terminal::new(4, 1);
+        terminal.process_pty_output(b"\x1b[?25l\x1b[41m \x1b[m");
+
+        let pixels = draw(&mut renderer, device, queue, &terminal);
+        let (cell_width, cell_height) = renderer.cell_size();
+        let alpha =
+            |x: f32, y: f32| pixels[(y as usize * TARGET_WIDTH as usize + x as usize) * 4 + 3];
+        let middle = cell_height / 2.0;
+        assert_eq!(alpha(cell_width / 2.0, middle), 255);
+        let default = alpha(cell_width * 2.5, middle);
+        assert!((127..=128).contains(&default), "alpha {}", default);
+
+        // An opaque surface ignores the opacity
+        renderer.set_background_opacity(0.5, CompositeAlphaMode::Opaque, true);
+        let pixels = draw(&mut renderer, device, queue, &terminal);
+        let index = ((middle as usize) * TARGET_WIDTH as usize + (cell_width * 2.5) as usize) * 4;
+        assert_eq!(pixels[index + 3], 255);
+    }
+
+    #[test]
+    fn translucent_cell_backgrounds_follow_the_opacity() {
+        let (default, red) = (Color(0, 0, 0, 255), Color(255, 0, 0, 255));
+        assert_eq!(cell_background(red, default, 1.0, false), red);
+        assert_eq!(cell_background(default, default, 0.5, true).3, 0);
+        assert_eq!(cell_background(red, default, 0.5, true), red);
+        assert_eq!(cell_background(red, default, 0.5, false).3, 128);
+    }
+
+    #[test]
+    fn opaque_surfaces_are_the_fallback() {
+        use CompositeAlphaMode::{Inherit, Opaque, PostMultiplied, PreMultiplied};
+        let all = [Opaque, PreMultiplied, PostMultiplied];
+        assert_eq!(choose_alpha_mode(0.8, &all), PreMultiplied);
+        assert_eq!(
+            choose_alpha_mode(0.8, &[Opaque, PostMultiplied]),
+            PostMultiplied
+        );
+        assert_eq!(choose_alpha_mode(0.8, &[Opaque]), Opaque);
+        assert_eq!(choose_alpha_mode(1.0, &all), Opaque);
+        assert_eq!(choose_alpha_mode(0.8, &[Inherit]), Inherit);
+    }
+
+    #[test]
+    fn box_drawing_lines_light_every_pixel_across_the_cells() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let (device, queue) = (&gpu.device, &gpu.queue);
+        let mut renderer = Renderer::with_text(device, &target_config(), text);
+        renderer.set_builtin_box_drawing(true);
+        let mut terminal = Terminal::new(4, 1);
+        terminal.process_pty_output("\x1b[?25l────".as_bytes());
+
+        let pixels = draw(&mut renderer, device, queue, &terminal);
+        let background = &pixels[..4];
+        let grid_width = (renderer.cell_size().0 * 4.0).round() as usize;
+        let lit_rows = pixels
+            .chunks(TARGET_WIDTH as usize * 4)
+            .filter(|row| {
+                row[..grid_width * 4]
+                    .chunks(4)
+                    .any(|pixel| pixel != background)
+            })
+            .collect::<Vec<_>>();
+        assert!(!lit_rows.is_empty());
+        for row in lit_rows {
+            assert!(row[..grid_width * 4]
+                .chunks(4)
+                .all(|pixel| pixel != background));
+        }
+    }
+
+    #[test]
+    fn cells_are_as_wide_as_the_advance_of_m() {
+        let Ok(text) = TextRenderer::from_system_font(FONT_SIZE) else {
+            eprintln!("skipping: no monospace font");
+            return;
+        };
+        let advance = text.font.metrics('M', FONT_SIZE).advance_width;
+        let line = text.font.horizontal_line_metrics(FONT_SIZE).unwrap();
+        assert_eq!(text.cell_size(), (advance, line.new_line_size));
+        assert_ne!(text.cell_size(), cell_size_for(FONT_SIZE));
+    }
+
+    #[test]
+    fn spacing_is_added_to_the_cell_in_whole_pixels() {
+        let cell = (9.63, 18.9);
+        assert_eq!(spaced_cell_size(cell, 1.0, 0.0), (10.0, 19.0));
+        assert_eq!(spaced_cell_size(cell, 1.5, 2.0), (12.0, 28.0));
+        assert_eq!(spaced_cell_size(cell, 1.0, -20.0), (1.0, 19.0));
+    }
+
+    #[test]
+    fn the_renderer_cell_follows_the_spacing_settings() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let (natural_width, natural_height) = text.cell_size();
+        let mut renderer = Renderer::with_text(&gpu.device, &target_config(), text);
+        assert_eq!(
+            renderer.cell_size(),
+            (natural_width.round(), natural_height.round())
+        );
+        renderer.set_line_spacing(1.5);
+        renderer.set_letter_spacing(1.0);
+        assert_eq!(
+            renderer.cell_size(),
+            (
+                (natural_width + 1.0).round(),
+                (natural_height * 1.5).round()
+            )
+        );
+        renderer.set_line_spacing(100.0);
+        assert_eq!(
+            renderer.cell_size().1,
+            (natural_height * MAX_LINE_SPACING).round()
+        );
+    }
+
+    #[test]
+    fn the_scrollbar_thumb_shows_the_visible_share_of_the_scrollback() {
+        // 300 lines of history and 100 rows in a 400 pixel track
+        assert_eq!(scrollbar_thumb(300, 0.0, 100, 400.0), Some((300.0, 100.0)));
+        assert_eq!(scrollbar_thumb(300, 300.0, 100, 400.0), Some((0.0, 100.0)));
+        assert_eq!(
+            scrollbar_thumb(300, 150.0, 100, 400.0),
+            Some((150.0, 100.0))
+        );
+        // Offsets past either end are clamped
+        assert_eq!(scrollbar_thumb(300, 900.0, 100, 400.0), Some((0.0, 100.0)));
+        assert_eq!(scrollbar_thumb(300, -5.0, 100, 400.0), Some((300.0, 100.0)));
+    }
+
+    #[test]
+    fn the_scrollbar_thumb_keeps_a_minimum_size() {
+        let (top, height) = scrollbar_thumb(100_000, 0.0, 24, 480.0).unwrap();
+        assert_eq!(height, MIN_SCROLLBAR_THUMB);
+        assert_eq!(top + height, 480.0);
+        let (_, height) = scrollbar_thumb(1, 0.0, 24, 8.0).unwrap();
+        assert!(height <= 8.0);
+        assert_eq!(scrollbar_thumb(0, 0.0, 24, 480.0), None);
+        assert_eq!(scrollbar_thumb(10, 0.0, 24, 0.0), None);
+    }
+
+    #[test]
+    fn cell_metrics_scale_with_the_scale_factor() {
+        let Ok(mut text) = TextRenderer::from_system_font(FONT_SIZE) else {
+            eprintln!("skipping: no monospace font");
+            return;
+        };
+        let (width, height) = text.cell_size();
+        for scale_factor in [1.25, 1.5, 2.0, 3.0] {
+            text.set_scale_factor(scale_factor);
+            assert_eq!(text.pixel_size(), FONT_SIZE * scale_factor);
+            let (scaled_width, scaled_height) = text.cell_size();
+            assert!((scaled_width - width * scale_factor).abs() < 0.5);
+            assert!((scaled_height - height * scale_factor).abs() < 0.5);
+
+            // Whole pixels, so a wide grid fits its window without drift
+            let cell = spaced_cell_size(text.cell_size(), 1.0, 0.0);
+            assert_eq!((cell.0.fract(), cell.1.fract()), (0.0, 0.0));
+            let geometry = Geometry {
+                cols: 200,
+                rows: 60,
+            };
+            let size = window_size(geometry, cell, 4.0, scale_factor as f64);
+            assert_eq!(grid_size(size, cell, 4.0, scale_factor as f64), geometry);
+        }
+    }
+
+    #[test]
+    fn a_new_scale_factor_re_rasterizes_every_glyph() {
+        let Ok(mut text) = TextRenderer::from_system_font(FONT_SIZE) else {
+            eprintln!("skipping: no monospace font");
+            return;
+        };
+        let small = text.glyph('M').unwrap();
+        let generation = text.generation();
+
+        text.set_scale_factor(1.0);
+        assert_eq!(text.generation(), generation);
+        text.set_scale_factor(0.0);
+        assert_eq!(text.generation(), generation);
+
+        text.set_scale_factor(2.0);
+        assert_eq!(text.generation(), generation + 1);
+        assert!(text.glyphs.is_empty());
+        let large = text.glyph('M').unwrap();
+        assert!(large.width > small.width * 1.5);
+    }
+
+    #[test]
+    fn the_cursor_takes_the_unfocused_color_without_focus() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let mut renderer = Renderer::with_text(&gpu.device, &target_config(), text);
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"ab");
+        let cell = CellRange::cell(2, 0);
+        assert_eq!(
+            renderer.cursor_layer(&terminal),
+            Some(CellRangeHighlight::inverse(cell))
+        );
+        renderer.set_focused(false);
+        assert_eq!(
+            renderer.cursor_layer(&terminal),
+            Some(CellRangeHighlight::underline(cell))
+        );
+
+        let focused = Color(255, 204, 0, 255);
+        let unfocused = Color(128, 102, 0, 255);
+        terminal.set_cursor_color(Some(focused));
+        terminal.set_cursor_color_unfocused(Some(unfocused));
+        assert_eq!(
+            renderer.cursor_layer(&terminal).unwrap().bg,
+            Some(unfocused)
+        );
+        renderer.set_focused(true);
+        assert_eq!(renderer.cursor_layer(&terminal).unwrap().bg, Some(focused));
+    }
+
+    /// Prepares `terminal` with nothing drawn over it.
+    fn prepare(renderer: &mut Renderer, gpu: &Gpu, terminal: &Terminal) {
+        renderer.prepare(
+            &gpu.device,
+            &gpu.queue,
+            terminal,
+            &RenderLayers::new(),
+            None,
+            None,
+            None,
+            &[],
+        );
+    }
+
+    #[test]
+    fn cells_are_drawn_as_quads_from_the_grid_origin() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let mut renderer = Renderer::with_text(&gpu.device, &target_config(), text);
+        renderer.set_ligatures(false);
+        let mut terminal = Terminal::new(3, 2);
+        terminal.process_pty_output(b"\x1b[41;4ma");
+        prepare(&mut renderer, &gpu, &terminal);
+
+        let (width, height) = renderer.cell_size();
+        let solid = renderer.text.solid_uv();
+        let red = linear_color(Color::from_ansi_color(1));
+        // Background, underline and glyph of the first cell, then a
+        // background for each blank one
+        let instances = &renderer.instances;
+        assert_eq!(instances.len(), 3 + 5);
+        assert_eq!(instances[0].rect, [0.0, 0.0, width, height]);
+        assert_eq!((instances[0].uv, instances[0].color), (solid, red));
+        assert_eq!(instances[1].rect[3], height);
+        assert_ne!(instances[2].uv, solid);
+        assert!(instances[2].rect[0] >= 0.0 && instances[2].rect[2] <= width);
+        assert_eq!(instances[3].rect, [width, 0.0, 2.0 * width, height]);
+        assert_eq!(
+            instances[7].rect,
+            [2.0 * width, height, 3.0 * width, 2.0 * height]
+        );
+        assert_eq!(renderer.stats().grid_builds, 1);
+    }
+
+    #[test]
+    fn scrolling_by_part_of_a_line_only_moves_the_grid() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let mut renderer = Renderer::with_text(&gpu.device, &target_config(), text);
+        let mut terminal = Terminal::new(3, 2);
+        terminal.process_pty_output(b"a\r\nb\r\nc\r\nd");
+        let (_, height) = renderer.cell_size();
+        prepare(&mut renderer, &gpu, &terminal);
+        prepare(&mut renderer, &gpu, &terminal);
+        assert_eq!(renderer.stats().grid_builds, 1);
+
+        // The row above comes into view, drawn a line above the grid
+        terminal.scroll_state.offset = 1.25;
+        prepare(&mut renderer, &gpu, &terminal);
+        assert_eq!(renderer.stats().grid_builds, 2);
+        assert!(renderer.instances[..renderer.grid_len]
+            .iter()
+            .any(|instance| instance.rect[1] == -height));
+        let scroll = |renderer: &Renderer| renderer.grid_binding.written.unwrap().scroll;
+        assert_eq!(scroll(&renderer), 0.25 * height);
+
+        let grid = renderer.instances.clone();
+        terminal.scroll_state.offset = 1.4;
+        prepare(&mut renderer, &gpu, &terminal);
+        assert_eq!(renderer.stats().grid_builds, 2);
+        assert_eq!(renderer.instances, grid);
+        assert!((scroll(&renderer) - 0.4 * height).abs() < 1e-3);
+        assert_eq!(renderer.overlay_binding.written.unwrap().scroll, 0.0);
+        // Drawing cuts the moved rows off at the padding
+        renderer.set_padding(2.0);
+        draw(&mut renderer, &gpu.device, &gpu.queue, &terminal);
+        assert_eq!(renderer.stats().grid_builds, 2);
+
+        // New output in view is drawn even mid-way through a scroll
+        terminal.process_pty_output(b"\x1b[1;2He");
+        prepare(&mut renderer, &gpu, &terminal);
+        assert_eq!(renderer.stats().grid_builds, 3);
+    }
+
+    /// Times `prepare` on a full 300x100 grid, rebuilding the grid and
+    /// scrolling by part of a line. Run with
+    /// `cargo test --release prepare_benchmark -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn prepare_benchmark() {
+        let (Some(gpu), Ok(text)) = (gpu(), TextRenderer::from_system_font(FONT_SIZE)) else {
+            eprintln!("skipping: no GPU adapter or no monospace font");
+            return;
+        };
+        let mut renderer = Renderer::with_text(&gpu.device, &target_config(), text);
+        let mut terminal = Terminal::new(300, 100);
+        for line in 0..200 {
+            let text: String = (0..300)
+                .map(|x| char::from(b'!' + ((x + line) % 94) as u8))
+                .collect();
+            let color = format!("\x1b[3{}m", line % 8);
+            terminal.process_pty_output(format!("{}{}\r\n", color, text).as_bytes());
+        }
+        terminal.scroll_state.offset = 50.0;
+        prepare(&mut renderer, &gpu, &terminal);
+
+        const FRAMES: u32 = 100;
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            renderer.grid_key = None;
+            prepare(&mut renderer, &gpu, &terminal);
+        }
+        let rebuild = start.elapsed() / FRAMES;
+        let instances = renderer.instances.len();
+
+        let start = Instant::now();
+        for frame in 0..FRAMES {
+            terminal.scroll_state.offset = 50.05 + 0.4 * frame as f32 / FRAMES as f32;
+            prepare(&mut renderer, &gpu, &terminal);
+        }
+        let scroll = start.elapsed() / FRAMES;
+        println!(
+            "300x100: {} instances, rebuild {:.2?}, partial-line scroll {:.2?}",
+            instances, rebuild, scroll
+        );
+    }
+}
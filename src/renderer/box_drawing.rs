@@ -0,0 +1,343 @@
+//! Box-drawing (U+2500–257F) and block element (U+2580–259F) characters
+//! built from rectangles sized to the cell, so borders join without the
+//! seams a rasterized font glyph leaves at small sizes.
+
+/// Weight of one arm of a line character.
+const NONE: u8 = 0;
+const LIGHT: u8 = 1;
+const HEAVY: u8 = 2;
+const DOUBLE: u8 = 3;
+
+/// Arms of U+2500–254B as `[left, up, right, down]`. The dashed lines at
+/// U+2504–250B are drawn separately and have no entry.
+#[rustfmt::skip]
+const LINES: [[u8; 4]; 0x4c] = [
+    [1, 0, 1, 0], [2, 0, 2, 0], [0, 1, 0, 1], [0, 2, 0, 2],
+    [0; 4], [0; 4], [0; 4], [0; 4], [0; 4], [0; 4], [0; 4], [0; 4],
+    [0, 0, 1, 1], [0, 0, 2, 1], [0, 0, 1, 2], [0, 0, 2, 2],
+    [1, 0, 0, 1], [2, 0, 0, 1], [1, 0, 0, 2], [2, 0, 0, 2],
+    [0, 1, 1, 0], [0, 1, 2, 0], [0, 2, 1, 0], [0, 2, 2, 0],
+    [1, 1, 0, 0], [2, 1, 0, 0], [1, 2, 0, 0], [2, 2, 0, 0],
+    [0, 1, 1, 1], [0, 1, 2, 1], [0, 2, 1, 1], [0, 1, 1, 2],
+    [0, 2, 1, 2], [0, 2, 2, 1], [0, 1, 2, 2], [0, 2, 2, 2],
+    [1, 1, 0, 1], [2, 1, 0, 1], [1, 2, 0, 1], [1, 1, 0, 2],
+    [1, 2, 0, 2], [2, 2, 0, 1], [2, 1, 0, 2], [2, 2, 0, 2],
+    [1, 0, 1, 1], [2, 0, 1, 1], [1, 0, 2, 1], [2, 0, 2, 1],
+    [1, 0, 1, 2], [2, 0, 1, 2], [1, 0, 2, 2], [2, 0, 2, 2],
+    [1, 1, 1, 0], [2, 1, 1, 0], [1, 1, 2, 0], [2, 1, 2, 0],
+    [1, 2, 1, 0], [2, 2, 1, 0], [1, 2, 2, 0], [2, 2, 2, 0],
+    [1, 1, 1, 1], [2, 1, 1, 1], [1, 1, 2, 1], [2, 1, 2, 1],
+    [1, 2, 1, 1], [1, 1, 1, 2], [1, 2, 1, 2], [2, 2, 1, 1],
+    [1, 2, 2, 1], [2, 1, 1, 2], [1, 1, 2, 2], [2, 2, 2, 1],
+    [2, 1, 2, 2], [2, 2, 1, 2], [1, 2, 2, 2], [2, 2, 2, 2],
+];
+
+/// Arms of U+2550–256C, the double lines.
+#[rustfmt::skip]
+const DOUBLE_LINES: [[u8; 4]; 0x1d] = [
+    [3, 0, 3, 0], [0, 3, 0, 3],
+    [0, 0, 3, 1], [0, 0, 1, 3], [0, 0, 3, 3],
+    [3, 0, 0, 1], [1, 0, 0, 3], [3, 0, 0, 3],
+    [0, 1, 3, 0], [0, 3, 1, 0], [0, 3, 3, 0],
+    [3, 1, 0, 0], [1, 3, 0, 0], [3, 3, 0, 0],
+    [0, 1, 3, 1], [0, 3, 1, 3], [0, 3, 3, 3],
+    [3, 1, 0, 1], [1, 3, 0, 3], [3, 3, 0, 3],
+    [3, 0, 3, 1], [1, 0, 1, 3], [3, 0, 3, 3],
+    [3, 1, 3, 0], [1, 3, 1, 0], [3, 3, 3, 0],
+    [3, 1, 3, 1], [1, 3, 1, 3], [3, 3, 3, 3],
+];
+
+/// Arms of U+256D–2570 (rounded corners, drawn square) and the half lines
+/// at U+2574–257F.
+#[rustfmt::skip]
+const CORNERS_AND_HALVES: [[u8; 4]; 0x13] = [
+    [0, 0, 1, 1], [1, 0, 0, 1], [1, 1, 0, 0], [0, 1, 1, 0],
+    // U+2571–2573 are diagonals, left to the font
+    [0; 4], [0; 4], [0; 4],
+    [1, 0, 0, 0], [0, 1, 0, 0], [0, 0, 1, 0], [0, 0, 0, 1],
+    [2, 0, 0, 0], [0, 2, 0, 0], [0, 0, 2, 0], [0, 0, 0, 2],
+    [1, 0, 2, 0], [0, 1, 0, 2], [2, 0, 1, 0], [0, 2, 0, 1],
+];
+
+/// A filled rectangle `[left, top, right, bottom]` in pixels and the
+/// fraction of the foreground alpha it is drawn with.
+pub type Shape = ([f32; 4], f32);
+
+/// Shapes drawing `ch` in the cell `[left, top, right, bottom]`, or `None`
+/// if `ch` isn't handled here and should come from the font.
+pub fn shapes(ch: char, cell: [f32; 4]) -> Option<Vec<Shape>> {
+    // Snap the cell to whole pixels so neighbors share their edges
+    let cell = cell.map(f32::round);
+    let code = ch as u32;
+    let arms = match code {
+        0x2504..=0x250b => return Some(dashes(code, cell)),
+        0x254c..=0x254f => return Some(dashes(code, cell)),
+        0x2500..=0x254b => LINES[(code - 0x2500) as usize],
+        0x2550..=0x256c => DOUBLE_LINES[(code - 0x2550) as usize],
+        0x256d..=0x257f => CORNERS_AND_HALVES[(code - 0x256d) as usize],
+        0x2580..=0x259f => return Some(block(code, cell)),
+        _ => return None,
+    };
+    if arms == [NONE; 4] {
+        return None;
+    }
+    Some(lines(arms, cell))
+}
+
+/// Thickness of a light line; heavy lines are twice as thick, and the
+/// strokes of a double line are one light line apart.
+fn light_thickness(cell: [f32; 4]) -> f32 {
+    ((cell[2] - cell[0]) / 8.0).round().max(1.0)
+}
+
+/// Center of a stroke of thickness `thickness` across `[start, end]`,
+/// placed so the stroke's edges fall on whole pixels.
+fn center(start: f32, end: f32, thickness: f32) -> f32 {
+    start + ((end - start - thickness) / 2.0).floor() + thickness / 2.0
+}
+
+fn lines(arms: [u8; 4], cell: [f32; 4]) -> Vec<Shape> {
+    let [left, top, right, bottom] = cell;
+    let light = light_thickness(cell);
+    let gap = light;
+    let cx = center(left, right, light);
+    let cy = center(top, bottom, light);
+    let thickness = |weight: u8| if weight == HEAVY { 2.0 * light } else { light };
+    // How far an arm reaches past the center line, across its axis
+    let extent = |weight: u8| match weight {
+        NONE => 0.0,
+        DOUBLE => gap + light / 2.0,
+        weight => thickness(weight) / 2.0,
+    };
+    let [arm_left, arm_up, arm_right, arm_down] = arms;
+    let mut shapes = Vec::new();
+
+    // Each arm runs from the cell edge to just past the center. `sides`
+    // are the perpendicular arms on either side of the arm's strokes.
+    let mut arm = |weight: u8, horizontal: bool, toward_end: bool, sides: [u8; 2]| {
+        if weight == NONE {
+            return;
+        }
+        let (center_along, center_across, start, end) = if horizontal {
+            (cx, cy, left, right)
+        } else {
+            (cy, cx, top, bottom)
+        };
+        let mut strokes = Vec::new();
+        if weight == DOUBLE {
+            for (side, offset) in [(0, -gap), (1, gap)] {
+                // Stop at the inner edge of a perpendicular arm on this side,
+                // otherwise cover the far side of the perpendicular arms
+                let reach = if sides[side] != NONE {
+                    light - extent(sides[side])
+                } else {
+                    extent(sides[1 - side])
+                };
+                strokes.push((center_across + offset, light, reach));
+            }
+        } else {
+            let reach = extent(sides[0]).max(extent(sides[1]));
+            strokes.push((center_across, thickness(weight), reach));
+        }
+        for (across, width, reach) in strokes {
+            let (from, to) = if toward_end {
+                (center_along - reach, end)
+            } else {
+                (start, center_along + reach)
+            };
+            let (near, far) = (across - width / 2.0, across + width / 2.0);
+            let rect = if horizontal {
+                [from, near, to, far]
+            } else {
+                [near, from, far, to]
+            };
+            shapes.push((rect, 1.0));
+        }
+    };
+    arm(arm_left, true, false, [arm_up, arm_down]);
+    arm(arm_right, true, true, [arm_up, arm_down]);
+    arm(arm_up, false, false, [arm_left, arm_right]);
+    arm(arm_down, false, true, [arm_left, arm_right]);
+    shapes
+}
+
+/// The dashed lines at U+2504–250B and U+254C–254F.
+fn dashes(code: u32, cell: [f32; 4]) -> Vec<Shape> {
+    let (count, weight, horizontal) = match code {
+        0x2504 => (3, LIGHT, true),
+        0x2505 => (3, HEAVY, true),
+        0x2506 => (3, LIGHT, false),
+        0x2507 => (3, HEAVY, false),
+        0x2508 => (4, LIGHT, true),
+        0x2509 => (4, HEAVY, true),
+        0x250a => (4, LIGHT, false),
+        0x250b => (4, HEAVY, false),
+        0x254c => (2, LIGHT, true),
+        0x254d => (2, HEAVY, true),
+        0x254e => (2, LIGHT, false),
+        _ => (2, HEAVY, false),
+    };
+    let [left, top, right, bottom] = cell;
+    let light = light_thickness(cell);
+    let thickness = if weight == HEAVY { 2.0 * light } else { light };
+    let (start, end) = if horizontal {
+        (left, right)
+    } else {
+        (top, bottom)
+    };
+    let across = if horizontal {
+        center(top, bottom, thickness)
+    } else {
+        center(left, right, thickness)
+    };
+    // Each dash is centered in its share of the cell, leaving half a gap at
+    // either end so dashes stay evenly spaced across neighboring cells
+    let period = (end - start) / count as f32;
+    let gap = (period / 4.0).round().max(1.0);
+    (0..count)
+        .map(|i| {
+            let from = (start + i as f32 * period + gap / 2.0).round();
+            let to = (start + (i + 1) as f32 * period - gap / 2.0).round();
+            let (near, far) = (across - thickness / 2.0, across + thickness / 2.0);
+            let rect = if horizontal {
+                [from, near, to, far]
+            } else {
+                [near, from, far, to]
+            };
+            (rect, 1.0)
+        })
+        .collect()
+}
+
+/// The block elements at U+2580–259F.
+fn block(code: u32, cell: [f32; 4]) -> Vec<Shape> {
+    let [left, top, right, bottom] = cell;
+    let x = |fraction: f32| (left + (right - left) * fraction).round();
+    let y = |fraction: f32| (top + (bottom - top) * fraction).round();
+    let full = |coverage: f32| vec![(cell, coverage)];
+    let quadrants = |upper_left: bool, upper_right: bool, lower_left: bool, lower_right: bool| {
+        let (mid_x, mid_y) = (x(0.5), y(0.5));
+        [
+            (upper_left, [left, top, mid_x, mid_y]),
+            (upper_right, [mid_x, top, right, mid_y]),
+            (lower_left, [left, mid_y, mid_x, bottom]),
+            (lower_right, [mid_x, mid_y, right, bottom]),
+        ]
+        .into_iter()
+        .filter(|&(on, _)| on)
+        .map(|(_, rect)| (rect, 1.0))
+        .collect()
+    };
+    match code {
+        0x2580 => vec![([left, top, right, y(0.5)], 1.0)],
+        // Lower one eighth to seven eighths
+        0x2581..=0x2587 => {
+            let eighths = (code - 0x2580) as f32;
+            vec![([left, y(1.0 - eighths / 8.0), right, bottom], 1.0)]
+        }
+        0x2588 => full(1.0),
+        // Left seven eighths down to one eighth
+        0x2589..=0x258f => {
+            let eighths = (0x2590 - code) as f32;
+            vec![([left, top, x(eighths / 8.0), bottom], 1.0)]
+        }
+        0x2590 => vec![([x(0.5), top, right, bottom], 1.0)],
+        0x2591 => full(0.25),
+        0x2592 => full(0.5),
+        0x2593 => full(0.75),
+        0x2594 => vec![([left, top, right, y(1.0 / 8.0)], 1.0)],
+        0x2595 => vec![([x(7.0 / 8.0), top, right, bottom], 1.0)],
+        0x2596 => quadrants(false, false, true, false),
+        0x2597 => quadrants(false, false, false, true),
+        0x2598 => quadrants(true, false, false, false),
+        0x2599 => quadrants(true, false, true, true),
+        0x259a => quadrants(true, false, false, true),
+        0x259b => quadrants(true, true, true, false),
+        0x259c => quadrants(true, true, false, true),
+        0x259d => quadrants(false, true, false, false),
+        0x259e => quadrants(false, true, true, false),
+        _ => quadrants(false, true, true, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Which pixels of a `width` by `height` cell `ch` lights.
+    fn raster(ch: char, width: usize, height: usize) -> Vec<Vec<bool>> {
+        let cell = [0.0, 0.0, width as f32, height as f32];
+        let mut pixels = vec![vec![false; width]; height];
+        for ([left, top, right, bottom], _) in shapes(ch, cell).unwrap() {
+            for (y, row) in pixels.iter_mut().enumerate() {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let (x, y) = (x as f32 + 0.5, y as f32 + 0.5);
+                    *pixel |= (left..right).contains(&x) && (top..bottom).contains(&y);
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn a_horizontal_line_spans_the_whole_cell() {
+        for (width, height) in [(7, 15), (9, 18), (10, 21)] {
+            let pixels = raster('─', width, height);
+            let lit: Vec<usize> = (0..height)
+                .filter(|&y| pixels[y].iter().any(|&lit| lit))
+                .collect();
+            assert!(!lit.is_empty());
+            for y in lit {
+                assert!(
+                    pixels[y].iter().all(|&lit| lit),
+                    "row {} of {:?}",
+                    y,
+                    (width, height)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn crossing_arms_meet_in_the_middle() {
+        let pixels = raster('┼', 9, 18);
+        let row = pixels.iter().position(|row| row.iter().all(|&lit| lit));
+        let column = (0..9).find(|&x| pixels.iter().all(|row| row[x]));
+        assert!(row.is_some() && column.is_some());
+
+        let pixels = raster('┌', 9, 18);
+        assert!(!pixels[0].iter().any(|&lit| lit));
+        assert!(pixels[17].iter().any(|&lit| lit));
+        assert!(pixels.iter().all(|row| !row[0]));
+        assert!(pixels.iter().any(|row| row[8]));
+    }
+
+    #[test]
+    fn blocks_fill_their_share_of_the_cell() {
+        assert!(raster('█', 9, 18).iter().flatten().all(|&lit| lit));
+        let upper_half = raster('▀', 9, 18);
+        assert!(upper_half[..9].iter().flatten().all(|&lit| lit));
+        assert!(!upper_half[9..].iter().flatten().any(|&lit| lit));
+        let shade = shapes('▒', [0.0, 0.0, 9.0, 18.0]).unwrap();
+        assert_eq!(shade, [([0.0, 0.0, 9.0, 18.0], 0.5)]);
+    }
+
+    #[test]
+    fn neighbors_share_their_edges() {
+        let first = shapes('─', [0.0, 0.0, 9.6, 19.0]).unwrap();
+        let second = shapes('─', [9.6, 0.0, 19.2, 19.0]).unwrap();
+        let right = first.iter().map(|(rect, _)| rect[2]).fold(0.0, f32::max);
+        let left = second
+            .iter()
+            .map(|(rect, _)| rect[0])
+            .fold(f32::MAX, f32::min);
+        assert_eq!(right, left);
+    }
+
+    #[test]
+    fn other_characters_come_from_the_font() {
+        for ch in ['a', '╱', '╳', '\u{25a0}'] {
+            assert_eq!(shapes(ch, [0.0, 0.0, 9.0, 18.0]), None);
+        }
+    }
+}
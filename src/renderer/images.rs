@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferBindingType,
+    ColorTargetState, ColorWrites, Device, Extent3d, FilterMode, FragmentState, ImageCopyTexture,
+    ImageDataLayout, MultisampleState, Origin3d, PipelineLayoutDescriptor, PrimitiveState, Queue,
+    RenderPass, RenderPipeline, RenderPipelineDescriptor, Sampler, SamplerBindingType,
+    SamplerDescriptor, ShaderModuleDescriptor, ShaderSource, ShaderStages, Texture, TextureAspect,
+    TextureDescriptor, TextureDimension, TextureFormat, TextureSampleType, TextureUsages,
+    TextureViewDescriptor, TextureViewDimension, VertexState,
+};
+
+use super::Instance;
+use crate::terminal::images::TerminalImage;
+
+/// An image uploaded to the GPU.
+struct ImageTexture {
+    /// Kept alive for the bind group sampling it.
+    _texture: Texture,
+    bind_group: BindGroup,
+    /// Drawn this frame; textures that weren't are dropped after it.
+    used: bool,
+}
+
+/// Draws inline images over the grid, one instance each, with a pipeline
+/// sampling each image's own RGBA texture. Textures are cached by image
+/// id while the image stays on screen.
+pub(super) struct ImageRenderer {
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    sampler: Sampler,
+    textures: HashMap<u64, ImageTexture>,
+    /// Image of each image instance this frame, in instance order.
+    draws: Vec<u64>,
+}
+
+impl ImageRenderer {
+    pub(super) fn new(device: &Device, format: TextureFormat) -> Self {
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Image Shader"),
+            source: ShaderSource::Wgsl(include_str!("../shaders/image.wgsl").into()),
+        });
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Image Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Image Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Image Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Image Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[Instance::layout()],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            textures: HashMap::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    /// Starts a frame with no images drawn.
+    pub(super) fn begin(&mut self) {
+        self.draws.clear();
+        for texture in self.textures.values_mut() {
+            texture.used = false;
+        }
+    }
+
+    /// Draws `image` with the next image instance, uploading it if it
+    /// isn't cached. `uniforms` is the renderer's uniform buffer.
+    pub(super) fn draw(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        uniforms: &Buffer,
+        image: &TerminalImage,
+    ) {
+        let layout = &self.bind_group_layout;
+        let sampler = &self.sampler;
+        let texture = self
+            .textures
+            .entry(image.id())
+            .or_insert_with(|| upload(device, queue, layout, sampler, uniforms, image));
+        texture.used = true;
+        self.draws.push(image.id());
+    }
+
+    /// Drops the textures of images not drawn this frame.
+    pub(super) fn finish(&mut self) {
+        self.textures.retain(|_, texture| texture.used);
+    }
+
+    /// Image instances drawn this frame.
+    pub(super) fn count(&self) -> usize {
+        self.draws.len()
+    }
+
+    /// Draws the frame's images from instance `first` on. The instance
+    /// buffer must already be bound.
+    pub(super) fn render<'a>(&'a self, render_pass: &mut RenderPass<'a>, first: u32) {
+        render_pass.set_pipeline(&self.pipeline);
+        for (index, id) in self.draws.iter().enumerate() {
+            if let Some(texture) = self.textures.get(id) {
+                let instance = first + index as u32;
+                render_pass.set_bind_group(0, &texture.bind_group, &[]);
+                render_pass.draw(0..6, instance..instance + 1);
+            }
+        }
+    }
+}
+
+fn upload(
+    device: &Device,
+    queue: &Queue,
+    layout: &BindGroupLayout,
+    sampler: &Sampler,
+    uniforms: &Buffer,
+    image: &TerminalImage,
+) -> ImageTexture {
+    let size = Extent3d {
+        width: image.width(),
+        height: image.height(),
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Inline Image"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8UnormSrgb,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        image.rgba(),
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(4 * image.width()),
+            rows_per_image: Some(image.height()),
+        },
+        size,
+    );
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    let bind_group = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Image Bind Group"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&view),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: uniforms.as_entire_binding(),
+            },
+        ],
+    });
+    ImageTexture {
+        _texture: texture,
+        bind_group,
+        used: true,
+    }
+}
+
+/// `rect` cut to `bounds`, both `[left, top, right, bottom]`, with the
+/// texture coordinates of the part of the image left. `None` if none is.
+pub fn clip_image(rect: [f32; 4], bounds: [f32; 4]) -> Option<([f32; 4], [f32; 4])> {
+    let [left, top, right, bottom] = rect;
+    let clipped = [
+        left.max(bounds[0]),
+        top.max(bounds[1]),
+        right.min(bounds[2]),
+        bottom.min(bounds[3]),
+    ];
+    if clipped[0] >= clipped[2] || clipped[1] >= clipped[3] {
+        return None;
+    }
+    let (width, height) = (right - left, bottom - top);
+    let uv = [
+        (clipped[0] - left) / width,
+        (clipped[1] - top) / height,
+        (clipped[2] - left) / width,
+        (clipped[3] - top) / height,
+    ];
+    Some((clipped, uv))
+}
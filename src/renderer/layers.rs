@@ -0,0 +1,245 @@
+use crate::terminal::selection::Selection;
+use crate::terminal::Color;
+
+/// Viewport cells from `start` to `end` inclusive, as `(column, row)`
+/// pairs in reading order, so a range spanning rows covers the end of the
+/// first and the start of the last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CellRange {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl CellRange {
+    pub fn new(start: (usize, usize), end: (usize, usize)) -> Self {
+        Self { start, end }
+    }
+
+    /// The single cell at `(x, y)`.
+    pub fn cell(x: usize, y: usize) -> Self {
+        Self::new((x, y), (x, y))
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (y, x) >= (self.start.1, self.start.0) && (y, x) <= (self.end.1, self.end.0)
+    }
+
+    fn covers_row(&self, y: usize) -> bool {
+        (self.start.1..=self.end.1).contains(&y)
+    }
+}
+
+impl From<Selection> for CellRange {
+    fn from(selection: Selection) -> Self {
+        Self::new(selection.start, selection.end)
+    }
+}
+
+/// Colors and decoration drawn over a range of cells. Colors left `None`
+/// keep what the layers below produced.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CellRangeHighlight {
+    pub range: CellRange,
+    pub bg: Option<Color>,
+    pub fg: Option<Color>,
+    pub underline: bool,
+    /// Swaps the colors below before `fg` and `bg` apply, as selection and
+    /// the block cursor do.
+    pub inverse: bool,
+}
+
+impl CellRangeHighlight {
+    pub fn colors(range: CellRange, fg: Option<Color>, bg: Option<Color>) -> Self {
+        Self {
+            range,
+            bg,
+            fg,
+            underline: false,
+            inverse: false,
+        }
+    }
+
+    pub fn inverse(range: CellRange) -> Self {
+        Self {
+            inverse: true,
+            ..Self::colors(range, None, None)
+        }
+    }
+
+    pub fn underline(range: CellRange) -> Self {
+        Self {
+            underline: true,
+            ..Self::colors(range, None, None)
+        }
+    }
+}
+
+/// How a cell looks once every layer over it is applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LayeredCell {
+    pub fg: Color,
+    pub bg: Color,
+    pub underline: bool,
+}
+
+/// Highlights the app computes from terminal state each frame, such as
+/// search matches, the selection and the cursor, in drawing order. Later
+/// layers go over earlier ones: their colors replace those below, inverse
+/// swaps whatever the cell has so far, and any layer can add an
+/// underline.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RenderLayers {
+    layers: Vec<CellRangeHighlight>,
+}
+
+impl RenderLayers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, layer: CellRangeHighlight) {
+        self.layers.push(layer);
+    }
+
+    pub fn clear(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// The layers touching row `y`, for composing a row without testing
+    /// every layer at every cell.
+    pub fn on_row(&self, y: usize) -> impl Iterator<Item = &CellRangeHighlight> {
+        self.layers
+            .iter()
+            .filter(move |layer| layer.range.covers_row(y))
+    }
+
+    /// Composes the layers over cell `(x, y)`, whose own colors are `fg`
+    /// and `bg`.
+    pub fn compose(&self, x: usize, y: usize, fg: Color, bg: Color) -> LayeredCell {
+        compose(self.on_row(y), x, y, fg, bg)
+    }
+}
+
+/// Applies `layers` bottom to top over a cell's own colors.
+pub(super) fn compose<'a>(
+    layers: impl IntoIterator<Item = &'a CellRangeHighlight>,
+    x: usize,
+    y: usize,
+    fg: Color,
+    bg: Color,
+) -> LayeredCell {
+    let mut cell = LayeredCell {
+        fg,
+        bg,
+        underline: false,
+    };
+    for layer in layers {
+        if !layer.range.contains(x, y) {
+            continue;
+        }
+        if layer.inverse {
+            std::mem::swap(&mut cell.fg, &mut cell.bg);
+        }
+        cell.fg = layer.fg.unwrap_or(cell.fg);
+        cell.bg = layer.bg.unwrap_or(cell.bg);
+        cell.underline |= layer.underline;
+    }
+    cell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FG: Color = Color(200, 200, 200, 255);
+    const BG: Color = Color(0, 0, 0, 255);
+    const MATCH: Color = Color(255, 200, 0, 255);
+    const SELECTION: Color = Color(60, 90, 200, 255);
+
+    #[test]
+    fn ranges_follow_reading_order_across_rows() {
+        let range = CellRange::new((7, 1), (2, 3));
+        assert!(range.contains(7, 1) && range.contains(9, 1));
+        assert!(!range.contains(6, 1));
+        assert!(range.contains(0, 2) && range.contains(50, 2));
+        assert!(range.contains(2, 3) && !range.contains(3, 3));
+        assert!(!range.contains(0, 0) && !range.contains(0, 4));
+    }
+
+    #[test]
+    fn selection_goes_over_a_search_match() {
+        let mut layers = RenderLayers::new();
+        let search = CellRange::new((2, 0), (6, 0));
+        let selection = CellRange::new((4, 0), (9, 0));
+        layers.push(CellRangeHighlight::colors(search, Some(BG), Some(MATCH)));
+        layers.push(CellRangeHighlight::colors(selection, None, Some(SELECTION)));
+
+        let cell = |x| layers.compose(x, 0, FG, BG);
+        assert_eq!((cell(0).fg, cell(0).bg), (FG, BG));
+        assert_eq!((cell(3).fg, cell(3).bg), (BG, MATCH));
+        // The selection replaces the match background but keeps its text
+        assert_eq!((cell(5).fg, cell(5).bg), (BG, SELECTION));
+        assert_eq!((cell(8).fg, cell(8).bg), (FG, SELECTION));
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let range = CellRange::cell(1, 1);
+        let mut layers = RenderLayers::new();
+        layers.push(CellRangeHighlight::colors(range, None, Some(SELECTION)));
+        layers.push(CellRangeHighlight::colors(range, None, Some(MATCH)));
+        assert_eq!(layers.compose(1, 1, FG, BG).bg, MATCH);
+
+        let mut layers = RenderLayers::new();
+        layers.push(CellRangeHighlight::colors(range, None, Some(MATCH)));
+        layers.push(CellRangeHighlight::colors(range, None, Some(SELECTION)));
+        assert_eq!(layers.compose(1, 1, FG, BG).bg, SELECTION);
+    }
+
+    #[test]
+    fn inverse_swaps_what_the_layers_below_produced() {
+        let range = CellRange::cell(0, 0);
+        let mut layers = RenderLayers::new();
+        layers.push(CellRangeHighlight::colors(range, None, Some(MATCH)));
+        layers.push(CellRangeHighlight::inverse(range));
+        let cell = layers.compose(0, 0, FG, BG);
+        assert_eq!((cell.fg, cell.bg), (MATCH, FG));
+
+        // Two inverses cancel out
+        layers.push(CellRangeHighlight::inverse(range));
+        let cell = layers.compose(0, 0, FG, BG);
+        assert_eq!((cell.fg, cell.bg), (FG, MATCH));
+    }
+
+    #[test]
+    fn any_layer_can_add_an_underline() {
+        let mut layers = RenderLayers::new();
+        layers.push(CellRangeHighlight::underline(CellRange::new(
+            (0, 2),
+            (3, 2),
+        )));
+        layers.push(CellRangeHighlight::colors(
+            CellRange::new((0, 0), (9, 9)),
+            None,
+            Some(SELECTION),
+        ));
+        assert!(layers.compose(2, 2, FG, BG).underline);
+        assert!(!layers.compose(4, 2, FG, BG).underline);
+        assert_eq!(layers.on_row(2).count(), 2);
+        assert_eq!(layers.on_row(10).count(), 0);
+        layers.clear();
+        assert!(layers.is_empty());
+        assert_eq!(
+            layers.compose(2, 2, FG, BG),
+            LayeredCell {
+                fg: FG,
+                bg: BG,
+                underline: false
+            }
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::rc::Rc;
+
+use rustybuzz::{Direction, Face, UnicodeBuffer};
+
+use crate::terminal::rows::Row;
+use crate::terminal::TerminalCell;
+
+/// Shaped rows kept before the cache starts over.
+const MAX_CACHED_ROWS: usize = 4096;
+
+/// A glyph from shaping, placed on the grid rather than by its advance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ShapedGlyph {
+    pub id: u16,
+    /// Column of the first cell of the glyph's cluster.
+    pub col: usize,
+    /// Columns the cluster covers; more than one for a ligature.
+    pub cols: usize,
+    /// Offset from the cluster's origin in font units, for glyphs after the
+    /// first in a cluster and for positioned marks.
+    pub x_offset: f32,
+    pub y_offset: f32,
+}
+
+/// Shapes rows of cells with rustybuzz so fonts can substitute ligatures
+/// and contextual alternates, while the grid stays one cell per column.
+pub struct Shaper {
+    font_data: Vec<u8>,
+    units_per_em: f32,
+    cache: HashMap<u64, Rc<[ShapedGlyph]>>,
+}
+
+impl Shaper {
+    /// Returns `None` if rustybuzz can't parse the font.
+    pub fn new(font_data: &[u8]) -> Option<Self> {
+        let face = Face::from_slice(font_data, 0)?;
+        Some(Self {
+            units_per_em: face.units_per_em() as f32,
+            font_data: font_data.to_vec(),
+            cache: HashMap::new(),
+        })
+    }
+
+    pub fn units_per_em(&self) -> f32 {
+        self.units_per_em
+    }
+
+    /// Shapes `cells` as a single run. Columns are relative to the first
+    /// cell.
+    pub fn shape_cells(&self, cells: &[TerminalCell]) -> Vec<ShapedGlyph> {
+        let mut glyphs = Vec::new();
+        if let Some(face) = Face::from_slice(&self.font_data, 0) {
+            shape_run(&face, cells, 0, false, &mut glyphs);
+        }
+        glyphs
+    }
+
+    /// Shapes a visual row run by run and returns the glyphs that differ
+    /// from drawing each cell's character on its own: ligatures, contextual
+    /// alternates and positioned marks. Runs break on style changes and on
+    /// either side of `cursor`, so a ligature under the cursor falls apart
+    /// while it is edited. Rows are shaped on their own, so nothing joins
+    /// across a soft wrap.
+    pub fn shape_row(
+        &mut self,
+        cells: &[TerminalCell],
+        cursor: Option<usize>,
+    ) -> Rc<[ShapedGlyph]> {
+        let runs = runs(cells, cursor);
+        let mut hasher = DefaultHasher::new();
+        for run in &runs {
+            run.hash(&mut hasher);
+            for cell in &cells[run.clone()] {
+                cell.ch.hash(&mut hasher);
+                cell.combining.hash(&mut hasher);
+                cell.width.hash(&mut hasher);
+            }
+        }
+        let key = hasher.finish();
+        if let Some(glyphs) = self.cache.get(&key) {
+            return glyphs.clone();
+        }
+
+        let mut glyphs = Vec::new();
+        if let Some(face) = Face::from_slice(&self.font_data, 0) {
+            for run in runs {
+                shape_run(&face, &cells[run.clone()], run.start, true, &mut glyphs);
+            }
+        }
+        if self.cache.len() >= MAX_CACHED_ROWS {
+            self.cache.clear();
+        }
+        let glyphs: Rc<[ShapedGlyph]> = glyphs.into();
+        self.cache.insert(key, glyphs.clone());
+        glyphs
+    }
+}
+
+/// Splits a row into runs of cells with the same colors and attributes,
+/// with the cell at `cursor` in a run of its own.
+fn runs(cells: &[TerminalCell], cursor: Option<usize>) -> Vec<Range<usize>> {
+    let cursor_end = cursor.map(|col| {
+        let width = cells.get(col).map_or(1, |cell| cell.width.max(1));
+        col + width as usize
+    });
+    let mut runs = Vec::new();
+    for (_, run) in Row::new(cells).spans() {
+        let mut start = run.start;
+        for split in [cursor, cursor_end].into_iter().flatten() {
+            if start < split && split < run.end {
+                runs.push(start..split);
+                start = split;
+            }
+        }
+        runs.push(start..run.end);
+    }
+    runs
+}
+
+/// Shapes one run starting at column `first_col`, appending its glyphs to
+/// `out`. With `substitutions_only`, glyphs the font would draw for the
+/// cell's character anyway are left out.
+fn shape_run(
+    face: &Face,
+    cells: &[TerminalCell],
+    first_col: usize,
+    substitutions_only: bool,
+    out: &mut Vec<ShapedGlyph>,
+) {
+    // Byte offset in the run's text and column of each cell's cluster
+    let mut starts = Vec::with_capacity(cells.len());
+    let mut text = String::with_capacity(cells.len());
+    for (col, cell) in cells.iter().enumerate() {
+        if cell.is_spacer() {
+            continue;
+        }
+        starts.push((text.len(), col));
+        text.push(cell.ch);
+        text.push_str(cell.combining.as_str());
+    }
+    if text.is_empty() {
+        return;
+    }
+
+    let mut buffer = UnicodeBuffer::new();
+    buffer.push_str(&text);
+    buffer.set_direction(Direction::LeftToRight);
+    let shaped = rustybuzz::shape(face, &[], buffer);
+    let (infos, positions) = (shaped.glyph_infos(), shaped.glyph_positions());
+
+    let cell_of = |cluster: u32| {
+        starts
+            .partition_point(|&(byte, _)| byte <= cluster as usize)
+            .saturating_sub(1)
+    };
+    let mut i = 0;
+    while i < infos.len() {
+        let cluster = infos[i].cluster;
+        let end = (i..infos.len())
+            .find(|&j| infos[j].cluster != cluster)
+            .unwrap_or(infos.len());
+        let first = cell_of(cluster);
+        let last = if end < infos.len() {
+            cell_of(infos[end].cluster)
+        } else {
+            starts.len()
+        };
+        let col = starts[first].1;
+        let end_col = starts.get(last).map_or(cells.len(), |&(_, col)| col);
+        let cols = end_col.saturating_sub(col).max(1);
+
+        let cell = &cells[col];
+        let nominal = end - i == 1
+            && cols == cell.width.max(1) as usize
+            && cell.combining.is_empty()
+            && face.glyph_index(cell.ch).map(|id| id.0 as u32) == Some(infos[i].glyph_id);
+        if !(substitutions_only && nominal) {
+            let mut pen = 0;
+            for (info, position) in infos[i..end].iter().zip(&positions[i..end]) {
+                out.push(ShapedGlyph {
+                    id: info.glyph_id as u16,
+                    col: first_col + col,
+                    cols,
+                    x_offset: (pen + position.x_offset) as f32,
+                    y_offset: position.y_offset as f32,
+                });
+                pen += position.x_advance;
+            }
+        }
+        i = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Terminal;
+
+    /// DejaVu Sans ligates "fi"; the monospace fonts found on CI machines
+    /// usually have no ligatures at all.
+    const LIGATURE_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+    fn shaper() -> Option<Shaper> {
+        Shaper::new(&std::fs::read(LIGATURE_FONT).ok()?)
+    }
+
+    fn cells(text: &[u8]) -> Vec<TerminalCell> {
+        let mut terminal = Terminal::new(20, 1);
+        terminal.process_pty_output(text);
+        (0..20)
+            .filter_map(|x| terminal.get_cell_at(x, 0).copied())
+            .collect()
+    }
+
+    #[test]
+    fn runs_break_on_style_changes_and_around_the_cursor() {
+        let row = cells(b"abc\x1b[1mdef\x1b[mghij");
+        assert_eq!(runs(&row[..10], None), [0..3, 3..6, 6..10]);
+        assert_eq!(runs(&row[..10], Some(7)), [0..3, 3..6, 6..7, 7..8, 8..10]);
+        assert_eq!(runs(&row[..10], Some(3)), [0..3, 3..4, 4..6, 6..10]);
+        // A wide character under the cursor is kept whole
+        let row = cells("a漢b".as_bytes());
+        assert_eq!(runs(&row[..4], Some(1)), [0..1, 1..3, 3..4]);
+    }
+
+    #[test]
+    fn a_ligature_has_fewer_glyphs_than_characters() {
+        let Some(shaper) = shaper() else {
+            eprintln!("skipping: no font with ligatures");
+            return;
+        };
+        let row = cells(b"fi");
+        let glyphs = shaper.shape_cells(&row[..2]);
+        assert_eq!(glyphs.len(), 1);
+        assert_eq!((glyphs[0].col, glyphs[0].cols), (0, 2));
+    }
+
+    #[test]
+    fn the_cursor_splits_a_ligature() {
+        let Some(mut shaper) = shaper() else {
+            eprintln!("skipping: no font with ligatures");
+            return;
+        };
+        let row = cells(b"x fi");
+        let ligature = shaper.shape_row(&row, None);
+        assert_eq!(ligature.len(), 1);
+        assert_eq!((ligature[0].col, ligature[0].cols), (2, 2));
+        // Split apart, each cell draws its own character again
+        assert!(shaper.shape_row(&row, Some(3)).is_empty());
+        assert!(shaper.shape_row(&row, Some(2)).is_empty());
+        assert_eq!(shaper.shape_row(&row, Some(0)), ligature);
+    }
+}
@@ -0,0 +1,76 @@
+use std::io;
+
+use crate::pty::{PtySession, PtySize};
+use crate::terminal::Terminal;
+
+/// A terminal grid and the PTY its program runs on, resized together so
+/// the shell never sees a different size than the grid has.
+pub struct Session<'a> {
+    pub terminal: &'a mut Terminal,
+    /// `None` while replaying a recording.
+    pub pty: Option<&'a PtySession>,
+}
+
+impl<'a> Session<'a> {
+    pub fn new(terminal: &'a mut Terminal, pty: Option<&'a PtySession>) -> Self {
+        Self { terminal, pty }
+    }
+
+    /// Resizes the grid to `cols` by `rows`, gives the PTY the same size
+    /// and sends SIGWINCH to the program in the foreground.
+    pub fn set_size(&mut self, cols: usize, rows: usize) -> io::Result<()> {
+        if cols == 0 || rows == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("terminal size must be non-zero, got {}x{}", cols, rows),
+            ));
+        }
+        let size = PtySize {
+            rows: u16::try_from(rows).unwrap_or(u16::MAX),
+            cols: u16::try_from(cols).unwrap_or(u16::MAX),
+            pixel_width: 0,
+            pixel_height: 0,
+        };
+        self.terminal.resize(size.cols as usize, size.rows as usize);
+        if let Some(pty) = self.pty {
+            pty.resize(size)?;
+            pty.notify_resize()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pty::PtyConfig;
+
+    #[test]
+    fn grid_and_pty_are_resized_together() {
+        let mut terminal = Terminal::new(80, 24);
+        let mut pty = PtySession::spawn(&PtyConfig {
+            shell: "sleep".to_string(),
+            args: vec!["5".to_string()],
+            ..PtyConfig::default()
+        })
+        .unwrap();
+
+        Session::new(&mut terminal, Some(&pty))
+            .set_size(100, 40)
+            .unwrap();
+        assert_eq!((terminal.width, terminal.height), (100, 40));
+        let size = pty.size().unwrap();
+        assert_eq!((size.cols, size.rows), (100, 40));
+        pty.kill();
+    }
+
+    #[test]
+    fn zero_sizes_are_rejected() {
+        let mut terminal = Terminal::new(80, 24);
+        let mut session = Session::new(&mut terminal, None);
+        let error = session.set_size(0, 40).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(session.set_size(100, 0).is_err());
+        assert_eq!((terminal.width, terminal.height), (80, 24));
+    }
+}
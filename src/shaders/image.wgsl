@@ -0,0 +1,58 @@
+// Maps pixels from the grid origin to clip space; `scroll` moves the grid
+// down by part of a line while it scrolls.
+struct Uniforms {
+    surface_size: vec2<f32>,
+    origin: vec2<f32>,
+    scroll: f32,
+};
+
+// One image quad per instance: `rect` and `uv` are [left, top, right,
+// bottom]; `color` tints the image, normally opaque white.
+struct InstanceInput {
+    @location(0) rect: vec4<f32>,
+    @location(1) uv: vec4<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var image_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var image_sampler: sampler;
+@group(0) @binding(2)
+var<uniform> uniforms: Uniforms;
+
+// Corners of the two triangles of a quad, as fractions of its rect.
+const CORNERS = array<vec2<f32>, 6>(
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(1.0, 0.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, 0.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, 1.0),
+);
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, in: InstanceInput) -> VertexOutput {
+    var corners = CORNERS;
+    let corner = corners[index];
+    let pixel = mix(in.rect.xy, in.rect.zw, corner) + uniforms.origin + vec2<f32>(0.0, uniforms.scroll);
+    let ndc = pixel / uniforms.surface_size * 2.0 - 1.0;
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc.x, -ndc.y, 0.0, 1.0);
+    out.tex_coords = mix(in.uv.xy, in.uv.zw, corner);
+    out.color = in.color;
+    return out;
+}
+
+// The texture holds the image's own RGBA, decoded from sRGB on sampling.
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(image_texture, image_sampler, in.tex_coords) * in.color;
+}
@@ -0,0 +1,107 @@
+use std::io;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Process signals relevant to the event loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalEvent {
+    /// SIGINT, SIGTERM or SIGHUP: close sessions and exit.
+    Shutdown,
+    /// SIGWINCH: the window size should be re-queried.
+    Resize,
+}
+
+/// Installs signal listeners that forward into the returned channel.
+///
+/// Must be called from within a Tokio runtime. The returned task handles
+/// should be aborted on shutdown.
+#[cfg(unix)]
+pub fn setup_signal_handlers(
+) -> io::Result<(mpsc::UnboundedReceiver<SignalEvent>, Vec<JoinHandle<()>>)> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let kinds = [
+        (SignalKind::interrupt(), SignalEvent::Shutdown),
+        (SignalKind::terminate(), SignalEvent::Shutdown),
+        (SignalKind::hangup(), SignalEvent::Shutdown),
+        (SignalKind::window_change(), SignalEvent::Resize),
+    ];
+
+    let mut handles = Vec::with_capacity(kinds.len());
+    for (kind, event) in kinds {
+        let mut stream = signal(kind)?;
+        let tx = tx.clone();
+        handles.push(tokio::spawn(async move {
+            while stream.recv().await.is_some() {
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        }));
+    }
+    Ok((rx, handles))
+}
+
+#[cfg(not(unix))]
+pub fn setup_signal_handlers(
+) -> io::Result<(mpsc::UnboundedReceiver<SignalEvent>, Vec<JoinHandle<()>>)> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let handle = tokio::spawn(async move {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if tx.send(SignalEvent::Shutdown).is_err() {
+                break;
+            }
+        }
+    });
+    Ok((rx, vec![handle]))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Raises each of `signals` in this process once the handlers are in
+    /// place, and waits for the event it should turn into. Other tests'
+    /// signals may arrive too, so unrelated events are skipped.
+    fn raise_and_expect(signals: &[(libc::c_int, SignalEvent)]) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (mut events, handles) = setup_signal_handlers().unwrap();
+            for &(signal, expected) in signals {
+                assert_eq!(unsafe { libc::raise(signal) }, 0);
+                let received = tokio::time::timeout(Duration::from_secs(5), async {
+                    while let Some(event) = events.recv().await {
+                        if event == expected {
+                            return true;
+                        }
+                    }
+                    false
+                });
+                assert!(received.await.unwrap_or(false), "signal {}", signal);
+            }
+            for handle in handles {
+                handle.abort();
+            }
+        });
+    }
+
+    #[test]
+    fn window_changes_are_resizes() {
+        raise_and_expect(&[(libc::SIGWINCH, SignalEvent::Resize)]);
+    }
+
+    #[test]
+    fn termination_and_hangups_shut_down() {
+        raise_and_expect(&[
+            (libc::SIGTERM, SignalEvent::Shutdown),
+            (libc::SIGHUP, SignalEvent::Shutdown),
+        ]);
+    }
+}
@@ -0,0 +1,2307 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use unicode_width::UnicodeWidthChar;
+
+mod ansi;
+mod dcs;
+pub mod diff;
+pub mod events;
+pub mod grapheme;
+pub mod images;
+mod kitty;
+pub mod line_editor;
+pub mod mouse;
+pub mod output;
+mod overflow;
+mod persist;
+mod reset;
+pub mod rows;
+pub mod selection;
+pub mod shared;
+pub mod sixel;
+pub mod title;
+pub mod triggers;
+
+use dcs::{DcsString, DCS_PAYLOAD_LIMIT};
+use events::TerminalEvent;
+use grapheme::Combining;
+use images::{ImagePlacement, ImageUpload};
+use kitty::{Apc, ImageStore, KittyUpload};
+use line_editor::LineEditor;
+use mouse::{MouseEncoding, MouseTracking};
+use output::TerminalOutput;
+use rows::Row;
+use selection::{Selection, DEFAULT_WORD_SEPARATORS};
+use sixel::SixelDecoder;
+use title::TitleTemplate;
+use triggers::{Trigger, TriggerEvent, TriggerHighlight};
+
+pub const DEFAULT_SCROLLBACK_LIMIT: usize = 10_000;
+/// Flow control characters: XON (Ctrl-Q) resumes output, XOFF (Ctrl-S)
+/// pauses it.
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+const DEFAULT_TAB_WIDTH: usize = 8;
+/// Maximum depth of the XTWINOPS title stack, matching xterm.
+const TITLE_STACK_LIMIT: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color(pub u8, pub u8, pub u8, pub u8);
+
+impl Color {
+    pub const BLACK: Color = Color(0, 0, 0, 255);
+    pub const WHITE: Color = Color(255, 255, 255, 255);
+
+    /// Resolves one of the 16 standard ANSI colors (xterm defaults).
+    pub fn from_ansi_color(index: u8) -> Color {
+        match index {
+            0 => Color(0, 0, 0, 255),
+            1 => Color(205, 0, 0, 255),
+            2 => Color(0, 205, 0, 255),
+            3 => Color(205, 205, 0, 255),
+            4 => Color(0, 0, 238, 255),
+            5 => Color(205, 0, 205, 255),
+            6 => Color(0, 205, 205, 255),
+            7 => Color(229, 229, 229, 255),
+            8 => Color(127, 127, 127, 255),
+            9 => Color(255, 0, 0, 255),
+            10 => Color(0, 255, 0, 255),
+            11 => Color(255, 255, 0, 255),
+            12 => Color(92, 92, 255, 255),
+            13 => Color(255, 0, 255, 255),
+            14 => Color(0, 255, 255, 255),
+            _ => Color(255, 255, 255, 255),
+        }
+    }
+
+    /// The 16 ANSI colors as xterm draws them by default.
+    pub fn ansi_palette() -> [Color; 16] {
+        std::array::from_fn(|index| Color::from_ansi_color(index as u8))
+    }
+
+    /// Resolves an index into xterm's 256-color palette: the 16 ANSI
+    /// colors, a 6x6x6 color cube from 16 to 231 and a 24-step grayscale
+    /// ramp from 232 to 255. [`Terminal::indexed_color`] takes the first 16
+    /// from the configured palette instead.
+    pub fn from_256(index: u8) -> Color {
+        match index {
+            0..=15 => Color::from_ansi_color(index),
+            16..=231 => {
+                let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+                let cube = index - 16;
+                Color(level(cube / 36), level(cube / 6 % 6), level(cube % 6), 255)
+            }
+            _ => {
+                let gray = 8 + (index - 232) * 10;
+                Color(gray, gray, gray, 255)
+            }
+        }
+    }
+
+    /// Parses `#rgb`, `#rrggbb` or `#rrggbbaa`.
+    pub fn from_hex(text: &str) -> Option<Color> {
+        let hex = text.strip_prefix('#')?;
+        if !hex.is_ascii() {
+            return None;
+        }
+        let byte = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok();
+        match hex.len() {
+            3 => {
+                // Each digit is repeated, so #fc0 is #ffcc00
+                let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok().map(|d| d * 17);
+                Some(Color(digit(0)?, digit(1)?, digit(2)?, 255))
+            }
+            6 => Some(Color(byte(0)?, byte(1)?, byte(2)?, 255)),
+            8 => Some(Color(byte(0)?, byte(1)?, byte(2)?, byte(3)?)),
+            _ => None,
+        }
+    }
+
+    /// Formats the color as `#rrggbb`, or `#rrggbbaa` when it isn't opaque.
+    pub fn to_hex(&self) -> String {
+        let Color(r, g, b, a) = *self;
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+
+    /// Parses an X11 color as xterm's OSC color sequences take it:
+    /// `rgb:r/g/b` with one to four hex digits per channel, or `#rgb`,
+    /// `#rrggbb`, `#rrrgggbbb` and `#rrrrggggbbbb`.
+    pub fn from_rgb_spec(spec: &str) -> Option<Color> {
+        // Scales a channel of `digits` hex digits to 8 bits
+        let channel = |hex: &str| {
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            let max = (1u32 << (4 * hex.len())) - 1;
+            Some((value * 255 / max) as u8)
+        };
+        let channels: Vec<&str> = if let Some(rgb) = spec.strip_prefix("rgb:") {
+            rgb.split('/').collect()
+        } else {
+            let hex = spec.strip_prefix('#')?;
+            if hex.is_empty() || hex.len() % 3 != 0 || !hex.is_ascii() {
+                return None;
+            }
+            let digits = hex.len() / 3;
+            (0..3).map(|i| &hex[i * digits..(i + 1) * digits]).collect()
+        };
+        match channels[..] {
+            [r, g, b] if [r, g, b].iter().all(|c| (1..=4).contains(&c.len())) => {
+                Some(Color(channel(r)?, channel(g)?, channel(b)?, 255))
+            }
+            _ => None,
+        }
+    }
+
+    /// Relative luminance as WCAG defines it, from 0 for black to 1 for
+    /// white. Alpha is ignored.
+    pub fn relative_luminance(&self) -> f32 {
+        let linear = |channel: u8| {
+            let c = channel as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+        0.2126 * linear(self.0) + 0.7152 * linear(self.1) + 0.0722 * linear(self.2)
+    }
+
+    /// WCAG contrast ratio between the two colors, from 1 for the same
+    /// luminance to 21 for black on white.
+    pub fn contrast_ratio(&self, other: Color) -> f32 {
+        let (a, b) = (self.relative_luminance(), other.relative_luminance());
+        (a.max(b) + 0.05) / (a.min(b) + 0.05)
+    }
+
+    /// The color lightened or darkened just enough to reach `ratio`
+    /// against `background`, moving away from the background's luminance
+    /// first. Where neither direction reaches it, the one with the most
+    /// contrast is taken.
+    pub fn with_min_contrast(self, background: Color, ratio: f32) -> Color {
+        if self.contrast_ratio(background) >= ratio {
+            return self;
+        }
+        let lighter_first = self.relative_luminance() >= background.relative_luminance();
+        let targets = if lighter_first {
+            [Color::WHITE, Color::BLACK]
+        } else {
+            [Color::BLACK, Color::WHITE]
+        };
+        let Some(target) = targets
+            .into_iter()
+            .find(|&target| target.contrast_ratio(background) >= ratio)
+        else {
+            let best = if Color::WHITE.contrast_ratio(background)
+                >= Color::BLACK.contrast_ratio(background)
+            {
+                Color::WHITE
+            } else {
+                Color::BLACK
+            };
+            return Color(best.0, best.1, best.2, self.3);
+        };
+        // Bisects the least mix toward the target that is readable
+        let (mut low, mut high) = (0.0, 1.0);
+        for _ in 0..16 {
+            let mid = (low + high) / 2.0;
+            if self.mix(target, mid).contrast_ratio(background) >= ratio {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        self.mix(target, high)
+    }
+
+    /// The color moved `amount` of the way toward `other`, keeping its alpha.
+    fn mix(self, other: Color, amount: f32) -> Color {
+        let channel =
+            |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * amount).round() as u8;
+        Color(
+            channel(self.0, other.0),
+            channel(self.1, other.1),
+            channel(self.2, other.2),
+            self.3,
+        )
+    }
+}
+
+/// How a cell is underlined, set by SGR 4 and its `4:n` forms.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnderlineStyle {
+    #[default]
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl UnderlineStyle {
+    /// The style selected by `SGR 4:n`, if `n` is a known one.
+    pub fn from_sgr(n: u16) -> Option<Self> {
+        Some(match n {
+            0 => UnderlineStyle::None,
+            1 => UnderlineStyle::Single,
+            2 => UnderlineStyle::Double,
+            3 => UnderlineStyle::Curly,
+            4 => UnderlineStyle::Dotted,
+            5 => UnderlineStyle::Dashed,
+            _ => return None,
+        })
+    }
+
+    /// `n` in the `SGR 4:n` that selects this style.
+    pub fn sgr(self) -> u16 {
+        match self {
+            UnderlineStyle::None => 0,
+            UnderlineStyle::Single => 1,
+            UnderlineStyle::Double => 2,
+            UnderlineStyle::Curly => 3,
+            UnderlineStyle::Dotted => 4,
+            UnderlineStyle::Dashed => 5,
+        }
+    }
+}
+
+/// How fast a cell blinks: SGR 5 selects slow blink, SGR 6 rapid.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Blink {
+    #[default]
+    None,
+    Slow,
+    Rapid,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellAttributes {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: UnderlineStyle,
+    pub blink: Blink,
+    pub inverse: bool,
+    /// A line along the top of the cell (SGR 53).
+    pub overline: bool,
+    /// Color of the underline when it differs from the text (SGR 58).
+    pub underline_color: Option<Color>,
+    /// Which of the 8 base colors the foreground was set to by SGR 30–37,
+    /// the only colors bold brightens. Equal colors given by index or as
+    /// RGB are left alone.
+    pub base_fg: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TerminalCell {
+    /// First character of the cell's grapheme cluster.
+    pub ch: char,
+    /// Rest of the cluster, usually empty.
+    pub combining: Combining,
+    pub fg_color: Color,
+    pub bg_color: Color,
+    pub attrs: CellAttributes,
+    /// Set on the last cell of a row whose text continues on the next row
+    /// because of autowrap.
+    pub wrapped: bool,
+    /// Columns taken by the glyph: 2 for a wide character, 0 for the spacer
+    /// cell that follows one.
+    pub width: u8,
+}
+
+impl Default for TerminalCell {
+    fn default() -> Self {
+        Self {
+            ch: ' ',
+            combining: Combining::EMPTY,
+            fg_color: Color::WHITE,
+            bg_color: Color::BLACK,
+            attrs: CellAttributes::default(),
+            wrapped: false,
+            width: 1,
+        }
+    }
+}
+
+impl TerminalCell {
+    /// Whether this cell is the right half of a wide character.
+    pub fn is_spacer(&self) -> bool {
+        self.width == 0
+    }
+}
+
+/// Number of columns `ch` takes on screen, 1 or 2.
+pub fn char_width(ch: char) -> usize {
+    if ch.width() == Some(2) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Tab stops every `DEFAULT_TAB_WIDTH` columns.
+fn default_tab_stops(width: usize) -> Vec<usize> {
+    (DEFAULT_TAB_WIDTH..width)
+        .step_by(DEFAULT_TAB_WIDTH)
+        .collect()
+}
+
+pub type TerminalBuffer = Vec<Vec<TerminalCell>>;
+
+/// Mouse wheel movement, either in notches (lines) or in pixels from a touchpad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScrollDelta {
+    Lines(f32),
+    Pixels(f32),
+}
+
+/// Viewport position within the scrollback, in lines above the live screen.
+/// `offset` animates towards `target_offset` in `update`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScrollState {
+    pub offset: f32,
+    pub target_offset: f32,
+}
+
+/// What a user sees of a terminal besides its contents, saved when a
+/// session is switched away from and restored when it comes back.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ViewState {
+    pub scroll_state: ScrollState,
+    pub selection: Option<Selection>,
+    pub h_scroll_offset: usize,
+}
+
+/// How wheel and touchpad input move the viewport.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScrollConfig {
+    /// Rate at which `offset` approaches `target_offset`, per second.
+    pub speed: f32,
+    /// Fraction of each animation step that is applied.
+    pub damping: f32,
+    pub lines_per_notch: f32,
+    /// Lines scrolled per pixel of touchpad movement.
+    pub pixel_factor: f32,
+    /// Animate towards the target instead of jumping to it.
+    pub smooth: bool,
+}
+
+impl Default for ScrollConfig {
+    fn default() -> Self {
+        Self {
+            speed: 8.0,
+            damping: 0.9,
+            lines_per_notch: 3.0,
+            pixel_factor: 0.1,
+            smooth: true,
+        }
+    }
+}
+
+/// How the cursor blinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorBlink {
+    /// Blink until a program turns it off with DECRST 12.
+    pub enabled: bool,
+    /// How long the cursor stays shown, and then hidden.
+    pub interval_ms: u64,
+    /// Never blink, whatever programs ask for.
+    pub force_off: bool,
+}
+
+impl Default for CursorBlink {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 500,
+            force_off: false,
+        }
+    }
+}
+
+/// Events that may deserve the user's attention when the window is unfocused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttentionEvent {
+    Bell,
+    /// A command reported through shell integration (OSC 133) finished.
+    CommandFinished {
+        duration: Duration,
+        exit_code: Option<i32>,
+    },
+}
+
+/// Window information supplied by the application for XTWINOPS reports.
+pub trait TerminalHost: Send {
+    /// Size of the text area in pixels as `(width, height)`.
+    fn text_area_size(&self) -> (u32, u32);
+    /// Size of a single cell in pixels as `(width, height)`.
+    fn cell_size(&self) -> (u32, u32);
+}
+
+/// Modes and saved state driven by escape sequences.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TerminalState {
+    /// LNM (mode 20): line feeds also return the carriage.
+    pub newline_mode: bool,
+    /// IRM (mode 4): printed characters shift the rest of the line right.
+    pub insert_mode: bool,
+    /// DECOM (?6)
+    pub origin_mode: bool,
+    /// DECAWM (?7)
+    pub autowrap: bool,
+    /// DECTCEM (?25)
+    pub cursor_visible: bool,
+    /// Cursor blinking (?12), as a program last set it.
+    pub cursor_blink: bool,
+    /// DECCKM (?1)
+    pub application_cursor_keys: bool,
+    /// Bracketed paste (?2004)
+    pub bracketed_paste: bool,
+    /// DECSCNM (?5): swap the default foreground and background on screen.
+    pub reverse_screen: bool,
+    /// Mouse reporting (1000, 1002, 1003).
+    pub mouse_tracking: MouseTracking,
+    /// Mouse report encoding (1005, 1006, 1015).
+    pub mouse_encoding: MouseEncoding,
+    /// XOFF (Ctrl-S) in the output pauses it until XON (Ctrl-Q) (1035).
+    pub flow_control: bool,
+    /// DECSDM (?80): sixel images go in the top left corner without moving
+    /// the cursor, instead of at the cursor.
+    pub sixel_display: bool,
+    /// DECSC state of the primary and the alternate screen.
+    pub saved_cursor: Option<SavedContext>,
+    pub alternate_saved_cursor: Option<SavedContext>,
+}
+
+impl Default for TerminalState {
+    fn default() -> Self {
+        Self {
+            newline_mode: false,
+            insert_mode: false,
+            origin_mode: false,
+            autowrap: true,
+            cursor_visible: true,
+            cursor_blink: false,
+            application_cursor_keys: false,
+            bracketed_paste: false,
+            reverse_screen: false,
+            mouse_tracking: MouseTracking::Off,
+            mouse_encoding: MouseEncoding::X10,
+            flow_control: false,
+            sixel_display: false,
+            saved_cursor: None,
+            alternate_saved_cursor: None,
+        }
+    }
+}
+
+/// Cursor state saved by DECSC (ESC 7, CSI s) and restored by DECRC
+/// (ESC 8, CSI u).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedContext {
+    /// Equal to the width while a wrap is pending after the last column.
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: CellAttributes,
+    pub origin_mode: bool,
+}
+
+pub struct Terminal {
+    pub width: usize,
+    pub height: usize,
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    buffer: TerminalBuffer,
+    history: VecDeque<Vec<TerminalCell>>,
+    pub scrollback_limit: usize,
+    pub scroll_state: ScrollState,
+    /// Columns the viewport is scrolled right by, before clamping to the
+    /// widest row in view.
+    h_scroll_offset: usize,
+    /// Cells printed past the right margin with autowrap off, by the line
+    /// of the primary screen they belong to, counted like image lines.
+    overflow: BTreeMap<usize, Vec<TerminalCell>>,
+    /// Keep output past the right margin with autowrap off on the primary
+    /// screen, to be scrolled to sideways, instead of overwriting the last
+    /// column as DECAWM specifies.
+    pub keep_overflow: bool,
+    scroll_config: ScrollConfig,
+    /// Snap the viewport to the bottom when new output arrives while scrolled up.
+    pub scroll_on_output: bool,
+    /// Snap the viewport to the bottom when the user types.
+    pub scroll_on_keystroke: bool,
+    alternate_screen: bool,
+    saved_primary: Option<(TerminalBuffer, usize, usize)>,
+    pending_input: Vec<u8>,
+    last_update: Instant,
+    parser: vte::Parser,
+    /// Device control string being received, if any.
+    dcs: Option<DcsString>,
+    /// Longest DCS payload buffered before the string is dropped.
+    dcs_payload_limit: usize,
+    /// Sixel image being received, if any.
+    sixel: Option<Box<SixelDecoder>>,
+    pub state: TerminalState,
+    pub title: String,
+    /// Template the window title is formatted with, if any.
+    title_template: Option<TitleTemplate>,
+    /// Directory and host the shell last reported with OSC 7; the host is
+    /// `None` for the local machine.
+    working_directory: Option<PathBuf>,
+    remote_host: Option<String>,
+    /// Scrolling region rows, inclusive (DECSTBM).
+    pub scroll_top: usize,
+    pub scroll_bottom: usize,
+    tabs: Vec<usize>,
+    current_fg: Color,
+    current_bg: Color,
+    current_attrs: CellAttributes,
+    /// Total lines ever pushed into the history, used to track output scrolling.
+    lines_scrolled: usize,
+    host: Option<Box<dyn TerminalHost>>,
+    /// Receives responses; without one they are queued in `pending_input`.
+    output: Option<Box<dyn TerminalOutput>>,
+    title_stack: Vec<String>,
+    /// Allow programs to resize the window with XTWINOPS (CSI 4/8 t).
+    pub allow_window_resize: bool,
+    window_resize_request: Option<(usize, usize)>,
+    /// Fill erased cells with the current background color rather than
+    /// the default one (xterm's `bce`).
+    pub background_color_erase: bool,
+    /// Draw bold text in one of the 8 base colors with its bright variant,
+    /// as xterm does.
+    pub bold_is_bright: bool,
+    /// The 16 ANSI colors SGR 30–37, 90–97 and indices 0–15 of the
+    /// 256-color palette set.
+    palette: [Color; 16],
+    /// Least contrast ratio between the colors text is drawn with; `None`
+    /// draws them as programs set them.
+    min_contrast: Option<f32>,
+    /// Trailing bytes of an incomplete UTF-8 sequence from the last `write_bytes`.
+    utf8_pending: Vec<u8>,
+    /// Colors of blank cells and of text without SGR colors.
+    default_fg: Color,
+    default_bg: Color,
+    /// Fill of the block cursor, `None` to invert the cell under it. OSC 12
+    /// changes the current one and OSC 112 restores the configured one.
+    cursor_color: Option<Color>,
+    configured_cursor_color: Option<Color>,
+    /// Fill of the cursor while the window is unfocused.
+    cursor_color_unfocused: Option<Color>,
+    cursor_blink: CursorBlink,
+    cursor_blink_visible: bool,
+    local_echo: bool,
+    line_editor: LineEditor,
+    /// Where the line being edited locally starts on screen.
+    local_origin: Option<(usize, usize)>,
+    triggers: Vec<Trigger>,
+    trigger_events: Vec<TriggerEvent>,
+    trigger_highlights: Vec<TriggerHighlight>,
+    /// When the running command started producing output (OSC 133 C).
+    command_started: Option<Instant>,
+    attention_events: Vec<AttentionEvent>,
+    /// Output after an XOFF, held until XON.
+    flow_paused: bool,
+    held_output: Vec<u8>,
+    event_senders: Vec<Sender<TerminalEvent>>,
+    /// Viewport rows and cursor as of the last `take_damage`.
+    damage_frame: TerminalBuffer,
+    damage_cursor: Option<(usize, usize)>,
+    /// Rows of the last snapshot, shared with the next one where unchanged.
+    snapshot_rows: RefCell<Vec<Arc<[TerminalCell]>>>,
+    /// Start of the first blink phase, and whether slow and rapidly
+    /// blinking text was shown in the phases of the last `needs_redraw`.
+    blink_epoch: Instant,
+    blink_visible: bool,
+    rapid_blink_visible: bool,
+    selection: Option<Selection>,
+    /// When the synchronized update (mode 2026) in progress began.
+    synchronized_since: Option<Instant>,
+    /// Characters besides whitespace that end a word when double-clicking.
+    word_separators: String,
+    /// Inline images over the grid, oldest first.
+    images: Vec<ImagePlacement>,
+    /// An OSC 1337 multipart file being received.
+    image_upload: Option<ImageUpload>,
+    /// APC string being received, which vte skips.
+    apc: Apc,
+    /// Images transmitted with the kitty graphics protocol.
+    kitty_images: ImageStore,
+    /// A chunked kitty graphics transmission being received.
+    kitty_upload: Option<KittyUpload>,
+}
+
+impl Terminal {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::new_with_colors(width, height, Color::WHITE, Color::BLACK)
+    }
+
+    pub fn new_with_colors(
+        width: usize,
+        height: usize,
+        default_fg: Color,
+        default_bg: Color,
+    ) -> Self {
+        let blank = TerminalCell {
+            fg_color: default_fg,
+            bg_color: default_bg,
+            ..TerminalCell::default()
+        };
+        Self {
+            width,
+            height,
+            cursor_x: 0,
+            cursor_y: 0,
+            buffer: vec![vec![blank; width]; height],
+            history: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK_LIMIT,
+            scroll_state: ScrollState::default(),
+            h_scroll_offset: 0,
+            overflow: BTreeMap::new(),
+            keep_overflow: false,
+            scroll_config: ScrollConfig::default(),
+            scroll_on_output: false,
+            scroll_on_keystroke: true,
+            alternate_screen: false,
+            saved_primary: None,
+            pending_input: Vec::new(),
+            last_update: Instant::now(),
+            parser: vte::Parser::new(),
+            dcs: None,
+            dcs_payload_limit: DCS_PAYLOAD_LIMIT,
+            sixel: None,
+            state: TerminalState::default(),
+            title: String::new(),
+            title_template: None,
+            working_directory: None,
+            remote_host: None,
+            scroll_top: 0,
+            scroll_bottom: height.saturating_sub(1),
+            tabs: default_tab_stops(width),
+            current_fg: default_fg,
+            current_bg: default_bg,
+            current_attrs: CellAttributes::default(),
+            lines_scrolled: 0,
+            host: None,
+            output: None,
+            title_stack: Vec::new(),
+            allow_window_resize: false,
+            window_resize_request: None,
+            background_color_erase: true,
+            bold_is_bright: true,
+            palette: Color::ansi_palette(),
+            min_contrast: None,
+            utf8_pending: Vec::new(),
+            default_fg,
+            default_bg,
+            cursor_color: None,
+            configured_cursor_color: None,
+            cursor_color_unfocused: None,
+            cursor_blink: CursorBlink::default(),
+            cursor_blink_visible: true,
+            local_echo: false,
+            line_editor: LineEditor::new(),
+            local_origin: None,
+            triggers: Vec::new(),
+            trigger_events: Vec::new(),
+            trigger_highlights: Vec::new(),
+            command_started: None,
+            attention_events: Vec::new(),
+            flow_paused: false,
+            held_output: Vec::new(),
+            event_senders: Vec::new(),
+            damage_frame: Vec::new(),
+            damage_cursor: None,
+            snapshot_rows: RefCell::new(Vec::new()),
+            blink_epoch: Instant::now(),
+            blink_visible: true,
+            rapid_blink_visible: true,
+            selection: None,
+            synchronized_since: None,
+            word_separators: DEFAULT_WORD_SEPARATORS.to_string(),
+            images: Vec::new(),
+            image_upload: None,
+            apc: Apc::default(),
+            kitty_images: ImageStore::default(),
+            kitty_upload: None,
+        }
+    }
+
+    /// Default `(foreground, background)` colors.
+    pub fn default_colors(&self) -> (Color, Color) {
+        (self.default_fg, self.default_bg)
+    }
+
+    /// Colors the block cursor, replacing any color a program set with
+    /// OSC 12. `None` draws it by inverting the cell under it.
+    pub fn set_cursor_color(&mut self, color: Option<Color>) {
+        self.configured_cursor_color = color;
+        self.set_program_cursor_color(color);
+    }
+
+    pub fn cursor_color(&self) -> Option<Color> {
+        self.cursor_color
+    }
+
+    /// Colors the cursor while the window is unfocused.
+    pub fn set_cursor_color_unfocused(&mut self, color: Option<Color>) {
+        self.cursor_color_unfocused = color;
+        self.damage_frame.clear();
+    }
+
+    pub fn cursor_color_unfocused(&self) -> Option<Color> {
+        self.cursor_color_unfocused
+    }
+
+    /// Sets how the cursor blinks, starting or stopping it as `enabled`
+    /// says until a program sets DECSET 12.
+    pub fn set_cursor_blink(&mut self, blink: CursorBlink) {
+        self.cursor_blink = blink;
+        self.state.cursor_blink = blink.enabled;
+    }
+
+    pub fn cursor_blink(&self) -> CursorBlink {
+        self.cursor_blink
+    }
+
+    /// Whether the cursor blinks: programs choose with DECSET 12 unless the
+    /// user turned blinking off for good.
+    pub fn cursor_blinks(&self) -> bool {
+        self.state.cursor_blink && !self.cursor_blink.force_off
+    }
+
+    fn set_program_cursor_color(&mut self, color: Option<Color>) {
+        if self.cursor_color != color {
+            self.cursor_color = color;
+            // The cursor row isn't damaged by a color change
+            self.damage_frame.clear();
+        }
+    }
+
+    pub fn set_host(&mut self, host: Box<dyn TerminalHost>) {
+        self.host = Some(host);
+    }
+
+    /// Returns the `(cols, rows)` a program asked the window to be resized to.
+    pub fn take_window_resize_request(&mut self) -> Option<(usize, usize)> {
+        self.window_resize_request.take()
+    }
+
+    /// Whether the text of `row` continues on the following row.
+    pub fn is_wrapped(row: &[TerminalCell]) -> bool {
+        Row::new(row).is_wrapped()
+    }
+
+    /// Drains bells and command completions since the last call.
+    pub fn take_attention_events(&mut self) -> Vec<AttentionEvent> {
+        std::mem::take(&mut self.attention_events)
+    }
+
+    /// Lightens or darkens text where needed so it is drawn with at least
+    /// `ratio` of WCAG contrast against its background, e.g. 4.5. Cells
+    /// keep the colors programs set; a ratio of 1 or less turns it off.
+    pub fn set_min_contrast(&mut self, ratio: f32) {
+        let ratio = (ratio > 1.0).then_some(ratio);
+        if self.min_contrast != ratio {
+            self.min_contrast = ratio;
+            self.damage_frame.clear();
+        }
+    }
+
+    pub fn min_contrast(&self) -> Option<f32> {
+        self.min_contrast
+    }
+
+    /// Foreground and background a cell should be drawn with, after applying
+    /// bold brightening, the inverse attribute, reverse screen mode and the
+    /// minimum contrast.
+    /// Replaces the 16 ANSI colors. Text already written keeps its colors.
+    pub fn set_palette(&mut self, palette: [Color; 16]) {
+        self.palette = palette;
+    }
+
+    /// Resolves an index into the 256-color palette, the first 16 from the
+    /// configured palette.
+    pub fn indexed_color(&self, index: u8) -> Color {
+        match self.palette.get(index as usize) {
+            Some(&color) => color,
+            None => Color::from_256(index),
+        }
+    }
+
+    pub fn resolve_colors(&self, cell: &TerminalCell) -> (Color, Color) {
+        let (mut fg, mut bg) = (cell.fg_color, cell.bg_color);
+        if self.bold_is_bright && cell.attrs.bold {
+            if let Some(index) = cell.attrs.base_fg {
+                fg = self.palette[index as usize + 8];
+            }
+        }
+        // Reverse screen mode inverts every cell, so inverse cells come out
+        // in their normal colors, as in xterm
+        if cell.attrs.inverse != self.state.reverse_screen {
+            std::mem::swap(&mut fg, &mut bg);
+        }
+        if let Some(ratio) = self.min_contrast {
+            fg = fg.with_min_contrast(bg, ratio);
+        }
+        (fg, bg)
+    }
+
+    /// Color behind the grid: the default background, or the default
+    /// foreground in reverse screen mode (DECSCNM).
+    pub fn screen_background(&self) -> Color {
+        if self.state.reverse_screen {
+            self.default_fg
+        } else {
+            self.default_bg
+        }
+    }
+
+    /// The screen rows as stored.
+    ///
+    /// Deprecated: [`Terminal::iter_rows`] and [`Terminal::line_text`] read
+    /// the screen without tying callers to its storage.
+    #[deprecated(note = "use iter_rows, line_text or get_cell_at")]
+    pub fn get_buffer(&self) -> &TerminalBuffer {
+        &self.buffer
+    }
+
+    pub fn get_cell_at(&self, x: usize, y: usize) -> Option<&TerminalCell> {
+        self.buffer.get(y).and_then(|row| row.get(x))
+    }
+
+    /// Like `get_cell_at`, but resolves the spacer half of a wide character
+    /// to the cell holding the glyph. Returns that cell's column with it.
+    pub fn cell_and_origin_at(&self, x: usize, y: usize) -> Option<(usize, &TerminalCell)> {
+        let row = self.buffer.get(y)?;
+        let cell = row.get(x)?;
+        if cell.is_spacer() && x > 0 {
+            Some((x - 1, &row[x - 1]))
+        } else {
+            Some((x, cell))
+        }
+    }
+
+    /// Columns taken by the glyph covering `(x, y)`.
+    pub fn cell_width_at(&self, x: usize, y: usize) -> Option<usize> {
+        self.cell_and_origin_at(x, y)
+            .map(|(_, cell)| cell.width.max(1) as usize)
+    }
+
+    /// Returns the row shown at viewport line `y`, taking the scroll offset
+    /// into account.
+    pub fn visible_row(&self, y: usize) -> Option<&[TerminalCell]> {
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        if y < offset {
+            self.history
+                .get(self.history.len() - offset + y)
+                .map(Vec::as_slice)
+        } else {
+            self.buffer.get(y - offset).map(Vec::as_slice)
+        }
+    }
+
+    /// Viewport position of the cursor, if it is shown and not scrolled
+    /// out of view.
+    pub fn visible_cursor(&self) -> Option<(usize, usize)> {
+        if !self.state.cursor_visible {
+            return None;
+        }
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        let y = self.cursor_y + offset;
+        (y < self.height).then_some((self.cursor_x.min(self.width.saturating_sub(1)), y))
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    /// Empties the scrollback and returns the viewport to the live screen,
+    /// which is left as it is. The history's memory is released.
+    pub fn clear_scrollback(&mut self) {
+        if self.scroll_state != ScrollState::default() {
+            // The selection may cover lines that are gone now
+            self.selection = None;
+        }
+        self.history = VecDeque::new();
+        self.trim_images();
+        self.snap_to_bottom();
+        // Repaint every row
+        self.damage_frame.clear();
+    }
+
+    /// Approximate bytes held by the screen and the scrollback.
+    pub fn grid_memory(&self) -> usize {
+        let cells: usize = self
+            .buffer
+            .iter()
+            .chain(self.history.iter())
+            .map(Vec::capacity)
+            .sum();
+        cells * std::mem::size_of::<TerminalCell>()
+    }
+
+    /// Returns a line of scrollback, where index 0 is the oldest line.
+    pub fn get_line_from_history(&self, index: usize) -> Option<&Vec<TerminalCell>> {
+        self.history.get(index)
+    }
+
+    /// Text of a scrollback line, where index 0 is the oldest line, with
+    /// trailing blanks trimmed.
+    pub fn history_line_text(&self, index: usize) -> Option<String> {
+        self.history.get(index).map(|line| Terminal::row_text(line))
+    }
+
+    /// Text of every scrollback line, oldest first.
+    pub fn iter_scrollback(&self) -> impl Iterator<Item = String> + '_ {
+        self.history.iter().map(|line| Terminal::row_text(line))
+    }
+
+    /// The grapheme clusters of a row, skipping wide-character spacers.
+    pub fn row_text(cells: &[TerminalCell]) -> String {
+        Row::new(cells).text()
+    }
+
+    pub fn is_alternate_screen(&self) -> bool {
+        self.alternate_screen
+    }
+
+    /// The saved cursor slot of the screen in use.
+    fn saved_cursor_slot(&mut self) -> &mut Option<SavedContext> {
+        if self.alternate_screen {
+            &mut self.state.alternate_saved_cursor
+        } else {
+            &mut self.state.saved_cursor
+        }
+    }
+
+    /// DECSC: saves the cursor position, rendition and origin mode for the
+    /// screen in use.
+    pub fn save_cursor(&mut self) {
+        let context = SavedContext {
+            cursor_x: self.cursor_x,
+            cursor_y: self.cursor_y,
+            fg: self.current_fg,
+            bg: self.current_bg,
+            attrs: self.current_attrs,
+            origin_mode: self.state.origin_mode,
+        };
+        *self.saved_cursor_slot() = Some(context);
+    }
+
+    /// DECRC: restores what `save_cursor` saved for the screen in use. With
+    /// nothing saved the cursor goes home with the default rendition.
+    pub fn restore_cursor(&mut self) {
+        let context = self.saved_cursor_slot().unwrap_or(SavedContext {
+            cursor_x: 0,
+            cursor_y: 0,
+            fg: self.default_fg,
+            bg: self.default_bg,
+            attrs: CellAttributes::default(),
+            origin_mode: false,
+        });
+        self.cursor_x = context.cursor_x.min(self.width);
+        self.cursor_y = context.cursor_y.min(self.height - 1);
+        self.current_fg = context.fg;
+        self.current_bg = context.bg;
+        self.current_attrs = context.attrs;
+        self.state.origin_mode = context.origin_mode;
+    }
+
+    /// Resizes the grid to `cols` by `rows`. Rows are cut or padded on the
+    /// right, not rewrapped. When the screen gets shorter, rows above the
+    /// cursor go to the scrollback first, then rows below it are dropped.
+    /// Margins and tab stops return to their defaults.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        if (cols, rows) == (self.width, self.height) {
+            return;
+        }
+        let blank = self.blank_cell();
+        let fit = |buffer: &mut TerminalBuffer, cursor_y: &mut usize| -> TerminalBuffer {
+            let scrolled = (*cursor_y + 1).saturating_sub(rows);
+            let gone: Vec<_> = buffer.drain(..scrolled.min(buffer.len())).collect();
+            *cursor_y -= scrolled;
+            buffer.truncate(rows);
+            buffer.resize(rows, vec![blank; cols]);
+            for row in buffer.iter_mut() {
+                let wrapped = Self::is_wrapped(row);
+                if let Some(last) = row.last_mut() {
+                    last.wrapped = false;
+                }
+                row.resize(cols, blank);
+                // A wide character cut in half loses both halves
+                if row[cols - 1].width == 2 {
+                    row[cols - 1] = blank;
+                }
+                row[cols - 1].wrapped = wrapped;
+            }
+            gone
+        };
+        let scrolled = fit(&mut self.buffer, &mut self.cursor_y);
+        if self.alternate_screen {
+            if let Some((buffer, _, y)) = self.saved_primary.as_mut() {
+                for line in fit(buffer, y) {
+                    self.push_history(line);
+                }
+            }
+        } else {
+            for line in scrolled {
+                self.push_history(line);
+            }
+        }
+        // Lines cut off the bottom lose what they kept past the margin
+        let top = self.lines_scrolled;
+        self.overflow
+            .retain(|&line, _| line.wrapping_sub(top) < rows);
+        self.width = cols;
+        self.height = rows;
+        self.trim_trigger_highlights();
+        self.cursor_x = self.cursor_x.min(cols);
+        if let Some((_, x, _)) = self.saved_primary.as_mut() {
+            *x = (*x).min(cols);
+        }
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.tabs = default_tab_stops(cols);
+        self.selection = None;
+        self.damage_frame.clear();
+    }
+
+    /// Switches to a blank alternate screen without scrollback, as used by
+    /// full-screen applications.
+    pub fn enter_alternate_screen(&mut self) {
+        if self.alternate_screen {
+            return;
+        }
+        let blank = vec![vec![self.blank_cell(); self.width]; self.height];
+        let primary = std::mem::replace(&mut self.buffer, blank);
+        self.saved_primary = Some((primary, self.cursor_x, self.cursor_y));
+        self.alternate_screen = true;
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.scroll_state = ScrollState::default();
+        self.remove_images(|placement| placement.alternate);
+        self.drop_alternate_trigger_highlights();
+    }
+
+    pub fn leave_alternate_screen(&mut self) {
+        if !self.alternate_screen {
+            return;
+        }
+        if let Some((buffer, x, y)) = self.saved_primary.take() {
+            self.buffer = buffer;
+            self.cursor_x = x;
+            self.cursor_y = y;
+        }
+        self.alternate_screen = false;
+        self.remove_images(|placement| placement.alternate);
+        self.drop_alternate_trigger_highlights();
+    }
+
+    /// Feeds program output, including escape sequences, into the terminal.
+    pub fn process_pty_output(&mut self, bytes: &[u8]) {
+        let scrolled_before = self.lines_scrolled;
+        let mut parser = std::mem::replace(&mut self.parser, vte::Parser::new());
+        for &byte in bytes {
+            self.advance(&mut parser, byte);
+        }
+        self.parser = parser;
+        self.on_output(self.lines_scrolled.wrapping_sub(scrolled_before));
+        self.debug_check_invariants();
+    }
+
+    /// Checks, in debug builds, what every escape sequence handler relies
+    /// on: the cursor and margins lie within the screen, and every row is
+    /// as wide as the screen.
+    fn debug_check_invariants(&self) {
+        debug_assert!(
+            self.cursor_x <= self.width,
+            "cursor column {} past width {}",
+            self.cursor_x,
+            self.width
+        );
+        debug_assert!(
+            self.cursor_y < self.height,
+            "cursor row {} past height {}",
+            self.cursor_y,
+            self.height
+        );
+        debug_assert!(
+            self.scroll_top < self.scroll_bottom || self.height == 1,
+            "margins {}..{} are empty",
+            self.scroll_top,
+            self.scroll_bottom
+        );
+        debug_assert!(
+            self.scroll_bottom < self.height,
+            "bottom margin {} past height {}",
+            self.scroll_bottom,
+            self.height
+        );
+        debug_assert_eq!(
+            self.buffer.len(),
+            self.height,
+            "screen has the wrong number of rows"
+        );
+        debug_assert!(
+            self.buffer.iter().all(|row| row.len() == self.width),
+            "a screen row isn't {} cells wide",
+            self.width
+        );
+    }
+
+    /// Parses `byte`, unless output is paused by XOFF: then it is held,
+    /// and an XON parses everything held so far. Only the rest of the read
+    /// that carried the XOFF is held, as the PTY isn't read while
+    /// [`Terminal::output_paused`] is set.
+    fn advance(&mut self, parser: &mut vte::Parser, byte: u8) {
+        if !self.flow_paused {
+            self.scan_apc(byte);
+            self.scan_dcs(byte);
+            parser.advance(self, byte);
+        } else if byte == XON {
+            self.flow_paused = false;
+            for byte in std::mem::take(&mut self.held_output) {
+                self.advance(parser, byte);
+            }
+        } else {
+            self.held_output.push(byte);
+        }
+    }
+
+    /// Whether an XOFF paused the output. The PTY shouldn't be read while
+    /// it is set, so the child blocks once the kernel's buffer fills.
+    pub fn output_paused(&self) -> bool {
+        self.flow_paused
+    }
+
+    /// Resumes output paused by XOFF, as an XON would.
+    pub fn resume_output(&mut self) {
+        if self.flow_paused {
+            self.flow_paused = false;
+            let held = std::mem::take(&mut self.held_output);
+            self.process_pty_output(&held);
+        }
+    }
+
+    /// Feeds a raw byte stream that may split UTF-8 sequences at arbitrary
+    /// boundaries. Incomplete trailing sequences are held until the next call
+    /// and malformed ones are replaced with U+FFFD.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut data = std::mem::take(&mut self.utf8_pending);
+        data.extend_from_slice(bytes);
+
+        let mut decoded = Vec::with_capacity(data.len());
+        let mut rest = data.as_slice();
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    decoded.extend_from_slice(valid.as_bytes());
+                    break;
+                }
+                Err(err) => {
+                    let (valid, invalid) = rest.split_at(err.valid_up_to());
+                    decoded.extend_from_slice(valid);
+                    match err.error_len() {
+                        Some(len) => {
+                            decoded.extend_from_slice("\u{FFFD}".as_bytes());
+                            rest = &invalid[len..];
+                        }
+                        None => {
+                            self.utf8_pending.extend_from_slice(invalid);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.process_pty_output(&decoded);
+    }
+
+    /// Keeps the viewport in place (or snaps it down) after `new_lines` lines
+    /// were pushed into the scrollback by output.
+    fn on_output(&mut self, new_lines: usize) {
+        if self.alternate_screen || self.scroll_state.target_offset <= 0.0 {
+            return;
+        }
+        if self.scroll_on_output {
+            self.snap_to_bottom();
+        } else if new_lines > 0 {
+            // Anchor the view on the content the user scrolled to.
+            let max = self.max_scroll_offset();
+            self.scroll_state.target_offset =
+                (self.scroll_state.target_offset + new_lines as f32).min(max);
+            self.scroll_state.offset = (self.scroll_state.offset + new_lines as f32).min(max);
+        }
+    }
+
+    /// Writes one character without escape sequence parsing. Line feeds and
+    /// wraps scroll only the scrolling region, as in the ANSI path.
+    pub fn write_char(&mut self, ch: char) {
+        match ch {
+            '\n' => {
+                self.complete_line();
+                self.line_feed();
+            }
+            '\r' => self.cursor_x = 0,
+            '\t' => {
+                // Tabs only move the cursor; the cells they pass over keep their contents
+                self.cursor_x = if self.tabs.is_empty() {
+                    ((self.cursor_x / DEFAULT_TAB_WIDTH + 1) * DEFAULT_TAB_WIDTH)
+                        .min(self.width - 1)
+                } else {
+                    self.next_tab_stop()
+                };
+            }
+            '\x08' => self.cursor_x = self.cursor_x.saturating_sub(1),
+            ch if ch.is_control() => {}
+            ch => {
+                if self.cursor_x + char_width(ch).min(self.width) > self.width {
+                    self.buffer[self.cursor_y][self.width - 1].wrapped = true;
+                    self.cursor_x = 0;
+                    self.new_line();
+                }
+                self.put_cell(TerminalCell {
+                    ch,
+                    ..self.blank_cell()
+                });
+            }
+        }
+    }
+
+    /// Column of the next tab stop after the cursor, or the last column.
+    fn next_tab_stop(&self) -> usize {
+        self.tabs
+            .iter()
+            .copied()
+            .find(|&stop| stop > self.cursor_x)
+            .unwrap_or(self.width - 1)
+            .min(self.width - 1)
+    }
+
+    /// Moves down a line. On the bottom margin this scrolls the scrolling
+    /// region instead; below the region the cursor stops at the last row.
+    fn new_line(&mut self) {
+        if self.cursor_y == self.scroll_bottom {
+            self.scroll_region_up(1);
+        } else if self.cursor_y + 1 < self.height {
+            self.cursor_y += 1;
+        }
+    }
+
+    /// LF, VT and FF: moves down a line, returning to column 0 as well in
+    /// newline mode (LNM).
+    fn line_feed(&mut self) {
+        self.new_line();
+        if self.state.newline_mode {
+            self.cursor_x = 0;
+        }
+    }
+
+    fn push_history(&mut self, mut line: Vec<TerminalCell>) {
+        if let Some(overflow) = self.take_top_overflow() {
+            line.extend(overflow);
+        }
+        self.history.push_back(line);
+        while self.history.len() > self.scrollback_limit {
+            self.history.pop_front();
+        }
+        self.lines_scrolled = self.lines_scrolled.wrapping_add(1);
+        self.trim_images();
+        self.trim_trigger_highlights();
+    }
+
+    /// Writes `cell` at the cursor and advances past it. A wide character
+    /// also claims the next column with a spacer; a wide character partly
+    /// overwritten here loses its other half.
+    fn put_cell(&mut self, mut cell: TerminalCell) {
+        let (x, y) = (self.cursor_x, self.cursor_y);
+        let width = char_width(cell.ch).min(self.width - x);
+        let blank = TerminalCell {
+            bg_color: cell.bg_color,
+            ..self.blank_cell()
+        };
+        let row = &mut self.buffer[y];
+        if row[x].is_spacer() && x > 0 {
+            row[x - 1] = blank;
+        }
+        if let Some(next) = row.get(x + width) {
+            if next.is_spacer() {
+                row[x + width] = blank;
+            }
+        }
+
+        cell.width = width as u8;
+        row[x] = cell;
+        if width == 2 {
+            row[x + 1] = TerminalCell { width: 0, ..blank };
+        }
+        self.cursor_x += width;
+        debug_assert_eq!(row.len(), self.width, "row {} changed length", y);
+    }
+
+    /// Cell used to fill erased or newly exposed areas.
+    fn blank_cell(&self) -> TerminalCell {
+        TerminalCell {
+            fg_color: self.default_fg,
+            bg_color: self.default_bg,
+            ..TerminalCell::default()
+        }
+    }
+
+    /// Cell used by erase and scroll operations: with background color erase
+    /// it takes the current background, like xterm's `bce`.
+    fn erase_cell(&self) -> TerminalCell {
+        if self.background_color_erase {
+            TerminalCell {
+                bg_color: self.current_bg,
+                ..self.blank_cell()
+            }
+        } else {
+            self.blank_cell()
+        }
+    }
+
+    /// Forgets what is tied to screen rows `rows` rather than held in their
+    /// cells, as when they are erased or moved within the screen.
+    fn forget_rows(&mut self, rows: Range<usize>) {
+        self.drop_overflow(rows.clone());
+        self.drop_trigger_highlights(rows);
+    }
+
+    /// Scrolls the lines of the scrolling region up by `count`, saving lines
+    /// that leave the top of the screen to the history.
+    fn scroll_region_up(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let count = count.min(bottom + 1 - top);
+        // Lines only keep their overflow and highlights when the whole
+        // primary screen scrolls
+        if (top, bottom) != (0, self.height - 1) || self.alternate_screen {
+            let end = if top == 0 { self.height } else { bottom + 1 };
+            self.forget_rows(top..end);
+        }
+        for _ in 0..count {
+            let line = self.buffer.remove(top);
+            self.buffer
+                .insert(bottom, vec![self.erase_cell(); self.width]);
+            if top == 0 && !self.alternate_screen {
+                self.push_history(line);
+            }
+        }
+    }
+
+    /// Scrolls the lines of the scrolling region down by `count`.
+    fn scroll_region_down(&mut self, count: usize) {
+        let (top, bottom) = (self.scroll_top, self.scroll_bottom);
+        let count = count.min(bottom + 1 - top);
+        self.forget_rows(top..bottom + 1);
+        for _ in 0..count {
+            self.buffer.remove(bottom);
+            self.buffer.insert(top, vec![self.erase_cell(); self.width]);
+        }
+    }
+
+    fn max_scroll_offset(&self) -> f32 {
+        if self.alternate_screen {
+            0.0
+        } else {
+            self.history.len() as f32
+        }
+    }
+
+    pub fn scroll_config(&self) -> ScrollConfig {
+        self.scroll_config
+    }
+
+    pub fn set_scroll_config(&mut self, config: ScrollConfig) {
+        self.scroll_config = config;
+    }
+
+    pub fn handle_scroll(&mut self, delta: ScrollDelta) {
+        let lines = match delta {
+            ScrollDelta::Lines(notches) => notches * self.scroll_config.lines_per_notch,
+            ScrollDelta::Pixels(pixels) => pixels * self.scroll_config.pixel_factor,
+        };
+        self.scroll_by(lines);
+    }
+
+    /// Moves the viewport by `lines` (positive scrolls back into history).
+    pub fn scroll_by(&mut self, lines: f32) {
+        let target = self.scroll_state.target_offset + lines;
+        self.scroll_state.target_offset = target.clamp(0.0, self.max_scroll_offset());
+    }
+
+    fn page_lines(&self) -> f32 {
+        self.height.saturating_sub(1).max(1) as f32
+    }
+
+    pub fn scroll_page_up(&mut self) {
+        self.scroll_by(self.page_lines());
+    }
+
+    pub fn scroll_page_down(&mut self) {
+        self.scroll_by(-self.page_lines());
+    }
+
+    /// Scrolls to the oldest line in the history.
+    pub fn scroll_to_top(&mut self) {
+        self.scroll_state.target_offset = self.max_scroll_offset();
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_state.target_offset = 0.0;
+    }
+
+    /// Columns the viewport is scrolled right by. Rows extend past the
+    /// screen with output kept past the last column (see `keep_overflow`),
+    /// and in the scrollback when kept from a wider window.
+    pub fn h_scroll_offset(&self) -> usize {
+        self.h_scroll_offset.min(self.max_h_scroll_offset())
+    }
+
+    /// How far the widest row in view extends past the right edge.
+    pub fn max_h_scroll_offset(&self) -> usize {
+        (0..self.height)
+            .filter_map(|y| {
+                let row = self.visible_row(y)?;
+                Some(row.len() + self.visible_overflow(y).len())
+            })
+            .map(|len| len.saturating_sub(self.width))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The cells shown at viewport line `y`: at most a screen's width of
+    /// them, from the horizontal scroll offset.
+    pub fn scrolled_row(&self, y: usize) -> Option<Cow<'_, [TerminalCell]>> {
+        Some(self.h_scrolled(self.visible_row(y)?, self.visible_overflow(y)))
+    }
+
+    /// How far the viewport is from the whole line it is drawn at, in
+    /// lines: positive mid-way through scrolling up to the line above,
+    /// negative down to the line below. Zero once a scroll settles.
+    pub fn scroll_fraction(&self) -> f32 {
+        let offset = self
+            .scroll_state
+            .offset
+            .clamp(0.0, self.history.len() as f32);
+        offset - offset.round()
+    }
+
+    /// The row coming into view mid-way through scrolling a line, shown
+    /// like `scrolled_row`, with the viewport line it is drawn at: -1 above
+    /// the first row, or `height` below the last.
+    pub fn scrolled_edge_row(&self) -> Option<(isize, Cow<'_, [TerminalCell]>)> {
+        let fraction = self.scroll_fraction();
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        if fraction > 0.0 {
+            let row = self
+                .history
+                .get(self.history.len().checked_sub(offset + 1)?)?;
+            Some((-1, self.h_scrolled(row, &[])))
+        } else if fraction < 0.0 {
+            let y = self.height.checked_sub(offset)?;
+            let overflow = if self.alternate_screen {
+                &[]
+            } else {
+                self.overflow(y)
+            };
+            Some((
+                self.height as isize,
+                self.h_scrolled(self.buffer.get(y)?, overflow),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// The columns of `row` and the `overflow` past it that are in view
+    /// at the horizontal scroll offset.
+    fn h_scrolled<'a>(
+        &self,
+        row: &'a [TerminalCell],
+        overflow: &[TerminalCell],
+    ) -> Cow<'a, [TerminalCell]> {
+        let row = if overflow.is_empty() {
+            Cow::Borrowed(row)
+        } else {
+            Cow::Owned([row, overflow].concat())
+        };
+        let offset = self.h_scroll_offset();
+        let range = offset.min(row.len())..row.len().min(offset + self.width);
+        match row {
+            Cow::Borrowed(row) => Cow::Borrowed(&row[range]),
+            Cow::Owned(mut row) => {
+                row.truncate(range.end);
+                row.drain(..range.start);
+                Cow::Owned(row)
+            }
+        }
+    }
+
+    /// Scrolls the viewport right by `columns`, or left when negative.
+    pub fn scroll_horizontal(&mut self, columns: isize) {
+        let current = self.h_scroll_offset();
+        let offset = current
+            .saturating_add_signed(columns)
+            .min(self.max_h_scroll_offset());
+        self.h_scroll_offset = offset;
+        if offset != current {
+            // Every row moves sideways
+            self.damage_frame.clear();
+        }
+    }
+
+    /// Snapshot of the viewport for [`Terminal::restore_view_state`].
+    pub fn save_view_state(&self) -> ViewState {
+        ViewState {
+            scroll_state: self.scroll_state,
+            selection: self.selection,
+            h_scroll_offset: self.h_scroll_offset,
+        }
+    }
+
+    /// Puts back a viewport saved by [`Terminal::save_view_state`]. Scroll
+    /// offsets are clamped in case the history was trimmed meanwhile.
+    pub fn restore_view_state(&mut self, view: ViewState) {
+        let max = self.max_scroll_offset();
+        self.scroll_state = ScrollState {
+            offset: view.scroll_state.offset.clamp(0.0, max),
+            target_offset: view.scroll_state.target_offset.clamp(0.0, max),
+        };
+        self.selection = view.selection;
+        self.h_scroll_offset = view.h_scroll_offset;
+        self.damage_frame.clear();
+    }
+
+    fn snap_to_bottom(&mut self) {
+        self.scroll_state = ScrollState::default();
+    }
+
+    pub fn handle_char_input(&mut self, ch: char) {
+        // Control keys are encoded by the input handler.
+        if ch.is_control() {
+            return;
+        }
+        let mut buf = [0u8; 4];
+        self.write_input(ch.encode_utf8(&mut buf).as_bytes());
+    }
+
+    /// Queues user input for the PTY, or edits it locally in local echo mode.
+    pub fn write_input(&mut self, bytes: &[u8]) {
+        // Ctrl-Q resumes paused output rather than reaching the program
+        if self.flow_paused && bytes == [XON] {
+            self.resume_output();
+            return;
+        }
+        if self.scroll_on_keystroke {
+            self.snap_to_bottom();
+        }
+        if self.local_echo {
+            self.local_input(bytes);
+            return;
+        }
+        self.pending_input.extend_from_slice(bytes);
+    }
+
+    /// Sends `text` as a paste, bracketed when the program enabled mode
+    /// 2004. An end marker inside `text` is dropped so it can't end the
+    /// paste early.
+    pub fn paste(&mut self, text: &str) {
+        if self.state.bracketed_paste {
+            let text = text.replace("\x1b[201~", "");
+            self.write_input(format!("\x1b[200~{}\x1b[201~", text).as_bytes());
+        } else {
+            self.write_input(text.as_bytes());
+        }
+    }
+
+    /// Drains bytes queued for the PTY: user input, and responses unless
+    /// they go to an output set with [`Terminal::set_output`].
+    pub fn take_pending_input(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending_input)
+    }
+
+    /// Whether `update` still has an animation to advance.
+    pub fn is_animating(&self) -> bool {
+        self.scroll_state.offset != self.scroll_state.target_offset
+    }
+
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        // Smoothly animate the viewport towards the scroll target
+        let ScrollConfig {
+            speed,
+            damping,
+            smooth,
+            ..
+        } = self.scroll_config;
+        let diff = self.scroll_state.target_offset - self.scroll_state.offset;
+        if !smooth || diff.abs() < 0.01 {
+            self.scroll_state.offset = self.scroll_state.target_offset;
+        } else {
+            self.scroll_state.offset += diff * (speed * dt).min(1.0) * damping;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(terminal: &Terminal, x: usize, y: usize) -> TerminalCell {
+        *terminal.get_cell_at(x, y).unwrap()
+    }
+
+    #[test]
+    fn restore_cursor_brings_back_the_saved_rendition() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"\x1b[31;1m\x1b7\x1b[32;22m\x1b[3;5H\x1b8x");
+        let restored = cell(&terminal, 0, 0);
+        assert_eq!(restored.ch, 'x');
+        assert_eq!(restored.fg_color, Color::from_256(1));
+        assert!(restored.attrs.bold);
+    }
+
+    #[test]
+    fn alternate_screen_keeps_its_own_saved_cursor() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"\x1b[31;1m\x1b[2;3H\x1b[?1049h");
+        terminal.process_pty_output(b"\x1b[32;22m\x1b[4;4H\x1b7\x1b[H\x1b8y");
+        let alternate = cell(&terminal, 3, 3);
+        assert_eq!(alternate.ch, 'y');
+        assert_eq!(alternate.fg_color, Color::from_256(2));
+        assert!(!alternate.attrs.bold);
+
+        terminal.process_pty_output(b"\x1b[?1049lx");
+        let main = cell(&terminal, 2, 1);
+        assert_eq!(main.ch, 'x');
+        assert_eq!(main.fg_color, Color::from_256(1));
+        assert!(main.attrs.bold);
+    }
+
+    #[test]
+    fn horizontal_scroll_is_clamped_to_the_widest_row() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.keep_overflow = true;
+        assert_eq!(terminal.max_h_scroll_offset(), 0);
+        terminal.scroll_horizontal(5);
+        assert_eq!(terminal.h_scroll_offset(), 0);
+
+        terminal.process_pty_output(b"\x1b[?7l0123456789abcdef");
+        terminal.scroll_horizontal(4);
+        assert_eq!(terminal.h_scroll_offset(), 4);
+        terminal.scroll_horizontal(100);
+        assert_eq!(terminal.h_scroll_offset(), 6);
+        terminal.scroll_horizontal(-100);
+        assert_eq!(terminal.h_scroll_offset(), 0);
+    }
+
+    #[test]
+    fn rows_are_shown_from_the_offset_column() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.keep_overflow = true;
+        terminal.process_pty_output(b"\x1b[?7l0123456789abcdef\r\nxyz");
+        terminal.scroll_horizontal(4);
+        let shown = |terminal: &Terminal, y| Terminal::row_text(&terminal.scrolled_row(y).unwrap());
+        assert_eq!(shown(&terminal, 0), "456789abcd");
+        assert_eq!(terminal.scrolled_row(1).unwrap().len(), 6);
+        terminal.scroll_horizontal(2);
+        assert_eq!(shown(&terminal, 0), "6789abcdef");
+    }
+
+    #[test]
+    fn the_row_coming_into_view_is_drawn_mid_way_through_a_scroll() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"0\r\n1\r\n2\r\n3\r\n4\r\n5\r\n6\r\n7\r\n8\r\n9");
+        let edge = |terminal: &Terminal| {
+            terminal
+                .scrolled_edge_row()
+                .map(|(y, row)| (y, Terminal::row_text(&row).trim_end().to_string()))
+        };
+        assert_eq!(terminal.scroll_fraction(), 0.0);
+        assert_eq!(edge(&terminal), None);
+
+        terminal.scroll_state.offset = 1.25;
+        assert_eq!(terminal.scroll_fraction(), 0.25);
+        let top = terminal.scrolled_row(0).unwrap();
+        assert_eq!(Terminal::row_text(&top).trim_end(), "6");
+        assert_eq!(edge(&terminal), Some((-1, "5".to_string())));
+
+        terminal.scroll_state.offset = 0.75;
+        assert_eq!(terminal.scroll_fraction(), -0.25);
+        assert_eq!(edge(&terminal), Some((3, "9".to_string())));
+
+        // Nothing is above the oldest line
+        terminal.scroll_state.offset = 7.5;
+        assert_eq!(terminal.scroll_fraction(), 0.0);
+        assert_eq!(edge(&terminal), None);
+    }
+
+    /// A terminal with `lines` lines of history, scrolled up by `up`.
+    fn scrolled_up(lines: usize, up: f32) -> Terminal {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output("x\r\n".repeat(lines + 2).as_bytes());
+        terminal.scroll_by(up);
+        terminal
+    }
+
+    #[test]
+    fn output_keeps_the_view_on_scrolled_to_content() {
+        let mut terminal = scrolled_up(10, 4.0);
+        terminal.scroll_on_output = false;
+        terminal.process_pty_output(b"a\r\nb\r\n");
+        assert_eq!(terminal.scroll_state.target_offset, 6.0);
+    }
+
+    #[test]
+    fn output_snaps_to_the_bottom_with_scroll_on_output() {
+        let mut terminal = scrolled_up(10, 4.0);
+        terminal.scroll_on_output = true;
+        terminal.process_pty_output(b"a\r\n");
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn typing_snaps_to_the_bottom_only_with_scroll_on_keystroke() {
+        for (snap, offset) in [(true, 0.0), (false, 4.0)] {
+            let mut terminal = scrolled_up(10, 4.0);
+            terminal.scroll_on_keystroke = snap;
+            terminal.handle_char_input('a');
+            assert_eq!(terminal.scroll_state.target_offset, offset);
+        }
+    }
+
+    #[test]
+    fn keyboard_scrolling_moves_by_pages_and_lines() {
+        let mut terminal = scrolled_up(10, 0.0);
+        terminal.scroll_page_up();
+        assert_eq!(terminal.scroll_state.target_offset, 2.0);
+        terminal.scroll_by(1.0);
+        assert_eq!(terminal.scroll_state.target_offset, 3.0);
+        terminal.scroll_to_top();
+        assert_eq!(terminal.scroll_state.target_offset, 10.0);
+        terminal.scroll_page_down();
+        assert_eq!(terminal.scroll_state.target_offset, 8.0);
+        terminal.scroll_to_bottom();
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn the_alternate_screen_does_not_scroll_back() {
+        let mut terminal = scrolled_up(10, 0.0);
+        terminal.process_pty_output(b"\x1b[?1049h");
+        terminal.scroll_page_up();
+        terminal.scroll_to_top();
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn write_bytes_joins_a_character_split_across_calls() {
+        let mut terminal = Terminal::new(10, 3);
+        let euro = "€".as_bytes();
+        terminal.write_bytes(&euro[..1]);
+        assert_eq!(cell(&terminal, 0, 0).ch, ' ');
+        terminal.write_bytes(&euro[1..]);
+        assert_eq!(cell(&terminal, 0, 0).ch, '€');
+        assert_eq!(cell(&terminal, 1, 0).ch, ' ');
+        assert_eq!(terminal.cursor_x, 1);
+    }
+
+    #[test]
+    fn write_bytes_replaces_malformed_sequences() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.write_bytes(b"a\xffb\xe2\x82c");
+        assert_eq!(
+            terminal.line_text(0).as_deref(),
+            Some("a\u{fffd}b\u{fffd}c")
+        );
+    }
+
+    #[test]
+    fn minimum_contrast_makes_unreadable_text_readable() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[38;2;40;40;40;48;2;30;30;30mx");
+        let stored = cell(&terminal, 0, 0);
+        assert_eq!(terminal.resolve_colors(&stored).0, Color(40, 40, 40, 255));
+
+        terminal.set_min_contrast(4.5);
+        let (fg, bg) = terminal.resolve_colors(&stored);
+        assert_eq!(bg, Color(30, 30, 30, 255));
+        assert!(fg.contrast_ratio(bg) >= 4.5);
+        assert!(fg.relative_luminance() > bg.relative_luminance());
+        assert_eq!(cell(&terminal, 0, 0), stored);
+    }
+
+    #[test]
+    fn minimum_contrast_leaves_readable_pairs_alone() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.set_min_contrast(4.5);
+        terminal.process_pty_output(b"\x1b[38;2;200;200;200;48;2;0;0;64mx");
+        let (fg, bg) = terminal.resolve_colors(&cell(&terminal, 0, 0));
+        assert_eq!((fg, bg), (Color(200, 200, 200, 255), Color(0, 0, 64, 255)));
+
+        terminal.set_min_contrast(1.0);
+        assert_eq!(terminal.min_contrast(), None);
+    }
+
+    #[test]
+    fn view_state_round_trips_scroll_and_selection() {
+        let mut terminal = scrolled_up(10, 4.0);
+        terminal.scroll_state.offset = 3.5;
+        let selection = Selection {
+            start: (1, 0),
+            end: (4, 2),
+        };
+        terminal.select(selection);
+        terminal.h_scroll_offset = 2;
+        let view = terminal.save_view_state();
+
+        terminal.scroll_to_bottom();
+        terminal.scroll_state.offset = 0.0;
+        terminal.clear_selection();
+        terminal.h_scroll_offset = 0;
+        terminal.restore_view_state(view);
+
+        assert_eq!(terminal.save_view_state(), view);
+        assert_eq!(terminal.scroll_state.offset, 3.5);
+        assert_eq!(terminal.scroll_state.target_offset, 4.0);
+        assert_eq!(terminal.selection(), Some(selection));
+    }
+
+    #[test]
+    fn restored_offsets_are_clamped_to_the_history() {
+        let mut terminal = scrolled_up(10, 8.0);
+        let view = terminal.save_view_state();
+        terminal.process_pty_output(b"\x1b[3J");
+        terminal.restore_view_state(view);
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn page_scrolling_moves_by_all_but_one_line() {
+        let mut terminal = scrolled_up(10, 0.0);
+        assert_eq!(terminal.history_len(), 10);
+        terminal.scroll_page_up();
+        assert_eq!(terminal.scroll_state.target_offset, 2.0);
+        terminal.scroll_page_up();
+        terminal.scroll_page_down();
+        assert_eq!(terminal.scroll_state.target_offset, 2.0);
+        terminal.scroll_page_down();
+        terminal.scroll_page_down();
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn scroll_to_top_reaches_the_oldest_line() {
+        let mut terminal = scrolled_up(10, 0.0);
+        terminal.scroll_to_top();
+        assert_eq!(terminal.scroll_state.target_offset, 10.0);
+        terminal.scroll_page_up();
+        assert_eq!(terminal.scroll_state.target_offset, 10.0);
+        terminal.scroll_to_bottom();
+        assert_eq!(terminal.scroll_state.target_offset, 0.0);
+    }
+
+    #[test]
+    fn reverse_screen_swaps_default_colors_when_drawn() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"x");
+        let stored = cell(&terminal, 0, 0);
+        let (fg, bg) = (terminal.default_fg, terminal.default_bg);
+        assert_eq!(terminal.resolve_colors(&stored), (fg, bg));
+
+        terminal.process_pty_output(b"\x1b[?5h");
+        assert_eq!(terminal.resolve_colors(&stored), (bg, fg));
+        assert_eq!(terminal.screen_background(), fg);
+        assert_eq!(cell(&terminal, 0, 0), stored);
+
+        terminal.process_pty_output(b"\x1b[?5l");
+        assert_eq!(terminal.resolve_colors(&stored), (fg, bg));
+        assert_eq!(terminal.screen_background(), bg);
+    }
+
+    #[test]
+    fn tabs_in_write_char_move_over_text_to_the_configured_stop() {
+        let mut terminal = Terminal::new(20, 2);
+        terminal.process_pty_output(b"abcdefghij\x1b[3g\x1b[1;4H\x1bH\x1b[1;13H\x1bH");
+        terminal.cursor_x = 0;
+        terminal.write_char('\t');
+        assert_eq!(terminal.cursor_x, 3);
+        terminal.write_char('\t');
+        assert_eq!(terminal.cursor_x, 12);
+        terminal.write_char('\t');
+        assert_eq!(terminal.cursor_x, 19);
+        assert_eq!(terminal.line_text(0).as_deref(), Some("abcdefghij"));
+    }
+
+    #[test]
+    fn tabs_in_write_char_fall_back_to_every_eight_columns() {
+        let mut terminal = Terminal::new(20, 2);
+        terminal.process_pty_output(b"abcdefghij\x1b[3g\r");
+        terminal.write_char('\t');
+        assert_eq!(terminal.cursor_x, 8);
+        terminal.write_char('x');
+        assert_eq!(terminal.line_text(0).as_deref(), Some("abcdefghxj"));
+    }
+
+    #[test]
+    fn custom_default_colors_fill_cleared_cells() {
+        let (fg, bg) = (Color(20, 20, 20, 255), Color(250, 250, 240, 255));
+        let mut terminal = Terminal::new_with_colors(10, 3, fg, bg);
+        assert_eq!(cell(&terminal, 9, 2).bg_color, bg);
+
+        terminal.process_pty_output(b"\x1b[41mred\x1b[m\x1b[2J\x1b[Hx\x1b[K\r\n\x1b[L");
+        for y in 0..3 {
+            for x in 0..10 {
+                assert_eq!(cell(&terminal, x, y).bg_color, bg);
+            }
+        }
+        assert_eq!(cell(&terminal, 0, 0).fg_color, fg);
+        assert_eq!(terminal.screen_background(), bg);
+    }
+
+    #[test]
+    fn without_smoothing_the_offset_reaches_the_target_in_one_update() {
+        let mut smooth = scrolled_up(20, 0.0);
+        smooth.handle_scroll(ScrollDelta::Lines(2.0));
+        smooth.update();
+        assert!(smooth.scroll_state.offset < 6.0);
+
+        let mut instant = scrolled_up(20, 0.0);
+        instant.set_scroll_config(ScrollConfig {
+            smooth: false,
+            ..ScrollConfig::default()
+        });
+        instant.handle_scroll(ScrollDelta::Lines(2.0));
+        instant.update();
+        assert_eq!(instant.scroll_state.offset, 6.0);
+        assert!(!instant.is_animating());
+    }
+
+    #[test]
+    fn more_lines_per_notch_scroll_farther() {
+        let mut terminal = scrolled_up(20, 0.0);
+        terminal.handle_scroll(ScrollDelta::Lines(1.0));
+        assert_eq!(terminal.scroll_state.target_offset, 3.0);
+
+        terminal.set_scroll_config(ScrollConfig {
+            lines_per_notch: 5.0,
+            pixel_factor: 0.5,
+            ..ScrollConfig::default()
+        });
+        terminal.handle_scroll(ScrollDelta::Lines(1.0));
+        assert_eq!(terminal.scroll_state.target_offset, 8.0);
+        terminal.handle_scroll(ScrollDelta::Pixels(-10.0));
+        assert_eq!(terminal.scroll_state.target_offset, 3.0);
+    }
+
+    #[test]
+    fn the_spacer_of_a_wide_character_resolves_to_its_first_cell() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.process_pty_output("a漢b".as_bytes());
+        for x in [1, 2] {
+            let (origin, cell) = terminal.cell_and_origin_at(x, 0).unwrap();
+            assert_eq!((origin, cell.ch), (1, '漢'));
+            assert_eq!(terminal.cell_width_at(x, 0), Some(2));
+        }
+        let (origin, cell) = terminal.cell_and_origin_at(3, 0).unwrap();
+        assert_eq!((origin, cell.ch), (3, 'b'));
+        assert_eq!(terminal.cell_width_at(3, 0), Some(1));
+        assert!(terminal.cell_and_origin_at(10, 0).is_none());
+    }
+
+    #[test]
+    fn scrollback_lines_read_as_trimmed_text() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.process_pty_output("plain  \r\n漢字 wide\r\ne\u{301}\r\n\r\n".as_bytes());
+        assert_eq!(terminal.history_line_text(0).as_deref(), Some("plain"));
+        assert_eq!(terminal.history_line_text(1).as_deref(), Some("漢字 wide"));
+        assert_eq!(terminal.history_line_text(4), None);
+        let lines: Vec<String> = terminal.iter_scrollback().collect();
+        assert_eq!(lines, ["plain", "漢字 wide", "e\u{301}"]);
+    }
+
+    #[test]
+    fn erasing_fills_with_the_current_background() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[1;41m");
+        let red = terminal.current_bg;
+        assert_ne!(red, terminal.default_bg);
+        terminal.process_pty_output(b"\x1b[2J");
+        for y in 0..3 {
+            for x in 0..10 {
+                let cell = cell(&terminal, x, y);
+                assert_eq!(cell.bg_color, red);
+                assert_eq!(cell.fg_color, terminal.default_fg);
+                assert_eq!(cell.attrs, CellAttributes::default());
+            }
+        }
+    }
+
+    #[test]
+    fn erasing_uses_the_default_background_without_bce() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.background_color_erase = false;
+        terminal.process_pty_output(b"\x1b[41m\x1b[2J\x1b[K\r\n\x1b[L");
+        for y in 0..3 {
+            for x in 0..10 {
+                assert_eq!(cell(&terminal, x, y).bg_color, terminal.default_bg);
+            }
+        }
+    }
+
+    #[test]
+    fn plain_line_feeds_scroll_only_the_region() {
+        let mut terminal = Terminal::new(5, 4);
+        for ch in "top\r\na\r\nb\r\nend".chars() {
+            terminal.write_char(ch);
+        }
+        terminal.process_pty_output(b"\x1b[2;3r\x1b[3;1H");
+        for ch in "c\r\nd".chars() {
+            terminal.write_char(ch);
+        }
+        let rows: Vec<_> = (0..4).filter_map(|y| terminal.line_text(y)).collect();
+        assert_eq!(rows, ["top", "c", "d", "end"]);
+        assert_eq!(terminal.history_len(), 0);
+    }
+
+    #[test]
+    fn plain_wraps_scroll_only_the_region() {
+        let mut terminal = Terminal::new(3, 4);
+        terminal.process_pty_output(b"top\r\n\r\n\r\nend\x1b[2;3r\x1b[3;1H");
+        for ch in "abcdefg".chars() {
+            terminal.write_char(ch);
+        }
+        let rows: Vec<_> = (0..4).filter_map(|y| terminal.line_text(y)).collect();
+        assert_eq!(rows, ["top", "def", "g", "end"]);
+        assert_eq!(terminal.cursor_y, 2);
+    }
+
+    #[test]
+    fn bold_base_colors_are_drawn_bright() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[1;31mA\x1b[22mB\x1b[1;91mC\x1b[38;2;1;2;3mD");
+        let red = Color::from_ansi_color(1);
+        let bright_red = Color::from_ansi_color(9);
+        // The stored color is still SGR 31
+        assert_eq!(cell(&terminal, 0, 0).fg_color, red);
+        assert_eq!(
+            terminal.resolve_colors(&cell(&terminal, 0, 0)).0,
+            bright_red
+        );
+        assert_eq!(terminal.resolve_colors(&cell(&terminal, 1, 0)).0, red);
+        assert_eq!(
+            terminal.resolve_colors(&cell(&terminal, 2, 0)).0,
+            bright_red
+        );
+        let rgb = Color(1, 2, 3, 255);
+        assert_eq!(terminal.resolve_colors(&cell(&terminal, 3, 0)).0, rgb);
+    }
+
+    #[test]
+    fn bold_leaves_base_colors_given_by_index_or_rgb_alone() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[1;38;2;205;0;0mA\x1b[38;5;1mB\x1b[31mC\x1b[39mD");
+        let red = Color::from_ansi_color(1);
+        let drawn: Vec<_> = (0..3)
+            .map(|x| terminal.resolve_colors(&cell(&terminal, x, 0)).0)
+            .collect();
+        assert_eq!(drawn, [red, red, Color::from_ansi_color(9)]);
+        // Resetting the color forgets that it was SGR 31
+        assert_eq!(cell(&terminal, 3, 0).attrs.base_fg, None);
+    }
+
+    #[test]
+    fn bold_keeps_its_color_without_bold_is_bright() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.bold_is_bright = false;
+        terminal.process_pty_output(b"\x1b[1;31mA");
+        let red = Color::from_ansi_color(1);
+        assert_eq!(terminal.resolve_colors(&cell(&terminal, 0, 0)).0, red);
+    }
+
+    #[test]
+    fn pastes_are_bracketed_in_mode_2004() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.paste("'a b'");
+        assert_eq!(terminal.take_pending_input(), b"'a b'");
+        terminal.process_pty_output(b"\x1b[?2004h");
+        terminal.paste("x\x1b[201~y");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[200~xy\x1b[201~");
+    }
+
+    #[test]
+    fn clearing_the_scrollback_leaves_the_screen_alone() {
+        for clear in [
+            |terminal: &mut Terminal| terminal.process_pty_output(b"\x1b[3J"),
+            |terminal: &mut Terminal| terminal.clear_scrollback(),
+        ] {
+            let mut terminal = scrolled_up(10, 4.0);
+            terminal.process_pty_output(b"\x1b[1;1Hscreen");
+            terminal.take_damage();
+            let screen = terminal.buffer.clone();
+            let memory = terminal.grid_memory();
+
+            clear(&mut terminal);
+            assert_eq!(terminal.history_len(), 0);
+            assert_eq!(terminal.scroll_state.offset, 0.0);
+            assert_eq!(terminal.scroll_state.target_offset, 0.0);
+            assert_eq!(terminal.buffer, screen);
+            assert!(terminal.grid_memory() < memory);
+            assert_eq!(terminal.take_damage(), [0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn reverse_screen_inverts_explicit_colors_and_inverse_cells() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[32;44mc\x1b[m\x1b[7mi");
+        let (green, blue) = (Color::from_ansi_color(2), Color::from_ansi_color(4));
+        let (fg, bg) = (terminal.default_fg, terminal.default_bg);
+        let colored = cell(&terminal, 0, 0);
+        let inverse = cell(&terminal, 1, 0);
+        assert_eq!(terminal.resolve_colors(&colored), (green, blue));
+        assert_eq!(terminal.resolve_colors(&inverse), (bg, fg));
+
+        terminal.process_pty_output(b"\x1b[?5h");
+        assert_eq!(terminal.resolve_colors(&colored), (blue, green));
+        // Inverse twice over: drawn in the normal colors, as in xterm
+        assert_eq!(terminal.resolve_colors(&inverse), (fg, bg));
+        assert_eq!(cell(&terminal, 0, 0), colored);
+    }
+
+    #[test]
+    fn hex_colors_parse_in_every_length() {
+        assert_eq!(Color::from_hex("#fc0"), Some(Color(255, 204, 0, 255)));
+        assert_eq!(Color::from_hex("#1e90ff"), Some(Color(30, 144, 255, 255)));
+        assert_eq!(Color::from_hex("#1E90FF80"), Some(Color(30, 144, 255, 128)));
+        for bad in ["1e90ff", "#", "#1e90f", "#ggg", "#1e90ff8", "#ééé"] {
+            assert_eq!(Color::from_hex(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn hex_colors_round_trip() {
+        for color in [
+            Color(30, 144, 255, 255),
+            Color(0, 0, 0, 0),
+            Color(1, 2, 3, 200),
+        ] {
+            assert_eq!(Color::from_hex(&color.to_hex()), Some(color));
+        }
+        assert_eq!(Color(255, 204, 0, 255).to_hex(), "#ffcc00");
+        assert_eq!(Color(255, 204, 0, 128).to_hex(), "#ffcc0080");
+    }
+
+    #[test]
+    fn x11_specs_scale_each_channel_to_8_bits() {
+        assert_eq!(
+            Color::from_rgb_spec("rgb:ffff/8080/0000"),
+            Some(Color(255, 128, 0, 255))
+        );
+        assert_eq!(
+            Color::from_rgb_spec("rgb:f/8/0"),
+            Some(Color(255, 136, 0, 255))
+        );
+        assert_eq!(
+            Color::from_rgb_spec("rgb:ff/808/0"),
+            Some(Color(255, 128, 0, 255))
+        );
+        assert_eq!(Color::from_rgb_spec("#f80"), Some(Color(255, 136, 0, 255)));
+        assert_eq!(
+            Color::from_rgb_spec("#ffff80800000"),
+            Some(Color(255, 128, 0, 255))
+        );
+        for bad in ["rgb:ff/80", "rgb:fffff/0/0", "rgb:/0/0", "#ffff", "red"] {
+            assert_eq!(Color::from_rgb_spec(bad), None, "{}", bad);
+        }
+    }
+
+    #[test]
+    fn white_is_brighter_than_black() {
+        let white = Color(255, 255, 255, 255).relative_luminance();
+        let black = Color(0, 0, 0, 255).relative_luminance();
+        assert!((white - 1.0).abs() < 1e-6);
+        assert_eq!(black, 0.0);
+        // Green counts for more than red and blue
+        let green = Color(0, 255, 0, 255).relative_luminance();
+        assert!(green > Color(255, 0, 0, 255).relative_luminance());
+        assert!(green > Color(0, 0, 255, 255).relative_luminance());
+        assert!(black < green && green < white);
+    }
+
+    #[test]
+    fn indices_past_16_resolve_to_the_cube_and_the_gray_ramp() {
+        for index in 0..16 {
+            assert_eq!(Color::from_256(index), Color::from_ansi_color(index));
+        }
+        assert_eq!(Color::from_256(16), Color(0, 0, 0, 255));
+        assert_eq!(Color::from_256(196), Color(255, 0, 0, 255));
+        assert_eq!(Color::from_256(110), Color(135, 175, 215, 255));
+        assert_eq!(Color::from_256(231), Color(255, 255, 255, 255));
+        assert_eq!(Color::from_256(232), Color(8, 8, 8, 255));
+        assert_eq!(Color::from_256(244), Color(128, 128, 128, 255));
+        assert_eq!(Color::from_256(255), Color(238, 238, 238, 255));
+    }
+
+    #[test]
+    fn indexed_sgr_colors_use_the_256_color_palette() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[38;5;196;48;5;232ma\x1b[38:5:21mb\x1b[38;5;256mc");
+        let (a, b, c) = (
+            cell(&terminal, 0, 0),
+            cell(&terminal, 1, 0),
+            cell(&terminal, 2, 0),
+        );
+        assert_eq!(
+            (a.fg_color, a.bg_color),
+            (Color::from_256(196), Color::from_256(232))
+        );
+        assert_eq!(b.fg_color, Color(0, 0, 255, 255));
+        // Out of range indices leave the color as it was
+        assert_eq!(c.fg_color, b.fg_color);
+    }
+
+    #[test]
+    fn the_first_16_colors_come_from_the_palette() {
+        let mut terminal = Terminal::new(10, 3);
+        let mut palette = Color::ansi_palette();
+        palette[1] = Color(0xe0, 0x6c, 0x75, 255);
+        palette[9] = Color(0xff, 0x80, 0x80, 255);
+        terminal.set_palette(palette);
+        terminal.process_pty_output(b"\x1b[38;5;1ma\x1b[31mb\x1b[41mc\x1b[1md\x1b[38;5;196me");
+        let fg: Vec<_> = (0..5).map(|x| cell(&terminal, x, 0).fg_color).collect();
+        assert_eq!(fg[..3], [palette[1]; 3]);
+        assert_eq!(cell(&terminal, 2, 0).bg_color, palette[1]);
+        assert_eq!(
+            terminal.resolve_colors(&cell(&terminal, 3, 0)).0,
+            palette[9]
+        );
+        // The color cube isn't themed
+        assert_eq!(fg[4], Color::from_256(196));
+    }
+}
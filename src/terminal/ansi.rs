@@ -0,0 +1,1180 @@
+use vte::{Params, ParamsIter, Perform};
+
+use std::time::Instant;
+
+use super::events::{decode_base64, file_url_host, file_url_path, TerminalEvent};
+use super::grapheme::Combining;
+use super::mouse::{MouseEncoding, MouseTracking};
+use super::{
+    char_width, AttentionEvent, Blink, CellAttributes, Color, Terminal, TerminalCell,
+    UnderlineStyle, TITLE_STACK_LIMIT, XOFF,
+};
+
+/// Reads the color of SGR 38, 48 or 58 from the colon form
+/// (`38:5:n`, `38:2::r:g:b` or `38:2:r:g:b`) in `param`, or from the
+/// semicolon form (`38;5;n`, `38;2;r;g;b`) in the parameters that follow.
+fn extended_color(terminal: &Terminal, param: &[u16], rest: &mut ParamsIter) -> Option<Color> {
+    let indexed = |index: u16| {
+        u8::try_from(index)
+            .ok()
+            .map(|index| terminal.indexed_color(index))
+    };
+    let rgb = |r: u16, g: u16, b: u16| Color(r as u8, g as u8, b as u8, 255);
+    if param.len() > 1 {
+        return match param[1] {
+            5 => indexed(*param.get(2)?),
+            2 if param.len() >= 6 => Some(rgb(param[3], param[4], param[5])),
+            2 if param.len() == 5 => Some(rgb(param[2], param[3], param[4])),
+            _ => None,
+        };
+    }
+    let mut next = || rest.next().and_then(|p| p.first().copied());
+    match next()? {
+        5 => indexed(next()?),
+        2 => Some(rgb(next()?, next()?, next()?)),
+        _ => None,
+    }
+}
+
+/// Returns the first value of parameter `index`, or `default` when it is
+/// missing or zero.
+fn param(params: &Params, index: usize, default: usize) -> usize {
+    params
+        .iter()
+        .nth(index)
+        .and_then(|p| p.first())
+        .map(|&v| v as usize)
+        .filter(|&v| v != 0)
+        .unwrap_or(default)
+}
+
+impl Terminal {
+    fn reverse_index(&mut self) {
+        if self.cursor_y == self.scroll_top {
+            self.scroll_region_down(1);
+        } else {
+            self.cursor_y = self.cursor_y.saturating_sub(1);
+        }
+    }
+
+    /// CUD: moves down, stopping at the bottom margin if the cursor starts
+    /// inside the scrolling region.
+    fn cursor_down(&mut self, count: usize) {
+        let limit = if self.cursor_y <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.height - 1
+        };
+        self.cursor_y = (self.cursor_y + count).min(limit);
+    }
+
+    /// CUU: moves up, stopping at the top margin if the cursor starts inside
+    /// the scrolling region.
+    fn cursor_up(&mut self, count: usize) {
+        let limit = if self.cursor_y >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        };
+        self.cursor_y = self.cursor_y.saturating_sub(count).max(limit);
+    }
+
+    /// Moves to `row` (0-based) for CUP, HVP and VPA. In origin mode rows
+    /// count from the top margin and stop at the bottom margin.
+    fn cursor_to_row(&mut self, row: usize) {
+        self.cursor_y = if self.state.origin_mode {
+            (self.scroll_top + row).min(self.scroll_bottom)
+        } else {
+            row.min(self.height - 1)
+        };
+    }
+
+    /// Moves to the home position: the top-left of the scrolling region in
+    /// origin mode, of the screen otherwise.
+    fn home_cursor(&mut self) {
+        self.cursor_x = 0;
+        self.cursor_to_row(0);
+    }
+
+    fn clamp_cursor(&mut self) {
+        self.cursor_x = self.cursor_x.min(self.width - 1);
+        self.cursor_y = self.cursor_y.min(self.height - 1);
+    }
+
+    fn erase_in_display(&mut self, mode: usize) {
+        let blank = self.erase_cell();
+        let (x, y) = (self.cursor_x.min(self.width - 1), self.cursor_y);
+        match mode {
+            0 => {
+                self.forget_rows(y..self.height);
+                self.buffer[y][x..].fill(blank);
+                for row in &mut self.buffer[y + 1..] {
+                    row.fill(blank);
+                }
+            }
+            1 => {
+                self.forget_rows(0..y);
+                self.buffer[y][..=x].fill(blank);
+                for row in &mut self.buffer[..y] {
+                    row.fill(blank);
+                }
+            }
+            2 => {
+                self.forget_rows(0..self.height);
+                for row in &mut self.buffer {
+                    row.fill(blank);
+                }
+                self.clear_screen_images();
+            }
+            3 => self.clear_scrollback(),
+            _ => {}
+        }
+    }
+
+    fn erase_in_line(&mut self, mode: usize) {
+        let blank = self.erase_cell();
+        let (x, y) = (self.cursor_x.min(self.width - 1), self.cursor_y);
+        if mode != 1 {
+            self.forget_rows(y..y + 1);
+        }
+        let row = &mut self.buffer[y];
+        match mode {
+            0 => row[x..].fill(blank),
+            1 => row[..=x].fill(blank),
+            2 => row.fill(blank),
+            _ => {}
+        }
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        if self.cursor_y < self.scroll_top || self.cursor_y > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom + 1 - self.cursor_y);
+        self.forget_rows(self.cursor_y..self.scroll_bottom + 1);
+        for _ in 0..count {
+            self.buffer.remove(self.scroll_bottom);
+            self.buffer
+                .insert(self.cursor_y, vec![self.erase_cell(); self.width]);
+        }
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        if self.cursor_y < self.scroll_top || self.cursor_y > self.scroll_bottom {
+            return;
+        }
+        let count = count.min(self.scroll_bottom + 1 - self.cursor_y);
+        self.forget_rows(self.cursor_y..self.scroll_bottom + 1);
+        for _ in 0..count {
+            self.buffer.remove(self.cursor_y);
+            self.buffer
+                .insert(self.scroll_bottom, vec![self.erase_cell(); self.width]);
+        }
+    }
+
+    /// Inserts `count` blanks at the cursor, as ICH does and IRM before a
+    /// character is printed. Cells shift right within the row; those
+    /// pushed past the right margin are lost. Blanks take the current
+    /// background with background color erase.
+    pub fn insert_blank(&mut self, count: usize) {
+        self.forget_rows(self.cursor_y..self.cursor_y + 1);
+        let blank = self.erase_cell();
+        let x = self.cursor_x.min(self.width - 1);
+        let row = &mut self.buffer[self.cursor_y];
+        let count = count.min(row.len() - x);
+        // A wide character split by the insertion point loses both halves
+        if row[x].is_spacer() && x > 0 {
+            row[x - 1] = blank;
+            row[x] = blank;
+        }
+        row[x..].rotate_right(count);
+        row[x..x + count].fill(blank);
+        self.repair_row_end();
+    }
+
+    /// Deletes `count` cells at the cursor, as DCH does: cells shift left
+    /// within the row and blanks fill in from the right margin.
+    pub fn delete_chars(&mut self, count: usize) {
+        self.forget_rows(self.cursor_y..self.cursor_y + 1);
+        let blank = self.erase_cell();
+        let x = self.cursor_x.min(self.width - 1);
+        let row = &mut self.buffer[self.cursor_y];
+        let count = count.min(row.len() - x);
+        if row[x].is_spacer() && x > 0 {
+            row[x - 1] = blank;
+        }
+        row[x..].rotate_left(count);
+        let len = row.len();
+        row[len - count..].fill(blank);
+        // The second half of a wide character whose first half was deleted
+        if row[x].is_spacer() {
+            row[x] = blank;
+        }
+        self.repair_row_end();
+    }
+
+    fn erase_chars(&mut self, count: usize) {
+        let blank = self.erase_cell();
+        let x = self.cursor_x.min(self.width - 1);
+        let row = &mut self.buffer[self.cursor_y];
+        let end = (x + count).min(row.len());
+        if row[x].is_spacer() && x > 0 {
+            row[x - 1] = blank;
+        }
+        if row.get(end).is_some_and(TerminalCell::is_spacer) {
+            row[end] = blank;
+        }
+        row[x..end].fill(blank);
+        self.repair_row_end();
+    }
+
+    /// Blanks a wide character left in the last column without its second
+    /// half, and checks the cursor row still holds exactly `width` cells.
+    fn repair_row_end(&mut self) {
+        let blank = self.erase_cell();
+        let row = &mut self.buffer[self.cursor_y];
+        debug_assert_eq!(
+            row.len(),
+            self.width,
+            "row {} changed length",
+            self.cursor_y
+        );
+        if let Some(last) = row.last_mut() {
+            if last.width == 2 {
+                *last = blank;
+            }
+        }
+    }
+
+    fn handle_sgr(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.reset_sgr();
+            return;
+        }
+        let mut iter = params.iter();
+        while let Some(param) = iter.next() {
+            let code = param.first().copied().unwrap_or(0);
+            match code {
+                0 => self.reset_sgr(),
+                1 => self.current_attrs.bold = true,
+                3 => self.current_attrs.italic = true,
+                4 => match param.get(1) {
+                    Some(&n) => {
+                        if let Some(style) = UnderlineStyle::from_sgr(n) {
+                            self.current_attrs.underline = style;
+                        }
+                    }
+                    None => self.current_attrs.underline = UnderlineStyle::Single,
+                },
+                5 => self.current_attrs.blink = Blink::Slow,
+                6 => self.current_attrs.blink = Blink::Rapid,
+                7 => self.current_attrs.inverse = true,
+                21 => self.current_attrs.underline = UnderlineStyle::Double,
+                22 => self.current_attrs.bold = false,
+                23 => self.current_attrs.italic = false,
+                24 => self.current_attrs.underline = UnderlineStyle::None,
+                25 => self.current_attrs.blink = Blink::None,
+                27 => self.current_attrs.inverse = false,
+                30..=37 => {
+                    let index = (code - 30) as u8;
+                    self.current_fg = self.palette[index as usize];
+                    self.current_attrs.base_fg = Some(index);
+                }
+                38 => {
+                    if let Some(color) = extended_color(self, param, &mut iter) {
+                        self.current_fg = color;
+                        self.current_attrs.base_fg = None;
+                    }
+                }
+                39 => {
+                    self.current_fg = self.default_fg;
+                    self.current_attrs.base_fg = None;
+                }
+                40..=47 => self.current_bg = self.palette[code as usize - 40],
+                48 => {
+                    if let Some(color) = extended_color(self, param, &mut iter) {
+                        self.current_bg = color;
+                    }
+                }
+                49 => self.current_bg = self.default_bg,
+                53 => self.current_attrs.overline = true,
+                55 => self.current_attrs.overline = false,
+                58 => {
+                    if let Some(color) = extended_color(self, param, &mut iter) {
+                        self.current_attrs.underline_color = Some(color);
+                    }
+                }
+                59 => self.current_attrs.underline_color = None,
+                90..=97 => {
+                    self.current_fg = self.palette[code as usize - 90 + 8];
+                    self.current_attrs.base_fg = None;
+                }
+                100..=107 => self.current_bg = self.palette[code as usize - 100 + 8],
+                _ => {}
+            }
+        }
+    }
+
+    pub(super) fn reset_sgr(&mut self) {
+        self.current_fg = self.default_fg;
+        self.current_bg = self.default_bg;
+        self.current_attrs = CellAttributes::default();
+    }
+
+    /// XTWINOPS (CSI Ps t). Unsupported operations are ignored.
+    fn window_ops(&mut self, params: &Params) {
+        let op = param(params, 0, 0);
+        match op {
+            4 | 8 if self.allow_window_resize => {
+                let (height, width) = (param(params, 1, 0), param(params, 2, 0));
+                let (cols, rows) = if op == 8 {
+                    (width, height)
+                } else {
+                    let Some((cell_width, cell_height)) = self.host.as_ref().map(|h| h.cell_size())
+                    else {
+                        return;
+                    };
+                    (
+                        width / cell_width.max(1) as usize,
+                        height / cell_height.max(1) as usize,
+                    )
+                };
+                let cols = if cols == 0 { self.width } else { cols };
+                let rows = if rows == 0 { self.height } else { rows };
+                self.window_resize_request = Some((cols, rows));
+            }
+            14 => {
+                if let Some((width, height)) = self.host.as_ref().map(|h| h.text_area_size()) {
+                    let report = format!("\x1b[4;{};{}t", height, width);
+                    self.respond(report.as_bytes());
+                }
+            }
+            16 => {
+                if let Some((width, height)) = self.host.as_ref().map(|h| h.cell_size()) {
+                    let report = format!("\x1b[6;{};{}t", height, width);
+                    self.respond(report.as_bytes());
+                }
+            }
+            18 => {
+                let report = format!("\x1b[8;{};{}t", self.height, self.width);
+                self.respond(report.as_bytes());
+            }
+            22 => {
+                if self.title_stack.len() >= TITLE_STACK_LIMIT {
+                    self.title_stack.remove(0);
+                }
+                self.title_stack.push(self.title.clone());
+            }
+            23 => {
+                if let Some(title) = self.title_stack.pop() {
+                    self.set_title(title);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// DECCOLM: switches to `cols` columns, clearing the screen, resetting
+    /// the margins and homing the cursor.
+    fn set_column_mode(&mut self, cols: usize) {
+        if self.host.is_some() {
+            self.emit(TerminalEvent::ColumnModeChange(cols));
+        } else {
+            self.resize(cols, self.height);
+        }
+        let blank = self.erase_cell();
+        for row in &mut self.buffer {
+            row.fill(blank);
+        }
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height - 1;
+        self.home_cursor();
+    }
+
+    fn set_modes(&mut self, params: &Params, private: bool, enable: bool) {
+        for param in params.iter() {
+            let mode = param.first().copied().unwrap_or(0);
+            match (private, mode) {
+                (false, 4) => self.state.insert_mode = enable,
+                (false, 20) => self.state.newline_mode = enable,
+                (true, 1) => self.state.application_cursor_keys = enable,
+                (true, 3) => self.set_column_mode(if enable { 132 } else { 80 }),
+                (true, 5) => {
+                    // Every row repaints, though no cell changed
+                    if self.state.reverse_screen != enable {
+                        self.damage_frame.clear();
+                    }
+                    self.state.reverse_screen = enable;
+                }
+                (true, 6) => {
+                    self.state.origin_mode = enable;
+                    self.home_cursor();
+                }
+                (true, 7) => self.state.autowrap = enable,
+                (true, 12) => self.state.cursor_blink = enable,
+                (true, 25) => self.state.cursor_visible = enable,
+                (true, 80) => self.state.sixel_display = enable,
+                (true, 47) | (true, 1047) => {
+                    if enable {
+                        self.enter_alternate_screen();
+                    } else {
+                        self.leave_alternate_screen();
+                    }
+                }
+                // Like 1047, saving the cursor before entering and restoring
+                // it after leaving
+                (true, 1049) => {
+                    if enable {
+                        if !self.alternate_screen {
+                            self.save_cursor();
+                        }
+                        self.enter_alternate_screen();
+                    } else if self.alternate_screen {
+                        self.leave_alternate_screen();
+                        self.restore_cursor();
+                    }
+                }
+                (true, 1000) => self.set_mouse_tracking(MouseTracking::Click, enable),
+                (true, 1002) => self.set_mouse_tracking(MouseTracking::Drag, enable),
+                (true, 1003) => self.set_mouse_tracking(MouseTracking::Motion, enable),
+                (true, 1005) => self.set_mouse_encoding(MouseEncoding::Utf8, enable),
+                (true, 1006) => self.set_mouse_encoding(MouseEncoding::Sgr, enable),
+                (true, 1015) => self.set_mouse_encoding(MouseEncoding::Urxvt, enable),
+                (true, 1035) => self.state.flow_control = enable,
+                (true, 2004) => self.state.bracketed_paste = enable,
+                // Redraws wait until the update ends
+                (true, 2026) => {
+                    if !enable {
+                        self.synchronized_since = None;
+                    } else if self.synchronized_since.is_none() {
+                        self.synchronized_since = Some(Instant::now());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether a mode `set_modes` handles is set, or `None` for modes it
+    /// doesn't know.
+    fn mode_state(&self, private: bool, mode: u16) -> Option<bool> {
+        let state = &self.state;
+        Some(match (private, mode) {
+            (false, 4) => state.insert_mode,
+            (false, 20) => state.newline_mode,
+            (true, 1) => state.application_cursor_keys,
+            (true, 3) => self.width == 132,
+            (true, 5) => state.reverse_screen,
+            (true, 6) => state.origin_mode,
+            (true, 7) => state.autowrap,
+            (true, 12) => state.cursor_blink,
+            (true, 25) => state.cursor_visible,
+            (true, 80) => state.sixel_display,
+            (true, 47) | (true, 1047) | (true, 1049) => self.alternate_screen,
+            (true, 1000) => state.mouse_tracking == MouseTracking::Click,
+            (true, 1002) => state.mouse_tracking == MouseTracking::Drag,
+            (true, 1003) => state.mouse_tracking == MouseTracking::Motion,
+            (true, 1005) => state.mouse_encoding == MouseEncoding::Utf8,
+            (true, 1006) => state.mouse_encoding == MouseEncoding::Sgr,
+            (true, 1015) => state.mouse_encoding == MouseEncoding::Urxvt,
+            (true, 1035) => state.flow_control,
+            (true, 2004) => state.bracketed_paste,
+            (true, 2026) => self.is_synchronized(),
+            _ => return None,
+        })
+    }
+
+    /// DECRQM: replies with `1` if the mode is set, `2` if it is reset and
+    /// `0` if it isn't recognized.
+    fn report_mode(&mut self, private: bool, mode: u16) {
+        let value = match self.mode_state(private, mode) {
+            Some(true) => 1,
+            Some(false) => 2,
+            None => 0,
+        };
+        let marker = if private { "?" } else { "" };
+        let report = format!("\x1b[{}{};{}$y", marker, mode, value);
+        self.respond(report.as_bytes());
+    }
+}
+
+impl Perform for Terminal {
+    fn print(&mut self, c: char) {
+        if self.print_overflow(c) || self.extend_cluster(c) {
+            return;
+        }
+        let width = char_width(c).min(self.width);
+        if self.cursor_x + width > self.width {
+            if self.state.autowrap {
+                self.buffer[self.cursor_y][self.width - 1].wrapped = true;
+                self.cursor_x = 0;
+                self.new_line();
+            } else {
+                // Without autowrap the cursor stays on the last column,
+                // overwriting it (or, for a wide character, the last two)
+                self.cursor_x = self.width - width;
+            }
+        }
+        if self.state.insert_mode {
+            self.insert_blank(width);
+        }
+        self.put_cell(TerminalCell {
+            ch: c,
+            combining: Combining::EMPTY,
+            fg_color: self.current_fg,
+            bg_color: self.current_bg,
+            attrs: self.current_attrs,
+            wrapped: false,
+            width: 1,
+        });
+        // Only autowrap, or overflow kept on the primary screen, leaves the
+        // cursor past the last column
+        if !self.state.autowrap && !self.keeps_overflow() {
+            self.cursor_x = self.cursor_x.min(self.width - 1);
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => self.cursor_x = self.cursor_x.min(self.width).saturating_sub(1),
+            0x09 => self.cursor_x = self.next_tab_stop(),
+            0x0A..=0x0C => {
+                self.complete_line();
+                self.line_feed();
+            }
+            0x07 => {
+                self.attention_events.push(AttentionEvent::Bell);
+                self.emit(TerminalEvent::Bell);
+            }
+            0x0D => self.cursor_x = 0,
+            XOFF if self.state.flow_control => self.flow_paused = true,
+            _ => {}
+        }
+    }
+
+    fn hook(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        self.dcs_hook(params, intermediates, ignore, action);
+    }
+
+    fn put(&mut self, byte: u8) {
+        self.dcs_put(byte);
+    }
+
+    fn unhook(&mut self) {
+        self.dcs_unhook();
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], bell_terminated: bool) {
+        match params {
+            [b"0", title, ..] | [b"2", title, ..] => {
+                self.set_title(String::from_utf8_lossy(title).into_owned());
+            }
+            [b"7", url, ..] => {
+                if let Some(path) = file_url_path(url) {
+                    self.working_directory = Some(path.clone());
+                    self.remote_host = file_url_host(url);
+                    self.emit(TerminalEvent::WorkingDirectoryChanged(path));
+                }
+            }
+            // Queries ("?") aren't answered so programs can't read the clipboard
+            [b"52", _, data, ..] if *data != b"?" => {
+                if let Some(text) = decode_base64(data) {
+                    let text = String::from_utf8_lossy(&text).into_owned();
+                    self.emit(TerminalEvent::ClipboardSet(text));
+                }
+            }
+            [b"12", b"?", ..] => {
+                let Color(r, g, b, _) = self.cursor_color.unwrap_or(self.default_fg);
+                let terminator = if bell_terminated { "\x07" } else { "\x1b\\" };
+                let reply = format!(
+                    "\x1b]12;rgb:{:02x}{:02x}/{:02x}{:02x}/{:02x}{:02x}{}",
+                    r, r, g, g, b, b, terminator
+                );
+                self.respond(reply.as_bytes());
+            }
+            [b"12", spec, ..] => {
+                let color = std::str::from_utf8(spec)
+                    .ok()
+                    .and_then(Color::from_rgb_spec);
+                if let Some(color) = color {
+                    self.set_program_cursor_color(Some(color));
+                }
+            }
+            [b"1337", command @ ..] => self.iterm_file(command),
+            [b"112", ..] => self.set_program_cursor_color(self.configured_cursor_color),
+            // Shell integration: C marks command output start, D its end
+            [b"133", b"C", ..] => self.command_started = Some(Instant::now()),
+            [b"133", mark, rest @ ..] if mark.first() == Some(&b'D') => {
+                if let Some(started) = self.command_started.take() {
+                    let exit_code = rest
+                        .first()
+                        .and_then(|code| std::str::from_utf8(code).ok())
+                        .and_then(|code| code.parse().ok());
+                    self.attention_events.push(AttentionEvent::CommandFinished {
+                        duration: started.elapsed(),
+                        exit_code,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+        let private = intermediates.first() == Some(&b'?');
+        match action {
+            'A' => self.cursor_up(param(params, 0, 1)),
+            'B' | 'e' => self.cursor_down(param(params, 0, 1)),
+            'C' | 'a' => self.cursor_x += param(params, 0, 1),
+            'D' => {
+                self.cursor_x = self
+                    .cursor_x
+                    .min(self.width)
+                    .saturating_sub(param(params, 0, 1))
+            }
+            'E' => {
+                self.cursor_down(param(params, 0, 1));
+                self.cursor_x = 0;
+            }
+            'F' => {
+                self.cursor_up(param(params, 0, 1));
+                self.cursor_x = 0;
+            }
+            'G' | '`' => self.cursor_x = param(params, 0, 1) - 1,
+            'H' | 'f' => {
+                self.cursor_to_row(param(params, 0, 1) - 1);
+                self.cursor_x = param(params, 1, 1) - 1;
+            }
+            'd' => self.cursor_to_row(param(params, 0, 1) - 1),
+            'J' => self.erase_in_display(param(params, 0, 0)),
+            'K' => self.erase_in_line(param(params, 0, 0)),
+            'L' => self.insert_lines(param(params, 0, 1)),
+            'M' => self.delete_lines(param(params, 0, 1)),
+            '@' => self.insert_blank(param(params, 0, 1)),
+            'P' => self.delete_chars(param(params, 0, 1)),
+            'X' => self.erase_chars(param(params, 0, 1)),
+            'S' => self.scroll_region_up(param(params, 0, 1)),
+            'T' => self.scroll_region_down(param(params, 0, 1)),
+            'm' => self.handle_sgr(params),
+            't' if intermediates.is_empty() => self.window_ops(params),
+            'h' => self.set_modes(params, private, true),
+            'l' => self.set_modes(params, private, false),
+            'r' => {
+                let top = param(params, 0, 1) - 1;
+                let bottom = param(params, 1, self.height).min(self.height) - 1;
+                if top < bottom {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.home_cursor();
+                }
+            }
+            'g' => match param(params, 0, 0) {
+                0 => self.tabs.retain(|&stop| stop != self.cursor_x),
+                3 => self.tabs.clear(),
+                _ => {}
+            },
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            'n' => match param(params, 0, 0) {
+                5 => self.respond(b"\x1b[0n"),
+                6 => {
+                    // Rows are relative to the top margin in origin mode
+                    let origin = if self.state.origin_mode {
+                        self.scroll_top
+                    } else {
+                        0
+                    };
+                    let report = format!(
+                        "\x1b[{};{}R",
+                        self.cursor_y.saturating_sub(origin) + 1,
+                        self.cursor_x.min(self.width - 1) + 1
+                    );
+                    self.respond(report.as_bytes());
+                }
+                _ => {}
+            },
+            // VT220 with sixel graphics
+            'c' if intermediates.is_empty() => self.respond(b"\x1b[?62;4c"),
+            'p' if intermediates == b"!" => self.soft_reset(),
+            'p' if intermediates.last() == Some(&b'$') => {
+                self.report_mode(private, param(params, 0, 0) as u16)
+            }
+            _ => {}
+        }
+        if !matches!(action, 'm' | 'u') {
+            self.clamp_cursor();
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.new_line(),
+            b'E' => {
+                self.complete_line();
+                self.cursor_x = 0;
+                self.new_line();
+            }
+            b'H' if !self.tabs.contains(&self.cursor_x) => {
+                self.tabs.push(self.cursor_x);
+                self.tabs.sort_unstable();
+            }
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::{CursorBlink, TerminalHost};
+
+    #[test]
+    fn erase_in_display_blanks_a_large_grid() {
+        let mut terminal = Terminal::new(500, 200);
+        let fill = "x".repeat(500 * 200);
+        terminal.process_pty_output(format!("\x1b[1;31;44m{}\x1b[m", fill).as_bytes());
+        terminal.erase_in_display(2);
+        assert_eq!(terminal.buffer.len(), 200);
+        assert!(
+            terminal
+                .buffer
+                .iter()
+                .all(|row| row.len() == 500
+                    && row.iter().all(|cell| *cell == TerminalCell::default()))
+        );
+    }
+
+    #[test]
+    fn line_feeds_return_to_column_0_only_in_newline_mode() {
+        for control in [b'\n', 0x0b, 0x0c] {
+            let mut terminal = Terminal::new(10, 4);
+            terminal.process_pty_output(&[b'a', b'b', control]);
+            assert_eq!((terminal.cursor_x, terminal.cursor_y), (2, 1));
+
+            terminal.process_pty_output(b"\x1b[20h");
+            terminal.process_pty_output(&[b'c', control]);
+            assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 2));
+        }
+    }
+
+    #[test]
+    fn line_feeds_scroll_only_the_scrolling_region() {
+        let mut terminal = Terminal::new(10, 8);
+        for y in 0..8 {
+            terminal.process_pty_output(format!("\x1b[{};1Hrow{}", y + 1, y).as_bytes());
+        }
+        // Rows 2 to 5, then three line feeds past the bottom margin
+        terminal.process_pty_output(b"\x1b[3;6r\x1b[6;1H\n\n\nnew");
+        let lines: Vec<String> = (0..8).map(|y| terminal.line_text(y).unwrap()).collect();
+        assert_eq!(
+            lines,
+            ["row0", "row1", "row5", "", "", "new", "row6", "row7"]
+        );
+        assert_eq!(terminal.history_len(), 0);
+    }
+
+    #[test]
+    fn cursor_down_stops_at_the_bottom_margin() {
+        let mut terminal = Terminal::new(10, 8);
+        terminal.process_pty_output(b"\x1b[3;6r\x1b[4;1H\x1b[10B");
+        assert_eq!(terminal.cursor_y, 5);
+        terminal.process_pty_output(b"\x1b[20A");
+        assert_eq!(terminal.cursor_y, 2);
+    }
+
+    #[test]
+    fn cursor_addressing_follows_origin_mode() {
+        // Margins at rows 5 to 10 of 15, as xterm's ctlseqs describes them:
+        // (origin mode, sequence after setting the margins, 1-based row and
+        // column reached)
+        for (origin, sequence, row, column) in [
+            (false, "", 1, 1),
+            (true, "", 5, 1),
+            (false, "\x1b[3;4H", 3, 4),
+            (true, "\x1b[3;4H", 7, 4),
+            (true, "\x1b[H", 5, 1),
+            (true, "\x1b[20;2f", 10, 2),
+            (false, "\x1b[20;2f", 15, 2),
+            (true, "\x1b[4d", 8, 1),
+            (false, "\x1b[4d", 4, 1),
+            (true, "\x1b[3;3H\x1b[9A", 5, 3),
+            (true, "\x1b[3;3H\x1b[9B", 10, 3),
+            (true, "\x1b[3;3H\x1b[2E", 9, 1),
+            (true, "\x1b[3;3H\x1b[9F", 5, 1),
+            (false, "\x1b[2;3H\x1b[9A", 1, 3),
+            (false, "\x1b[12;3H\x1b[9B", 15, 3),
+            (false, "\x1b[7;3H\x1b[9B", 10, 3),
+            (false, "\x1b[7;3H\x1b[9A", 5, 3),
+            // Setting the margins again homes the cursor
+            (true, "\x1b[3;3H\x1b[6;8r", 6, 1),
+            (false, "\x1b[3;3H\x1b[6;8r", 1, 1),
+            // Leaving origin mode homes it to the top of the screen
+            (true, "\x1b[3;3H\x1b[?6l", 1, 1),
+        ] {
+            let mut terminal = Terminal::new(20, 15);
+            terminal.process_pty_output(b"\x1b[5;10r");
+            if origin {
+                terminal.process_pty_output(b"\x1b[?6h");
+            }
+            terminal.process_pty_output(sequence.as_bytes());
+            assert_eq!(
+                (terminal.cursor_y + 1, terminal.cursor_x + 1),
+                (row, column),
+                "origin {} {:?}",
+                origin,
+                sequence
+            );
+        }
+    }
+
+    #[test]
+    fn cursor_reports_are_relative_to_the_origin() {
+        let mut terminal = Terminal::new(20, 15);
+        terminal.process_pty_output(b"\x1b[5;10r\x1b[?6h\x1b[2;3H\x1b[6n");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[2;3R");
+        terminal.process_pty_output(b"\x1b[?6l\x1b[2;3H\x1b[6n");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[2;3R");
+    }
+
+    #[test]
+    fn printing_past_the_margin_wraps_only_with_autowrap() {
+        let rows = |terminal: &Terminal| -> Vec<String> {
+            (0..3).filter_map(|y| terminal.line_text(y)).collect()
+        };
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"0123456789abc");
+        assert_eq!(rows(&terminal), ["0123456789", "abc", ""]);
+
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[?7l0123456789abc");
+        assert_eq!(rows(&terminal), ["012345678c", "", ""]);
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (9, 0));
+
+        // A wide character takes the last two columns
+        terminal.process_pty_output("\r\n0123456789漢".as_bytes());
+        assert_eq!(rows(&terminal)[1], "01234567漢");
+
+        // A wrap left pending is dropped once autowrap is turned off
+        terminal.process_pty_output(b"\x1b[3;1H012345678\x1b[?7h9");
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (10, 2));
+        terminal.process_pty_output(b"\x1b[?7lx\x1b[?7hy");
+        assert_eq!(rows(&terminal), ["012345678c", "01234567漢", "012345678y"]);
+    }
+
+    #[test]
+    fn xoff_pauses_printing_until_xon() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"\x1b[?1035h");
+        terminal.process_pty_output(b"ab\x13cd");
+        assert!(terminal.output_paused());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("ab"));
+
+        terminal.process_pty_output(b"ef\x11gh");
+        assert!(!terminal.output_paused());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("abcdefgh"));
+    }
+
+    #[test]
+    fn xoff_is_printed_through_without_flow_control() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"ab\x13cd");
+        assert!(!terminal.output_paused());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("abcd"));
+    }
+
+    #[test]
+    fn ctrl_q_resumes_paused_output() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"\x1b[?1035h\x13held");
+        terminal.write_input(b"\x11");
+        assert!(!terminal.output_paused());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("held"));
+        assert!(terminal.take_pending_input().is_empty());
+    }
+
+    #[test]
+    fn decrqm_reports_set_reset_and_unknown_modes() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[?2004h\x1b[?2004$p\x1b[?1049$p\x1b[?9999$p\x1b[4$p");
+        assert_eq!(
+            terminal.take_pending_input(),
+            b"\x1b[?2004;1$y\x1b[?1049;2$y\x1b[?9999;0$y\x1b[4;2$y"
+        );
+    }
+
+    #[test]
+    fn decrqm_follows_modes_as_they_change() {
+        let mut terminal = Terminal::new(10, 4);
+        for mode in [7, 25] {
+            let query = format!("\x1b[?{mode}$p\x1b[?{mode}l\x1b[?{mode}$p");
+            terminal.process_pty_output(query.as_bytes());
+            let expected = format!("\x1b[?{mode};1$y\x1b[?{mode};2$y");
+            assert_eq!(terminal.take_pending_input(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn hammering_ich_at_the_right_margin_keeps_rows_width_long() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"0123456789\x1b[1;10H");
+        for _ in 0..1000 {
+            terminal.process_pty_output(b"\x1b[5@\x1b[@\x1b[1;10H");
+        }
+        assert_eq!(terminal.line_text(0).as_deref(), Some("012345678"));
+        terminal
+            .process_pty_output("\x1b[2H\x1b[4h\x1b[?7l漢字abcdefgh漢\x1b[1;10H\x1b[3P".as_bytes());
+        assert!(terminal.buffer.iter().all(|row| row.len() == 10));
+    }
+
+    #[test]
+    fn sgr_sets_and_clears_rapid_blink_and_overline() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[6;53m");
+        assert_eq!(terminal.current_attrs.blink, Blink::Rapid);
+        assert!(terminal.current_attrs.overline);
+        terminal.process_pty_output(b"\x1b[55m");
+        assert!(!terminal.current_attrs.overline);
+        assert_eq!(terminal.current_attrs.blink, Blink::Rapid);
+        terminal.process_pty_output(b"\x1b[25m");
+        assert_eq!(terminal.current_attrs.blink, Blink::None);
+        terminal.process_pty_output(b"\x1b[5m\x1b[25m");
+        assert_eq!(terminal.current_attrs.blink, Blink::None);
+    }
+
+    #[test]
+    fn sgr_4_sub_parameters_select_the_underline_style() {
+        let mut terminal = Terminal::new(10, 4);
+        for (sgr, style) in [
+            ("4:3", UnderlineStyle::Curly),
+            ("24", UnderlineStyle::None),
+            ("4", UnderlineStyle::Single),
+            ("4:2", UnderlineStyle::Double),
+            ("4:0", UnderlineStyle::None),
+            ("4:4", UnderlineStyle::Dotted),
+            // An unknown style keeps the current one
+            ("4:9", UnderlineStyle::Dotted),
+            ("4:5", UnderlineStyle::Dashed),
+            ("21", UnderlineStyle::Double),
+            ("4:1", UnderlineStyle::Single),
+            ("0", UnderlineStyle::None),
+        ] {
+            terminal.process_pty_output(format!("\x1b[{}m", sgr).as_bytes());
+            assert_eq!(terminal.current_attrs.underline, style, "SGR {}", sgr);
+        }
+
+        // With a semicolon the 3 is italic rather than a style
+        terminal.process_pty_output(b"\x1b[4;3m");
+        assert_eq!(terminal.current_attrs.underline, UnderlineStyle::Single);
+        assert!(terminal.current_attrs.italic);
+    }
+
+    #[test]
+    fn sgr_58_sets_the_underline_color_apart_from_the_foreground() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[4;31;58;5;9ma\x1b[58;2;0;255;0mb\x1b[58:2::1:2:3mc");
+        let red = Color::from_ansi_color(1);
+        for (x, underline) in [
+            (0, Color::from_ansi_color(9)),
+            (1, Color(0, 255, 0, 255)),
+            (2, Color(1, 2, 3, 255)),
+        ] {
+            let cell = &terminal.buffer[0][x];
+            assert_eq!(cell.attrs.underline_color, Some(underline));
+            assert_eq!(cell.fg_color, red);
+        }
+
+        terminal.process_pty_output(b"\x1b[59md\x1b[58;5;2m\x1b[39me");
+        assert_eq!(terminal.buffer[0][3].attrs.underline_color, None);
+        assert_eq!(terminal.buffer[0][3].fg_color, red);
+        let green = Some(Color::from_ansi_color(2));
+        assert_eq!(terminal.buffer[0][4].attrs.underline_color, green);
+        terminal.process_pty_output(b"\x1b[0m");
+        assert_eq!(terminal.current_attrs.underline_color, None);
+    }
+
+    #[test]
+    fn insert_blank_shifts_cells_right_and_drops_the_overflow() {
+        let mut terminal = Terminal::new(6, 2);
+        terminal.process_pty_output(b"abcdef\x1b[1;3H\x1b[44m");
+        terminal.insert_blank(2);
+        assert_eq!(terminal.line_text(0).as_deref(), Some("ab  cd"));
+        assert_eq!(terminal.buffer[0].len(), 6);
+        assert_eq!(terminal.buffer[0][2].bg_color, Color::from_ansi_color(4));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (2, 0));
+    }
+
+    #[test]
+    fn delete_chars_pulls_cells_left_and_fills_with_blanks() {
+        let mut terminal = Terminal::new(6, 2);
+        terminal.process_pty_output(b"abcdef\x1b[1;2H\x1b[44m");
+        terminal.delete_chars(2);
+        assert_eq!(terminal.line_text(0).as_deref(), Some("adef"));
+        assert_eq!(terminal.buffer[0].len(), 6);
+        assert!(terminal.buffer[0][4..]
+            .iter()
+            .all(|cell| cell.ch == ' ' && cell.bg_color == Color::from_ansi_color(4)));
+
+        let mut escaped = Terminal::new(6, 2);
+        escaped.process_pty_output(b"abcdef\x1b[1;2H\x1b[44m\x1b[2P");
+        assert_eq!(escaped.buffer[0], terminal.buffer[0]);
+    }
+
+    struct FakeHost;
+
+    impl TerminalHost for FakeHost {
+        fn text_area_size(&self) -> (u32, u32) {
+            (800, 480)
+        }
+
+        fn cell_size(&self) -> (u32, u32) {
+            (10, 20)
+        }
+    }
+
+    #[test]
+    fn xtwinops_reports_sizes_from_the_host() {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.process_pty_output(b"\x1b[14t\x1b[16t\x1b[18t");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[8;24;80t");
+
+        terminal.set_host(Box::new(FakeHost));
+        terminal.process_pty_output(b"\x1b[14t\x1b[16t\x1b[18t\x1b[99t");
+        assert_eq!(
+            terminal.take_pending_input(),
+            b"\x1b[4;480;800t\x1b[6;20;10t\x1b[8;24;80t"
+        );
+    }
+
+    #[test]
+    fn xtwinops_pushes_and_pops_titles() {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.process_pty_output(b"\x1b]2;shell\x07\x1b[22t\x1b]2;vim\x07");
+        assert_eq!(terminal.title, "vim");
+        terminal.process_pty_output(b"\x1b[23t");
+        assert_eq!(terminal.title, "shell");
+        terminal.process_pty_output(b"\x1b[23t");
+        assert_eq!(terminal.title, "shell");
+    }
+
+    #[test]
+    fn xtwinops_resizes_only_when_allowed() {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.set_host(Box::new(FakeHost));
+        terminal.process_pty_output(b"\x1b[8;30;100t");
+        assert_eq!(terminal.take_window_resize_request(), None);
+
+        terminal.allow_window_resize = true;
+        terminal.process_pty_output(b"\x1b[8;30;100t");
+        assert_eq!(terminal.take_window_resize_request(), Some((100, 30)));
+        terminal.process_pty_output(b"\x1b[4;400;500t");
+        assert_eq!(terminal.take_window_resize_request(), Some((50, 20)));
+        terminal.process_pty_output(b"\x1b[8;0;100t");
+        assert_eq!(terminal.take_window_resize_request(), Some((100, 24)));
+    }
+
+    #[test]
+    fn column_mode_clears_homes_and_asks_the_host_to_resize() {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.set_host(Box::new(FakeHost));
+        let events = terminal.subscribe_events();
+        terminal.process_pty_output(b"text\x1b[5;10r\x1b[?6h\x1b[3;7H\x1b[?3h");
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [TerminalEvent::ColumnModeChange(132)]
+        );
+        // The host resizes the grid; until then it keeps its size
+        assert_eq!(terminal.width, 80);
+        assert!((0..24).all(|y| terminal.line_text(y).as_deref() == Some("")));
+        assert_eq!((terminal.scroll_top, terminal.scroll_bottom), (0, 23));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+
+        terminal.process_pty_output(b"\x1b[?3l");
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(received, [TerminalEvent::ColumnModeChange(80)]);
+    }
+
+    #[test]
+    fn column_mode_resizes_the_grid_without_a_host() {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.process_pty_output(b"text\x1b[5;10r\x1b[3;7H\x1b[?3h");
+        assert_eq!((terminal.width, terminal.height), (132, 24));
+        assert!(terminal.buffer.iter().all(|row| row.len() == 132));
+        assert_eq!(terminal.line_text(0).as_deref(), Some(""));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+        assert_eq!((terminal.scroll_top, terminal.scroll_bottom), (0, 23));
+
+        terminal.process_pty_output(b"\x1b[?3l");
+        assert_eq!(terminal.width, 80);
+    }
+
+    #[test]
+    fn osc_12_sets_reports_and_restores_the_cursor_color() {
+        let mut terminal = Terminal::new(10, 3);
+        let configured = Color(255, 204, 0, 255);
+        terminal.set_cursor_color(Some(configured));
+
+        terminal.process_pty_output(b"\x1b]12;rgb:ff/80/00\x07");
+        assert_eq!(terminal.cursor_color(), Some(Color(255, 128, 0, 255)));
+        terminal.process_pty_output(b"\x1b]12;#0000ff\x1b\\");
+        assert_eq!(terminal.cursor_color(), Some(Color(0, 0, 255, 255)));
+        // Malformed specs leave the color alone
+        terminal.process_pty_output(b"\x1b]12;rgb:ff/80\x07\x1b]12;#12345\x07");
+        assert_eq!(terminal.cursor_color(), Some(Color(0, 0, 255, 255)));
+
+        terminal.process_pty_output(b"\x1b]12;?\x07");
+        assert_eq!(
+            terminal.take_pending_input(),
+            b"\x1b]12;rgb:0000/0000/ffff\x07"
+        );
+        terminal.process_pty_output(b"\x1b]12;?\x1b\\");
+        assert_eq!(
+            terminal.take_pending_input(),
+            b"\x1b]12;rgb:0000/0000/ffff\x1b\\"
+        );
+
+        terminal.process_pty_output(b"\x1b]112\x07");
+        assert_eq!(terminal.cursor_color(), Some(configured));
+    }
+
+    #[test]
+    fn decset_12_turns_cursor_blinking_on_and_off() {
+        let mut terminal = Terminal::new(10, 3);
+        assert!(!terminal.cursor_blinks());
+        terminal.process_pty_output(b"\x1b[?12h\x1b[?12$p");
+        assert!(terminal.cursor_blinks());
+        assert_eq!(terminal.take_pending_input(), b"\x1b[?12;1$y");
+        terminal.process_pty_output(b"\x1b[?12l\x1b[?12$p");
+        assert!(!terminal.cursor_blinks());
+        assert_eq!(terminal.take_pending_input(), b"\x1b[?12;2$y");
+    }
+
+    #[test]
+    fn forcing_blinking_off_wins_over_programs() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.set_cursor_blink(CursorBlink {
+            enabled: true,
+            force_off: true,
+            ..CursorBlink::default()
+        });
+        assert!(!terminal.cursor_blinks());
+        terminal.process_pty_output(b"\x1b[?12h");
+        assert!(!terminal.cursor_blinks());
+
+        terminal.set_cursor_blink(CursorBlink {
+            enabled: true,
+            ..CursorBlink::default()
+        });
+        assert!(terminal.cursor_blinks());
+    }
+}
@@ -0,0 +1,335 @@
+use vte::Params;
+
+use super::sixel::SixelDecoder;
+use super::{Blink, Color, Terminal, UnderlineStyle};
+
+/// Default for the longest DCS payload that is buffered. Longer strings
+/// are dropped so a runaway program can't grow memory without bound.
+pub const DCS_PAYLOAD_LIMIT: usize = 64 * 1024;
+
+/// The device control strings that are answered. Others, tmux passthrough
+/// among them, are discarded without being buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DcsKind {
+    /// DECRQSS, `DCS $ q`.
+    StatusString,
+    /// XTGETTCAP, `DCS + q`.
+    Termcap,
+}
+
+/// A device control string being received.
+#[derive(Debug)]
+pub(super) struct DcsString {
+    kind: DcsKind,
+    payload: Vec<u8>,
+    /// The payload outgrew the limit and is being discarded.
+    overflowed: bool,
+}
+
+impl Terminal {
+    /// Limits the bytes buffered for a single device control string.
+    /// Sixel data is decoded as it arrives and isn't affected.
+    pub fn set_dcs_payload_limit(&mut self, bytes: usize) {
+        self.dcs_payload_limit = bytes;
+    }
+
+    /// Drops the string being received when `byte` is CAN or SUB. vte
+    /// ends the string on them as it does on ST, but a cancelled string
+    /// isn't answered.
+    pub(super) fn scan_dcs(&mut self, byte: u8) {
+        if matches!(byte, 0x18 | 0x1a) {
+            self.sixel = None;
+            self.dcs = None;
+        }
+    }
+
+    pub(super) fn dcs_hook(
+        &mut self,
+        params: &Params,
+        intermediates: &[u8],
+        ignore: bool,
+        action: char,
+    ) {
+        self.sixel = None;
+        self.dcs = None;
+        if ignore {
+            return;
+        }
+        let kind = match (intermediates, action) {
+            // Sixel data is decoded as it arrives rather than buffered
+            (b"", 'q') => {
+                let params: Vec<u16> = params.iter().map(|param| param[0]).collect();
+                let Color(r, g, b, a) = self.default_bg;
+                self.sixel = Some(Box::new(SixelDecoder::new(&params, [r, g, b, a])));
+                return;
+            }
+            (b"$", 'q') => DcsKind::StatusString,
+            (b"+", 'q') => DcsKind::Termcap,
+            _ => return,
+        };
+        self.dcs = Some(DcsString {
+            kind,
+            payload: Vec::new(),
+            overflowed: false,
+        });
+    }
+
+    pub(super) fn dcs_put(&mut self, byte: u8) {
+        if let Some(sixel) = self.sixel.as_mut() {
+            sixel.put(byte);
+            return;
+        }
+        let Some(dcs) = self.dcs.as_mut() else {
+            return;
+        };
+        if dcs.overflowed {
+            return;
+        }
+        if dcs.payload.len() >= self.dcs_payload_limit {
+            eprintln!(
+                "Dropping DCS string longer than {} bytes",
+                self.dcs_payload_limit
+            );
+            dcs.overflowed = true;
+            dcs.payload = Vec::new();
+            return;
+        }
+        dcs.payload.push(byte);
+    }
+
+    pub(super) fn dcs_unhook(&mut self) {
+        if let Some(sixel) = self.sixel.take() {
+            if let Some(image) = sixel.finish() {
+                self.show_sixel(image);
+            }
+            return;
+        }
+        let Some(dcs) = self.dcs.take() else {
+            return;
+        };
+        if dcs.overflowed {
+            return;
+        }
+        match dcs.kind {
+            DcsKind::StatusString => self.request_status_string(&dcs.payload),
+            DcsKind::Termcap => self.request_termcap(&dcs.payload),
+        }
+    }
+
+    /// DECRQSS: reports the setting named by `request`. Unknown requests
+    /// get the "invalid" reply so the program doesn't wait for one.
+    fn request_status_string(&mut self, request: &[u8]) {
+        let report = match request {
+            b"m" => Some(format!("{}m", self.sgr_report())),
+            b"r" => Some(format!(
+                "{};{}r",
+                self.scroll_top + 1,
+                self.scroll_bottom + 1
+            )),
+            _ => None,
+        };
+        let reply = match report {
+            Some(report) => format!("\x1bP1$r{}\x1b\\", report),
+            None => "\x1bP0$r\x1b\\".to_string(),
+        };
+        self.respond(reply.as_bytes());
+    }
+
+    /// XTGETTCAP: reports the terminfo capabilities named, hex-encoded and
+    /// separated by `;`, in `request`. Like xterm, answering stops at the
+    /// first unknown name, which gets the failure reply.
+    fn request_termcap(&mut self, request: &[u8]) {
+        let mut reply = Vec::new();
+        for name in request.split(|&byte| byte == b';') {
+            let value = hex_decode(name)
+                .and_then(|name| TERMCAPS.iter().find(|(cap, _)| cap.as_bytes() == name))
+                .map(|(_, value)| value);
+            match value {
+                Some(Some(value)) => reply.push(format!(
+                    "\x1bP1+r{}={}\x1b\\",
+                    String::from_utf8_lossy(name),
+                    hex_encode(value.as_bytes())
+                )),
+                Some(None) => {
+                    reply.push(format!("\x1bP1+r{}\x1b\\", String::from_utf8_lossy(name)))
+                }
+                None => {
+                    reply.push(format!("\x1bP0+r{}\x1b\\", String::from_utf8_lossy(name)));
+                    break;
+                }
+            }
+        }
+        self.respond(reply.concat().as_bytes());
+    }
+
+    /// SGR parameters that recreate the current rendition from a reset.
+    fn sgr_report(&self) -> String {
+        let mut codes = vec!["0".to_string()];
+        for (set, code) in [
+            (self.current_attrs.bold, "1"),
+            (self.current_attrs.italic, "3"),
+            (self.current_attrs.blink == Blink::Slow, "5"),
+            (self.current_attrs.blink == Blink::Rapid, "6"),
+            (self.current_attrs.inverse, "7"),
+            (self.current_attrs.overline, "53"),
+        ] {
+            if set {
+                codes.push(code.to_string());
+            }
+        }
+        match self.current_attrs.underline {
+            UnderlineStyle::None => {}
+            UnderlineStyle::Single => codes.push("4".to_string()),
+            style => codes.push(format!("4:{}", style.sgr())),
+        }
+        if self.current_fg != self.default_fg {
+            codes.push(color_code(&self.palette, self.current_fg, 30, 90, 38));
+        }
+        if self.current_bg != self.default_bg {
+            codes.push(color_code(&self.palette, self.current_bg, 40, 100, 48));
+        }
+        if let Some(color) = self.current_attrs.underline_color {
+            codes.push(format!("58;2;{};{};{}", color.0, color.1, color.2));
+        }
+        codes.join(";")
+    }
+}
+
+/// Capabilities XTGETTCAP answers: string capabilities with their value,
+/// boolean ones with `None`.
+const TERMCAPS: &[(&str, Option<&str>)] = &[
+    ("TN", Some("xterm-256color")),
+    ("name", Some("xterm-256color")),
+    ("Co", Some("256")),
+    ("colors", Some("256")),
+    ("RGB", Some("8/8/8")),
+    ("Tc", None),
+    ("bce", None),
+    ("Su", None),
+    ("Sync", Some("\x1b[?2026%?%p1%{1}%-%tl%eh%;")),
+    ("Smulx", Some("\x1b[4:%p1%dm")),
+    (
+        "Setulc",
+        Some("\x1b[58:2::%p1%{65536}%/%d:%p1%{256}%/%{255}%&%d:%p1%{255}%&%dm"),
+    ),
+];
+
+/// Decodes a hex string such as `436f`, or `None` if it isn't one.
+fn hex_decode(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// Encodes `bytes` as upper-case hex, as XTGETTCAP replies are.
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+/// SGR code for `color`: a palette code when it matches one of the 16 ANSI
+/// colors in `palette`, otherwise a direct RGB color.
+fn color_code(
+    palette: &[Color; 16],
+    color: Color,
+    base: u8,
+    bright_base: u8,
+    direct: u8,
+) -> String {
+    match (0..16).find(|&index| palette[index as usize] == color) {
+        Some(index @ 0..=7) => (base + index).to_string(),
+        Some(index) => (bright_base + index - 8).to_string(),
+        None => format!("{};2;{};{};{}", direct, color.0, color.1, color.2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The replies to `output`.
+    fn replies(terminal: &mut Terminal, output: &[u8]) -> String {
+        terminal.process_pty_output(output);
+        String::from_utf8(terminal.take_pending_input()).unwrap()
+    }
+
+    #[test]
+    fn a_bounded_dcs_string_is_answered() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.set_dcs_payload_limit(4);
+        assert_eq!(
+            replies(&mut terminal, b"\x1bP$qm\x1b\\"),
+            "\x1bP1$r0m\x1b\\"
+        );
+    }
+
+    #[test]
+    fn an_oversized_dcs_string_is_dropped() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.set_dcs_payload_limit(4);
+        assert_eq!(replies(&mut terminal, b"\x1bP$qmmmmmm\x1b\\"), "");
+        // The next string starts afresh
+        assert_eq!(
+            replies(&mut terminal, b"\x1bP$qm\x1b\\"),
+            "\x1bP1$r0m\x1b\\"
+        );
+    }
+
+    #[test]
+    fn a_cancelled_dcs_string_is_dropped() {
+        let mut terminal = Terminal::new(20, 4);
+        assert_eq!(replies(&mut terminal, b"\x1bP$qm\x18ok"), "");
+        assert_eq!(replies(&mut terminal, b"\x1bPq#1~\x1a"), "");
+        assert!(terminal.images().is_empty());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("ok"));
+    }
+
+    #[test]
+    fn decrqss_reports_the_current_rendition() {
+        let mut terminal = Terminal::new(20, 4);
+        let reply = replies(&mut terminal, b"\x1b[1;4;31;48;2;1;2;3m\x1bP$qm\x1b\\");
+        assert_eq!(reply, "\x1bP1$r0;1;4;31;48;2;1;2;3m\x1b\\");
+    }
+
+    #[test]
+    fn unknown_dcs_strings_get_no_reply() {
+        let mut terminal = Terminal::new(20, 4);
+        assert_eq!(replies(&mut terminal, b"\x1bPtmux;\x1b\x1b[1m\x1b\\"), "");
+        assert_eq!(terminal.line_text(0).as_deref(), Some(""));
+    }
+
+    #[test]
+    fn decrqss_reports_the_scrolling_region() {
+        let mut terminal = Terminal::new(20, 10);
+        let reply = replies(&mut terminal, b"\x1b[3;8r\x1bP$qr\x1b\\");
+        assert_eq!(reply, "\x1bP1$r3;8r\x1b\\");
+    }
+
+    #[test]
+    fn decrqss_rejects_unknown_settings() {
+        let mut terminal = Terminal::new(20, 4);
+        assert_eq!(replies(&mut terminal, b"\x1bP$qx\x1b\\"), "\x1bP0$r\x1b\\");
+    }
+
+    #[test]
+    fn xtgettcap_answers_known_capabilities() {
+        let mut terminal = Terminal::new(20, 4);
+        // Co;bce
+        let reply = replies(&mut terminal, b"\x1bP+q436f;626365\x1b\\");
+        assert_eq!(reply, "\x1bP1+r436f=323536\x1b\\\x1bP1+r626365\x1b\\");
+    }
+
+    #[test]
+    fn xtgettcap_stops_at_an_unknown_capability() {
+        let mut terminal = Terminal::new(20, 4);
+        // xyz;Co
+        let reply = replies(&mut terminal, b"\x1bP+q78797a;436f\x1b\\");
+        assert_eq!(reply, "\x1bP0+r78797a\x1b\\");
+        assert_eq!(
+            replies(&mut terminal, b"\x1bP+qzz\x1b\\"),
+            "\x1bP0+rzz\x1b\\"
+        );
+    }
+}
@@ -0,0 +1,514 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::{Blink, Terminal, TerminalCell, TerminalState};
+
+/// How long blinking text stays shown, and then hidden.
+pub const BLINK_INTERVAL: Duration = Duration::from_millis(500);
+/// The same for rapidly blinking text: 200 blinks a minute, above the
+/// 150 that ECMA-48 sets as the bound between slow and rapid.
+pub const RAPID_BLINK_INTERVAL: Duration = Duration::from_millis(150);
+/// Longest a synchronized update (mode 2026) holds back redraws, in case
+/// the program never ends it.
+pub const SYNC_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// A copy of the screen and the state needed to draw it, taken by
+/// [`Terminal::snapshot`]. Rows are shared with earlier snapshots when they
+/// haven't changed, so taking one every frame is cheap.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TerminalSnapshot {
+    pub width: usize,
+    pub height: usize,
+    /// Screen rows, top to bottom, without the scrollback.
+    pub rows: Vec<Arc<[TerminalCell]>>,
+    /// Column and row of the cursor; the column equals the width while a
+    /// wrap is pending.
+    pub cursor: (usize, usize),
+    pub title: String,
+    pub modes: TerminalState,
+    pub alternate_screen: bool,
+    lines_scrolled: usize,
+}
+
+/// A difference between two snapshots, from [`TerminalSnapshot::diff`].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CellChange {
+    /// The grid changed size; every row of the new one follows as `Row`.
+    Resize { width: usize, height: usize },
+    /// A row that changed in more than half its cells, sent whole.
+    Row {
+        row: usize,
+        cells: Arc<[TerminalCell]>,
+    },
+    Cell {
+        row: usize,
+        col: usize,
+        cell: TerminalCell,
+    },
+}
+
+impl CellChange {
+    /// The row the change is on, if it is on one.
+    pub fn row(&self) -> Option<usize> {
+        match *self {
+            CellChange::Resize { .. } => None,
+            CellChange::Row { row, .. } | CellChange::Cell { row, .. } => Some(row),
+        }
+    }
+}
+
+impl TerminalSnapshot {
+    /// Changes to the grid that turn this snapshot into `other`, row by
+    /// row. Cursor, title and mode changes are left to the caller to
+    /// compare.
+    pub fn diff(&self, other: &TerminalSnapshot) -> Vec<CellChange> {
+        let mut changes = Vec::new();
+        let resized = (self.width, self.height) != (other.width, other.height);
+        if resized {
+            changes.push(CellChange::Resize {
+                width: other.width,
+                height: other.height,
+            });
+        }
+        for (row, cells) in other.rows.iter().enumerate() {
+            let previous = self.rows.get(row).filter(|_| !resized);
+            let Some(previous) = previous else {
+                changes.push(CellChange::Row {
+                    row,
+                    cells: cells.clone(),
+                });
+                continue;
+            };
+            if Arc::ptr_eq(previous, cells) || previous == cells {
+                continue;
+            }
+            let changed: Vec<usize> = (0..cells.len())
+                .filter(|&col| previous.get(col) != Some(&cells[col]))
+                .collect();
+            if changed.len() * 2 > cells.len() || previous.len() != cells.len() {
+                changes.push(CellChange::Row {
+                    row,
+                    cells: cells.clone(),
+                });
+            } else {
+                changes.extend(changed.into_iter().map(|col| CellChange::Cell {
+                    row,
+                    col,
+                    cell: cells[col],
+                }));
+            }
+        }
+        changes
+    }
+}
+
+/// A change to apply to a mirror of the screen, in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineUpdate {
+    /// The whole screen moved up by `lines`; rows exposed at the bottom are
+    /// followed by `Line` updates.
+    Scroll { lines: usize },
+    /// New contents of a row that differs from the mirror.
+    Line {
+        row: usize,
+        cells: Vec<TerminalCell>,
+    },
+}
+
+impl Terminal {
+    pub fn snapshot(&self) -> TerminalSnapshot {
+        // Rows from the last snapshot are reused until they change
+        let mut shared = self.snapshot_rows.borrow_mut();
+        shared.truncate(self.buffer.len());
+        for (y, row) in self.buffer.iter().enumerate() {
+            match shared.get_mut(y) {
+                Some(cells) if **cells == **row => {}
+                Some(cells) => *cells = Arc::from(row.as_slice()),
+                None => shared.push(Arc::from(row.as_slice())),
+            }
+        }
+        TerminalSnapshot {
+            width: self.width,
+            height: self.height,
+            rows: shared.clone(),
+            cursor: (self.cursor_x, self.cursor_y),
+            title: self.title.clone(),
+            modes: self.state.clone(),
+            alternate_screen: self.alternate_screen,
+            lines_scrolled: self.lines_scrolled,
+        }
+    }
+
+    /// Updates that turn the screen captured in `snapshot` into the current
+    /// one. Output that scrolled the screen is reported as a single `Scroll`
+    /// followed by the rows that still differ.
+    pub fn diff_since(&self, snapshot: &TerminalSnapshot) -> Vec<LineUpdate> {
+        let mut updates = Vec::new();
+        let same_size = snapshot.width == self.width && snapshot.rows.len() == self.height;
+        let scrolled = self.lines_scrolled.wrapping_sub(snapshot.lines_scrolled);
+
+        let shift = if same_size && scrolled > 0 && scrolled < self.height {
+            updates.push(LineUpdate::Scroll { lines: scrolled });
+            scrolled
+        } else {
+            0
+        };
+
+        for (row, cells) in self.buffer.iter().enumerate() {
+            let previous = if same_size && scrolled < self.height {
+                snapshot.rows.get(row + shift)
+            } else {
+                None
+            };
+            if previous.map(|previous| &previous[..]) != Some(cells.as_slice()) {
+                updates.push(LineUpdate::Line {
+                    row,
+                    cells: cells.clone(),
+                });
+            }
+        }
+        updates
+    }
+
+    /// Viewport rows whose contents changed, or that the cursor entered or
+    /// left, since the last call. Empty when nothing needs repainting.
+    pub fn take_damage(&mut self) -> Vec<usize> {
+        let mut frame = std::mem::take(&mut self.damage_frame);
+        frame.resize_with(self.height, Vec::new);
+
+        let mut damage = Vec::new();
+        for (y, previous) in frame.iter_mut().enumerate() {
+            let row = self.visible_row(y).unwrap_or_default();
+            if previous.as_slice() != row {
+                previous.clear();
+                previous.extend_from_slice(row);
+                damage.push(y);
+            }
+        }
+        self.damage_frame = frame;
+
+        let cursor = (self.cursor_x, self.cursor_y);
+        if self.damage_cursor != Some(cursor) {
+            let rows = self
+                .damage_cursor
+                .map(|(_, y)| y)
+                .into_iter()
+                .chain([cursor.1]);
+            for y in rows {
+                if y < self.height && !damage.contains(&y) {
+                    damage.push(y);
+                }
+            }
+            damage.sort_unstable();
+            self.damage_cursor = Some(cursor);
+        }
+        damage
+    }
+
+    /// Whether the window needs repainting at `now`: rows or the cursor
+    /// changed, blinking text or the cursor changed phase, or the viewport
+    /// is still scrolling. Consumes the damage like `take_damage`. During
+    /// a synchronized update nothing is reported, and the damage builds up
+    /// until the update ends or times out.
+    pub fn needs_redraw(&mut self, now: Instant) -> bool {
+        if self.synchronized_until().is_some_and(|until| until > now) {
+            return false;
+        }
+        self.synchronized_since = None;
+        let damaged = !self.take_damage().is_empty();
+        let (slow, rapid) = self.visible_blinks();
+        let phase = blink_phase_at(self.blink_epoch, now, BLINK_INTERVAL);
+        let rapid_phase = blink_phase_at(self.blink_epoch, now, RAPID_BLINK_INTERVAL);
+        let blinked = (slow && phase != self.blink_visible)
+            || (rapid && rapid_phase != self.rapid_blink_visible);
+        self.blink_visible = phase;
+        self.rapid_blink_visible = rapid_phase;
+        let cursor_phase = !self.cursor_blinks()
+            || blink_phase_at(self.blink_epoch, now, self.cursor_blink_interval());
+        let cursor_blinked = cursor_phase != self.cursor_blink_visible;
+        self.cursor_blink_visible = cursor_phase;
+        damaged || blinked || cursor_blinked || self.is_animating()
+    }
+
+    /// Whether a synchronized update (mode 2026) is holding back redraws.
+    pub fn is_synchronized(&self) -> bool {
+        self.synchronized_since.is_some()
+    }
+
+    /// When the synchronized update in progress, if any, times out.
+    pub fn synchronized_until(&self) -> Option<Instant> {
+        self.synchronized_since.map(|since| since + SYNC_TIMEOUT)
+    }
+
+    /// Whether text blinking at `blink` speed is shown in the current
+    /// phase, as of the last `needs_redraw`.
+    pub fn blink_visible(&self, blink: Blink) -> bool {
+        match blink {
+            Blink::None => true,
+            Blink::Slow => self.blink_visible,
+            Blink::Rapid => self.rapid_blink_visible,
+        }
+    }
+
+    /// Whether a blinking cursor is in its shown phase, as of the last
+    /// `needs_redraw`. Always true for a cursor that doesn't blink.
+    pub fn cursor_blink_visible(&self) -> bool {
+        self.cursor_blink_visible
+    }
+
+    fn cursor_blink_interval(&self) -> Duration {
+        Duration::from_millis(self.cursor_blink.interval_ms.max(1))
+    }
+
+    /// When blinking text or the cursor next changes phase, or `None` if
+    /// nothing on screen blinks.
+    pub fn next_blink(&self, now: Instant) -> Option<Instant> {
+        let (slow, rapid) = self.visible_blinks();
+        let next_phase = |interval: Duration| {
+            let interval = interval.as_nanos();
+            let elapsed = now.saturating_duration_since(self.blink_epoch).as_nanos();
+            now + Duration::from_nanos((interval - elapsed % interval) as u64)
+        };
+        let slow = slow.then(|| next_phase(BLINK_INTERVAL));
+        let rapid = rapid.then(|| next_phase(RAPID_BLINK_INTERVAL));
+        let cursor_blinks = self.cursor_blinks() && self.visible_cursor().is_some();
+        let cursor = cursor_blinks.then(|| next_phase(self.cursor_blink_interval()));
+        slow.into_iter().chain(rapid).chain(cursor).min()
+    }
+
+    /// Whether text blinking slowly, and rapidly, is on screen.
+    fn visible_blinks(&self) -> (bool, bool) {
+        let mut blinks = (false, false);
+        for cell in (0..self.height)
+            .filter_map(|y| self.visible_row(y))
+            .flatten()
+        {
+            match cell.attrs.blink {
+                Blink::None => {}
+                Blink::Slow => blinks.0 = true,
+                Blink::Rapid => blinks.1 = true,
+            }
+        }
+        blinks
+    }
+}
+
+/// Whether blinking text is in its shown phase at `now`, with phases of
+/// `interval` starting at `epoch`.
+fn blink_phase_at(epoch: Instant, now: Instant, interval: Duration) -> bool {
+    let elapsed = now.saturating_duration_since(epoch);
+    (elapsed.as_nanos() / interval.as_nanos()).is_multiple_of(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::CursorBlink;
+
+    #[test]
+    fn synchronized_updates_hold_back_redraws_until_they_end() {
+        let mut terminal = Terminal::new(10, 4);
+        let now = Instant::now();
+        terminal.needs_redraw(now);
+
+        terminal.process_pty_output(b"\x1b[?2026h");
+        assert!(!terminal.needs_redraw(now));
+        terminal.process_pty_output(b"frame\r\n\x1b[2;3Hmore");
+        assert!(!terminal.needs_redraw(now));
+        terminal.process_pty_output(b"\x1b[?2026l");
+        assert!(!terminal.is_synchronized());
+        assert!(terminal.needs_redraw(now));
+        assert!(!terminal.needs_redraw(now));
+    }
+
+    #[test]
+    fn synchronized_updates_time_out() {
+        let mut terminal = Terminal::new(10, 4);
+        let now = Instant::now();
+        terminal.needs_redraw(now);
+
+        terminal.process_pty_output(b"\x1b[?2026hframe");
+        assert!(!terminal.needs_redraw(now));
+        let later = terminal.synchronized_until().unwrap();
+        assert!(terminal.needs_redraw(later));
+        assert!(!terminal.is_synchronized());
+    }
+
+    #[test]
+    fn a_quiescent_terminal_needs_no_redraw() {
+        let mut terminal = Terminal::new(10, 4);
+        let now = Instant::now();
+        terminal.process_pty_output(b"\x1b[?12lidle");
+        assert!(terminal.needs_redraw(now));
+        assert!(!terminal.needs_redraw(now));
+        assert!(!terminal.needs_redraw(now + BLINK_INTERVAL * 3));
+    }
+
+    #[test]
+    fn scroll_animations_and_blinking_text_need_redraws() {
+        let mut terminal = Terminal::new(10, 4);
+        let now = Instant::now();
+        terminal.process_pty_output("\x1b[?12lx\r\n".repeat(10).as_bytes());
+        terminal.needs_redraw(now);
+        terminal.scroll_by(3.0);
+        assert!(terminal.is_animating());
+        assert!(terminal.needs_redraw(now));
+        assert!(terminal.needs_redraw(now));
+
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[?12l\x1b[5mblink");
+        let now = terminal.blink_epoch;
+        terminal.needs_redraw(now);
+        assert!(!terminal.needs_redraw(now));
+        assert!(terminal.needs_redraw(now + BLINK_INTERVAL));
+    }
+
+    #[test]
+    fn a_blinking_cursor_changes_phase_every_interval() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.set_cursor_blink(CursorBlink {
+            enabled: true,
+            interval_ms: 300,
+            force_off: false,
+        });
+        let interval = Duration::from_millis(300);
+        let now = terminal.blink_epoch;
+        terminal.needs_redraw(now);
+        assert!(terminal.cursor_blink_visible());
+        assert_eq!(terminal.next_blink(now), Some(now + interval));
+
+        assert!(terminal.needs_redraw(now + interval));
+        assert!(!terminal.cursor_blink_visible());
+        assert!(!terminal.needs_redraw(now + interval + interval / 2));
+        assert!(terminal.needs_redraw(now + interval * 2));
+        assert!(terminal.cursor_blink_visible());
+
+        // A steady cursor is always shown and never needs a redraw
+        terminal.process_pty_output(b"\x1b[?12l");
+        terminal.needs_redraw(now + interval * 3);
+        assert!(terminal.cursor_blink_visible());
+        assert_eq!(terminal.next_blink(now + interval * 3), None);
+    }
+
+    #[test]
+    fn snapshot_diffs_match_the_damage_of_a_redraw() {
+        let mut terminal = Terminal::new(20, 6);
+        terminal.process_pty_output(
+            b"fn main() {\r\n    println!();\r\n}\r\n~\r\n~\r\n\x1b[7m-- INSERT --\x1b[m",
+        );
+        terminal.take_damage();
+        let before = terminal.snapshot();
+
+        // What vim sends for typing a character and updating its status line
+        terminal.process_pty_output(
+            b"\x1b[?25l\x1b[2;13H\"hi\"\x1b[6;1H\x1b[K\x1b[7m-- INSERT --\x1b[m\x1b[6;15H2,17\x1b[2;17H\x1b[?25h",
+        );
+        let damage = terminal.take_damage();
+        let after = terminal.snapshot();
+        let changes = before.diff(&after);
+
+        let mut changed_rows: Vec<usize> = changes.iter().filter_map(CellChange::row).collect();
+        changed_rows.dedup();
+        assert_eq!(changed_rows, [1, 5]);
+        assert!(changed_rows.iter().all(|row| damage.contains(row)));
+        assert!(changes
+            .iter()
+            .all(|change| matches!(change, CellChange::Cell { .. })));
+        assert_eq!(
+            changes[0],
+            CellChange::Cell {
+                row: 1,
+                col: 12,
+                cell: *terminal.get_cell_at(12, 1).unwrap()
+            }
+        );
+        assert_eq!((after.cursor, after.title.as_str()), ((16, 1), ""));
+    }
+
+    #[test]
+    fn unchanged_rows_are_shared_between_snapshots() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"one\r\ntwo");
+        let before = terminal.snapshot();
+        terminal.process_pty_output(b"\x1b[3;1Hthree more");
+        let after = terminal.snapshot();
+        assert!(Arc::ptr_eq(&before.rows[0], &after.rows[0]));
+        assert!(!Arc::ptr_eq(&before.rows[2], &after.rows[2]));
+        // Most of the row changed, so it is sent whole
+        assert_eq!(
+            before.diff(&after),
+            [CellChange::Row {
+                row: 2,
+                cells: after.rows[2].clone()
+            }]
+        );
+        assert!(after.diff(&after).is_empty());
+    }
+
+    #[test]
+    fn a_resize_resends_every_row() {
+        let mut terminal = Terminal::new(10, 3);
+        let before = terminal.snapshot();
+        terminal.resize(12, 2);
+        let changes = before.diff(&terminal.snapshot());
+        assert_eq!(
+            changes[0],
+            CellChange::Resize {
+                width: 12,
+                height: 2
+            }
+        );
+        assert_eq!(
+            changes[1..]
+                .iter()
+                .map(|change| change.row())
+                .collect::<Vec<_>>(),
+            [Some(0), Some(1)]
+        );
+    }
+
+    fn line(update: &LineUpdate) -> (usize, String) {
+        match update {
+            LineUpdate::Line { row, cells } => (*row, Terminal::row_text(cells).trim_end().into()),
+            LineUpdate::Scroll { .. } => panic!("expected a line, got {:?}", update),
+        }
+    }
+
+    #[test]
+    fn diff_since_reports_a_single_changed_cell_as_its_row() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"one\r\ntwo");
+        let snapshot = terminal.snapshot();
+        assert!(terminal.diff_since(&snapshot).is_empty());
+
+        terminal.process_pty_output(b"\x1b[1;2HX");
+        let updates = terminal.diff_since(&snapshot);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(line(&updates[0]), (0, "oXe".to_string()));
+    }
+
+    #[test]
+    fn diff_since_reports_a_rewritten_line() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"one\r\ntwo");
+        let snapshot = terminal.snapshot();
+        terminal.process_pty_output(b"\r\x1b[2Kreplaced");
+        let updates = terminal.diff_since(&snapshot);
+        assert_eq!(updates.len(), 1);
+        assert_eq!(line(&updates[0]), (1, "replaced".to_string()));
+    }
+
+    #[test]
+    fn diff_since_reports_scrolling_and_the_exposed_lines() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"a\r\nb\r\nc");
+        let snapshot = terminal.snapshot();
+        terminal.process_pty_output(b"\r\nd\r\ne");
+        let updates = terminal.diff_since(&snapshot);
+        assert_eq!(updates[0], LineUpdate::Scroll { lines: 2 });
+        let lines: Vec<_> = updates[1..].iter().map(line).collect();
+        assert_eq!(lines, [(1, "d".to_string()), (2, "e".to_string())]);
+    }
+}
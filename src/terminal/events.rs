@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+
+use super::Terminal;
+
+/// Something a program running in the terminal did that the host may want
+/// to react to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalEvent {
+    /// The title was set (OSC 0/2), restored from the title stack or cleared.
+    TitleChanged(String),
+    Bell,
+    /// The shell reported its working directory (OSC 7).
+    WorkingDirectoryChanged(PathBuf),
+    /// A program asked to set the clipboard (OSC 52).
+    ClipboardSet(String),
+    /// DECCOLM switched to this many columns. The host resizes the grid,
+    /// the PTY and possibly the window; without a host the terminal
+    /// resizes itself.
+    ColumnModeChange(usize),
+}
+
+impl Terminal {
+    /// Returns a receiver of every event from now on. Any number of
+    /// subscribers may be open; dropped receivers are forgotten.
+    pub fn subscribe_events(&mut self) -> Receiver<TerminalEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.event_senders.push(sender);
+        receiver
+    }
+
+    pub(super) fn emit(&mut self, event: TerminalEvent) {
+        self.event_senders
+            .retain(|sender| sender.send(event.clone()).is_ok());
+    }
+
+    pub(super) fn set_title(&mut self, title: String) {
+        if self.title != title {
+            self.title = title;
+            self.emit(TerminalEvent::TitleChanged(self.title.clone()));
+        }
+    }
+}
+
+/// Path of an OSC 7 `file://host/path` URL, with `%XX` escapes decoded.
+pub(super) fn file_url_path(url: &[u8]) -> Option<PathBuf> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut bytes = path.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [*bytes.next()?, *bytes.next()?];
+            let hex = std::str::from_utf8(&hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            decoded.push(byte);
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(decoded).ok()?))
+}
+
+/// Host of an OSC 7 `file://host/path` URL, unless it is empty or
+/// `localhost`.
+pub(super) fn file_url_host(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    let host = &rest[..rest.iter().position(|&b| b == b'/')?];
+    let host = std::str::from_utf8(host).ok()?;
+    (!host.is_empty() && host != "localhost").then(|| host.to_string())
+}
+
+/// Decodes standard base64, ignoring padding. Returns `None` on any other
+/// character outside the alphabet.
+pub(super) fn decode_base64(data: &[u8]) -> Option<Vec<u8>> {
+    let value = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for &byte in data.iter().filter(|&&byte| byte != b'=') {
+        bits = (bits << 6) | value(byte)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn title_and_bell_are_sent_to_subscribers() {
+        let mut terminal = Terminal::new(10, 3);
+        let events = terminal.subscribe_events();
+        terminal.process_pty_output(b"\x1b]2;build\x07\x07");
+        let received: Vec<_> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            [
+                TerminalEvent::TitleChanged("build".to_string()),
+                TerminalEvent::Bell,
+            ]
+        );
+    }
+
+    #[test]
+    fn dropped_subscribers_are_forgotten() {
+        let mut terminal = Terminal::new(10, 3);
+        drop(terminal.subscribe_events());
+        let events = terminal.subscribe_events();
+        terminal.process_pty_output(b"\x07");
+        assert_eq!(terminal.event_senders.len(), 1);
+        assert_eq!(events.try_recv(), Ok(TerminalEvent::Bell));
+    }
+
+    #[test]
+    fn working_directory_urls_are_decoded() {
+        let url = b"file://localhost/home/me/my%20dir";
+        assert_eq!(file_url_path(url), Some(PathBuf::from("/home/me/my dir")));
+        assert_eq!(file_url_host(url), None);
+        assert_eq!(decode_base64(b"aGk="), Some(b"hi".to_vec()));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::{char_width, Terminal, TerminalCell};
+
+/// Bytes a `Combining` holds without interning.
+const INLINE_LEN: usize = 4;
+/// `len` of a `Combining` whose bytes are an index into the interned table.
+const INTERNED: u8 = u8::MAX;
+/// Longest cluster tail kept; characters past it are dropped, so stacks of
+/// combining marks can't grow a cell without bound.
+pub(super) const MAX_COMBINING_LEN: usize = 64;
+const ZERO_WIDTH_JOINER: char = '\u{200d}';
+const EMOJI_PRESENTATION: char = '\u{fe0f}';
+
+/// Clusters too long to store inline, kept for the life of the process.
+#[derive(Default)]
+struct Interner {
+    strings: Vec<&'static str>,
+    ids: HashMap<&'static str, u32>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Mutex::default)
+}
+
+/// The characters of a grapheme cluster after its first one, such as
+/// combining marks, variation selectors, skin tone modifiers or the rest of
+/// a ZWJ sequence. Up to four bytes of UTF-8 are stored inline; longer
+/// strings are interned so cells stay `Copy`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Combining {
+    len: u8,
+    bytes: [u8; INLINE_LEN],
+}
+
+impl Combining {
+    pub const EMPTY: Combining = Combining {
+        len: 0,
+        bytes: [0; INLINE_LEN],
+    };
+
+    pub fn new(text: &str) -> Self {
+        let mut bytes = [0; INLINE_LEN];
+        if text.len() <= INLINE_LEN {
+            bytes[..text.len()].copy_from_slice(text.as_bytes());
+            return Self {
+                len: text.len() as u8,
+                bytes,
+            };
+        }
+        let mut interner = interner().lock().unwrap_or_else(|e| e.into_inner());
+        let id = match interner.ids.get(text) {
+            Some(&id) => id,
+            None => {
+                let text: &'static str = Box::leak(text.into());
+                let id = interner.strings.len() as u32;
+                interner.strings.push(text);
+                interner.ids.insert(text, id);
+                id
+            }
+        };
+        bytes.copy_from_slice(&id.to_le_bytes());
+        Self {
+            len: INTERNED,
+            bytes,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_str(&self) -> &str {
+        if self.len == INTERNED {
+            let id = u32::from_le_bytes(self.bytes) as usize;
+            let interner = interner().lock().unwrap_or_else(|e| e.into_inner());
+            interner.strings[id]
+        } else {
+            // Only ever built from a whole `str`
+            std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+        }
+    }
+
+    /// Combining marks, drawn over the first character when the cluster
+    /// can't be rendered as a unit.
+    pub fn marks(&self) -> impl Iterator<Item = char> + '_ {
+        self.as_str().chars().filter(|&c| is_mark(c))
+    }
+}
+
+/// Serialized as the text of the cluster tail.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Combining {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Combining {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        if text.len() > MAX_COMBINING_LEN {
+            return Err(serde::de::Error::custom("grapheme cluster is too long"));
+        }
+        Ok(Combining::new(&text))
+    }
+}
+
+impl fmt::Debug for Combining {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// Nonspacing combining marks from the main combining blocks.
+fn is_mark(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036f}'
+        | '\u{1ab0}'..='\u{1aff}'
+        | '\u{1dc0}'..='\u{1dff}'
+        | '\u{20d0}'..='\u{20ff}'
+        | '\u{fe20}'..='\u{fe2f}')
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+/// Columns a whole cluster takes: two for emoji presentation, flags and
+/// ZWJ sequences, otherwise the width of its first character.
+pub fn cluster_width(first: char, rest: &str) -> usize {
+    let emoji = rest.contains(EMOJI_PRESENTATION)
+        || rest.contains(ZERO_WIDTH_JOINER)
+        || (is_regional_indicator(first) && rest.chars().any(is_regional_indicator));
+    if emoji {
+        2
+    } else {
+        char_width(first)
+    }
+}
+
+impl TerminalCell {
+    /// The whole grapheme cluster of the cell.
+    pub fn text(&self) -> String {
+        let mut text = String::from(self.ch);
+        text.push_str(self.combining.as_str());
+        text
+    }
+}
+
+impl Terminal {
+    /// Appends `c` to the cluster of the character before the cursor if
+    /// it continues it, as a combining mark, modifier or part of a ZWJ
+    /// sequence or flag does. Returns whether it did.
+    pub(super) fn extend_cluster(&mut self, c: char) -> bool {
+        // Nothing below the combining diacritics block extends a cluster
+        if c < '\u{0300}' {
+            return false;
+        }
+        let y = self.cursor_y;
+        let Some(mut x) = self.cursor_x.min(self.width).checked_sub(1) else {
+            return false;
+        };
+        if self.buffer[y][x].is_spacer() && x > 0 {
+            x -= 1;
+        }
+        let cell = self.buffer[y][x];
+        if cell.ch == ' ' && cell.combining.is_empty() {
+            return false;
+        }
+        let mut text = cell.text();
+        text.push(c);
+        if text.graphemes(true).nth(1).is_some() {
+            return false;
+        }
+        let rest = &text[cell.ch.len_utf8()..];
+        if rest.len() > MAX_COMBINING_LEN {
+            return true;
+        }
+        let width = cluster_width(cell.ch, rest);
+        let row = &mut self.buffer[y];
+        row[x].combining = Combining::new(rest);
+        // A cluster that became wide claims the next column if it is free
+        if width == 2 && row[x].width == 1 && x + 1 < self.width && x + 1 == self.cursor_x {
+            row[x].width = 2;
+            row[x + 1] = TerminalCell {
+                width: 0,
+                ch: ' ',
+                combining: Combining::EMPTY,
+                wrapped: row[x + 1].wrapped,
+                ..row[x]
+            };
+            self.cursor_x += 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Prints `text` and returns the cells of the first row up to the
+    /// cursor.
+    fn printed(text: &str) -> (Terminal, Vec<TerminalCell>) {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.process_pty_output(text.as_bytes());
+        let cells = terminal.buffer[0][..terminal.cursor_x].to_vec();
+        (terminal, cells)
+    }
+
+    #[test]
+    fn a_decomposed_accent_joins_its_letter() {
+        let (terminal, cells) = printed("e\u{301}x");
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].text(), "e\u{301}");
+        assert_eq!(cells[0].combining.marks().collect::<Vec<_>>(), ['\u{301}']);
+        assert_eq!(cells[1].ch, 'x');
+        assert_eq!(terminal.line_text(0).as_deref(), Some("e\u{301}x"));
+    }
+
+    #[test]
+    fn a_flag_is_one_wide_cell() {
+        let (_, cells) = printed("\u{1f1eb}\u{1f1f7}!");
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[0].text(), "\u{1f1eb}\u{1f1f7}");
+        assert_eq!(cells[0].width, 2);
+        assert!(cells[1].is_spacer());
+        assert_eq!(cells[2].ch, '!');
+
+        // Two flags in a row stay two flags
+        let (_, cells) = printed("\u{1f1eb}\u{1f1f7}\u{1f1e9}\u{1f1ea}");
+        assert_eq!(cells.len(), 4);
+        assert_eq!(cells[2].text(), "\u{1f1e9}\u{1f1ea}");
+    }
+
+    #[test]
+    fn skin_tones_and_zwj_sequences_stay_in_one_cell() {
+        let (_, cells) = printed("\u{1f44b}\u{1f3fd}");
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].text(), "\u{1f44b}\u{1f3fd}");
+
+        let family = "\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}";
+        let (_, cells) = printed(&format!("{}a", family));
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[0].text(), family);
+        // Longer than four bytes, so the tail is interned
+        assert_eq!(cells[0].combining.len, INTERNED);
+        assert_eq!(cells[2].ch, 'a');
+    }
+
+    #[test]
+    fn a_text_character_with_emoji_presentation_becomes_wide() {
+        let (_, cells) = printed("\u{2764}\u{fe0f}");
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].width, 2);
+        assert_eq!(cluster_width('\u{2764}', ""), 1);
+    }
+
+    #[test]
+    fn combining_tails_round_trip_and_are_bounded() {
+        for text in ["", "\u{301}", "\u{200d}\u{1f469}\u{200d}\u{1f467}"] {
+            assert_eq!(Combining::new(text).as_str(), text);
+        }
+        assert_eq!(Combining::new("abcdefgh"), Combining::new("abcdefgh"));
+
+        let marks = "\u{301}".repeat(100);
+        let (_, cells) = printed(&format!("a{}", marks));
+        assert_eq!(cells.len(), 1);
+        assert!(cells[0].combining.as_str().len() <= MAX_COMBINING_LEN);
+    }
+}
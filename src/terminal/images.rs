@@ -0,0 +1,464 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use super::events::decode_base64;
+use super::Terminal;
+
+/// Largest base64 payload of an inline image, including multipart ones.
+pub const MAX_IMAGE_DATA: usize = 32 * 1024 * 1024;
+/// Largest decoded image, in pixels.
+pub const MAX_IMAGE_PIXELS: u64 = 4096 * 4096;
+/// Cell size assumed for sizing images when the host reports none.
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+
+static NEXT_IMAGE_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Decoded RGBA pixels of an inline image, shared by its placements.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TerminalImage {
+    /// Unique for the life of the process, so renderers can cache
+    /// textures by it.
+    id: u64,
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+}
+
+impl TerminalImage {
+    /// Wraps `rgba`, four bytes per pixel, row by row.
+    pub fn new(width: u32, height: u32, rgba: Vec<u8>) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err("image is empty".to_string());
+        }
+        if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+            return Err(format!("image of {}x{} pixels is too large", width, height));
+        }
+        if rgba.len() != width as usize * height as usize * 4 {
+            return Err(format!(
+                "expected {} bytes of pixels for {}x{}, got {}",
+                width as usize * height as usize * 4,
+                width,
+                height,
+                rgba.len()
+            ));
+        }
+        Ok(Self {
+            id: NEXT_IMAGE_ID.fetch_add(1, Ordering::Relaxed),
+            width,
+            height,
+            rgba,
+        })
+    }
+
+    /// Decodes a PNG, JPEG, GIF or any other format the image crate reads.
+    pub fn decode(data: &[u8]) -> Result<Self, String> {
+        let reader = image::io::Reader::new(std::io::Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| e.to_string())?;
+        let (width, height) = reader.into_dimensions().map_err(|e| e.to_string())?;
+        if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+            return Err(format!("image of {}x{} pixels is too large", width, height));
+        }
+        let rgba = image::load_from_memory(data)
+            .map_err(|e| e.to_string())?
+            .into_rgba8();
+        Self::new(rgba.width(), rgba.height(), rgba.into_raw())
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+}
+
+/// An image shown over the grid, anchored to the line it was placed on so
+/// it scrolls with the text around it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImagePlacement {
+    pub image: Arc<TerminalImage>,
+    /// Line of the top row, counted like lines pushed into the scrollback:
+    /// screen row `y` is line `lines_scrolled + y`.
+    pub line: usize,
+    pub col: usize,
+    /// Size in cells, fractional where the image doesn't fill its last
+    /// column or row.
+    pub width: f32,
+    pub height: f32,
+    /// Placed on the alternate screen, and gone when it is left.
+    pub alternate: bool,
+    /// Image and placement id given with the kitty graphics protocol; the
+    /// placement id is 0 when it had none.
+    pub kitty_id: Option<(u32, u32)>,
+}
+
+impl ImagePlacement {
+    /// Columns the image covers.
+    pub fn cols(&self) -> usize {
+        (self.width.ceil() as usize).max(1)
+    }
+
+    /// Rows the image covers.
+    pub fn rows(&self) -> usize {
+        (self.height.ceil() as usize).max(1)
+    }
+}
+
+/// Requested extent of an image along one axis, as OSC 1337 writes it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageSize {
+    /// The image's own size.
+    Auto,
+    Cells(f32),
+    Pixels(f32),
+    /// Percent of the screen.
+    Percent(f32),
+}
+
+impl ImageSize {
+    fn parse(value: &str) -> Option<Self> {
+        if value == "auto" {
+            return Some(ImageSize::Auto);
+        }
+        let number = |text: &str| text.parse::<f32>().ok().filter(|n| *n > 0.0);
+        if let Some(pixels) = value.strip_suffix("px") {
+            Some(ImageSize::Pixels(number(pixels)?))
+        } else if let Some(percent) = value.strip_suffix('%') {
+            Some(ImageSize::Percent(number(percent)?))
+        } else {
+            Some(ImageSize::Cells(number(value)?))
+        }
+    }
+
+    /// The extent in pixels, given the image's own, a cell's and the
+    /// screen's; `None` for `Auto`.
+    fn pixels(self, cell: f32, screen: f32) -> Option<f32> {
+        match self {
+            ImageSize::Auto => None,
+            ImageSize::Cells(cells) => Some(cells * cell),
+            ImageSize::Pixels(pixels) => Some(pixels),
+            ImageSize::Percent(percent) => Some(screen * percent / 100.0),
+        }
+    }
+}
+
+/// A multipart OSC 1337 file being received.
+#[derive(Debug)]
+pub(super) struct ImageUpload {
+    args: String,
+    data: Vec<u8>,
+}
+
+impl Terminal {
+    /// Size of a cell in pixels for sizing images.
+    fn image_cell_size(&self) -> (f32, f32) {
+        let (width, height) = self
+            .host
+            .as_ref()
+            .map(|host| host.cell_size())
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .unwrap_or(DEFAULT_CELL_SIZE);
+        (width as f32, height as f32)
+    }
+
+    /// Cells covered by `image` shown `width` by `height`, scaled to fit
+    /// the requested box when `preserve_aspect` is set.
+    pub fn image_cells(
+        &self,
+        image: &TerminalImage,
+        width: ImageSize,
+        height: ImageSize,
+        preserve_aspect: bool,
+    ) -> (f32, f32) {
+        let (cell_width, cell_height) = self.image_cell_size();
+        let (own_width, own_height) = (image.width as f32, image.height as f32);
+        let screen = (
+            self.width as f32 * cell_width,
+            self.height as f32 * cell_height,
+        );
+        let (mut pixels_wide, mut pixels_high) = match (
+            width.pixels(cell_width, screen.0),
+            height.pixels(cell_height, screen.1),
+        ) {
+            (None, None) => (own_width, own_height),
+            (Some(w), None) if preserve_aspect => (w, own_height * w / own_width),
+            (None, Some(h)) if preserve_aspect => (own_width * h / own_height, h),
+            (Some(w), Some(h)) if preserve_aspect => {
+                let scale = (w / own_width).min(h / own_height);
+                (own_width * scale, own_height * scale)
+            }
+            (w, h) => (w.unwrap_or(own_width), h.unwrap_or(own_height)),
+        };
+        // Too wide to fit shrinks to the screen width
+        if pixels_wide > screen.0 {
+            if preserve_aspect {
+                pixels_high *= screen.0 / pixels_wide;
+            }
+            pixels_wide = screen.0;
+        }
+        (pixels_wide / cell_width, pixels_high / cell_height)
+    }
+
+    /// Shows `image` at the cursor covering `width` by `height` cells,
+    /// returning the placement.
+    pub fn place_image(
+        &mut self,
+        image: Arc<TerminalImage>,
+        width: f32,
+        height: f32,
+    ) -> &ImagePlacement {
+        let placement = ImagePlacement {
+            image,
+            line: self.lines_scrolled.wrapping_add(self.cursor_y),
+            col: self.cursor_x.min(self.width - 1),
+            width,
+            height,
+            alternate: self.alternate_screen,
+            kitty_id: None,
+        };
+        self.images.push(placement);
+        self.damage_frame.clear();
+        self.images.last().unwrap()
+    }
+
+    /// Moves the cursor to the right of a placement's last row, scrolling
+    /// as needed, as iTerm2 leaves it after an inline image.
+    pub(super) fn move_past_image(&mut self, cols: usize, rows: usize) {
+        let col = self.cursor_x;
+        for _ in 1..rows {
+            self.new_line();
+        }
+        self.cursor_x = (col + cols).min(self.width - 1);
+    }
+
+    /// Images on the screen in use, with the viewport row of their top:
+    /// negative when scrolled partly out of view at the top.
+    pub fn visible_images(&self) -> impl Iterator<Item = (isize, &ImagePlacement)> {
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        let top = self.lines_scrolled.wrapping_sub(offset);
+        let height = self.height as isize;
+        self.images
+            .iter()
+            .filter(move |placement| placement.alternate == self.alternate_screen)
+            .map(move |placement| (placement.line.wrapping_sub(top) as isize, placement))
+            .filter(move |(row, placement)| *row < height && row + placement.rows() as isize > 0)
+    }
+
+    /// Every placement, in the order they were placed.
+    pub fn images(&self) -> &[ImagePlacement] {
+        &self.images
+    }
+
+    /// Removes the placements `remove` picks, repainting if any went.
+    pub(super) fn remove_images(&mut self, mut remove: impl FnMut(&ImagePlacement) -> bool) {
+        let before = self.images.len();
+        self.images.retain(|placement| !remove(placement));
+        if self.images.len() != before {
+            self.damage_frame.clear();
+        }
+    }
+
+    /// Drops placements whose top line left the scrollback.
+    pub(super) fn trim_images(&mut self) {
+        if self.images.is_empty() {
+            return;
+        }
+        let oldest = self.lines_scrolled.wrapping_sub(self.history.len());
+        self.remove_images(|placement| {
+            !placement.alternate && (placement.line.wrapping_sub(oldest) as isize) < 0
+        });
+    }
+
+    /// Drops placements on the rows of the screen in use, as ED 2 does.
+    pub(super) fn clear_screen_images(&mut self) {
+        let (top, alternate) = (self.lines_scrolled, self.alternate_screen);
+        self.remove_images(|placement| {
+            placement.alternate == alternate
+                && (placement.line + placement.rows()).wrapping_sub(top) as isize > 0
+        });
+    }
+
+    /// OSC 1337 `File=ARGS:BASE64` and its multipart form:
+    /// `MultipartFile=ARGS`, any number of `FilePart=BASE64` and `FileEnd`.
+    /// Only files with `inline=1` are shown; others would be downloads.
+    pub(super) fn iterm_file(&mut self, params: &[&[u8]]) {
+        // The arguments are separated by the same `;` as OSC parameters
+        let command = params.join(&b';');
+        let Ok(command) = std::str::from_utf8(&command) else {
+            return;
+        };
+        if let Some(file) = command.strip_prefix("File=") {
+            let (args, data) = file.split_once(':').unwrap_or((file, ""));
+            if data.len() > MAX_IMAGE_DATA {
+                eprintln!("rt: inline image of {} bytes is too large", data.len());
+                return;
+            }
+            self.show_iterm_file(args, data.as_bytes());
+        } else if let Some(args) = command.strip_prefix("MultipartFile=") {
+            self.image_upload = Some(ImageUpload {
+                args: args.to_string(),
+                data: Vec::new(),
+            });
+        } else if let Some(part) = command.strip_prefix("FilePart=") {
+            let Some(upload) = self.image_upload.as_mut() else {
+                return;
+            };
+            if upload.data.len() + part.len() > MAX_IMAGE_DATA {
+                eprintln!(
+                    "rt: inline image larger than {} bytes dropped",
+                    MAX_IMAGE_DATA
+                );
+                self.image_upload = None;
+                return;
+            }
+            upload.data.extend_from_slice(part.as_bytes());
+        } else if command == "FileEnd" {
+            if let Some(upload) = self.image_upload.take() {
+                self.show_iterm_file(&upload.args, &upload.data);
+            }
+        }
+    }
+
+    fn show_iterm_file(&mut self, args: &str, data: &[u8]) {
+        let (mut width, mut height) = (ImageSize::Auto, ImageSize::Auto);
+        let (mut inline, mut preserve_aspect) = (false, true);
+        for (key, value) in args.split(';').filter_map(|arg| arg.split_once('=')) {
+            match key {
+                "inline" => inline = value == "1",
+                "width" => width = ImageSize::parse(value).unwrap_or(ImageSize::Auto),
+                "height" => height = ImageSize::parse(value).unwrap_or(ImageSize::Auto),
+                "preserveAspectRatio" => preserve_aspect = value != "0",
+                _ => {}
+            }
+        }
+        if !inline {
+            return;
+        }
+        let image = decode_base64(data)
+            .ok_or_else(|| "invalid base64".to_string())
+            .and_then(|bytes| TerminalImage::decode(&bytes));
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                eprintln!("rt: inline image: {}", e);
+                return;
+            }
+        };
+        let (cols, rows) = self.image_cells(&image, width, height, preserve_aspect);
+        let placement = self.place_image(Arc::new(image), cols, rows);
+        let (cols, rows) = (placement.cols(), placement.rows());
+        self.move_past_image(cols, rows);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A red 16x32 PNG: two by two cells of the default cell size.
+    const PNG: &str = "iVBORw0KGgoAAAANSUhEUgAAABAAAAAgCAIAAACU62+bAAAAHElEQVR42mP4z8BAEmIY1TCqYVTDqIZRDSNBAwA/xP4QT+xC0QAAAABJRU5ErkJggg==";
+
+    fn file(args: &str) -> Vec<u8> {
+        format!("\x1b]1337;File={}:{}\x07", args, PNG).into_bytes()
+    }
+
+    fn sizes(terminal: &Terminal) -> Vec<(f32, f32)> {
+        terminal
+            .images()
+            .iter()
+            .map(|placement| (placement.width, placement.height))
+            .collect()
+    }
+
+    #[test]
+    fn a_png_is_placed_at_the_cursor_in_cells() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(b"\r\n\r\nab");
+        terminal.process_pty_output(&file("name=cmVk;size=85;inline=1"));
+        let placement = &terminal.images()[0];
+        assert_eq!((placement.line, placement.col), (2, 2));
+        assert_eq!((placement.width, placement.height), (2.0, 2.0));
+        assert_eq!(
+            (placement.image.width(), placement.image.height()),
+            (16, 32)
+        );
+        assert_eq!(&placement.image.rgba()[..4], [255, 0, 0, 255]);
+        // The cursor is left beside the last row of the image
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (4, 3));
+        assert_eq!(terminal.visible_images().count(), 1);
+    }
+
+    #[test]
+    fn sizes_follow_the_width_height_and_aspect_arguments() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(&file("inline=1;width=4"));
+        terminal.process_pty_output(&file("inline=1;width=4;preserveAspectRatio=0"));
+        terminal.process_pty_output(&file("inline=1;height=48px"));
+        terminal.process_pty_output(&file("inline=1;width=50%;height=1"));
+        // Too wide for the screen, so it shrinks to fit
+        terminal.process_pty_output(&file("inline=1;width=40"));
+        assert_eq!(
+            sizes(&terminal),
+            [(4.0, 4.0), (4.0, 2.0), (3.0, 3.0), (1.0, 1.0), (20.0, 20.0)]
+        );
+    }
+
+    #[test]
+    fn only_inline_files_are_shown() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(&file("name=cmVk"));
+        terminal.process_pty_output(b"\x1b]1337;File=inline=1:bm90IGFuIGltYWdl\x07");
+        assert!(terminal.images().is_empty());
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+    }
+
+    #[test]
+    fn multipart_files_are_put_together() {
+        let mut terminal = Terminal::new(20, 10);
+        let (first, rest) = PNG.split_at(40);
+        terminal.process_pty_output(b"\x1b]1337;MultipartFile=inline=1\x07");
+        for part in [first, rest] {
+            terminal.process_pty_output(format!("\x1b]1337;FilePart={}\x07", part).as_bytes());
+        }
+        assert!(terminal.images().is_empty());
+        terminal.process_pty_output(b"\x1b]1337;FileEnd\x07");
+        assert_eq!(sizes(&terminal), [(2.0, 2.0)]);
+    }
+
+    #[test]
+    fn erasing_the_display_removes_images() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(&file("inline=1"));
+        terminal.process_pty_output(b"\x1b[J");
+        assert_eq!(terminal.images().len(), 1);
+        terminal.process_pty_output(b"\x1b[2J");
+        assert!(terminal.images().is_empty());
+    }
+
+    #[test]
+    fn images_leave_with_their_screen_or_the_scrollback() {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.process_pty_output(b"\x1b[?1049h");
+        terminal.process_pty_output(&file("inline=1"));
+        assert!(terminal.images()[0].alternate);
+        terminal.process_pty_output(b"\x1b[?1049l");
+        assert!(terminal.images().is_empty());
+
+        terminal.scrollback_limit = 2;
+        terminal.process_pty_output(&file("inline=1"));
+        terminal.process_pty_output(b"\n\n\n\n");
+        assert_eq!(terminal.images().len(), 1);
+        terminal.process_pty_output(b"\n");
+        assert!(terminal.images().is_empty());
+    }
+}
@@ -0,0 +1,707 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::events::decode_base64;
+use super::images::{ImagePlacement, ImageSize, TerminalImage, MAX_IMAGE_DATA, MAX_IMAGE_PIXELS};
+use super::Terminal;
+
+/// Memory kept for images transmitted with the kitty graphics protocol,
+/// as kitty's own storage quota. The least recently used go first.
+pub const DEFAULT_IMAGE_BUDGET: usize = 320 * 1024 * 1024;
+
+const ESC: u8 = 0x1b;
+/// Longest APC string kept: a whole image sent in one command plus its
+/// control data.
+const APC_LIMIT: usize = MAX_IMAGE_DATA + 4096;
+/// First id given to images the program only numbered, well clear of the
+/// small ids programs pick themselves.
+const FIRST_ASSIGNED_ID: u32 = 1 << 24;
+
+/// Progress through an APC string (`ESC _ ... ESC \`). vte skips them, so
+/// the terminal watches the bytes it feeds the parser for them.
+#[derive(Debug, Default)]
+pub(super) enum Apc {
+    #[default]
+    Ground,
+    /// An ESC outside a string.
+    Escape,
+    String(ApcString),
+    /// An ESC inside a string: ST if a `\` follows.
+    StringEscape(ApcString),
+}
+
+#[derive(Debug, Default)]
+pub(super) struct ApcString {
+    data: Vec<u8>,
+    /// The string outgrew `APC_LIMIT` and is being discarded.
+    overflowed: bool,
+}
+
+/// A kitty graphics command: the control data of `ESC _ G control ; payload
+/// ESC \`. Keys that aren't supported are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GraphicsCommand {
+    /// `a`: transmit, transmit and place, place, delete or query.
+    action: u8,
+    /// `f`: 24 for RGB, 32 for RGBA or 100 for PNG.
+    format: u32,
+    /// `t`: only direct transmission, in the payload, is supported.
+    medium: u8,
+    /// `o`: compression; none is supported.
+    compression: Option<u8>,
+    /// `s` and `v`: size of RGB and RGBA data in pixels.
+    width: u32,
+    height: u32,
+    /// `i`, `I` and `p`: image id, image number and placement id.
+    id: u32,
+    number: u32,
+    placement: u32,
+    /// `m=1`: more chunks of the payload follow.
+    more: bool,
+    /// `q`: 1 suppresses OK replies, 2 errors too.
+    quiet: u32,
+    /// `c` and `r`: size of the placement in cells.
+    cols: u32,
+    rows: u32,
+    /// `C=1`: the cursor stays where it is after placing.
+    keep_cursor: bool,
+    /// `d`: what a delete command removes.
+    delete: u8,
+}
+
+impl Default for GraphicsCommand {
+    fn default() -> Self {
+        Self {
+            action: b't',
+            format: 32,
+            medium: b'd',
+            compression: None,
+            width: 0,
+            height: 0,
+            id: 0,
+            number: 0,
+            placement: 0,
+            more: false,
+            quiet: 0,
+            cols: 0,
+            rows: 0,
+            keep_cursor: false,
+            delete: b'a',
+        }
+    }
+}
+
+impl GraphicsCommand {
+    /// Parses comma separated `key=value` pairs.
+    fn parse(control: &[u8]) -> Result<Self, String> {
+        let mut command = Self::default();
+        for pair in control
+            .split(|&b| b == b',')
+            .filter(|pair| !pair.is_empty())
+        {
+            let (&key, value) = match pair {
+                [key, b'=', value @ ..] if !value.is_empty() => (key, value),
+                _ => return Err(format!("bad key {}", String::from_utf8_lossy(pair))),
+            };
+            let number = || {
+                std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse::<u32>().ok())
+                    .ok_or_else(|| format!("bad value for {}", key as char))
+            };
+            match key {
+                b'a' => command.action = value[0],
+                b'f' => command.format = number()?,
+                b't' => command.medium = value[0],
+                b'o' => command.compression = Some(value[0]),
+                b's' => command.width = number()?,
+                b'v' => command.height = number()?,
+                b'i' => command.id = number()?,
+                b'I' => command.number = number()?,
+                b'p' => command.placement = number()?,
+                b'm' => command.more = number()? == 1,
+                b'q' => command.quiet = number()?,
+                b'c' => command.cols = number()?,
+                b'r' => command.rows = number()?,
+                b'C' => command.keep_cursor = number()? == 1,
+                b'd' => command.delete = value[0],
+                _ => {}
+            }
+        }
+        Ok(command)
+    }
+}
+
+/// A chunked transmission being received.
+#[derive(Debug)]
+pub(super) struct KittyUpload {
+    command: GraphicsCommand,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct StoredImage {
+    image: Arc<TerminalImage>,
+    /// `ImageStore::clock` when the image was last transmitted or placed.
+    used: u64,
+}
+
+/// Images transmitted with the kitty graphics protocol, by id, within a
+/// memory budget.
+#[derive(Debug)]
+pub(super) struct ImageStore {
+    images: HashMap<u32, StoredImage>,
+    /// Newest image id for each image number.
+    numbers: HashMap<u32, u32>,
+    clock: u64,
+    bytes: usize,
+    budget: usize,
+    next_id: u32,
+}
+
+impl Default for ImageStore {
+    fn default() -> Self {
+        Self {
+            images: HashMap::new(),
+            numbers: HashMap::new(),
+            clock: 0,
+            bytes: 0,
+            budget: DEFAULT_IMAGE_BUDGET,
+            next_id: FIRST_ASSIGNED_ID,
+        }
+    }
+}
+
+impl ImageStore {
+    fn get(&mut self, id: u32) -> Option<Arc<TerminalImage>> {
+        self.clock += 1;
+        let stored = self.images.get_mut(&id)?;
+        stored.used = self.clock;
+        Some(stored.image.clone())
+    }
+
+    /// Stores `image` as `id`, returning the images it replaced or evicted
+    /// to stay within the budget. The new image itself is never evicted.
+    fn insert(&mut self, id: u32, image: Arc<TerminalImage>) -> Vec<Arc<TerminalImage>> {
+        let mut dropped: Vec<_> = self.remove(id).into_iter().collect();
+        self.clock += 1;
+        self.bytes += image.rgba().len();
+        self.images.insert(
+            id,
+            StoredImage {
+                image,
+                used: self.clock,
+            },
+        );
+        while self.bytes > self.budget {
+            let oldest = self
+                .images
+                .iter()
+                .filter(|(&stored, _)| stored != id)
+                .min_by_key(|(_, stored)| stored.used)
+                .map(|(&id, _)| id);
+            match oldest.and_then(|oldest| self.remove(oldest)) {
+                Some(image) => dropped.push(image),
+                None => break,
+            }
+        }
+        dropped
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Arc<TerminalImage>> {
+        let stored = self.images.remove(&id)?;
+        self.bytes -= stored.image.rgba().len();
+        self.numbers.retain(|_, numbered| *numbered != id);
+        Some(stored.image)
+    }
+
+    /// An id no stored image has, for images sent with only a number.
+    fn assign_id(&mut self) -> u32 {
+        while self.next_id == 0 || self.images.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1).max(FIRST_ASSIGNED_ID);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        id
+    }
+
+    fn clear(&mut self) {
+        self.images.clear();
+        self.numbers.clear();
+        self.bytes = 0;
+    }
+}
+
+impl Terminal {
+    /// Follows `byte` through APC strings, handling each one that ends.
+    pub(super) fn scan_apc(&mut self, byte: u8) {
+        if matches!(self.apc, Apc::Ground) && byte != ESC {
+            return;
+        }
+        self.apc = match (std::mem::take(&mut self.apc), byte) {
+            (Apc::Ground | Apc::Escape, ESC) => Apc::Escape,
+            (Apc::Escape, b'_') => Apc::String(ApcString::default()),
+            (Apc::Ground | Apc::Escape, _) => Apc::Ground,
+            (Apc::String(string), ESC) => Apc::StringEscape(string),
+            // CAN and SUB cancel the string
+            (Apc::String(_), 0x18 | 0x1a) => Apc::Ground,
+            (Apc::String(mut string), byte) => {
+                if string.data.len() >= APC_LIMIT {
+                    string.data = Vec::new();
+                    string.overflowed = true;
+                }
+                if !string.overflowed {
+                    string.data.push(byte);
+                }
+                Apc::String(string)
+            }
+            (Apc::StringEscape(string), b'\\') => {
+                if !string.overflowed {
+                    self.apc_dispatch(&string.data);
+                }
+                Apc::Ground
+            }
+            (Apc::StringEscape(_), b'_') => Apc::String(ApcString::default()),
+            (Apc::StringEscape(_), _) => Apc::Ground,
+        };
+    }
+
+    fn apc_dispatch(&mut self, data: &[u8]) {
+        // Other APC strings are unsupported and ignored
+        if let Some(command) = data.strip_prefix(b"G") {
+            self.kitty_graphics(command);
+        }
+    }
+
+    /// Limits the memory kept for images transmitted with the kitty
+    /// graphics protocol; the least recently used are dropped, with their
+    /// placements, to stay within it.
+    pub fn set_image_budget(&mut self, bytes: usize) {
+        self.kitty_images.budget = bytes;
+    }
+
+    /// Images stored with the kitty graphics protocol and their size in
+    /// bytes.
+    pub fn stored_images(&self) -> (usize, usize) {
+        (self.kitty_images.images.len(), self.kitty_images.bytes)
+    }
+
+    /// A kitty graphics command, `control;payload`.
+    fn kitty_graphics(&mut self, command: &[u8]) {
+        let (control, payload) = match command.iter().position(|&b| b == b';') {
+            Some(split) => (&command[..split], &command[split + 1..]),
+            None => (command, &[][..]),
+        };
+        let command = match GraphicsCommand::parse(control) {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("rt: kitty graphics: {}", e);
+                return;
+            }
+        };
+
+        // Later chunks of a transmission only carry `m` and maybe `q`
+        if let Some(mut upload) = self.kitty_upload.take() {
+            if upload.data.len() + payload.len() > MAX_IMAGE_DATA {
+                let error = format!("EFBIG:image larger than {} bytes", MAX_IMAGE_DATA);
+                self.kitty_reply(&upload.command, Err(error));
+                return;
+            }
+            upload.data.extend_from_slice(payload);
+            if command.more {
+                self.kitty_upload = Some(upload);
+            } else {
+                self.kitty_transmit(upload.command, &upload.data);
+            }
+            return;
+        }
+
+        match command.action {
+            b't' | b'T' | b'q' if command.more => {
+                self.kitty_upload = Some(KittyUpload {
+                    command,
+                    data: payload.to_vec(),
+                });
+            }
+            b't' | b'T' | b'q' => self.kitty_transmit(command, payload),
+            b'p' => {
+                let id = self.kitty_image_id(&command);
+                let result = self.kitty_place(&command, id);
+                self.kitty_reply(&command, result);
+            }
+            b'd' => self.kitty_delete(&command),
+            action => {
+                let error = format!("EINVAL:unsupported action {}", action as char);
+                self.kitty_reply(&command, Err(error));
+            }
+        }
+    }
+
+    /// Id of the image `command` refers to: `i`, or the newest image
+    /// numbered `I`.
+    fn kitty_image_id(&self, command: &GraphicsCommand) -> u32 {
+        match command.id {
+            0 => self
+                .kitty_images
+                .numbers
+                .get(&command.number)
+                .copied()
+                .unwrap_or(0),
+            id => id,
+        }
+    }
+
+    /// Stores a complete transmission, placing it for `a=T`. A query
+    /// (`a=q`) only reports whether it could be.
+    fn kitty_transmit(&mut self, mut command: GraphicsCommand, data: &[u8]) {
+        let image = match kitty_decode(&command, data) {
+            Ok(image) => Arc::new(image),
+            Err(error) => return self.kitty_reply(&command, Err(error)),
+        };
+        if command.action == b'q' {
+            return self.kitty_reply(&command, Ok(()));
+        }
+        if image.rgba().len() > self.kitty_images.budget {
+            let error = "EFBIG:image larger than the storage quota".to_string();
+            return self.kitty_reply(&command, Err(error));
+        }
+
+        let id = match command.id {
+            0 => self.kitty_images.assign_id(),
+            id => id,
+        };
+        if command.number != 0 {
+            command.id = id;
+        }
+        for dropped in self.kitty_images.insert(id, image) {
+            self.remove_images(|placement| Arc::ptr_eq(&placement.image, &dropped));
+        }
+        if command.number != 0 {
+            self.kitty_images.numbers.insert(command.number, id);
+        }
+        let result = if command.action == b'T' {
+            self.kitty_place(&command, id)
+        } else {
+            Ok(())
+        };
+        self.kitty_reply(&command, result);
+    }
+
+    /// Places image `id` at the cursor, replacing the placement of the same
+    /// image with the same placement id.
+    fn kitty_place(&mut self, command: &GraphicsCommand, id: u32) -> Result<(), String> {
+        let image = self
+            .kitty_images
+            .get(id)
+            .ok_or_else(|| "ENOENT:no such image".to_string())?;
+        let size = |cells: u32| match cells {
+            0 => ImageSize::Auto,
+            cells => ImageSize::Cells(cells as f32),
+        };
+        // Giving one of the columns and rows keeps the aspect ratio
+        let preserve_aspect = command.cols == 0 || command.rows == 0;
+        let (cols, rows) = self.image_cells(
+            &image,
+            size(command.cols),
+            size(command.rows),
+            preserve_aspect,
+        );
+        if command.placement != 0 {
+            let key = Some((id, command.placement));
+            self.remove_images(|placement| placement.kitty_id == key);
+        }
+        self.place_image(image, cols, rows);
+        let placement = self.images.last_mut().unwrap();
+        placement.kitty_id = Some((id, command.placement));
+        let (cols, rows) = (placement.cols(), placement.rows());
+        if !command.keep_cursor {
+            self.move_past_image(cols, rows);
+        }
+        Ok(())
+    }
+
+    /// `a=d`: removes placements, and with an uppercase `d` the stored
+    /// images they leave unused. Supports all placements on screen (`a`),
+    /// by id (`i`), by number (`n`) and under the cursor (`c`).
+    fn kitty_delete(&mut self, command: &GraphicsCommand) {
+        let (id, placement_id) = (self.kitty_image_id(command), command.placement);
+        let (x, y) = (self.cursor_x, self.cursor_y as isize);
+        let doomed: Vec<ImagePlacement> = match command.delete.to_ascii_lowercase() {
+            // By id reaches placements scrolled out of view too
+            b'i' | b'n' => self
+                .images
+                .iter()
+                .filter(|placement| {
+                    placement.kitty_id.is_some_and(|(image, placement)| {
+                        image == id && (placement_id == 0 || placement == placement_id)
+                    })
+                })
+                .cloned()
+                .collect(),
+            b'a' => self
+                .visible_images()
+                .filter(|(_, placement)| placement.kitty_id.is_some())
+                .map(|(_, placement)| placement.clone())
+                .collect(),
+            b'c' => self
+                .visible_images()
+                .filter(|(row, placement)| {
+                    placement.kitty_id.is_some()
+                        && (*row..*row + placement.rows() as isize).contains(&y)
+                        && (placement.col..placement.col + placement.cols()).contains(&x)
+                })
+                .map(|(_, placement)| placement.clone())
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.remove_images(|placement| doomed.contains(placement));
+
+        if command.delete.is_ascii_uppercase() {
+            let by_id = matches!(command.delete, b'I' | b'N').then_some(id);
+            let ids = doomed
+                .iter()
+                .filter_map(|placement| placement.kitty_id.map(|(id, _)| id))
+                .chain(by_id);
+            for id in ids.collect::<Vec<_>>() {
+                let placed = self
+                    .images
+                    .iter()
+                    .any(|placement| placement.kitty_id.is_some_and(|(image, _)| image == id));
+                if !placed {
+                    self.kitty_images.remove(id);
+                }
+            }
+        }
+    }
+
+    /// Answers a command that gave an image id or number, unless `q`
+    /// silences the answer.
+    fn kitty_reply(&mut self, command: &GraphicsCommand, result: Result<(), String>) {
+        if command.id == 0 && command.number == 0 {
+            return;
+        }
+        let message = match result {
+            Ok(()) if command.quiet == 0 => "OK".to_string(),
+            Err(error) if command.quiet < 2 => error,
+            _ => return,
+        };
+        let mut keys = format!("i={}", command.id);
+        if command.number != 0 {
+            keys.push_str(&format!(",I={}", command.number));
+        }
+        if command.placement != 0 {
+            keys.push_str(&format!(",p={}", command.placement));
+        }
+        self.respond(format!("\x1b_G{};{}\x1b\\", keys, message).as_bytes());
+    }
+
+    /// Drops stored kitty images and any transmission in progress, as RIS
+    /// does.
+    pub(super) fn reset_kitty_images(&mut self) {
+        self.kitty_images.clear();
+        self.kitty_upload = None;
+    }
+}
+
+/// Decodes the base64 payload of a transmission in `command`'s format.
+fn kitty_decode(command: &GraphicsCommand, data: &[u8]) -> Result<TerminalImage, String> {
+    if command.medium != b'd' {
+        return Err("EINVAL:only direct transmission is supported".to_string());
+    }
+    if command.compression.is_some() {
+        return Err("EINVAL:compression is not supported".to_string());
+    }
+    let data = decode_base64(data).ok_or_else(|| "EINVAL:bad base64".to_string())?;
+    let channels = match command.format {
+        100 => return TerminalImage::decode(&data).map_err(|e| format!("EBADPNG:{}", e)),
+        24 => 3,
+        32 => 4,
+        format => return Err(format!("EINVAL:unsupported format {}", format)),
+    };
+    let (width, height) = (command.width, command.height);
+    if width == 0 || height == 0 {
+        return Err("EINVAL:missing image size".to_string());
+    }
+    if width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+        return Err(format!("EFBIG:image of {}x{} pixels", width, height));
+    }
+    let len = width as usize * height as usize * channels;
+    if data.len() < len {
+        return Err(format!(
+            "ENODATA:expected {} bytes, got {}",
+            len,
+            data.len()
+        ));
+    }
+    let rgba = if channels == 4 {
+        data[..len].to_vec()
+    } else {
+        data[..len]
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect()
+    };
+    TerminalImage::new(width, height, rgba).map_err(|e| format!("EINVAL:{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two by two red pixels as RGBA, base64 encoded.
+    const RED_RGBA: &str = "/wAA//8AAP//AAD//wAA/w==";
+    /// Two by two green pixels as RGB.
+    const GREEN_RGB: &str = "AP8AAP8AAP8AAP8A";
+
+    fn apc(command: &str) -> Vec<u8> {
+        format!("\x1b_G{}\x1b\\", command).into_bytes()
+    }
+
+    fn replies(terminal: &mut Terminal) -> String {
+        String::from_utf8(terminal.take_pending_input()).unwrap()
+    }
+
+    #[test]
+    fn the_docs_query_is_answered() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(&apc("i=31,s=1,v=1,a=q,t=d,f=24;AAAA"));
+        assert_eq!(replies(&mut terminal), "\x1b_Gi=31;OK\x1b\\");
+        // A query stores nothing
+        assert_eq!(terminal.stored_images(), (0, 0));
+
+        terminal.process_pty_output(&apc("i=31,s=1,v=1,a=q,t=f,f=24;AAAA"));
+        assert_eq!(
+            replies(&mut terminal),
+            "\x1b_Gi=31;EINVAL:only direct transmission is supported\x1b\\"
+        );
+    }
+
+    #[test]
+    fn rgb_and_rgba_transmissions_are_stored_as_rgba() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(&apc(&format!("a=t,f=32,s=2,v=2,i=1;{}", RED_RGBA)));
+        terminal.process_pty_output(&apc(&format!("a=T,f=24,s=2,v=2,i=2;{}", GREEN_RGB)));
+        assert_eq!(
+            replies(&mut terminal),
+            "\x1b_Gi=1;OK\x1b\\\x1b_Gi=2;OK\x1b\\"
+        );
+        assert_eq!(terminal.stored_images(), (2, 32));
+
+        // Only a=T placed its image
+        let placement = &terminal.images()[0];
+        assert_eq!(terminal.images().len(), 1);
+        assert_eq!(placement.kitty_id, Some((2, 0)));
+        assert_eq!(&placement.image.rgba()[..4], [0, 255, 0, 255]);
+
+        terminal.process_pty_output(&apc("a=t,f=24,s=2,v=2,i=3;AAAA"));
+        assert_eq!(
+            replies(&mut terminal),
+            "\x1b_Gi=3;ENODATA:expected 12 bytes, got 3\x1b\\"
+        );
+    }
+
+    #[test]
+    fn chunked_transmissions_are_reassembled() {
+        let mut terminal = Terminal::new(20, 10);
+        let (first, rest) = RED_RGBA.split_at(8);
+        let (second, last) = rest.split_at(8);
+        terminal.process_pty_output(&apc(&format!("a=T,f=32,s=2,v=2,i=5,m=1;{}", first)));
+        terminal.process_pty_output(&apc(&format!("m=1;{}", second)));
+        assert!(terminal.images().is_empty());
+        // The end of a chunk may arrive in a later read
+        let end = apc(&format!("m=0;{}", last));
+        let (head, tail) = end.split_at(5);
+        terminal.process_pty_output(head);
+        terminal.process_pty_output(tail);
+
+        assert_eq!(replies(&mut terminal), "\x1b_Gi=5;OK\x1b\\");
+        assert_eq!(terminal.images().len(), 1);
+        assert_eq!(
+            terminal.images()[0].image.rgba(),
+            [255, 0, 0, 255].repeat(4)
+        );
+    }
+
+    #[test]
+    fn png_images_are_placed_by_id_and_number() {
+        let mut terminal = Terminal::new(20, 10);
+        let png = "iVBORw0KGgoAAAANSUhEUgAAABAAAAAgCAIAAACU62+bAAAAHElEQVR42mP4z8BAEmIY1TCqYVTDqIZRDSNBAwA/xP4QT+xC0QAAAABJRU5ErkJggg==";
+        terminal.process_pty_output(&apc(&format!("a=t,f=100,I=13;{}", png)));
+        let reply = replies(&mut terminal);
+        let id: u32 = reply
+            .strip_prefix("\x1b_Gi=")
+            .and_then(|rest| rest.split(',').next())
+            .and_then(|id| id.parse().ok())
+            .unwrap();
+        assert_eq!(reply, format!("\x1b_Gi={},I=13;OK\x1b\\", id));
+        assert!(id >= FIRST_ASSIGNED_ID);
+
+        terminal.process_pty_output(&apc("a=p,I=13,p=7,c=4,C=1"));
+        terminal.process_pty_output(&apc(&format!("a=p,i={},p=7,c=6,r=2", id)));
+        // The same placement id replaces the first placement
+        let placement = &terminal.images()[0];
+        assert_eq!(terminal.images().len(), 1);
+        assert_eq!(placement.kitty_id, Some((id, 7)));
+        assert_eq!((placement.width, placement.height), (6.0, 2.0));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (6, 1));
+
+        terminal.process_pty_output(&apc("a=p,i=99"));
+        assert!(replies(&mut terminal).ends_with("\x1b_Gi=99;ENOENT:no such image\x1b\\"));
+    }
+
+    #[test]
+    fn deletes_remove_placements_and_maybe_their_images() {
+        let mut terminal = Terminal::new(20, 10);
+        for id in [1, 2] {
+            let command = format!("a=T,f=32,s=2,v=2,i={},q=1;{}", id, RED_RGBA);
+            terminal.process_pty_output(&apc(&command));
+        }
+        assert_eq!(terminal.images().len(), 2);
+
+        terminal.process_pty_output(&apc("a=d,d=i,i=1"));
+        assert_eq!(terminal.images().len(), 1);
+        assert_eq!(terminal.stored_images().0, 2);
+        terminal.process_pty_output(&apc("a=d,d=I,i=1"));
+        assert_eq!(terminal.stored_images().0, 1);
+
+        terminal.process_pty_output(&apc("a=d"));
+        assert!(terminal.images().is_empty());
+        assert_eq!(terminal.stored_images().0, 1);
+        terminal.process_pty_output(&apc("a=p,i=2,q=1\x1b\\\x1b_Ga=d,d=A"));
+        assert_eq!(terminal.stored_images(), (0, 0));
+        assert_eq!(replies(&mut terminal), "");
+    }
+
+    #[test]
+    fn the_least_recently_used_images_are_evicted() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.set_image_budget(32);
+        terminal.process_pty_output(&apc(&format!("a=T,f=32,s=2,v=2,i=1,q=2;{}", RED_RGBA)));
+        terminal.process_pty_output(&apc(&format!("a=t,f=32,s=2,v=2,i=2,q=2;{}", RED_RGBA)));
+        // Placing image 1 again makes image 2 the least recently used
+        terminal.process_pty_output(&apc("a=p,i=1,q=2"));
+        terminal.process_pty_output(&apc(&format!("a=t,f=32,s=2,v=2,i=3,q=2;{}", RED_RGBA)));
+        assert_eq!(terminal.stored_images(), (2, 32));
+        assert_eq!(terminal.images().len(), 2);
+
+        terminal.process_pty_output(&apc(&format!("a=t,f=32,s=2,v=2,i=4,q=2;{}", RED_RGBA)));
+        // Image 1 went, and its placements with it
+        assert!(terminal.images().is_empty());
+        terminal.process_pty_output(&apc("a=p,i=2"));
+        assert_eq!(
+            replies(&mut terminal),
+            "\x1b_Gi=2;ENOENT:no such image\x1b\\"
+        );
+    }
+
+    #[test]
+    fn cancelled_and_other_apc_strings_are_ignored() {
+        let mut terminal = Terminal::new(20, 10);
+        let mut cancelled = apc(&format!("a=T,f=32,s=2,v=2,i=1;{}", RED_RGBA));
+        cancelled.insert(cancelled.len() - 2, 0x18);
+        terminal.process_pty_output(&cancelled);
+        terminal.process_pty_output(b"\x1b_Xother\x1b\\ok");
+        assert!(terminal.images().is_empty());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("ok"));
+    }
+}
@@ -0,0 +1,185 @@
+use super::Terminal;
+
+/// Keys understood by the [`LineEditor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditorKey {
+    Char(char),
+    Backspace,
+    Left,
+    Right,
+    Enter,
+}
+
+/// A single-line editor for using the terminal as a prompt without a PTY.
+#[derive(Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    on_line: Option<Box<dyn FnMut(String) + Send>>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the callback that receives each line completed with Enter.
+    pub fn on_line(&mut self, callback: impl FnMut(String) + Send + 'static) {
+        self.on_line = Some(Box::new(callback));
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    /// Cursor position within the line, in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Applies a key, returning the completed line on Enter.
+    pub fn handle_key(&mut self, key: EditorKey) -> Option<String> {
+        match key {
+            EditorKey::Char(ch) => {
+                self.line.insert(self.cursor, ch);
+                self.cursor += 1;
+            }
+            EditorKey::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+            }
+            EditorKey::Backspace => {}
+            EditorKey::Left => self.cursor = self.cursor.saturating_sub(1),
+            EditorKey::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            EditorKey::Enter => {
+                let line = self.line();
+                self.line.clear();
+                self.cursor = 0;
+                if let Some(callback) = self.on_line.as_mut() {
+                    callback(line.clone());
+                }
+                return Some(line);
+            }
+        }
+        None
+    }
+}
+
+/// Translates input bytes, as produced by the input handler, into editor keys.
+fn decode_keys(bytes: &[u8]) -> Vec<EditorKey> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut keys = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\r' | '\n' => keys.push(EditorKey::Enter),
+            '\x7f' | '\x08' => keys.push(EditorKey::Backspace),
+            '\x1b' => {
+                // Only the arrow keys mean something on a single line
+                if chars.next_if_eq(&'[').is_some() {
+                    match chars.next() {
+                        Some('C') => keys.push(EditorKey::Right),
+                        Some('D') => keys.push(EditorKey::Left),
+                        _ => {}
+                    }
+                }
+            }
+            ch if ch.is_control() => {}
+            ch => keys.push(EditorKey::Char(ch)),
+        }
+    }
+    keys
+}
+
+impl Terminal {
+    /// Edits input locally instead of sending it to the PTY. Typed text is
+    /// echoed into the grid and completed lines go to the line editor's
+    /// `on_line` callback.
+    pub fn set_local_echo(&mut self, enabled: bool) {
+        self.local_echo = enabled;
+        self.local_origin = None;
+    }
+
+    pub fn local_echo(&self) -> bool {
+        self.local_echo
+    }
+
+    pub fn line_editor_mut(&mut self) -> &mut LineEditor {
+        &mut self.line_editor
+    }
+
+    pub(super) fn local_input(&mut self, bytes: &[u8]) {
+        for key in decode_keys(bytes) {
+            let origin = *self
+                .local_origin
+                .get_or_insert((self.cursor_x, self.cursor_y));
+            if self.line_editor.handle_key(key).is_some() {
+                self.process_pty_output(b"\r\n");
+                self.local_origin = None;
+            } else {
+                self.redraw_local_line(origin);
+            }
+        }
+    }
+
+    /// Redraws the edited line from `origin` and places the cursor on it.
+    fn redraw_local_line(&mut self, (x, y): (usize, usize)) {
+        self.cursor_x = x;
+        self.cursor_y = y;
+        let mut text = String::from("\x1b[J");
+        text.extend(self.line_editor.line.iter());
+
+        let scrolled_before = self.lines_scrolled;
+        self.process_pty_output(text.as_bytes());
+        let scrolled = self.lines_scrolled.wrapping_sub(scrolled_before);
+        let y = y.saturating_sub(scrolled);
+        self.local_origin = Some((x, y));
+
+        let offset = x + self.line_editor.cursor;
+        self.cursor_x = offset % self.width;
+        self.cursor_y = (y + offset / self.width).min(self.height - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn typing_backspace_and_enter_complete_the_line() {
+        let mut editor = LineEditor::new();
+        for key in "helo".chars().map(EditorKey::Char) {
+            editor.handle_key(key);
+        }
+        editor.handle_key(EditorKey::Left);
+        editor.handle_key(EditorKey::Char('l'));
+        editor.handle_key(EditorKey::Right);
+        editor.handle_key(EditorKey::Char('!'));
+        editor.handle_key(EditorKey::Backspace);
+        assert_eq!((editor.line(), editor.cursor()), ("hello".to_string(), 5));
+        assert_eq!(editor.handle_key(EditorKey::Enter), Some("hello".into()));
+        assert_eq!((editor.line(), editor.cursor()), (String::new(), 0));
+    }
+
+    #[test]
+    fn local_echo_edits_in_the_grid_and_reports_lines() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut terminal = Terminal::new(20, 3);
+        terminal.set_local_echo(true);
+        let received = Arc::clone(&lines);
+        terminal
+            .line_editor_mut()
+            .on_line(move |line| received.lock().unwrap().push(line));
+        terminal.process_pty_output(b"> ");
+
+        terminal.write_input(b"lx\x7fs\x1b[D\x1b[Dl");
+        assert_eq!(terminal.line_text(0).as_deref(), Some("> lls"));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (3, 0));
+        terminal.write_input(b"\r");
+        assert_eq!(*lines.lock().unwrap(), ["lls"]);
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 1));
+        assert!(terminal.take_pending_input().is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+use super::Terminal;
+
+/// Largest 1-based coordinate the X10 encoding can carry in one byte.
+const X10_MAX_COORD: usize = 223;
+/// Largest 1-based coordinate the UTF-8 (1005) encoding can carry in two
+/// bytes.
+const UTF8_MAX_COORD: usize = 2015;
+
+/// Which mouse events a program asked to receive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MouseTracking {
+    #[default]
+    Off,
+    /// Presses and releases (1000).
+    Click,
+    /// Also motion while a button is held (1002).
+    Drag,
+    /// Also motion with no button held (1003).
+    Motion,
+}
+
+/// How mouse reports are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MouseEncoding {
+    /// `CSI M` followed by three bytes; coordinates stop at 223.
+    #[default]
+    X10,
+    /// Like X10, with coordinates as UTF-8 characters (1005).
+    Utf8,
+    /// `CSI Cb ; Cx ; Cy M` in decimal (1015).
+    Urxvt,
+    /// `CSI < Cb ; Cx ; Cy M`, or `m` for a release (1006).
+    Sgr,
+}
+
+impl MouseEncoding {
+    /// Preference when several encodings are enabled; SGR wins.
+    fn priority(self) -> u8 {
+        match self {
+            MouseEncoding::X10 => 0,
+            MouseEncoding::Utf8 => 1,
+            MouseEncoding::Urxvt => 2,
+            MouseEncoding::Sgr => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    WheelUp,
+    WheelDown,
+    /// Motion with no button held.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press,
+    Release,
+    Motion,
+}
+
+/// A mouse event at a 0-based viewport cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    pub button: MouseButton,
+    pub col: usize,
+    pub row: usize,
+    pub shift: bool,
+    pub alt: bool,
+    pub ctrl: bool,
+}
+
+/// Encodes `event` as the bytes a program expects in `encoding`.
+/// Coordinates past what the encoding can carry are clamped.
+pub fn encode_mouse_event(event: &MouseEvent, encoding: MouseEncoding) -> Vec<u8> {
+    let release = event.kind == MouseEventKind::Release;
+    let mut code = match event.button {
+        // Only SGR says which button was released
+        _ if release && encoding != MouseEncoding::Sgr => 3,
+        MouseButton::Left => 0,
+        MouseButton::Middle => 1,
+        MouseButton::Right => 2,
+        MouseButton::None => 3,
+        MouseButton::WheelUp => 64,
+        MouseButton::WheelDown => 65,
+    };
+    if event.kind == MouseEventKind::Motion {
+        code += 32;
+    }
+    if event.shift {
+        code += 4;
+    }
+    if event.alt {
+        code += 8;
+    }
+    if event.ctrl {
+        code += 16;
+    }
+    let (x, y) = (event.col + 1, event.row + 1);
+
+    match encoding {
+        MouseEncoding::X10 => {
+            let coord = |c: usize| (32 + c.min(X10_MAX_COORD)) as u8;
+            vec![0x1b, b'[', b'M', 32 + code as u8, coord(x), coord(y)]
+        }
+        MouseEncoding::Utf8 => {
+            let mut out = String::from("\x1b[M");
+            for value in [
+                code + 32,
+                x.min(UTF8_MAX_COORD) + 32,
+                y.min(UTF8_MAX_COORD) + 32,
+            ] {
+                // Clamped values are always valid characters
+                out.extend(char::from_u32(value as u32));
+            }
+            out.into_bytes()
+        }
+        MouseEncoding::Urxvt => format!("\x1b[{};{};{}M", code + 32, x, y).into_bytes(),
+        MouseEncoding::Sgr => {
+            let end = if release { 'm' } else { 'M' };
+            format!("\x1b[<{};{};{}{}", code, x, y, end).into_bytes()
+        }
+    }
+}
+
+impl Terminal {
+    pub fn mouse_tracking(&self) -> MouseTracking {
+        self.state.mouse_tracking
+    }
+
+    pub fn mouse_encoding(&self) -> MouseEncoding {
+        self.state.mouse_encoding
+    }
+
+    /// Queues a report of `event` for the program if its tracking mode asks
+    /// for it. Returns whether the event was reported.
+    pub fn report_mouse(&mut self, event: &MouseEvent) -> bool {
+        let wanted = match (self.state.mouse_tracking, event.kind) {
+            (MouseTracking::Off, _) => false,
+            (_, MouseEventKind::Press | MouseEventKind::Release) => true,
+            (MouseTracking::Drag, MouseEventKind::Motion) => event.button != MouseButton::None,
+            (MouseTracking::Motion, MouseEventKind::Motion) => true,
+            (MouseTracking::Click, MouseEventKind::Motion) => false,
+        };
+        // Wheels have no release
+        let wheel = matches!(event.button, MouseButton::WheelUp | MouseButton::WheelDown);
+        if !wanted || (wheel && event.kind == MouseEventKind::Release) {
+            return false;
+        }
+        let report = encode_mouse_event(event, self.state.mouse_encoding);
+        self.respond(&report);
+        true
+    }
+
+    /// Sets or resets one of the encoding modes 1005, 1006 and 1015. A
+    /// lower-priority encoding doesn't replace a higher one that is active,
+    /// and resetting the active encoding falls back to X10.
+    pub(super) fn set_mouse_encoding(&mut self, encoding: MouseEncoding, enable: bool) {
+        let current = self.state.mouse_encoding;
+        if enable {
+            if encoding.priority() >= current.priority() {
+                self.state.mouse_encoding = encoding;
+            }
+        } else if current == encoding {
+            self.state.mouse_encoding = MouseEncoding::X10;
+        }
+    }
+
+    /// Sets or resets one of the tracking modes 1000, 1002 and 1003.
+    pub(super) fn set_mouse_tracking(&mut self, tracking: MouseTracking, enable: bool) {
+        if enable {
+            self.state.mouse_tracking = tracking;
+        } else if self.state.mouse_tracking == tracking {
+            self.state.mouse_tracking = MouseTracking::Off;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click(kind: MouseEventKind, col: usize, row: usize) -> MouseEvent {
+        MouseEvent {
+            kind,
+            button: MouseButton::Left,
+            col,
+            row,
+            shift: false,
+            alt: false,
+            ctrl: false,
+        }
+    }
+
+    #[test]
+    fn a_click_is_encoded_in_each_form() {
+        let press = click(MouseEventKind::Press, 9, 4);
+        let release = click(MouseEventKind::Release, 9, 4);
+        for (encoding, pressed, released) in [
+            (MouseEncoding::X10, &b"\x1b[M *%"[..], &b"\x1b[M#*%"[..]),
+            (MouseEncoding::Utf8, b"\x1b[M *%", b"\x1b[M#*%"),
+            (MouseEncoding::Urxvt, b"\x1b[32;10;5M", b"\x1b[35;10;5M"),
+            (MouseEncoding::Sgr, b"\x1b[<0;10;5M", b"\x1b[<0;10;5m"),
+        ] {
+            assert_eq!(
+                encode_mouse_event(&press, encoding),
+                pressed,
+                "{:?}",
+                encoding
+            );
+            assert_eq!(
+                encode_mouse_event(&release, encoding),
+                released,
+                "{:?}",
+                encoding
+            );
+        }
+    }
+
+    #[test]
+    fn far_coordinates_are_clamped_to_what_the_encoding_carries() {
+        let press = click(MouseEventKind::Press, 299, 2999);
+        assert_eq!(
+            encode_mouse_event(&press, MouseEncoding::X10),
+            [0x1b, b'[', b'M', 32, 255, 255]
+        );
+        assert_eq!(
+            encode_mouse_event(&press, MouseEncoding::Utf8),
+            "\x1b[M \u{14c}\u{7ff}".as_bytes()
+        );
+        assert_eq!(
+            encode_mouse_event(&press, MouseEncoding::Sgr),
+            b"\x1b[<0;300;3000M"
+        );
+    }
+
+    #[test]
+    fn modifiers_and_motion_add_to_the_button_code() {
+        let event = MouseEvent {
+            button: MouseButton::Right,
+            shift: true,
+            ctrl: true,
+            ..click(MouseEventKind::Motion, 0, 0)
+        };
+        assert_eq!(
+            encode_mouse_event(&event, MouseEncoding::Sgr),
+            b"\x1b[<54;1;1M"
+        );
+        let wheel = MouseEvent {
+            button: MouseButton::WheelDown,
+            ..click(MouseEventKind::Press, 0, 0)
+        };
+        assert_eq!(
+            encode_mouse_event(&wheel, MouseEncoding::Sgr),
+            b"\x1b[<65;1;1M"
+        );
+    }
+
+    #[test]
+    fn sgr_wins_when_several_encodings_are_set() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[?1000;1006h\x1b[?1015h\x1b[?1005h");
+        assert_eq!(terminal.mouse_encoding(), MouseEncoding::Sgr);
+        terminal.process_pty_output(b"\x1b[?1005l");
+        assert_eq!(terminal.mouse_encoding(), MouseEncoding::Sgr);
+        terminal.process_pty_output(b"\x1b[?1006l");
+        assert_eq!(terminal.mouse_encoding(), MouseEncoding::X10);
+    }
+
+    #[test]
+    fn only_events_the_tracking_mode_asks_for_are_reported() {
+        let mut terminal = Terminal::new(10, 3);
+        let press = click(MouseEventKind::Press, 1, 1);
+        let drag = click(MouseEventKind::Motion, 2, 1);
+        assert!(!terminal.report_mouse(&press));
+
+        terminal.process_pty_output(b"\x1b[?1000h\x1b[?1006h");
+        assert!(terminal.report_mouse(&press));
+        assert!(!terminal.report_mouse(&drag));
+        terminal.process_pty_output(b"\x1b[?1002h");
+        assert!(terminal.report_mouse(&drag));
+        assert_eq!(terminal.mouse_tracking(), MouseTracking::Drag);
+        assert_eq!(terminal.take_pending_input(), b"\x1b[<0;2;2M\x1b[<32;3;2M");
+    }
+}
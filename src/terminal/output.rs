@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use super::Terminal;
+
+/// Where the terminal writes bytes it generates for the program: replies to
+/// status and mode queries, device attributes and mouse reports.
+pub trait TerminalOutput: Send {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// Keeps everything written to it, for checking replies without a PTY.
+/// Clones share the recording.
+#[derive(Debug, Clone, Default)]
+pub struct RecordingOutput(Arc<Mutex<Vec<u8>>>);
+
+impl RecordingOutput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Everything written so far.
+    pub fn recorded(&self) -> Vec<u8> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl TerminalOutput for RecordingOutput {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extend_from_slice(bytes);
+    }
+}
+
+impl Terminal {
+    /// Sends responses to `output` instead of queueing them with user input
+    /// for [`Terminal::take_pending_input`].
+    pub fn set_output(&mut self, output: Box<dyn TerminalOutput>) {
+        self.output = Some(output);
+    }
+
+    /// Writes a reply to the program.
+    pub(super) fn respond(&mut self, bytes: &[u8]) {
+        match &mut self.output {
+            Some(output) => output.write(bytes),
+            None => self.pending_input.extend_from_slice(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_are_recorded_in_order() {
+        let mut terminal = Terminal::new(10, 4);
+        let output = RecordingOutput::new();
+        terminal.set_output(Box::new(output.clone()));
+        terminal.process_pty_output(b"\x1b[2;3H\x1b[6n\x1b[c");
+        assert_eq!(output.recorded(), b"\x1b[2;3R\x1b[?62;4c");
+        assert!(terminal.take_pending_input().is_empty());
+    }
+
+    #[test]
+    fn replies_are_queued_with_input_without_an_output() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"\x1b[c\x1b[6n");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[?62;4c\x1b[1;1R");
+    }
+}
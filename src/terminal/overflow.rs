@@ -0,0 +1,202 @@
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::grapheme::{Combining, MAX_COMBINING_LEN};
+use super::{char_width, Terminal, TerminalCell};
+
+impl Terminal {
+    /// Whether output past the right margin is kept rather than
+    /// overwriting the last column.
+    pub(super) fn keeps_overflow(&self) -> bool {
+        self.keep_overflow && !self.alternate_screen
+    }
+
+    /// Keeps `c` past the right margin when autowrap is off on the primary
+    /// screen and `keep_overflow` is set, so the line can be scrolled
+    /// sideways to read it, rather than overwriting the last column.
+    /// Returns whether it did.
+    pub(super) fn print_overflow(&mut self, c: char) -> bool {
+        if self.state.autowrap || !self.keeps_overflow() {
+            return false;
+        }
+        let line = self.lines_scrolled.wrapping_add(self.cursor_y);
+        if self.cursor_x < self.width {
+            // A wide character that doesn't fit in the last column
+            if self.cursor_x + char_width(c) <= self.width || self.extends_cell_before(c) {
+                return false;
+            }
+            self.cursor_x = self.width;
+        } else if !self.overflow.contains_key(&line) && self.extends_cell_before(c) {
+            // A mark on the character in the last column
+            return false;
+        }
+
+        let cell = TerminalCell {
+            ch: c,
+            combining: Combining::EMPTY,
+            fg_color: self.current_fg,
+            bg_color: self.current_bg,
+            attrs: self.current_attrs,
+            wrapped: false,
+            width: 1,
+        };
+        let cells = self.overflow.entry(line).or_default();
+        if let Some(last) = cells.iter_mut().rev().find(|cell| !cell.is_spacer()) {
+            let mut text = last.text();
+            text.push(c);
+            if text.graphemes(true).nth(1).is_none() {
+                let rest = &text[last.ch.len_utf8()..];
+                if rest.len() <= MAX_COMBINING_LEN {
+                    last.combining = Combining::new(rest);
+                }
+                return true;
+            }
+        }
+        if char_width(c) == 2 {
+            cells.push(TerminalCell { width: 2, ..cell });
+            cells.push(TerminalCell {
+                ch: ' ',
+                width: 0,
+                ..cell
+            });
+        } else {
+            cells.push(cell);
+        }
+        true
+    }
+
+    /// Whether `c` continues the grapheme cluster of the cell before the
+    /// cursor.
+    fn extends_cell_before(&self, c: char) -> bool {
+        if c < '\u{0300}' {
+            return false;
+        }
+        let Some(mut x) = self.cursor_x.min(self.width).checked_sub(1) else {
+            return false;
+        };
+        let row = &self.buffer[self.cursor_y];
+        if row[x].is_spacer() && x > 0 {
+            x -= 1;
+        }
+        let mut text = row[x].text();
+        text.push(c);
+        row[x].ch != ' ' && text.graphemes(true).nth(1).is_none()
+    }
+
+    /// Cells kept past the right margin of screen row `y`.
+    pub fn overflow(&self, y: usize) -> &[TerminalCell] {
+        self.overflow
+            .get(&self.lines_scrolled.wrapping_add(y))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Cells kept past the right margin of the row shown at viewport line
+    /// `y`. Rows in the scrollback carry theirs in the row itself.
+    pub fn visible_overflow(&self, y: usize) -> &[TerminalCell] {
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        match y.checked_sub(offset) {
+            Some(y) if !self.alternate_screen => self.overflow(y),
+            _ => &[],
+        }
+    }
+
+    /// Forgets what was kept past the right margin of screen rows `rows`,
+    /// as when they are erased or moved within the screen.
+    pub(super) fn drop_overflow(&mut self, rows: Range<usize>) {
+        if self.overflow.is_empty() || self.alternate_screen {
+            return;
+        }
+        let start = self.lines_scrolled.wrapping_add(rows.start);
+        let end = self.lines_scrolled.wrapping_add(rows.end);
+        if start <= end {
+            self.overflow.retain(|line, _| !(start..end).contains(line));
+        } else {
+            self.overflow
+                .retain(|line, _| !(*line >= start || *line < end));
+        }
+    }
+
+    /// The overflow of the line leaving the top of the screen for the
+    /// history, to be appended to it there.
+    pub(super) fn take_top_overflow(&mut self) -> Option<Vec<TerminalCell>> {
+        self.overflow.remove(&self.lines_scrolled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(cells: &[TerminalCell]) -> String {
+        cells
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .map(TerminalCell::text)
+            .collect()
+    }
+
+    fn keeping_overflow(width: usize, height: usize) -> Terminal {
+        let mut terminal = Terminal::new(width, height);
+        terminal.keep_overflow = true;
+        terminal
+    }
+
+    #[test]
+    fn the_last_column_is_overwritten_by_default() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b[?7l0123456789abcdef");
+        assert_eq!(terminal.line_text(0).as_deref(), Some("012345678f"));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (9, 0));
+        assert!(terminal.overflow(0).is_empty());
+        assert_eq!(terminal.max_h_scroll_offset(), 0);
+    }
+
+    #[test]
+    fn output_past_the_margin_is_kept_without_autowrap() {
+        let mut terminal = keeping_overflow(10, 3);
+        terminal.process_pty_output(b"\x1b[?7l0123456789abcdef");
+        assert_eq!(terminal.line_text(0).as_deref(), Some("0123456789"));
+        assert_eq!(text(terminal.overflow(0)), "abcdef");
+        assert_eq!(terminal.max_h_scroll_offset(), 6);
+    }
+
+    #[test]
+    fn the_last_column_is_overwritten_on_the_alternate_screen() {
+        let mut terminal = keeping_overflow(10, 3);
+        terminal.process_pty_output(b"\x1b[?1049h\x1b[?7l0123456789abcdef");
+        assert_eq!(terminal.line_text(0).as_deref(), Some("012345678f"));
+        assert!(terminal.overflow(0).is_empty());
+        assert_eq!(terminal.max_h_scroll_offset(), 0);
+    }
+
+    #[test]
+    fn wide_characters_and_marks_overflow_whole() {
+        let mut terminal = keeping_overflow(10, 3);
+        terminal.process_pty_output("\x1b[?7l012345678漢e\u{301}".as_bytes());
+        assert_eq!(terminal.line_text(0).as_deref(), Some("012345678"));
+        assert_eq!(text(terminal.overflow(0)), "漢e\u{301}");
+        assert_eq!(terminal.overflow(0).len(), 3);
+    }
+
+    #[test]
+    fn overflow_follows_its_line_into_the_history() {
+        let mut terminal = keeping_overflow(10, 2);
+        terminal.process_pty_output(b"\x1b[?7l0123456789abc\r\n\n\n");
+        assert!(terminal.overflow(0).is_empty());
+        assert_eq!(terminal.history_len(), 2);
+        assert_eq!(
+            Terminal::row_text(&terminal.history[0]).trim_end(),
+            "0123456789abc"
+        );
+    }
+
+    #[test]
+    fn erasing_the_line_drops_its_overflow() {
+        let mut terminal = keeping_overflow(10, 3);
+        terminal.process_pty_output(b"\x1b[?7l0123456789abc\x1b[2K");
+        assert!(terminal.overflow(0).is_empty());
+        terminal.process_pty_output(b"\r\n0123456789abc\x1b[2J");
+        assert!(terminal.overflow(1).is_empty());
+    }
+}
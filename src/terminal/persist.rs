@@ -0,0 +1,490 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::grapheme::Combining;
+use super::{Blink, CellAttributes, Color, Terminal, TerminalCell, UnderlineStyle};
+
+/// File signature, followed by a format version byte.
+const MAGIC: &[u8; 4] = b"RTSB";
+const VERSION: u8 = 6;
+
+const BOLD: u8 = 1 << 0;
+const ITALIC: u8 = 1 << 1;
+/// Set when the cell is underlined; from version 3 the style follows the
+/// flags.
+const UNDERLINE: u8 = 1 << 2;
+const BLINK: u8 = 1 << 3;
+const INVERSE: u8 = 1 << 4;
+const WRAPPED: u8 = 1 << 5;
+/// Set when an underline color follows the flags (version 2).
+const UNDERLINE_COLOR: u8 = 1 << 6;
+/// Set when the rest of a grapheme cluster follows, as a length byte and
+/// UTF-8 (version 4).
+const COMBINING: u8 = 1 << 7;
+
+// Second flags byte, from version 5
+/// With `BLINK`, the cell blinks rapidly rather than slowly.
+const RAPID_BLINK: u8 = 1 << 0;
+const OVERLINE: u8 = 1 << 1;
+/// Set when the base color index of an SGR 30–37 foreground follows the
+/// underline color (version 6).
+const BASE_FG: u8 = 1 << 2;
+
+/// Line drawn under a restored session.
+const DIVIDER_COLOR: Color = Color(128, 128, 128, 255);
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_color(out: &mut Vec<u8>, color: Color) {
+    out.extend_from_slice(&[color.0, color.1, color.2, color.3]);
+}
+
+fn write_cell(out: &mut Vec<u8>, cell: &TerminalCell) {
+    write_u32(out, cell.ch as u32);
+    write_color(out, cell.fg_color);
+    write_color(out, cell.bg_color);
+    let mut flags = 0;
+    for (set, flag) in [
+        (cell.attrs.bold, BOLD),
+        (cell.attrs.italic, ITALIC),
+        (cell.attrs.underline != UnderlineStyle::None, UNDERLINE),
+        (cell.attrs.blink != Blink::None, BLINK),
+        (cell.attrs.inverse, INVERSE),
+        (cell.wrapped, WRAPPED),
+        (cell.attrs.underline_color.is_some(), UNDERLINE_COLOR),
+        (!cell.combining.is_empty(), COMBINING),
+    ] {
+        if set {
+            flags |= flag;
+        }
+    }
+    out.push(flags);
+    let mut more_flags = 0;
+    for (set, flag) in [
+        (cell.attrs.blink == Blink::Rapid, RAPID_BLINK),
+        (cell.attrs.overline, OVERLINE),
+        (cell.attrs.base_fg.is_some(), BASE_FG),
+    ] {
+        if set {
+            more_flags |= flag;
+        }
+    }
+    out.push(more_flags);
+    if cell.attrs.underline != UnderlineStyle::None {
+        out.push(cell.attrs.underline.sgr() as u8);
+    }
+    if let Some(color) = cell.attrs.underline_color {
+        write_color(out, color);
+    }
+    if let Some(index) = cell.attrs.base_fg {
+        out.push(index);
+    }
+    if !cell.combining.is_empty() {
+        let text = cell.combining.as_str();
+        out.push(text.len() as u8);
+        out.extend_from_slice(text.as_bytes());
+    }
+    out.push(cell.width);
+}
+
+/// Writes `count` lines of a `width` column terminal to `path`, one line at
+/// a time so large histories don't build up in memory.
+fn write_lines<'a>(
+    path: &Path,
+    width: usize,
+    lines: impl Iterator<Item = &'a [TerminalCell]>,
+    count: usize,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    write_u32(&mut out, width as u32);
+    write_u32(&mut out, count as u32);
+    for line in lines {
+        write_u32(&mut out, line.len() as u32);
+        for cell in line {
+            write_cell(&mut out, cell);
+        }
+        file.write_all(&out)?;
+        out.clear();
+    }
+    file.write_all(&out)?;
+    file.flush()
+}
+
+/// Reads values from a loaded file, failing on truncation.
+struct Reader<'a> {
+    data: &'a [u8],
+    version: u8,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> io::Result<&[u8]> {
+        if self.data.len() < len {
+            return Err(invalid_data("scrollback file is truncated"));
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn color(&mut self) -> io::Result<Color> {
+        let bytes = self.bytes(4)?;
+        Ok(Color(bytes[0], bytes[1], bytes[2], bytes[3]))
+    }
+
+    fn cell(&mut self) -> io::Result<TerminalCell> {
+        let ch = char::from_u32(self.u32()?).ok_or_else(|| invalid_data("invalid character"))?;
+        let fg_color = self.color()?;
+        let bg_color = self.color()?;
+        let flags = self.u8()?;
+        let more_flags = if self.version >= 5 { self.u8()? } else { 0 };
+        let underline = if flags & UNDERLINE == 0 {
+            UnderlineStyle::None
+        } else if self.version >= 3 {
+            UnderlineStyle::from_sgr(self.u8()? as u16)
+                .ok_or_else(|| invalid_data("invalid underline style"))?
+        } else {
+            UnderlineStyle::Single
+        };
+        let underline_color = if flags & UNDERLINE_COLOR != 0 {
+            Some(self.color()?)
+        } else {
+            None
+        };
+        let base_fg = if more_flags & BASE_FG != 0 {
+            Some(self.u8()?)
+        } else {
+            None
+        };
+        let combining = if flags & COMBINING != 0 {
+            let len = self.u8()? as usize;
+            let text = std::str::from_utf8(self.bytes(len)?)
+                .map_err(|_| invalid_data("invalid grapheme cluster"))?;
+            Combining::new(text)
+        } else {
+            Combining::EMPTY
+        };
+        let width = self.u8()?;
+        Ok(TerminalCell {
+            ch,
+            combining,
+            fg_color,
+            bg_color,
+            attrs: CellAttributes {
+                bold: flags & BOLD != 0,
+                italic: flags & ITALIC != 0,
+                underline,
+                blink: match (flags & BLINK != 0, more_flags & RAPID_BLINK != 0) {
+                    (false, _) => Blink::None,
+                    (true, false) => Blink::Slow,
+                    (true, true) => Blink::Rapid,
+                },
+                inverse: flags & INVERSE != 0,
+                overline: more_flags & OVERLINE != 0,
+                underline_color,
+                base_fg,
+            },
+            wrapped: flags & WRAPPED != 0,
+            width,
+        })
+    }
+}
+
+impl Terminal {
+    /// Writes the scrollback history (not the screen) to `path` in a compact
+    /// binary format that keeps colors and attributes.
+    pub fn save_scrollback(&self, path: &Path) -> io::Result<()> {
+        write_lines(
+            path,
+            self.width,
+            self.history.iter().map(Vec::as_slice),
+            self.history.len(),
+        )
+    }
+
+    /// Saves the newest `max_lines` lines of history followed by the
+    /// primary screen down to its last line with text, for [`Terminal::restore_session`]
+    /// on the next start. Lines are written as they are encoded, and the
+    /// old file is replaced only once the new one is complete.
+    pub fn save_session(&self, path: &Path, max_lines: usize) -> io::Result<()> {
+        // A full-screen program's screen goes away with it
+        let screen = match &self.saved_primary {
+            Some((primary, _, _)) if self.alternate_screen => primary,
+            _ => &self.buffer,
+        };
+        let screen_rows = screen
+            .iter()
+            .rposition(|row| row.iter().any(|cell| cell.ch != ' '))
+            .map_or(0, |last| last + 1);
+        let history = self
+            .history
+            .len()
+            .min(max_lines.saturating_sub(screen_rows));
+        let lines = self
+            .history
+            .iter()
+            .skip(self.history.len() - history)
+            .chain(&screen[..screen_rows])
+            .map(Vec::as_slice);
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("tmp");
+        write_lines(&temp, self.width, lines, history + screen_rows)?;
+        fs::rename(&temp, path)
+    }
+
+    /// Loads a session saved by [`Terminal::save_session`] into the
+    /// scrollback, with a divider line under it so the restored output
+    /// stands apart from the new shell's.
+    pub fn restore_session(&mut self, path: &Path) -> io::Result<()> {
+        self.load_scrollback(path)?;
+        if self.history.is_empty() {
+            return Ok(());
+        }
+        let divider = TerminalCell {
+            ch: '\u{2500}',
+            fg_color: DIVIDER_COLOR,
+            ..self.blank_cell()
+        };
+        let mut line = vec![divider; self.width];
+        let label = " restored session ";
+        let start = self.width.saturating_sub(label.len()) / 2;
+        for (cell, ch) in line[start..].iter_mut().zip(label.chars()) {
+            cell.ch = ch;
+        }
+        self.push_history(line);
+        Ok(())
+    }
+
+    /// Replaces the scrollback history with the one saved at `path`, keeping
+    /// the newest `scrollback_limit` lines. Files saved at another width are
+    /// reflowed to the current one.
+    pub fn load_scrollback(&mut self, path: &Path) -> io::Result<()> {
+        let data = fs::read(path)?;
+        let mut reader = Reader {
+            data: &data,
+            version: 0,
+        };
+        if reader.bytes(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("not a scrollback file"));
+        }
+        reader.version = reader.u8()?;
+        if !(1..=VERSION).contains(&reader.version) {
+            return Err(invalid_data("unsupported scrollback file version"));
+        }
+        let width = reader.u32()? as usize;
+        let count = reader.u32()? as usize;
+        let mut lines = Vec::with_capacity(count.min(self.scrollback_limit));
+        for _ in 0..count {
+            let len = reader.u32()? as usize;
+            let line = (0..len)
+                .map(|_| reader.cell())
+                .collect::<io::Result<Vec<_>>>()?;
+            lines.push(line);
+        }
+
+        if width != self.width {
+            lines = self.reflow(lines);
+        }
+        let skip = lines.len().saturating_sub(self.scrollback_limit);
+        self.history = lines.into_iter().skip(skip).collect();
+        self.snap_to_bottom();
+        Ok(())
+    }
+
+    /// Rewraps rows of another width to the current one. Soft-wrapped rows
+    /// are joined into logical lines before splitting them again.
+    fn reflow(&self, rows: Vec<Vec<TerminalCell>>) -> Vec<Vec<TerminalCell>> {
+        let blank = self.blank_cell();
+        let mut reflowed = Vec::new();
+        let mut logical: Vec<TerminalCell> = Vec::new();
+
+        let count = rows.len();
+        for (index, row) in rows.into_iter().enumerate() {
+            // The last saved row may continue on the screen, which isn't saved
+            let wrapped = row.last().is_some_and(|cell| cell.wrapped) && index + 1 < count;
+            logical.extend(row.into_iter().map(|cell| TerminalCell {
+                wrapped: false,
+                ..cell
+            }));
+            if wrapped {
+                continue;
+            }
+
+            // Padding at the end of the line isn't content
+            while logical.last() == Some(&blank) {
+                logical.pop();
+            }
+            let mut cells = std::mem::take(&mut logical).into_iter().peekable();
+            loop {
+                let mut line = Vec::with_capacity(self.width);
+                while line.len() < self.width {
+                    // A wide character that doesn't fit moves to the next row
+                    let fits = |cell: &TerminalCell| {
+                        cell.width < 2 || line.len() + 1 < self.width || line.is_empty()
+                    };
+                    let Some(cell) = cells.next_if(fits) else {
+                        break;
+                    };
+                    line.push(cell);
+                }
+                let more = cells.peek().is_some();
+                line.resize(self.width, blank);
+                if more {
+                    line[self.width - 1].wrapped = true;
+                }
+                reflowed.push(line);
+                if !more {
+                    break;
+                }
+            }
+        }
+        reflowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const SESSION_V1: &[u8] = include_bytes!("../../tests/fixtures/session_v1.bin");
+
+    /// A path in the temporary directory unique to this process and test.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rt-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn session_round_trips_cell_for_cell() {
+        let mut terminal = Terminal::new(12, 3);
+        terminal.process_pty_output(
+            "\x1b[1;3;31mbold\x1b[m \x1b[4:3;58:2::1:2:3mcurl\x1b[m\r\n\
+             \x1b[6;53;44m漢字\x1b[m e\u{301}\r\n\
+             0123456789abcdef\r\nlast"
+                .as_bytes(),
+        );
+        let path = temp_path("round-trip");
+        terminal.save_session(&path, 100).unwrap();
+
+        let mut restored = Terminal::new(12, 3);
+        restored.restore_session(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let saved: Vec<&Vec<TerminalCell>> =
+            terminal.history.iter().chain(&terminal.buffer).collect();
+        // The restored lines are followed by the divider
+        assert_eq!(restored.history.len(), saved.len() + 1);
+        for (restored, saved) in restored.history.iter().zip(saved) {
+            assert_eq!(restored, saved);
+        }
+    }
+
+    #[test]
+    fn session_keeps_the_newest_lines() {
+        let mut terminal = Terminal::new(10, 2);
+        terminal.process_pty_output(b"1\r\n2\r\n3\r\n4\r\n5");
+        let path = temp_path("newest-lines");
+        terminal.save_session(&path, 3).unwrap();
+
+        let mut restored = Terminal::new(10, 2);
+        restored.load_scrollback(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let lines: Vec<String> = restored
+            .history
+            .iter()
+            .map(|line| Terminal::row_text(line).trim_end().to_string())
+            .collect();
+        assert_eq!(lines, ["3", "4", "5"]);
+    }
+
+    #[test]
+    fn version_1_sessions_still_load() {
+        let path = temp_path("session-v1");
+        fs::write(&path, SESSION_V1).unwrap();
+        let mut terminal = Terminal::new(4, 3);
+        terminal.restore_session(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(terminal.history.len(), 3);
+        let h = terminal.history[0][0];
+        assert_eq!((h.ch, h.fg_color), ('h', Color(205, 0, 0, 255)));
+        assert!(h.attrs.bold && !h.attrs.italic);
+        assert!(terminal.history[0][1].attrs.italic);
+        assert!(terminal.history[0][3].wrapped);
+        let (o, k) = (terminal.history[1][0], terminal.history[1][1]);
+        assert_eq!(o.attrs.underline, UnderlineStyle::Single);
+        assert_eq!(k.attrs.blink, Blink::Slow);
+        assert!(k.attrs.inverse);
+        assert_eq!(terminal.history[2][0].fg_color, DIVIDER_COLOR);
+    }
+
+    #[test]
+    fn other_files_are_rejected() {
+        let path = temp_path("not-a-session");
+        fs::write(&path, b"RTSB\x09").unwrap();
+        let mut terminal = Terminal::new(4, 3);
+        let error = terminal.load_scrollback(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn scrollback_round_trips_with_attributes() {
+        let mut terminal = Terminal::new(8, 2);
+        terminal.process_pty_output(
+            "\x1b[1;38;2;1;2;3;48;5;17mcolor\x1b[m\r\n\x1b[3;4;9mstyle\x1b[m\r\nwide漢字\r\n\r\n"
+                .as_bytes(),
+        );
+        let path = temp_path("scrollback");
+        terminal.save_scrollback(&path).unwrap();
+
+        let mut loaded = Terminal::new(8, 2);
+        loaded.load_scrollback(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.history, terminal.history);
+    }
+
+    #[test]
+    fn loading_reflows_and_keeps_the_limit() {
+        let mut terminal = Terminal::new(8, 2);
+        terminal.process_pty_output(b"one\r\n0123456789ab\r\n\r\n");
+        let path = temp_path("scrollback-reflow");
+        terminal.save_scrollback(&path).unwrap();
+
+        let mut wide = Terminal::new(16, 2);
+        wide.load_scrollback(&path).unwrap();
+        let lines: Vec<String> = (0..wide.history.len())
+            .map(|i| Terminal::row_text(&wide.history[i]).trim_end().to_string())
+            .collect();
+        assert_eq!(lines, ["one", "0123456789ab"]);
+
+        let mut limited = Terminal::new(8, 2);
+        limited.scrollback_limit = 1;
+        limited.load_scrollback(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(limited.history.len(), 1);
+        assert_eq!(limited.history[0], terminal.history[2]);
+    }
+}
@@ -0,0 +1,142 @@
+use super::{default_tab_stops, Terminal, TerminalState};
+
+impl Terminal {
+    /// DECSTR (CSI ! p): resets modes, attributes, margins and the saved
+    /// cursor. The screen, scrollback and cursor position are kept.
+    pub fn soft_reset(&mut self) {
+        let defaults = TerminalState::default();
+        let state = &mut self.state;
+        state.insert_mode = defaults.insert_mode;
+        state.origin_mode = defaults.origin_mode;
+        state.autowrap = defaults.autowrap;
+        state.cursor_visible = defaults.cursor_visible;
+        state.cursor_blink = self.cursor_blink.enabled;
+        state.application_cursor_keys = defaults.application_cursor_keys;
+        *self.saved_cursor_slot() = None;
+        self.reset_sgr();
+        self.scroll_top = 0;
+        self.scroll_bottom = self.height.saturating_sub(1);
+    }
+
+    /// RIS (ESC c): clears the screen and resets every mode, tab stop,
+    /// attribute, title and program-set cursor color. The scrollback and
+    /// what the host configured, such as the default colors, limits,
+    /// triggers and cursor blinking, are kept. Output held by XOFF, images
+    /// on the screen and those stored with the kitty graphics protocol are
+    /// dropped.
+    pub fn reset(&mut self) {
+        self.soft_reset();
+        self.state = TerminalState {
+            cursor_blink: self.cursor_blink.enabled,
+            ..TerminalState::default()
+        };
+        self.alternate_screen = false;
+        self.saved_primary = None;
+        self.buffer = vec![vec![self.blank_cell(); self.width]; self.height];
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.tabs = default_tab_stops(self.width);
+        self.set_title(String::new());
+        self.title_stack.clear();
+        self.set_program_cursor_color(self.configured_cursor_color);
+        self.dcs = None;
+        self.sixel = None;
+        self.selection = None;
+        self.synchronized_since = None;
+        self.window_resize_request = None;
+        self.flow_paused = false;
+        self.held_output.clear();
+        self.overflow.clear();
+        self.trigger_highlights.clear();
+        self.remove_images(|placement| placement.alternate);
+        self.clear_screen_images();
+        self.image_upload = None;
+        self.reset_kitty_images();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::output::RecordingOutput;
+    use crate::terminal::Color;
+
+    const DEFAULT_FG: Color = Color(1, 2, 3, 255);
+
+    fn terminal_with_content() -> Terminal {
+        let mut terminal = Terminal::new_with_colors(10, 4, DEFAULT_FG, Color::BLACK);
+        terminal.scrollback_limit = 50;
+        terminal.process_pty_output(
+            b"old\r\n\r\n\r\n\r\nkept\x1b[2;3r\x1b[4h\x1b[?7l\x1b[?25l\x1b[1;31m",
+        );
+        terminal
+    }
+
+    #[test]
+    fn soft_reset_keeps_content_and_resets_modes() {
+        let mut terminal = terminal_with_content();
+        let cursor = (terminal.cursor_x, terminal.cursor_y);
+        terminal.process_pty_output(b"\x1b[!p");
+
+        assert_eq!(terminal.line_text(3).as_deref(), Some("kept"));
+        assert_eq!(terminal.history_len(), 1);
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), cursor);
+        assert!(!terminal.state.insert_mode);
+        assert!(terminal.state.autowrap);
+        assert!(terminal.state.cursor_visible);
+        assert_eq!((terminal.scroll_top, terminal.scroll_bottom), (0, 3));
+        terminal.process_pty_output(b"\x1b[Hx");
+        let cell = *terminal.get_cell_at(0, 0).unwrap();
+        assert_eq!(cell.fg_color, DEFAULT_FG);
+        assert!(!cell.attrs.bold);
+    }
+
+    #[test]
+    fn full_reset_clears_content_and_keeps_host_configuration() {
+        let mut terminal = terminal_with_content();
+        let output = RecordingOutput::new();
+        terminal.set_output(Box::new(output.clone()));
+        terminal.process_pty_output(b"\x1bc");
+
+        assert!((0..4).all(|y| terminal.line_text(y).as_deref() == Some("")));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (0, 0));
+        assert!(terminal.state.autowrap && !terminal.state.insert_mode);
+        assert_eq!((terminal.scroll_top, terminal.scroll_bottom), (0, 3));
+        assert_eq!(terminal.scrollback_limit, 50);
+        assert_eq!(terminal.history_len(), 1);
+        terminal.process_pty_output(b"x");
+        assert_eq!(terminal.get_cell_at(0, 0).unwrap().fg_color, DEFAULT_FG);
+        terminal.process_pty_output(b"\x1b[6n");
+        assert_eq!(output.recorded(), b"\x1b[1;2R");
+    }
+
+    #[test]
+    fn each_reset_keeps_only_what_it_should() {
+        // Whether the title, tab stops, alternate screen, saved cursor and
+        // cursor color survive a soft and a full reset
+        for (soft, survives) in [(true, [true, true, true, false, true]), (false, [false; 5])] {
+            let mut terminal = terminal_with_content();
+            terminal.bold_is_bright = false;
+            terminal.background_color_erase = false;
+            terminal.process_pty_output(
+                b"\x1b]2;title\x07\x1b[3g\x1b[1;5H\x1bH\x1b7\x1b[?1049halt\x1b]12;#ff0000\x07",
+            );
+            if soft {
+                terminal.soft_reset();
+            } else {
+                terminal.reset();
+            }
+
+            let [title, tabs, alternate, saved_cursor, cursor_color] = survives;
+            assert_eq!(terminal.title == "title", title, "soft {}", soft);
+            assert_eq!(terminal.tabs == [4], tabs, "soft {}", soft);
+            assert_eq!(terminal.alternate_screen, alternate, "soft {}", soft);
+            assert_eq!(terminal.saved_cursor_slot().is_some(), saved_cursor);
+            assert_eq!(terminal.cursor_color.is_some(), cursor_color);
+            // Host configuration and the scrollback always survive
+            assert!(!terminal.bold_is_bright && !terminal.background_color_erase);
+            assert_eq!(terminal.scrollback_limit, 50);
+            assert_eq!(terminal.history_len(), 1);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+use std::cell::OnceCell;
+use std::ops::Range;
+
+use super::{CellAttributes, Color, Selection, Terminal, TerminalCell};
+
+/// Colors and attributes shared by a run of cells, as stored in the cells:
+/// inverse and bold brightening are not applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub attrs: CellAttributes,
+}
+
+impl Style {
+    pub fn of(cell: &TerminalCell) -> Self {
+        Self {
+            fg: cell.fg_color,
+            bg: cell.bg_color,
+            attrs: cell.attrs,
+        }
+    }
+}
+
+/// The viewport in a form for screen readers and other assistive
+/// technology. Positions are `(column, row)` pairs like [`Selection`]'s,
+/// but columns count characters rather than cells, so a wide character
+/// and its spacer are one column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct A11ySnapshot {
+    /// Text of each viewport row, top to bottom, with trailing blanks
+    /// trimmed.
+    pub lines: Vec<String>,
+    /// The cursor, if it is shown and in view.
+    pub cursor: Option<(usize, usize)>,
+    /// First and last selected characters.
+    pub selection: Option<((usize, usize), (usize, usize))>,
+}
+
+/// A borrowed row of cells with text extraction on top.
+pub struct Row<'a> {
+    cells: &'a [TerminalCell],
+    /// Text of the whole row and the byte offset of each cell in it, built
+    /// the first time spans ask for it.
+    text: OnceCell<(String, Vec<usize>)>,
+}
+
+impl<'a> Row<'a> {
+    pub fn new(cells: &'a [TerminalCell]) -> Self {
+        Self {
+            cells,
+            text: OnceCell::new(),
+        }
+    }
+
+    pub fn cells(&self) -> &'a [TerminalCell] {
+        self.cells
+    }
+
+    /// The grapheme clusters of the row, skipping wide-character spacers,
+    /// with trailing blanks trimmed.
+    pub fn text(&self) -> String {
+        self.full_text().trim_end().to_string()
+    }
+
+    /// Index among the row's characters of the one in cell `x`. A spacer
+    /// maps to the wide character it belongs to.
+    pub fn char_index(&self, x: usize) -> usize {
+        let x = x.min(self.cells.len());
+        let before = self.cells[..x]
+            .iter()
+            .filter(|cell| !cell.is_spacer())
+            .count();
+        if self.cells.get(x).is_some_and(TerminalCell::is_spacer) {
+            before.saturating_sub(1)
+        } else {
+            before
+        }
+    }
+
+    /// Whether the text of the row continues on the following row.
+    pub fn is_wrapped(&self) -> bool {
+        self.cells.last().is_some_and(|cell| cell.wrapped)
+    }
+
+    /// Splits the row into runs of cells with the same style, as cell
+    /// ranges. The spacer after a wide character stays in its run.
+    pub fn spans(&self) -> impl Iterator<Item = (Style, Range<usize>)> + 'a {
+        let cells = self.cells;
+        let mut start = 0;
+        std::iter::from_fn(move || {
+            let style = Style::of(cells.get(start)?);
+            let len = cells[start + 1..]
+                .iter()
+                .position(|cell| !cell.is_spacer() && Style::of(cell) != style)
+                .map_or(cells.len() - start, |len| len + 1);
+            let run = start..start + len;
+            start = run.end;
+            Some((style, run))
+        })
+    }
+
+    /// The text of each run of [`Row::spans`], covering the whole row
+    /// including trailing blanks.
+    pub fn styled_spans(&self) -> impl Iterator<Item = (Style, &str)> + '_ {
+        let (text, starts) = self.text.get_or_init(|| self.build_text());
+        self.spans()
+            .map(move |(style, run)| (style, &text[starts[run.start]..starts[run.end]]))
+    }
+
+    fn full_text(&self) -> &str {
+        &self.text.get_or_init(|| self.build_text()).0
+    }
+
+    fn build_text(&self) -> (String, Vec<usize>) {
+        let mut text = String::with_capacity(self.cells.len());
+        let mut starts = Vec::with_capacity(self.cells.len() + 1);
+        for cell in self.cells {
+            starts.push(text.len());
+            if !cell.is_spacer() {
+                text.push(cell.ch);
+                text.push_str(cell.combining.as_str());
+            }
+        }
+        starts.push(text.len());
+        (text, starts)
+    }
+}
+
+impl Terminal {
+    /// Rows of the screen from top to bottom, ignoring the scroll position.
+    pub fn iter_rows(&self) -> impl Iterator<Item = Row<'_>> {
+        self.buffer.iter().map(|row| Row::new(row))
+    }
+
+    /// Text of screen row `y` with trailing blanks trimmed.
+    pub fn line_text(&self, y: usize) -> Option<String> {
+        self.buffer.get(y).map(|row| Row::new(row).text())
+    }
+
+    /// The viewport's text, cursor and selection for assistive technology.
+    pub fn accessibility_snapshot(&self) -> A11ySnapshot {
+        let rows: Vec<Row> = (0..self.height)
+            .map(|y| Row::new(self.visible_row(y).unwrap_or_default()))
+            .collect();
+        let position = |(x, y): (usize, usize)| {
+            let column = rows.get(y).map_or(x, |row| row.char_index(x));
+            (column, y)
+        };
+        A11ySnapshot {
+            lines: rows.iter().map(Row::text).collect(),
+            cursor: self.visible_cursor().map(position),
+            selection: self
+                .selection
+                .map(|Selection { start, end }| (position(start), position(end))),
+        }
+    }
+
+    /// Text of the screen. Soft-wrapped rows are joined; other rows end
+    /// with a newline.
+    pub fn screen_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.iter_rows() {
+            if row.is_wrapped() {
+                text.push_str(row.full_text());
+            } else {
+                text.push_str(&row.text());
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_reports_lines_and_cursor_in_characters() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output("hello\r\n漢字ab".as_bytes());
+        let snapshot = terminal.accessibility_snapshot();
+        assert_eq!(snapshot.lines, ["hello", "漢字ab", ""]);
+        assert_eq!(snapshot.cursor, Some((4, 1)));
+        assert_eq!(snapshot.selection, None);
+    }
+
+    #[test]
+    fn snapshot_maps_the_selection_and_hides_a_hidden_cursor() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output("漢字ab\x1b[?25l".as_bytes());
+        terminal.select(Selection {
+            start: (2, 0),
+            end: (5, 0),
+        });
+        let snapshot = terminal.accessibility_snapshot();
+        assert_eq!(snapshot.cursor, None);
+        assert_eq!(snapshot.selection, Some(((1, 0), (3, 0))));
+    }
+
+    #[test]
+    fn spans_group_runs_of_the_same_style() {
+        let mut terminal = Terminal::new(12, 2);
+        terminal.process_pty_output("ab\x1b[1mc漢\x1b[31md\x1b[22me\x1b[m".as_bytes());
+        let row = terminal.iter_rows().next().unwrap();
+        let spans: Vec<(CellAttributes, &str)> = row
+            .styled_spans()
+            .map(|(style, text)| (style.attrs, text))
+            .collect();
+        let bold = CellAttributes {
+            bold: true,
+            ..CellAttributes::default()
+        };
+        let red = CellAttributes {
+            base_fg: Some(1),
+            ..CellAttributes::default()
+        };
+        assert_eq!(
+            spans,
+            [
+                (CellAttributes::default(), "ab"),
+                (bold, "c漢"),
+                (CellAttributes { bold: true, ..red }, "d"),
+                (red, "e"),
+                (CellAttributes::default(), "     "),
+            ]
+        );
+        let ranges: Vec<Range<usize>> = row.spans().map(|(_, range)| range).collect();
+        assert_eq!(ranges, [0..2, 2..5, 5..6, 6..7, 7..12]);
+        let styles: Vec<Style> = row.spans().map(|(style, _)| style).collect();
+        assert_eq!(styles[1].fg, styles[0].fg);
+        assert_ne!(styles[2].fg, styles[1].fg);
+        assert_eq!(styles[3].fg, styles[2].fg);
+    }
+
+    #[test]
+    fn wrapped_rows_are_joined_in_the_screen_text() {
+        let mut terminal = Terminal::new(5, 4);
+        terminal.process_pty_output(b"hello world\r\nx");
+        let wrapped: Vec<bool> = terminal.iter_rows().map(|row| row.is_wrapped()).collect();
+        assert_eq!(wrapped, [true, true, false, false]);
+        assert_eq!(terminal.line_text(1).as_deref(), Some(" worl"));
+        assert_eq!(terminal.screen_text(), "hello world\nx\n");
+        assert_eq!(terminal.line_text(4), None);
+    }
+}
@@ -0,0 +1,235 @@
+use super::{Terminal, TerminalCell};
+
+/// Characters that end a word for double-click selection, besides
+/// whitespace. `_` is left out so identifiers select as one word.
+pub const DEFAULT_WORD_SEPARATORS: &str = ",;:!?'\"`|()[]{}<>";
+
+/// A selected span of the viewport, from `start` to `end` inclusive, as
+/// `(column, row)` pairs in reading order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Selection {
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (y, x) >= (self.start.1, self.start.0) && (y, x) <= (self.end.1, self.end.0)
+    }
+}
+
+impl Terminal {
+    pub fn word_separators(&self) -> &str {
+        &self.word_separators
+    }
+
+    pub fn set_word_separators(&mut self, separators: &str) {
+        self.word_separators = separators.to_string();
+    }
+
+    fn is_word_char(&self, cell: &TerminalCell) -> bool {
+        // A spacer belongs to the wide character before it
+        cell.is_spacer() || !(cell.ch.is_whitespace() || self.word_separators.contains(cell.ch))
+    }
+
+    /// Columns `(first, last)` of the word under viewport cell `(x, y)`.
+    /// Outside a word, the range covers just that cell.
+    pub fn word_at(&self, x: usize, y: usize) -> (usize, usize) {
+        let Some(row) = self.visible_row(y) else {
+            return (x, x);
+        };
+        if !row.get(x).is_some_and(|cell| self.is_word_char(cell)) {
+            return (x, x);
+        }
+        let first = row[..x]
+            .iter()
+            .rposition(|cell| !self.is_word_char(cell))
+            .map_or(0, |index| index + 1);
+        let last = row[x..]
+            .iter()
+            .position(|cell| !self.is_word_char(cell))
+            .map_or(row.len() - 1, |index| x + index - 1);
+        (first, last)
+    }
+
+    /// Rows `(first, last)` of the logical line shown at viewport row `y`,
+    /// following soft wraps in both directions.
+    pub fn line_at(&self, y: usize) -> (usize, usize) {
+        let wrapped = |y: usize| self.visible_row(y).is_some_and(Terminal::is_wrapped);
+        let mut first = y;
+        while first > 0 && wrapped(first - 1) {
+            first -= 1;
+        }
+        let mut last = y;
+        while last + 1 < self.height && wrapped(last) {
+            last += 1;
+        }
+        (first, last)
+    }
+
+    pub fn selection(&self) -> Option<Selection> {
+        self.selection
+    }
+
+    pub fn select(&mut self, selection: Selection) {
+        self.selection = Some(selection);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Selects the word under viewport cell `(x, y)`.
+    pub fn select_word(&mut self, x: usize, y: usize) {
+        let (first, last) = self.word_at(x, y);
+        self.select(Selection {
+            start: (first, y),
+            end: (last, y),
+        });
+    }
+
+    /// Selects the whole logical line shown at viewport row `y`.
+    pub fn select_line(&mut self, y: usize) {
+        let (first, last) = self.line_at(y);
+        self.select(Selection {
+            start: (0, first),
+            end: (self.width.saturating_sub(1), last),
+        });
+    }
+
+    /// Selects the whole viewport.
+    pub fn select_all(&mut self) {
+        self.select(Selection {
+            start: (0, 0),
+            end: (self.width.saturating_sub(1), self.height.saturating_sub(1)),
+        });
+    }
+
+    /// Text of the selection. Soft-wrapped rows are joined; other rows end
+    /// with a newline.
+    pub fn selection_text(&self) -> Option<String> {
+        let selection = self.selection?;
+        let mut text = String::new();
+        for y in selection.start.1..=selection.end.1 {
+            let Some(row) = self.visible_row(y) else {
+                break;
+            };
+            let first = if y == selection.start.1 {
+                selection.start.0
+            } else {
+                0
+            };
+            let last = if y == selection.end.1 {
+                selection.end.0.min(row.len().saturating_sub(1))
+            } else {
+                row.len().saturating_sub(1)
+            };
+            if first < row.len() {
+                text.push_str(&Terminal::row_text(&row[first..=last]));
+            }
+            if y != selection.end.1 && !Terminal::is_wrapped(row) {
+                text.push('\n');
+            }
+        }
+        Some(text)
+    }
+
+    /// Copies the cells of the screen rectangle with corners `(x0, y0)` and
+    /// `(x1, y1)`, inclusive, in either order. Corners outside the screen
+    /// are clamped to it.
+    pub fn copy_region(
+        &self,
+        x0: usize,
+        y0: usize,
+        x1: usize,
+        y1: usize,
+    ) -> Vec<Vec<TerminalCell>> {
+        let (Some(max_x), Some(max_y)) = (self.width.checked_sub(1), self.height.checked_sub(1))
+        else {
+            return Vec::new();
+        };
+        let (left, right) = (x0.min(x1).min(max_x), x0.max(x1).min(max_x));
+        let (top, bottom) = (y0.min(y1).min(max_y), y0.max(y1).min(max_y));
+        self.buffer[top..=bottom]
+            .iter()
+            .map(|row| row[left..=right].to_vec())
+            .collect()
+    }
+
+    /// Text of `copy_region`, one line per row without trailing spaces.
+    pub fn region_text(&self, x0: usize, y0: usize, x1: usize, y1: usize) -> String {
+        self.copy_region(x0, y0, x1, y1)
+            .iter()
+            .map(|row| Terminal::row_text(row))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::Color;
+
+    #[test]
+    fn double_click_selects_a_whole_identifier() {
+        let mut terminal = Terminal::new(20, 3);
+        terminal.process_pty_output(b"let foo_bar = 1;");
+        assert_eq!(terminal.word_at(5, 0), (4, 10));
+        terminal.select_word(8, 0);
+        assert_eq!(terminal.selection_text().as_deref(), Some("foo_bar"));
+    }
+
+    #[test]
+    fn underscore_as_a_separator_splits_identifiers() {
+        let mut terminal = Terminal::new(20, 3);
+        terminal.set_word_separators("_");
+        terminal.process_pty_output(b"let foo_bar = 1;");
+        assert_eq!(terminal.word_at(5, 0), (4, 6));
+        assert_eq!(terminal.word_at(9, 0), (8, 10));
+        assert_eq!(terminal.word_at(7, 0), (7, 7));
+    }
+
+    #[test]
+    fn wide_characters_stay_in_their_word() {
+        let mut terminal = Terminal::new(20, 3);
+        terminal.process_pty_output("a 漢字x b".as_bytes());
+        assert_eq!(terminal.word_at(3, 0), (2, 6));
+    }
+
+    #[test]
+    fn triple_click_selects_the_logical_line() {
+        let mut terminal = Terminal::new(5, 6);
+        terminal.process_pty_output(b"one\r\ntwo and three\r\nfour");
+        assert_eq!(terminal.line_at(2), (1, 3));
+        terminal.select_line(2);
+        assert_eq!(terminal.selection_text().as_deref(), Some("two and three"));
+    }
+
+    #[test]
+    fn a_region_is_copied_cell_for_cell_and_as_text() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"0123456789\r\nab\x1b[31mcd\x1b[mef\r\nABCDEF");
+        let region = terminal.copy_region(1, 1, 3, 2);
+        assert_eq!(region.len(), 2);
+        assert!(region.iter().all(|row| row.len() == 3));
+        let chars: Vec<char> = region.iter().flatten().map(|cell| cell.ch).collect();
+        assert_eq!(chars, ['b', 'c', 'd', 'B', 'C', 'D']);
+        assert_eq!(region[0][1], terminal.buffer[1][2]);
+        assert_eq!(region[0][1].fg_color, Color::from_ansi_color(1));
+        assert_eq!(terminal.region_text(1, 1, 3, 2), "bcd\nBCD");
+    }
+
+    #[test]
+    fn region_corners_are_normalized_and_clamped() {
+        let mut terminal = Terminal::new(10, 4);
+        terminal.process_pty_output(b"0123456789\r\nab");
+        assert_eq!(
+            terminal.region_text(3, 2, 1, 1),
+            terminal.region_text(1, 1, 3, 2)
+        );
+        assert_eq!(terminal.region_text(7, 0, 50, 1), "789\n");
+        assert_eq!(terminal.copy_region(20, 20, 30, 30).len(), 1);
+        assert_eq!(terminal.region_text(0, 1, 4, 1), "ab");
+    }
+}
@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::Terminal;
+
+/// A terminal shared between a thread that parses PTY output and one that
+/// renders it.
+///
+/// Lock ordering: the terminal lock must never be held while writing to the
+/// PTY. Replies the terminal generates (device status reports and the like)
+/// are collected with [`SharedTerminal::take_responses`], which releases the
+/// lock before returning, and only then written out. A reader thread blocked
+/// on a full PTY while holding the lock could otherwise deadlock with a shell
+/// waiting for those replies.
+#[derive(Clone)]
+pub struct SharedTerminal(Arc<Mutex<Terminal>>);
+
+impl SharedTerminal {
+    pub fn new(terminal: Terminal) -> Self {
+        Self(Arc::new(Mutex::new(terminal)))
+    }
+
+    /// A panic while parsing leaves the grid usable, so a poisoned lock is
+    /// recovered rather than propagated.
+    fn lock(&self) -> MutexGuard<'_, Terminal> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Parses a chunk of PTY output.
+    pub fn feed(&self, bytes: &[u8]) {
+        self.lock().write_bytes(bytes);
+    }
+
+    /// Runs `f` with the terminal locked, e.g. to render a frame. Keep `f`
+    /// short: output parsing waits for it.
+    pub fn with_read<R>(&self, f: impl FnOnce(&Terminal) -> R) -> R {
+        f(&self.lock())
+    }
+
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut Terminal) -> R) -> R {
+        f(&mut self.lock())
+    }
+
+    /// Drains bytes queued for the PTY; write them after this returns.
+    pub fn take_responses(&self) -> Vec<u8> {
+        self.lock().take_pending_input()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn output_is_parsed_on_a_reader_thread_while_the_grid_is_read() {
+        let shared = SharedTerminal::new(Terminal::new(20, 5));
+        let reader = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for i in 0..500 {
+                    shared.feed(format!("line {}\r\n", i).as_bytes());
+                }
+                shared.feed(b"\x1b[6n");
+            })
+        };
+
+        while !reader.is_finished() {
+            shared.with_read(|terminal| {
+                assert_eq!(terminal.buffer.len(), terminal.height);
+                assert!(terminal
+                    .buffer
+                    .iter()
+                    .all(|row| row.len() == terminal.width));
+            });
+        }
+        reader.join().unwrap();
+
+        let last = shared.with_read(|terminal| terminal.line_text(3));
+        assert_eq!(last.as_deref(), Some("line 499"));
+        assert_eq!(shared.take_responses(), b"\x1b[5;1R");
+        assert!(shared.take_responses().is_empty());
+    }
+}
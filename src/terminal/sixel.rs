@@ -0,0 +1,394 @@
+use std::sync::Arc;
+
+use super::images::{ImageSize, TerminalImage, MAX_IMAGE_PIXELS};
+use super::Terminal;
+
+/// Largest sixel image along either side, in pixels. Sixels drawn past it
+/// are dropped.
+pub const MAX_SIXEL_SIZE: u32 = 4096;
+/// Color registers a sixel image can define.
+const REGISTERS: usize = 256;
+
+/// The VT340's default color registers; the rest start black.
+const VT340_COLORS: [[u8; 3]; 16] = [
+    [0, 0, 0],
+    [51, 51, 204],
+    [204, 36, 36],
+    [51, 204, 51],
+    [204, 51, 204],
+    [51, 204, 204],
+    [204, 204, 51],
+    [120, 120, 120],
+    [69, 69, 69],
+    [87, 87, 153],
+    [153, 69, 69],
+    [87, 153, 87],
+    [153, 87, 153],
+    [87, 153, 153],
+    [153, 153, 87],
+    [204, 204, 204],
+];
+
+/// Decodes the data of a sixel DCS (`ESC P P1;P2;P3 q DATA ESC \`) into
+/// RGBA pixels as it arrives.
+#[derive(Debug, Clone)]
+pub struct SixelDecoder {
+    palette: Vec<[u8; 4]>,
+    color: usize,
+    /// Position of the next sixel: its column and the top row of its band.
+    x: u32,
+    y: u32,
+    /// Size declared with raster attributes, which pixels drawn past
+    /// still extend.
+    declared: (u32, u32),
+    /// Extent drawn so far.
+    width: u32,
+    height: u32,
+    /// Pixels, `stride` wide; alpha 0 where nothing was drawn.
+    pixels: Vec<[u8; 4]>,
+    stride: u32,
+    /// Filled in where nothing was drawn; transparent when `None`.
+    background: Option<[u8; 4]>,
+    /// Command whose numeric parameters are being read: `!`, `#` or `"`.
+    command: Option<u8>,
+    params: Vec<u32>,
+}
+
+impl SixelDecoder {
+    /// A decoder for a sixel DCS with the parameters `params`. A second
+    /// parameter of 1 leaves undrawn pixels transparent; otherwise they
+    /// take `background`.
+    pub fn new(params: &[u16], background: [u8; 4]) -> Self {
+        let mut palette = vec![[0, 0, 0, 255]; REGISTERS];
+        for (register, [r, g, b]) in palette.iter_mut().zip(VT340_COLORS) {
+            *register = [r, g, b, 255];
+        }
+        Self {
+            palette,
+            color: 0,
+            x: 0,
+            y: 0,
+            declared: (0, 0),
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            stride: 0,
+            background: (params.get(1) != Some(&1)).then_some(background),
+            command: None,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn put(&mut self, byte: u8) {
+        if self.command.is_some() {
+            match byte {
+                b'0'..=b'9' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    let param = self.params.last_mut().unwrap();
+                    *param = param
+                        .saturating_mul(10)
+                        .saturating_add((byte - b'0') as u32);
+                    return;
+                }
+                b';' => {
+                    if self.params.is_empty() {
+                        self.params.push(0);
+                    }
+                    self.params.push(0);
+                    return;
+                }
+                _ => self.finish_command(),
+            }
+        }
+        // A repeat applies to the next byte only if it is a sixel
+        let count = match self.command.take() {
+            Some(b'!') => self.params.first().copied().unwrap_or(1).max(1),
+            _ => 1,
+        };
+        match byte {
+            b'?'..=b'~' => self.draw(byte - b'?', count),
+            b'!' | b'#' | b'"' => {
+                self.command = Some(byte);
+                self.params.clear();
+            }
+            b'$' => self.x = 0,
+            b'-' => {
+                self.x = 0;
+                self.y = self.y.saturating_add(6);
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a `#` or `"` command once its parameters are read. A repeat
+    /// (`!`) waits for the sixel it repeats.
+    fn finish_command(&mut self) {
+        let params = std::mem::take(&mut self.params);
+        match self.command {
+            Some(b'#') => {
+                self.command = None;
+                let Some(&register) = params.first() else {
+                    return;
+                };
+                let register = register as usize % REGISTERS;
+                self.color = register;
+                if let [_, space, x, y, z] = params[..] {
+                    let color = match space {
+                        1 => hls_to_rgb(x, y, z),
+                        2 => [percent(x), percent(y), percent(z)],
+                        _ => return,
+                    };
+                    self.palette[register] = [color[0], color[1], color[2], 255];
+                }
+            }
+            Some(b'"') => {
+                self.command = None;
+                if let [_, _, width, height, ..] = params[..] {
+                    self.declared = (width.min(MAX_SIXEL_SIZE), height.min(MAX_SIXEL_SIZE));
+                }
+            }
+            _ => self.params = params,
+        }
+    }
+
+    /// Draws `bits`, a column of six pixels from the top, `count` times.
+    fn draw(&mut self, bits: u8, count: u32) {
+        let end = self.x.saturating_add(count).min(MAX_SIXEL_SIZE);
+        let bottom = self.y.saturating_add(6).min(MAX_SIXEL_SIZE);
+        if bits != 0 && self.x < end && self.y < bottom && self.grow(end, bottom) {
+            let color = self.palette[self.color];
+            for row in self.y..bottom {
+                if bits & (1 << (row - self.y)) != 0 {
+                    let start = (row * self.stride) as usize;
+                    self.pixels[start + self.x as usize..start + end as usize].fill(color);
+                }
+            }
+            let last = (0..6).rev().find(|bit| bits & (1 << bit) != 0).unwrap_or(0);
+            self.width = self.width.max(end);
+            self.height = self.height.max((self.y + last + 1).min(bottom));
+        }
+        self.x = self.x.saturating_add(count);
+    }
+
+    /// Makes room for pixels up to `width` by `height`, returning whether
+    /// there is.
+    fn grow(&mut self, width: u32, height: u32) -> bool {
+        let rows = self.pixels.len() as u32 / self.stride.max(1);
+        if width <= self.stride && height <= rows {
+            return true;
+        }
+        let stride = self
+            .stride
+            .max(width.next_power_of_two())
+            .min(MAX_SIXEL_SIZE);
+        let rows = rows.max(height.next_power_of_two()).min(MAX_SIXEL_SIZE);
+        if width > stride || height > rows || stride as u64 * rows as u64 > MAX_IMAGE_PIXELS {
+            return false;
+        }
+        let mut pixels = vec![[0; 4]; (stride * rows) as usize];
+        for row in 0..self.height {
+            let old = (row * self.stride) as usize;
+            let new = (row * stride) as usize;
+            pixels[new..new + self.width as usize]
+                .copy_from_slice(&self.pixels[old..old + self.width as usize]);
+        }
+        self.pixels = pixels;
+        self.stride = stride;
+        true
+    }
+
+    /// The image drawn: as large as the raster attributes declared or as
+    /// the sixels reach, whichever is larger. `None` if it is empty.
+    pub fn finish(mut self) -> Option<TerminalImage> {
+        if self.command.is_some() {
+            self.finish_command();
+        }
+        let width = self.width.max(self.declared.0);
+        let height = self.height.max(self.declared.1);
+        if width == 0 || height == 0 || width as u64 * height as u64 > MAX_IMAGE_PIXELS {
+            return None;
+        }
+        let background = self.background.unwrap_or([0; 4]);
+        let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = match (x < self.stride, y * self.stride + x) {
+                    (true, index) => self.pixels.get(index as usize).copied(),
+                    (false, _) => None,
+                };
+                match pixel {
+                    Some(pixel) if pixel[3] != 0 => rgba.extend_from_slice(&pixel),
+                    _ => rgba.extend_from_slice(&background),
+                }
+            }
+        }
+        TerminalImage::new(width, height, rgba).ok()
+    }
+}
+
+/// A sixel color component, 0 to 100, as a byte.
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Converts sixel HLS, where hue 0 is blue, 120 red and 240 green, and
+/// lightness and saturation go to 100, to RGB.
+fn hls_to_rgb(hue: u32, lightness: u32, saturation: u32) -> [u8; 3] {
+    let hue = ((hue + 240) % 360) as f32 / 360.0;
+    let lightness = lightness.min(100) as f32 / 100.0;
+    let saturation = saturation.min(100) as f32 / 100.0;
+    let q = if lightness < 0.5 {
+        lightness * (1.0 + saturation)
+    } else {
+        lightness + saturation - lightness * saturation
+    };
+    let p = 2.0 * lightness - q;
+    let channel = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        let value = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (value * 255.0).round() as u8
+    };
+    [
+        channel(hue + 1.0 / 3.0),
+        channel(hue),
+        channel(hue - 1.0 / 3.0),
+    ]
+}
+
+impl Terminal {
+    /// Shows a finished sixel image. With sixel display mode (DECSDM) it
+    /// goes in the top left corner and the cursor stays put; otherwise it
+    /// goes at the cursor, which moves to the line below it, scrolling as
+    /// needed.
+    pub(super) fn show_sixel(&mut self, image: TerminalImage) {
+        let (cols, rows) = self.image_cells(&image, ImageSize::Auto, ImageSize::Auto, true);
+        let image = Arc::new(image);
+        if self.state.sixel_display {
+            let cursor = (self.cursor_x, self.cursor_y);
+            (self.cursor_x, self.cursor_y) = (0, 0);
+            self.place_image(image, cols, rows);
+            (self.cursor_x, self.cursor_y) = cursor;
+            return;
+        }
+        let col = self.cursor_x.min(self.width - 1);
+        let rows = self.place_image(image, cols, rows).rows();
+        for _ in 0..rows {
+            self.new_line();
+        }
+        self.cursor_x = col;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKGROUND: [u8; 4] = [1, 2, 3, 255];
+    const CLEAR: [u8; 4] = [0; 4];
+
+    /// Decodes `data` with undrawn pixels left transparent, returning the
+    /// size and pixels.
+    fn decode(data: &str) -> Option<(u32, u32, Vec<[u8; 4]>)> {
+        decode_with(&[0, 1], data)
+    }
+
+    fn decode_with(params: &[u16], data: &str) -> Option<(u32, u32, Vec<[u8; 4]>)> {
+        let mut decoder = SixelDecoder::new(params, BACKGROUND);
+        for &byte in data.as_bytes() {
+            decoder.put(byte);
+        }
+        let image = decoder.finish()?;
+        let pixels = image
+            .rgba()
+            .chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect();
+        Some((image.width(), image.height(), pixels))
+    }
+
+    #[test]
+    fn a_sixel_is_a_column_of_six_pixels() {
+        let blue = [51, 51, 204, 255];
+        assert_eq!(decode("#1~"), Some((1, 6, vec![blue; 6])));
+        // Bit 0 is the top pixel; the image ends at the lowest one drawn
+        assert_eq!(decode("#1A"), Some((1, 2, vec![CLEAR, blue])));
+        assert_eq!(decode("#1??"), None);
+    }
+
+    #[test]
+    fn colors_are_defined_in_rgb_or_hls() {
+        let red = [255, 0, 0, 255];
+        assert_eq!(decode("#3;2;100;0;0#3@"), Some((1, 1, vec![red])));
+        // Hue 120 is red in sixel's HLS, where 0 is blue
+        assert_eq!(decode("#3;1;120;50;100@"), Some((1, 1, vec![red])));
+        assert_eq!(
+            decode("#3;1;0;50;100@"),
+            Some((1, 1, vec![[0, 0, 255, 255]]))
+        );
+        // Percentages past 100 are clamped
+        assert_eq!(
+            decode("#3;2;0;200;50@"),
+            Some((1, 1, vec![[0, 255, 127, 255]]))
+        );
+    }
+
+    #[test]
+    fn repeats_returns_and_new_lines_move_the_position() {
+        let (red, green) = ([255, 0, 0, 255], [0, 255, 0, 255]);
+        let (width, height, pixels) = decode("#1;2;100;0;0#2;2;0;100;0#1!3@$#2_-#1@").unwrap();
+        assert_eq!((width, height), (3, 7));
+        // The return drew green over the bottom row of the red band
+        assert_eq!(pixels[..3], [red; 3]);
+        assert_eq!(pixels[5 * 3..6 * 3], [green, CLEAR, CLEAR]);
+        assert_eq!(pixels[6 * 3..], [red, CLEAR, CLEAR]);
+    }
+
+    #[test]
+    fn raster_attributes_declare_the_size() {
+        let (width, height, pixels) = decode("\"1;1;4;8#1@").unwrap();
+        assert_eq!((width, height), (4, 8));
+        assert_eq!(pixels.iter().filter(|pixel| **pixel != CLEAR).count(), 1);
+        // Without P2 = 1 the undrawn pixels are the background
+        let (_, _, pixels) = decode_with(&[0, 0], "\"1;1;4;8#1@").unwrap();
+        assert_eq!(pixels[1], BACKGROUND);
+        assert_eq!(decode("\"1;1;2;2"), Some((2, 2, vec![CLEAR; 4])));
+    }
+
+    #[test]
+    fn images_stop_at_the_maximum_size() {
+        let (width, height, _) = decode("!5000@").unwrap();
+        assert_eq!((width, height), (MAX_SIXEL_SIZE, 1));
+        // The band at 4092 is cut short and the ones below it dropped
+        let (_, height, _) = decode(&format!("@{}~-~", "-".repeat(682))).unwrap();
+        assert_eq!(height, MAX_SIXEL_SIZE);
+    }
+
+    #[test]
+    fn the_terminal_places_sixels_at_the_cursor() {
+        let mut terminal = Terminal::new(20, 10);
+        terminal.process_pty_output(b"\x1b[c");
+        assert_eq!(terminal.take_pending_input(), b"\x1b[?62;4c");
+
+        terminal.process_pty_output(b"ab\x1bPq\"1;1;16;32#1!16~\x1b\\");
+        let placement = &terminal.images()[0];
+        assert_eq!((placement.line, placement.col), (0, 2));
+        assert_eq!((placement.width, placement.height), (2.0, 2.0));
+        // Below the image, in the column it started in
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (2, 2));
+
+        terminal.process_pty_output(b"\x1b[?80h\x1bPq#1~\x1b\\");
+        let placement = &terminal.images()[1];
+        assert_eq!((placement.line, placement.col), (0, 0));
+        assert_eq!((terminal.cursor_x, terminal.cursor_y), (2, 2));
+    }
+}
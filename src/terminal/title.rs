@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use super::Terminal;
+
+/// A window title template with `{title}`, `{cwd}`, `{user}` and `{host}`
+/// placeholders, e.g. `{cwd}: {title}`. Other text, including unknown
+/// placeholders, is kept as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TitleTemplate {
+    template: String,
+    /// Looked up once, as the title is formatted every frame.
+    user: String,
+    host: String,
+    home: Option<PathBuf>,
+}
+
+impl TitleTemplate {
+    pub fn new(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            user: std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .unwrap_or_default(),
+            host: local_host().unwrap_or_default(),
+            home: std::env::var_os("HOME").map(PathBuf::from),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Fills in the placeholders. `host` replaces the local host name, as
+    /// when the shell reports a remote directory; a `cwd` under the home
+    /// directory starts with `~`.
+    pub fn render(&self, title: &str, cwd: Option<&Path>, host: Option<&str>) -> String {
+        let cwd = cwd.map_or_else(String::new, |cwd| self.abbreviate(cwd));
+        let host = host.unwrap_or(&self.host);
+        let mut out = String::with_capacity(self.template.len() + title.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let placeholders = [
+                ("{title}", title),
+                ("{cwd}", &cwd),
+                ("{user}", &self.user),
+                ("{host}", host),
+            ];
+            match placeholders.iter().find(|(name, _)| rest.starts_with(name)) {
+                Some((name, value)) => {
+                    out.push_str(value);
+                    rest = &rest[name.len()..];
+                }
+                None => {
+                    out.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn abbreviate(&self, path: &Path) -> String {
+        match self.home.as_deref().map(|home| path.strip_prefix(home)) {
+            Some(Ok(relative)) if relative.as_os_str().is_empty() => "~".to_string(),
+            Some(Ok(relative)) => format!("~/{}", relative.display()),
+            _ => path.display().to_string(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn local_host() -> Option<String> {
+    let mut name = [0u8; 256];
+    if unsafe { libc::gethostname(name.as_mut_ptr() as *mut libc::c_char, name.len()) } != 0 {
+        return None;
+    }
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    Some(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+#[cfg(not(unix))]
+fn local_host() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+impl Terminal {
+    /// Formats the window title with `template`; an empty template shows
+    /// the program's title as it is.
+    pub fn set_title_template(&mut self, template: &str) {
+        self.title_template = (!template.is_empty()).then(|| TitleTemplate::new(template));
+    }
+
+    pub fn title_template(&self) -> Option<&TitleTemplate> {
+        self.title_template.as_ref()
+    }
+
+    /// The directory the shell last reported with OSC 7.
+    pub fn working_directory(&self) -> Option<&Path> {
+        self.working_directory.as_deref()
+    }
+
+    /// The window title: the program's title through the template, or as
+    /// it is without one.
+    pub fn formatted_title(&self) -> String {
+        self.format_title(&self.title)
+    }
+
+    /// Puts `title` through the template, for hosts that show something
+    /// else, such as the foreground process, while the program sets none.
+    pub fn format_title(&self, title: &str) -> String {
+        match &self.title_template {
+            Some(template) => template.render(
+                title,
+                self.working_directory.as_deref(),
+                self.remote_host.as_deref(),
+            ),
+            None => title.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(template: &str) -> TitleTemplate {
+        TitleTemplate {
+            template: template.to_string(),
+            user: "ada".to_string(),
+            host: "desk".to_string(),
+            home: Some(PathBuf::from("/home/ada")),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let title = template("{user}@{host}: {cwd} — {title} {unknown} {");
+        assert_eq!(
+            title.render("vim", Some(Path::new("/srv/www")), None),
+            "ada@desk: /srv/www — vim {unknown} {"
+        );
+        assert_eq!(
+            title.render("vim", Some(Path::new("/srv/www")), Some("web1")),
+            "ada@web1: /srv/www — vim {unknown} {"
+        );
+        assert_eq!(title.render("", None, None), "ada@desk:  —  {unknown} {");
+    }
+
+    #[test]
+    fn directories_under_home_start_with_a_tilde() {
+        let cwd = template("{cwd}");
+        assert_eq!(cwd.render("", Some(Path::new("/home/ada")), None), "~");
+        assert_eq!(
+            cwd.render("", Some(Path::new("/home/ada/src/rt")), None),
+            "~/src/rt"
+        );
+        assert_eq!(
+            cwd.render("", Some(Path::new("/home/adam")), None),
+            "/home/adam"
+        );
+    }
+
+    #[test]
+    fn the_terminal_formats_its_title_and_directory() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.process_pty_output(b"\x1b]2;make\x07\x1b]7;file://localhost/tmp/build\x07");
+        assert_eq!(terminal.formatted_title(), "make");
+
+        terminal.set_title_template("{cwd}: {title} — rt");
+        assert_eq!(terminal.formatted_title(), "/tmp/build: make — rt");
+        terminal.process_pty_output(b"\x1b]0;cargo test\x07");
+        assert_eq!(terminal.formatted_title(), "/tmp/build: cargo test — rt");
+        assert_eq!(terminal.format_title("bash"), "/tmp/build: bash — rt");
+
+        terminal.set_title_template("");
+        assert_eq!(terminal.title_template(), None);
+        assert_eq!(terminal.formatted_title(), "cargo test");
+    }
+
+    #[test]
+    fn a_remote_directory_names_its_host() {
+        let mut terminal = Terminal::new(10, 3);
+        terminal.set_title_template("{host}:{cwd}");
+        terminal.process_pty_output(b"\x1b]7;file://web1/srv/www\x07");
+        assert_eq!(terminal.formatted_title(), "web1:/srv/www");
+        assert_eq!(terminal.working_directory(), Some(Path::new("/srv/www")));
+    }
+}
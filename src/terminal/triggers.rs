@@ -0,0 +1,375 @@
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use super::{Color, Terminal};
+
+/// Default cap on how often a single trigger may fire.
+const DEFAULT_MAX_PER_SECOND: u32 = 10;
+
+/// What a trigger does when its pattern matches a completed line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerAction {
+    /// Show a notification with the given title.
+    Notify(String),
+    /// Draw the matched cells over the given background until their line
+    /// leaves the screen or is overwritten.
+    Highlight(Color),
+    RunCommand(Vec<String>),
+    PlayBell,
+}
+
+/// A regex evaluated against each line of output once it is complete,
+/// written in the config as `action [argument] = regex`, e.g.
+/// `notify Build failed = ERROR` or `highlight #ff0000 = WARN`.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub regex: Regex,
+    pub action: TriggerAction,
+    pub max_per_second: u32,
+    window_start: Option<Instant>,
+    fired_in_window: u32,
+}
+
+/// An action from a trigger that the application has to carry out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TriggerEvent {
+    pub action: TriggerAction,
+    /// The complete line, without attributes.
+    pub line: String,
+    pub matched: String,
+}
+
+impl Trigger {
+    pub fn new(pattern: &str, action: TriggerAction) -> Result<Self, String> {
+        let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+        Ok(Self {
+            regex,
+            action,
+            max_per_second: DEFAULT_MAX_PER_SECOND,
+            window_start: None,
+            fired_in_window: 0,
+        })
+    }
+
+    /// Counts a firing against the rate limit, returning whether it's allowed.
+    fn allow(&mut self, now: Instant) -> bool {
+        match self.window_start {
+            Some(start) if now.duration_since(start) < Duration::from_secs(1) => {}
+            _ => {
+                self.window_start = Some(now);
+                self.fired_in_window = 0;
+            }
+        }
+        if self.fired_in_window >= self.max_per_second {
+            return false;
+        }
+        self.fired_in_window += 1;
+        true
+    }
+}
+
+impl FromStr for Trigger {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (action, pattern) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'action = regex' in '{}'", line))?;
+        let action = action.trim();
+        let (name, argument) = action
+            .split_once(char::is_whitespace)
+            .map_or((action, ""), |(name, argument)| (name, argument.trim()));
+
+        let action = match name {
+            "notify" => TriggerAction::Notify(argument.to_string()),
+            "highlight" => TriggerAction::Highlight(
+                Color::from_hex(argument)
+                    .ok_or_else(|| format!("expected a #rrggbb color, got '{}'", argument))?,
+            ),
+            "run" => {
+                let command = crate::profile::split_command(argument)?;
+                if command.is_empty() {
+                    return Err("run needs a command".to_string());
+                }
+                TriggerAction::RunCommand(command)
+            }
+            "bell" => TriggerAction::PlayBell,
+            _ => return Err(format!("unknown trigger action '{}'", name)),
+        };
+        Trigger::new(pattern.trim(), action)
+    }
+}
+
+/// Matched cells on one screen row, drawn over by a highlight trigger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct TriggerHighlight {
+    /// `lines_scrolled` plus the screen row, so that the highlight moves
+    /// with its row as the screen scrolls.
+    line: usize,
+    columns: Range<usize>,
+    color: Color,
+    alternate: bool,
+}
+
+impl Terminal {
+    pub fn set_triggers(&mut self, triggers: Vec<Trigger>) {
+        self.triggers = triggers;
+    }
+
+    /// Drains actions fired by triggers since the last call.
+    pub fn take_trigger_events(&mut self) -> Vec<TriggerEvent> {
+        std::mem::take(&mut self.trigger_events)
+    }
+
+    /// Runs the triggers over the logical line ending at the cursor row,
+    /// called when a line feed completes it.
+    pub(super) fn complete_line(&mut self) {
+        if self.triggers.is_empty() {
+            return;
+        }
+        let end = self.cursor_y.min(self.height - 1);
+        let mut start = end;
+        while start > 0 && Terminal::is_wrapped(&self.buffer[start - 1]) {
+            start -= 1;
+        }
+
+        // The line's text, with the cell and byte offset of each grapheme
+        // cluster in it
+        let mut line = String::new();
+        let mut positions = Vec::new();
+        let mut starts = Vec::new();
+        for y in start..=end {
+            for (x, cell) in self.buffer[y].iter().enumerate() {
+                positions.push((x, y));
+                starts.push(line.len());
+                if !cell.is_spacer() {
+                    line.push(cell.ch);
+                    line.push_str(cell.combining.as_str());
+                }
+            }
+        }
+        let line = line.trim_end().to_string();
+
+        let now = Instant::now();
+        let mut triggers = std::mem::take(&mut self.triggers);
+        for trigger in &mut triggers {
+            let matches: Vec<_> = trigger.regex.find_iter(&line).collect();
+            for found in matches {
+                if !trigger.allow(now) {
+                    break;
+                }
+                match &trigger.action {
+                    TriggerAction::Highlight(color) => {
+                        // The cells from the one holding the first matched
+                        // byte through the spacers after the last one
+                        let first = starts.partition_point(|&at| at <= found.start()) - 1;
+                        let mut last = starts.partition_point(|&at| at < found.end());
+                        while positions
+                            .get(last)
+                            .is_some_and(|&(x, y)| self.buffer[y][x].is_spacer())
+                        {
+                            last += 1;
+                        }
+                        for row in positions[first..last].chunk_by(|a, b| a.1 == b.1) {
+                            let (start, y) = row[0];
+                            self.trigger_highlights.push(TriggerHighlight {
+                                line: self.lines_scrolled.wrapping_add(y),
+                                columns: start..row[row.len() - 1].0 + 1,
+                                color: *color,
+                                alternate: self.alternate_screen,
+                            });
+                        }
+                        self.damage_frame.clear();
+                    }
+                    action => self.trigger_events.push(TriggerEvent {
+                        action: action.clone(),
+                        line: line.clone(),
+                        matched: found.as_str().to_string(),
+                    }),
+                }
+            }
+        }
+        self.triggers = triggers;
+    }
+
+    /// Highlights from triggers on the rows in view, as the viewport row,
+    /// the columns and the background to draw them over.
+    pub fn visible_trigger_highlights(
+        &self,
+    ) -> impl Iterator<Item = (usize, Range<usize>, Color)> + '_ {
+        let offset = (self.scroll_state.offset.round() as usize).min(self.history.len());
+        let top = self.lines_scrolled.wrapping_sub(offset);
+        self.trigger_highlights
+            .iter()
+            .filter(move |highlight| highlight.alternate == self.alternate_screen)
+            .map(move |highlight| {
+                let row = highlight.line.wrapping_sub(top);
+                (row, highlight.columns.clone(), highlight.color)
+            })
+            .filter(move |(row, _, _)| *row < self.height)
+    }
+
+    /// Drops the highlights for `keep` rejects, repainting if any went.
+    fn retain_trigger_highlights(&mut self, mut keep: impl FnMut(&TriggerHighlight) -> bool) {
+        let before = self.trigger_highlights.len();
+        self.trigger_highlights.retain(|highlight| keep(highlight));
+        if self.trigger_highlights.len() != before {
+            self.damage_frame.clear();
+        }
+    }
+
+    /// Drops highlights on screen rows `rows` of the screen in use, as when
+    /// they are erased or moved within the screen.
+    pub(super) fn drop_trigger_highlights(&mut self, rows: Range<usize>) {
+        if self.trigger_highlights.is_empty() {
+            return;
+        }
+        let (top, alternate) = (self.lines_scrolled, self.alternate_screen);
+        self.retain_trigger_highlights(|highlight| {
+            highlight.alternate != alternate || !rows.contains(&highlight.line.wrapping_sub(top))
+        });
+    }
+
+    /// Drops highlights on lines that left the top of the screen or are
+    /// past its bottom, after it scrolled or got shorter.
+    pub(super) fn trim_trigger_highlights(&mut self) {
+        if self.trigger_highlights.is_empty() {
+            return;
+        }
+        let (top, height) = (self.lines_scrolled, self.height);
+        self.retain_trigger_highlights(|highlight| {
+            highlight.alternate || highlight.line.wrapping_sub(top) < height
+        });
+    }
+
+    /// Drops every highlight on the alternate screen, as it is left
+    /// blank whenever it is entered.
+    pub(super) fn drop_alternate_trigger_highlights(&mut self) {
+        self.retain_trigger_highlights(|highlight| !highlight.alternate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color(255, 0, 0, 255);
+
+    fn terminal_with(trigger: &str) -> Terminal {
+        let mut terminal = Terminal::new(20, 4);
+        terminal.set_triggers(vec![trigger.parse().unwrap()]);
+        terminal
+    }
+
+    #[test]
+    fn notify_fires_once_per_occurrence() {
+        let mut terminal = terminal_with("notify Build = ERROR");
+        terminal.process_pty_output(b"ok\r\n\x1b[31mERROR\x1b[m one ERROR\r\nERR");
+        let events = terminal.take_trigger_events();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.matched == "ERROR"));
+        assert_eq!(events[0].line, "ERROR one ERROR");
+    }
+
+    #[test]
+    fn matches_span_soft_wrapped_rows() {
+        let mut terminal = terminal_with("notify Build = ERROR");
+        terminal.process_pty_output(b"0123456789012345ERROR\r\n");
+        assert_eq!(terminal.take_trigger_events().len(), 1);
+    }
+
+    /// The highlighted columns of viewport row `y`, as flags.
+    fn highlighted(terminal: &Terminal, y: usize, width: usize) -> Vec<bool> {
+        let mut flags = vec![false; width];
+        for (row, columns, color) in terminal.visible_trigger_highlights() {
+            if row == y {
+                assert_eq!(color, RED);
+                flags[columns].fill(true);
+            }
+        }
+        flags
+    }
+
+    #[test]
+    fn wide_characters_are_matched_without_their_spacers() {
+        let mut terminal = terminal_with("highlight #ff0000 = 漢字x");
+        terminal.process_pty_output("a漢字xb\r\n".as_bytes());
+        assert_eq!(
+            highlighted(&terminal, 0, 7),
+            [false, true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn combining_marks_are_part_of_the_line() {
+        let mut terminal = terminal_with("highlight #ff0000 = cafe\u{301}!");
+        terminal.process_pty_output("> cafe\u{301}!\r\n".as_bytes());
+        assert_eq!(
+            highlighted(&terminal, 0, 8),
+            [false, false, true, true, true, true, true, false]
+        );
+    }
+
+    #[test]
+    fn highlights_are_drawn_over_the_cells_without_changing_them() {
+        let mut terminal = terminal_with("highlight #ff0000 = WARN");
+        terminal.process_pty_output(b"0123456789012345WARNING\r\n");
+        assert!(terminal
+            .buffer
+            .iter()
+            .flatten()
+            .all(|cell| cell.bg_color != RED));
+        let mut wrapped = highlighted(&terminal, 0, 20);
+        wrapped.extend(highlighted(&terminal, 1, 20));
+        let expected: Vec<bool> = (0..40).map(|x| (16..20).contains(&x)).collect();
+        assert_eq!(wrapped, expected);
+    }
+
+    #[test]
+    fn highlights_follow_their_row_until_it_leaves_the_screen() {
+        let mut terminal = terminal_with("highlight #ff0000 = WARN");
+        terminal.process_pty_output(b"\r\nWARN\r\n\r\n");
+        assert!(highlighted(&terminal, 1, 4).iter().all(|&on| on));
+        terminal.process_pty_output(b"\r\n");
+        assert!(highlighted(&terminal, 0, 4).iter().all(|&on| on));
+        assert!(highlighted(&terminal, 1, 4).iter().all(|&on| !on));
+        terminal.process_pty_output(b"\r\n");
+        assert_eq!(terminal.visible_trigger_highlights().count(), 0);
+        assert_eq!(terminal.trigger_highlights.len(), 0);
+    }
+
+    #[test]
+    fn erasing_a_row_drops_its_highlight() {
+        let mut terminal = terminal_with("highlight #ff0000 = WARN");
+        terminal.process_pty_output(b"WARN\r\nWARN\r\n\x1b[1;1H\x1b[2K");
+        assert!(highlighted(&terminal, 0, 4).iter().all(|&on| !on));
+        assert!(highlighted(&terminal, 1, 4).iter().all(|&on| on));
+        terminal.process_pty_output(b"\x1b[?1049h");
+        assert_eq!(terminal.visible_trigger_highlights().count(), 0);
+        terminal.process_pty_output(b"\x1b[?1049l");
+        assert_eq!(terminal.visible_trigger_highlights().count(), 1);
+    }
+
+    #[test]
+    fn run_commands_are_split_like_a_shell_would() {
+        let trigger: Trigger = r#"run notify-send "Build failed" 'in $dir' = ERROR"#
+            .parse()
+            .unwrap();
+        assert_eq!(
+            trigger.action,
+            TriggerAction::RunCommand(vec![
+                "notify-send".to_string(),
+                "Build failed".to_string(),
+                "in $dir".to_string(),
+            ])
+        );
+        assert_eq!(
+            "run = ERROR".parse::<Trigger>().unwrap_err(),
+            "run needs a command"
+        );
+        assert!("run echo \"oops = ERROR".parse::<Trigger>().is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::terminal::Terminal;
+
+/// Bytes read from the file at a time.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks read ahead of the terminal, bounding memory for large files.
+const QUEUED_CHUNKS: usize = 16;
+
+/// A file of ANSI-colored text shown read-only with `--view`, loaded by a
+/// background thread in chunks so large logs don't block the UI.
+pub struct FileViewer {
+    name: String,
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    /// Size of the file, when known up front.
+    total: Option<u64>,
+    loaded: u64,
+    done: bool,
+    /// The last feed ran out of time with chunks still queued.
+    backlog: bool,
+    /// The last byte written was a carriage return, so a line feed after
+    /// it, even in the next chunk, needs none added.
+    after_cr: bool,
+}
+
+impl FileViewer {
+    /// Starts reading `path`, or standard input when it is `-`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::from_reader("stdin".to_string(), io::stdin(), None));
+        }
+        let file = File::open(path)?;
+        let total = file.metadata().ok().map(|metadata| metadata.len());
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+        Ok(Self::from_reader(name, file, total))
+    }
+
+    /// Starts reading `reader` on a background thread; `total` is its
+    /// length in bytes, if known, for the loading progress.
+    pub fn from_reader<R: Read + Send + 'static>(
+        name: String,
+        mut reader: R,
+        total: Option<u64>,
+    ) -> Self {
+        let (sender, chunks) = mpsc::sync_channel(QUEUED_CHUNKS);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    if sender.send(Ok(chunk)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        });
+        Self {
+            name,
+            chunks,
+            total,
+            loaded: 0,
+            done: false,
+            backlog: false,
+            after_cr: false,
+        }
+    }
+
+    /// The file name, or `stdin`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_loading(&self) -> bool {
+        !self.done
+    }
+
+    /// Whether chunks are waiting that the last feed had no time for.
+    pub fn has_backlog(&self) -> bool {
+        self.backlog
+    }
+
+    /// Bytes read so far.
+    pub fn loaded(&self) -> u64 {
+        self.loaded
+    }
+
+    /// Fraction of the file loaded, if its size is known.
+    pub fn progress(&self) -> Option<f64> {
+        self.total
+            .filter(|&total| total > 0)
+            .map(|total| (self.loaded as f64 / total as f64).min(1.0))
+    }
+
+    /// The window title: the file name, with progress while loading.
+    pub fn title(&self) -> String {
+        if self.done {
+            return self.name.clone();
+        }
+        match self.progress() {
+            Some(fraction) => format!("{} (loading {:.0}%)", self.name, fraction * 100.0),
+            None => format!("{} (loading {} KiB)", self.name, self.loaded / 1024),
+        }
+    }
+
+    /// Writes queued chunks into `terminal` until `budget` runs out.
+    /// Returns whether anything was written.
+    pub fn feed(&mut self, terminal: &mut Terminal, budget: Duration) -> bool {
+        let start = Instant::now();
+        let mut written = false;
+        self.backlog = false;
+        while !self.done {
+            if written && start.elapsed() >= budget {
+                self.backlog = true;
+                break;
+            }
+            match self.chunks.try_recv() {
+                Ok(Ok(chunk)) => {
+                    self.loaded += chunk.len() as u64;
+                    terminal.write_bytes(&self.translate_newlines(&chunk));
+                    written = true;
+                }
+                Ok(Err(e)) => {
+                    eprintln!("rt: --view {}: {}", self.name, e);
+                    self.done = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.done = true,
+            }
+        }
+        written
+    }
+
+    /// Turns bare line feeds into CR LF, as the tty does for a program's
+    /// output, so lines of a plain text file start at the left edge.
+    fn translate_newlines(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len() + chunk.len() / 32);
+        for &byte in chunk {
+            if byte == b'\n' && !self.after_cr {
+                out.push(b'\r');
+            }
+            out.push(byte);
+            self.after_cr = byte == b'\r';
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads one byte at a time, so every byte arrives in its own chunk.
+    struct Trickle(io::Cursor<Vec<u8>>);
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    fn load(viewer: &mut FileViewer, terminal: &mut Terminal) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while viewer.is_loading() && Instant::now() < deadline {
+            viewer.feed(terminal, Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!viewer.is_loading());
+    }
+
+    #[test]
+    fn a_fixture_file_is_shown_with_its_history() {
+        let path = Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/build.ansi"
+        ));
+        let mut viewer = FileViewer::open(path).unwrap();
+        assert_eq!(viewer.name(), "build.ansi");
+        let mut terminal = Terminal::new(30, 10);
+        load(&mut viewer, &mut terminal);
+
+        assert_eq!(viewer.progress(), Some(1.0));
+        assert_eq!(viewer.title(), "build.ansi");
+        // 30 lines and the empty one after the last newline
+        assert_eq!(terminal.history_len(), 21);
+        assert_eq!(terminal.line_text(0).as_deref(), Some("ok step 22"));
+        assert_eq!(
+            terminal.line_text(7).as_deref(),
+            Some("error: step 29 failed")
+        );
+        assert_eq!(terminal.line_text(8).as_deref(), Some("done"));
+        assert_eq!(terminal.line_text(9).as_deref(), Some(""));
+        let error = terminal.get_cell_at(0, 7).unwrap();
+        assert!(error.attrs.bold);
+        assert_ne!(error.fg_color, terminal.get_cell_at(5, 7).unwrap().fg_color);
+        assert_ne!(
+            terminal.get_cell_at(0, 0).unwrap().fg_color,
+            terminal.get_cell_at(3, 0).unwrap().fg_color
+        );
+    }
+
+    #[test]
+    fn line_feeds_get_one_carriage_return_across_chunks() {
+        let text = b"one\r\ntwo\nthree\r\r\nfour".to_vec();
+        let mut viewer =
+            FileViewer::from_reader("log".to_string(), Trickle(io::Cursor::new(text)), None);
+        assert_eq!(viewer.title(), "log (loading 0 KiB)");
+        assert_eq!(viewer.progress(), None);
+        let mut terminal = Terminal::new(10, 5);
+        load(&mut viewer, &mut terminal);
+
+        assert_eq!(viewer.loaded(), 21);
+        let lines: Vec<String> = (0..5).filter_map(|y| terminal.line_text(y)).collect();
+        assert_eq!(lines, ["one", "two", "three", "four", ""]);
+        assert_eq!(terminal.history_len(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Part of a window that must stay on a monitor, in physical pixels, so it
+/// can still be grabbed and moved.
+const MIN_VISIBLE: i32 = 64;
+
+/// Window placement and session details saved on a clean shutdown and
+/// restored on the next start.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowState {
+    /// Inner size in physical pixels.
+    pub size: Option<(u32, u32)>,
+    /// Outer position in physical pixels.
+    pub position: Option<(i32, i32)>,
+    pub maximized: bool,
+    /// Directory the shell was in.
+    pub working_directory: Option<PathBuf>,
+}
+
+/// A monitor's area in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonitorArea {
+    pub position: (i32, i32),
+    pub size: (u32, u32),
+}
+
+impl WindowState {
+    /// `$XDG_STATE_HOME/rt/state.json`, falling back to
+    /// `~/.local/state/rt/state.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| Path::new(&home).join(".local").join("state"))
+            })?;
+        Some(base.join("rt").join("state.json"))
+    }
+
+    /// Loads the state at `path`. A missing or unreadable file yields the
+    /// defaults, as restoring is only a convenience.
+    pub fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|source| Self::parse(&source))
+            .unwrap_or_default()
+    }
+
+    /// Writes the state to `path`, replacing the old file atomically.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, self.to_json())?;
+        fs::rename(&temp, path)
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some((width, height)) = self.size {
+            fields.push(format!("\"width\": {}", width));
+            fields.push(format!("\"height\": {}", height));
+        }
+        if let Some((x, y)) = self.position {
+            fields.push(format!("\"x\": {}", x));
+            fields.push(format!("\"y\": {}", y));
+        }
+        fields.push(format!("\"maximized\": {}", self.maximized));
+        if let Some(dir) = self.working_directory.as_ref().and_then(|dir| dir.to_str()) {
+            fields.push(format!("\"working_directory\": {}", json_string(dir)));
+        }
+        format!("{{\n  {}\n}}\n", fields.join(",\n  "))
+    }
+
+    /// Parses a saved state, or `None` if it is malformed. Unknown keys are
+    /// ignored so older versions can read newer files.
+    pub fn parse(source: &str) -> Option<Self> {
+        let object = parse_object(source)?;
+        let number = |key: &str| match object.get(key) {
+            Some(Value::Number(n)) => Some(*n),
+            _ => None,
+        };
+        let size = match (number("width"), number("height")) {
+            (Some(width), Some(height)) if width >= 1.0 && height >= 1.0 => {
+                Some((width as u32, height as u32))
+            }
+            _ => None,
+        };
+        let position = match (number("x"), number("y")) {
+            (Some(x), Some(y)) => Some((x as i32, y as i32)),
+            _ => None,
+        };
+        Some(Self {
+            size,
+            position,
+            maximized: matches!(object.get("maximized"), Some(Value::Bool(true))),
+            working_directory: match object.get("working_directory") {
+                Some(Value::String(dir)) => Some(PathBuf::from(dir)),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Moves a window at `position` of `size` back onto a monitor if too little
+/// of it would be visible, e.g. after the monitor it was on was unplugged.
+/// The window is placed at the top-left of the first monitor, which should
+/// be the primary one.
+pub fn clamp_to_monitors(
+    position: (i32, i32),
+    size: (u32, u32),
+    monitors: &[MonitorArea],
+) -> (i32, i32) {
+    let visible = |monitor: &MonitorArea| {
+        let overlap = |start: i32, len: u32, area_start: i32, area_len: u32| {
+            let end = start.saturating_add(len as i32);
+            let area_end = area_start.saturating_add(area_len as i32);
+            end.min(area_end) - start.max(area_start)
+        };
+        let width = overlap(position.0, size.0, monitor.position.0, monitor.size.0);
+        let height = overlap(position.1, size.1, monitor.position.1, monitor.size.1);
+        width >= MIN_VISIBLE.min(size.0 as i32) && height >= MIN_VISIBLE.min(size.1 as i32)
+    };
+    match monitors.first() {
+        Some(primary) if !monitors.iter().any(visible) => primary.position,
+        _ => position,
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in value.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            ch if ch.is_control() => {
+                let _ = write!(out, "\\u{:04x}", ch as u32);
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Number(f64),
+    Bool(bool),
+    Null,
+}
+
+/// Parses a flat JSON object with string, number, boolean and null values.
+fn parse_object(source: &str) -> Option<HashMap<String, Value>> {
+    let mut chars = source.trim().chars().peekable();
+    let mut object = HashMap::new();
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|ch| ch.is_whitespace()).is_some() {}
+    };
+
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return chars.next().is_none().then_some(object);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        let key = parse_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if chars.next()? != ':' {
+            return None;
+        }
+        skip_whitespace(&mut chars);
+        let value = match *chars.peek()? {
+            '"' => Value::String(parse_string(&mut chars)?),
+            't' | 'f' | 'n' => {
+                let word: String =
+                    std::iter::from_fn(|| chars.next_if(|ch| ch.is_ascii_alphabetic())).collect();
+                match word.as_str() {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    "null" => Value::Null,
+                    _ => return None,
+                }
+            }
+            _ => {
+                let number: String = std::iter::from_fn(|| {
+                    chars.next_if(|ch| ch.is_ascii_digit() || "+-.eE".contains(*ch))
+                })
+                .collect();
+                Value::Number(number.parse().ok()?)
+            }
+        };
+        object.insert(key, value);
+        skip_whitespace(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return chars.next().is_none().then_some(object),
+            _ => return None,
+        }
+    }
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(out),
+            '\\' => match chars.next()? {
+                'n' => out.push('\n'),
+                't' => out.push('\t'),
+                'r' => out.push('\r'),
+                'u' => {
+                    let hex: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+                }
+                ch => out.push(ch),
+            },
+            ch => out.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved() -> WindowState {
+        WindowState {
+            size: Some((1200, 800)),
+            position: Some((-1920, 40)),
+            maximized: true,
+            working_directory: Some(PathBuf::from("/home/me/\"quoted\" \\ dir\n")),
+        }
+    }
+
+    #[test]
+    fn state_round_trips_through_json() {
+        assert_eq!(WindowState::parse(&saved().to_json()), Some(saved()));
+        let empty = WindowState::default();
+        assert_eq!(WindowState::parse(&empty.to_json()), Some(empty));
+    }
+
+    #[test]
+    fn state_round_trips_through_a_file() {
+        let dir = std::env::temp_dir().join(format!("rt-{}-window-state", std::process::id()));
+        let path = dir.join("rt").join("state.json");
+        saved().save(&path).unwrap();
+        let loaded = WindowState::load(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded, saved());
+    }
+
+    #[test]
+    fn bad_state_files_fall_back_to_the_defaults() {
+        let missing = std::env::temp_dir()
+            .join("rt-no-such-dir")
+            .join("state.json");
+        assert_eq!(WindowState::load(&missing), WindowState::default());
+        for corrupt in ["", "{", "{\"width\": }", "[1]", "{\"x\": 1} trailing"] {
+            assert_eq!(WindowState::parse(corrupt), None, "{:?}", corrupt);
+        }
+        // Unknown keys and nonsense sizes are ignored
+        let state = WindowState::parse("{\"width\": 0, \"height\": 5, \"theme\": null}");
+        assert_eq!(state, Some(WindowState::default()));
+    }
+
+    #[test]
+    fn off_screen_windows_move_to_the_primary_monitor() {
+        let monitors = [
+            MonitorArea {
+                position: (0, 0),
+                size: (1920, 1080),
+            },
+            MonitorArea {
+                position: (1920, 0),
+                size: (1280, 1024),
+            },
+        ];
+        let size = (800, 600);
+        // On either monitor, or mostly off one edge
+        for position in [(100, 100), (2000, 200), (-700, 900), (3100, 960)] {
+            assert_eq!(clamp_to_monitors(position, size, &monitors), position);
+        }
+        // Where a monitor was unplugged, or too little shows to grab
+        for position in [(-1920, 40), (3300, 100), (1000, 1050), (100, -590)] {
+            assert_eq!(clamp_to_monitors(position, size, &monitors), (0, 0));
+        }
+        assert_eq!(clamp_to_monitors((-5000, 0), size, &[]), (-5000, 0));
+    }
+}
@@ -0,0 +1,153 @@
+//! Property tests feeding arbitrary output, escape sequences and resizes
+//! through the terminal. The fuzz target in `fuzz/` explores the same
+//! space for longer.
+
+use proptest::prelude::*;
+use rt::terminal::Terminal;
+
+#[derive(Debug, Clone)]
+enum Step {
+    Output(Vec<u8>),
+    Resize(usize, usize),
+}
+
+/// A CSI sequence with two parameters and one of the final bytes the
+/// terminal handles.
+fn csi() -> impl Strategy<Value = Vec<u8>> {
+    let actions = prop::sample::select(b"@ABCDEFGHJKLMPSTXdfhlmnrsu".to_vec());
+    (0u16..300, 0u16..300, actions)
+        .prop_map(|(a, b, action)| format!("\x1b[{};{}{}", a, b, action as char).into_bytes())
+}
+
+/// DECSET or DECRST of a private mode.
+fn private_mode() -> impl Strategy<Value = Vec<u8>> {
+    (0u16..3000, any::<bool>()).prop_map(|(mode, set)| {
+        let action = if set { 'h' } else { 'l' };
+        format!("\x1b[?{}{}", mode, action).into_bytes()
+    })
+}
+
+/// Bytes weighted towards escape sequences, so CSI, OSC and DCS
+/// parameters get exercised rather than only printable text.
+fn output() -> impl Strategy<Value = Vec<u8>> {
+    let fragment = prop_oneof![
+        prop::collection::vec(any::<u8>(), 0..16),
+        csi(),
+        private_mode(),
+        Just("日本語".as_bytes().to_vec()),
+        Just(b"e\xcc\x81".to_vec()),
+        Just(b"\x1bP$qm\x1b\\".to_vec()),
+        Just(b"\x1b7\x1b8\x1bM\x1bD\x1bE\x1bc".to_vec()),
+    ];
+    prop::collection::vec(fragment, 0..32).prop_map(|fragments| fragments.concat())
+}
+
+fn steps() -> impl Strategy<Value = Vec<Step>> {
+    let step = prop_oneof![
+        4 => output().prop_map(Step::Output),
+        1 => (0usize..200, 0usize..80).prop_map(|(cols, rows)| Step::Resize(cols, rows)),
+    ];
+    prop::collection::vec(step, 1..16)
+}
+
+/// Longest DCS payload buffered in the DCS tests.
+const DCS_LIMIT: usize = 64;
+
+/// A device control string and the reply it gets.
+fn dcs() -> impl Strategy<Value = (Vec<u8>, &'static str)> {
+    let unknown = prop::collection::vec(0x20u8..0x7f, 0..32)
+        .prop_map(|body| ([b"\x1bPz".as_slice(), &body, b"\x1b\\"].concat(), ""));
+    prop_oneof![
+        Just((b"\x1bP$qm\x1b\\".to_vec(), "\x1bP1$r0m\x1b\\")),
+        Just((b"\x1bP+q436f\x1b\\".to_vec(), "\x1bP1+r436f=323536\x1b\\")),
+        Just((b"\x1bPtmux;hello\x1b\\".to_vec(), "")),
+        Just((
+            format!("\x1bP$q{}\x1b\\", "m".repeat(DCS_LIMIT + 1)).into_bytes(),
+            ""
+        )),
+        // Cancelled by CAN
+        Just((b"\x1bP$qm\x18".to_vec(), "")),
+        unknown,
+    ]
+}
+
+/// Plain text and line breaks.
+fn text() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        "[a-z ]{0,12}".prop_map(String::into_bytes),
+        Just(b"\r\n".to_vec()),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn cursor_stays_within_the_grid(steps in steps()) {
+        let mut terminal = Terminal::new(80, 24);
+        for step in steps {
+            match step {
+                Step::Output(bytes) => terminal.process_pty_output(&bytes),
+                Step::Resize(cols, rows) => terminal.resize(cols, rows),
+            }
+            // A pending wrap leaves the cursor one past the last column
+            prop_assert!(terminal.cursor_x <= terminal.width);
+            prop_assert!(terminal.cursor_y < terminal.height);
+        }
+    }
+
+    #[test]
+    fn rows_keep_the_grid_width(steps in steps()) {
+        let mut terminal = Terminal::new(80, 24);
+        for step in steps {
+            match step {
+                Step::Output(bytes) => terminal.process_pty_output(&bytes),
+                Step::Resize(cols, rows) => terminal.resize(cols, rows),
+            }
+            prop_assert_eq!(terminal.iter_rows().count(), terminal.height);
+            for row in terminal.iter_rows() {
+                prop_assert_eq!(row.cells().len(), terminal.width);
+            }
+        }
+    }
+
+    #[test]
+    fn only_resizes_change_the_grid_size(bytes in output()) {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.process_pty_output(&bytes);
+        // DECCOLM (mode 3) is the one sequence that switches the width
+        prop_assert!(terminal.width == 80 || terminal.width == 132);
+        prop_assert_eq!(terminal.height, 24);
+    }
+
+    #[test]
+    fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+        let mut terminal = Terminal::new(80, 24);
+        terminal.process_pty_output(&bytes);
+        prop_assert!(terminal.history_len() <= terminal.scrollback_limit);
+    }
+
+    #[test]
+    fn dcs_strings_leave_the_output_around_them_alone(
+        pieces in prop::collection::vec((text(), dcs()), 1..12),
+        split in any::<prop::sample::Index>(),
+    ) {
+        let mut terminal = Terminal::new(40, 10);
+        terminal.set_dcs_payload_limit(DCS_LIMIT);
+        let mut plain = Terminal::new(40, 10);
+        let mut bytes = Vec::new();
+        let mut replies = String::new();
+        for (text, (dcs, reply)) in &pieces {
+            bytes.extend_from_slice(text);
+            bytes.extend_from_slice(dcs);
+            replies.push_str(reply);
+            plain.process_pty_output(text);
+        }
+        // Output arrives in reads that may end anywhere in a string
+        let (first, rest) = bytes.split_at(split.index(bytes.len() + 1));
+        terminal.process_pty_output(first);
+        terminal.process_pty_output(rest);
+
+        prop_assert_eq!(terminal.screen_text(), plain.screen_text());
+        prop_assert_eq!((terminal.cursor_x, terminal.cursor_y), (plain.cursor_x, plain.cursor_y));
+        prop_assert_eq!(String::from_utf8(terminal.take_pending_input()).unwrap(), replies);
+    }
+}